@@ -0,0 +1,82 @@
+/*
+ * pet-input-server: src/backpressure.rs
+ *
+ * Tells a client when its server-side queue is backing up, so it can degrade
+ * gracefully. Uses two watermarks (hysteresis) so the notice doesn't flap.
+ */
+
+/// Per-connection queue metrics and watermark state.
+pub struct BackpressureMonitor {
+    high: usize,
+    low: usize,
+    engaged: bool,
+    /// Total events this client missed because its queue overflowed.
+    pub dropped: u64,
+}
+
+impl BackpressureMonitor {
+    pub fn new(high: usize, low: usize) -> Self {
+        BackpressureMonitor {
+            high: high.max(1),
+            low: low.min(high),
+            engaged: false,
+            dropped: 0,
+        }
+    }
+
+    /// Records the current queue depth and any events dropped since the last call.
+    /// Returns the notice to send when the state flips: `"high"` once `pending`
+    /// reaches the high watermark (or anything was dropped), `"normal"` once it falls
+    /// back to the low watermark.
+    pub fn observe(&mut self, pending: usize, newly_dropped: u64) -> Option<&'static str> {
+        self.dropped += newly_dropped;
+        if !self.engaged && (pending >= self.high || newly_dropped > 0) {
+            self.engaged = true;
+            Some("high")
+        } else if self.engaged && pending <= self.low && newly_dropped == 0 {
+            self.engaged = false;
+            Some("normal")
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flips_at_the_watermarks_only() {
+        let mut monitor = BackpressureMonitor::new(100, 20);
+        assert_eq!(monitor.observe(99, 0), None);
+        assert_eq!(monitor.observe(100, 0), Some("high"));
+        // Between the watermarks it stays high instead of flapping.
+        for pending in [150, 60, 21, 99, 21] {
+            assert_eq!(monitor.observe(pending, 0), None, "{}", pending);
+        }
+        assert_eq!(monitor.observe(20, 0), Some("normal"));
+        assert_eq!(monitor.observe(60, 0), None);
+        assert_eq!(monitor.observe(100, 0), Some("high"));
+    }
+
+    #[test]
+    fn drops_engage_and_hold_it_high() {
+        let mut monitor = BackpressureMonitor::new(100, 20);
+        assert_eq!(monitor.observe(0, 3), Some("high"));
+        assert_eq!(monitor.observe(0, 2), None);
+        assert_eq!(monitor.observe(0, 0), Some("normal"));
+        assert_eq!(monitor.dropped, 5);
+    }
+
+    #[test]
+    fn watermarks_are_kept_sane() {
+        // A low watermark above the high one is clamped, and a zero high still engages.
+        let mut monitor = BackpressureMonitor::new(10, 50);
+        assert_eq!(monitor.observe(10, 0), Some("high"));
+        assert_eq!(monitor.observe(10, 0), Some("normal"));
+        let mut monitor = BackpressureMonitor::new(0, 0);
+        assert_eq!(monitor.observe(1, 0), Some("high"));
+        assert_eq!(monitor.observe(0, 0), Some("normal"));
+    }
+}
@@ -0,0 +1,49 @@
+/*
+ * pet-input-server: src/chords.rs
+ *
+ * Derived `ButtonChord` events: the mouse buttons held together, e.g.
+ * ["Mouse1","Mouse2"], sent when a press brings two or more buttons down
+ * at once. With `--button-chord-exit` an empty `ButtonChord` follows when
+ * the chord breaks. MousePress/MouseRelease are unaffected.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Buttons currently held, in press order.
+static HELD: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static REPORT_EXIT: AtomicBool = AtomicBool::new(false);
+
+pub fn configure(report_exit: bool) {
+    REPORT_EXIT.store(report_exit, Ordering::Relaxed);
+}
+
+fn chord(buttons: &[String]) -> Action {
+    crate::rates::count(ActionKind::ButtonChord);
+    Action::new(ActionKind::ButtonChord, ActionValue::Json(buttons.into()))
+}
+
+/// Called for every mouse button press with the mapped name.
+pub fn on_button_press(button: &str, broadcast_tx: &broadcast::Sender<Action>) {
+    let mut held = HELD.lock().unwrap();
+    if held.iter().any(|b| b == button) {
+        return;
+    }
+    held.push(button.to_string());
+    if held.len() >= 2 {
+        let _ = broadcast_tx.send(chord(&held));
+    }
+}
+
+/// Called for every mouse button release.
+pub fn on_button_release(button: &str, broadcast_tx: &broadcast::Sender<Action>) {
+    let mut held = HELD.lock().unwrap();
+    let was_chord = held.len() >= 2;
+    held.retain(|b| b != button);
+    if was_chord && held.len() < 2 && REPORT_EXIT.load(Ordering::Relaxed) {
+        let _ = broadcast_tx.send(chord(&[]));
+    }
+}
@@ -0,0 +1,182 @@
+/*
+ * pet-input-server: src/clientqueue.rs
+ *
+ * What happens when a client's queue of live input is full: `--queue-policy`
+ * for everyone, `?queue=` per connection.
+ *
+ * - `drop-oldest` (default): the queue is the client's place in the broadcast
+ *   ring. A client a whole ring behind has its oldest events overwritten, so
+ *   what it does get is the freshest state. Right for a cursor-following pet.
+ * - `drop-newest`: a forwarder copies events into a queue of the same size and
+ *   discards new ones while it is full. The client gets an unbroken run and
+ *   then a gap, instead of a hole somewhere in what it already had queued.
+ *   Right for clients that replay sequences, such as macro recorders.
+ * - `block`: the forwarder waits up to `--queue-block-timeout-ms` for room.
+ *   Nothing is dropped silently: if the client doesn't catch up in time (or
+ *   the forwarder falls a whole ring behind while waiting) the connection is
+ *   closed with code 4004 so the client knows to resync. While it waits, the
+ *   backlog sits in the broadcast ring and counts towards `--shed-at-percent`.
+ *
+ * Drops look the same under every policy: a `Lagged` count, which feeds the
+ * Backpressure notice and the lag metrics.
+ */
+
+use crate::Action;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{self, error::SendTimeoutError, error::TrySendError};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum QueuePolicy {
+    /// Overwrite the oldest queued events; the client always gets the freshest state
+    #[default]
+    DropOldest,
+    /// Discard new events while the queue is full; what was queued stays in order
+    DropNewest,
+    /// Wait for room, and close the connection if the client doesn't catch up
+    Block,
+}
+
+impl QueuePolicy {
+    /// Parses a `?queue=` value.
+    pub fn parse(name: &str) -> Option<QueuePolicy> {
+        <QueuePolicy as clap::ValueEnum>::from_str(name, false).ok()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QueuePolicy::DropOldest => "drop-oldest",
+            QueuePolicy::DropNewest => "drop-newest",
+            QueuePolicy::Block => "block",
+        }
+    }
+
+    /// Every policy's name, for error messages and `/capabilities`.
+    pub fn names() -> Vec<&'static str> {
+        <QueuePolicy as clap::ValueEnum>::value_variants().iter().map(|policy| policy.as_str()).collect()
+    }
+}
+
+/// What the forwarder reports back to the connection.
+#[derive(Default)]
+struct Shared {
+    /// Events discarded since the connection last looked.
+    dropped: AtomicU64,
+    /// Set when a `block` client failed to catch up.
+    stalled: AtomicBool,
+}
+
+/// One client's live input under its overflow policy.
+pub struct ClientQueue(Inner);
+
+enum Inner {
+    /// `drop-oldest`: the broadcast receiver is the queue.
+    Ring(broadcast::Receiver<Action>),
+    /// `drop-newest` and `block`: a forwarder task fills a bounded queue.
+    Forwarded {
+        rx: mpsc::Receiver<Action>,
+        shared: Arc<Shared>,
+        forwarder: tokio::task::JoinHandle<()>,
+    },
+}
+
+impl ClientQueue {
+    pub fn new(rx: broadcast::Receiver<Action>, policy: QueuePolicy, block_timeout: Duration) -> ClientQueue {
+        if policy == QueuePolicy::DropOldest {
+            return ClientQueue(Inner::Ring(rx));
+        }
+        let (tx, queue) = mpsc::channel(crate::BROADCAST_CAPACITY);
+        let shared = Arc::new(Shared::default());
+        let forwarder = tokio::spawn(forward(rx, tx, policy, block_timeout, shared.clone()));
+        ClientQueue(Inner::Forwarded {
+            rx: queue,
+            shared,
+            forwarder,
+        })
+    }
+
+    /// The next event, or `Lagged(n)` when `n` were dropped since the last call.
+    /// `Closed` also ends a `block` connection that fell behind; see `stalled`.
+    pub async fn recv(&mut self) -> Result<Action, RecvError> {
+        match &mut self.0 {
+            Inner::Ring(rx) => rx.recv().await,
+            Inner::Forwarded { rx, shared, .. } => {
+                if shared.stalled.load(Ordering::Relaxed) {
+                    return Err(RecvError::Closed);
+                }
+                let dropped = shared.dropped.swap(0, Ordering::Relaxed);
+                if dropped > 0 {
+                    return Err(RecvError::Lagged(dropped));
+                }
+                rx.recv().await.ok_or(RecvError::Closed)
+            }
+        }
+    }
+
+    /// Events waiting to be sent.
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Inner::Ring(rx) => rx.len(),
+            Inner::Forwarded { rx, .. } => rx.len(),
+        }
+    }
+
+    /// Whether a `block` client was given up on.
+    pub fn stalled(&self) -> bool {
+        match &self.0 {
+            Inner::Ring(_) => false,
+            Inner::Forwarded { shared, .. } => shared.stalled.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for ClientQueue {
+    fn drop(&mut self) {
+        if let Inner::Forwarded { forwarder, .. } = &self.0 {
+            forwarder.abort();
+        }
+    }
+}
+
+async fn forward(
+    mut rx: broadcast::Receiver<Action>,
+    tx: mpsc::Sender<Action>,
+    policy: QueuePolicy,
+    block_timeout: Duration,
+    shared: Arc<Shared>,
+) {
+    loop {
+        let action = match rx.recv().await {
+            Ok(action) => action,
+            Err(RecvError::Lagged(_)) if policy == QueuePolicy::Block => {
+                shared.stalled.store(true, Ordering::Relaxed);
+                return;
+            }
+            Err(RecvError::Lagged(n)) => {
+                shared.dropped.fetch_add(n, Ordering::Relaxed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if policy == QueuePolicy::Block {
+            match tx.send_timeout(action, block_timeout).await {
+                Ok(()) => {}
+                Err(SendTimeoutError::Timeout(_)) => {
+                    shared.stalled.store(true, Ordering::Relaxed);
+                    return;
+                }
+                Err(SendTimeoutError::Closed(_)) => return,
+            }
+        } else {
+            match tx.try_send(action) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Closed(_)) => return,
+            }
+        }
+    }
+}
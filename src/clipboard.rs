@@ -0,0 +1,43 @@
+/*
+ * pet-input-server: src/clipboard.rs
+ *
+ * `--watch-clipboard` (`clipboard` feature): polls the system clipboard and
+ * broadcasts `ClipboardChange` when its text changes. Only a hash of the
+ * text is kept between polls, and the event carries no contents.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+const POLL_EVERY: Duration = Duration::from_millis(500);
+
+fn fingerprint(clipboard: &mut arboard::Clipboard) -> Option<u64> {
+    let text = clipboard.get_text().ok()?;
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+pub fn spawn(broadcast_tx: broadcast::Sender<Action>) -> Result<(), String> {
+    let mut clipboard =
+        arboard::Clipboard::new().map_err(|e| format!("Cannot open the clipboard for --watch-clipboard: {}", e))?;
+    std::thread::spawn(move || {
+        let mut last = fingerprint(&mut clipboard);
+        loop {
+            std::thread::sleep(POLL_EVERY);
+            let current = fingerprint(&mut clipboard);
+            if current != last {
+                last = current;
+                let _ = broadcast_tx.send(Action::new(
+                    ActionKind::ClipboardChange,
+                    ActionValue::Json(serde_json::Value::Null),
+                ));
+            }
+        }
+    });
+    println!("Watching the clipboard for changes.");
+    Ok(())
+}
@@ -0,0 +1,32 @@
+/*
+ * pet-input-server: src/combo.rs
+ *
+ * Key combos written as protocol key names joined with `+`, the key last:
+ * "Control+Alt+Shift+KeyP". Used by the pause hotkey and `--grab`.
+ */
+
+use std::collections::HashSet;
+
+/// A key plus the modifiers that must be held when it is pressed.
+pub struct Combo {
+    modifiers: Vec<String>,
+    key: String,
+}
+
+impl Combo {
+    pub fn parse(text: &str) -> Result<Combo, String> {
+        let mut parts: Vec<String> = text.split('+').map(|part| part.trim().to_string()).collect();
+        let key = parts.pop().unwrap_or_default();
+        for part in parts.iter().chain([&key]) {
+            if crate::key_for_name(part).is_none() {
+                return Err(format!("unknown key '{}' in combo '{}'", part, text));
+            }
+        }
+        Ok(Combo { modifiers: parts, key })
+    }
+
+    /// Whether pressing `key` while `held` is down completes the combo.
+    pub fn matches(&self, key: &str, held: &HashSet<String>) -> bool {
+        self.key == key && self.modifiers.iter().all(|modifier| held.contains(modifier))
+    }
+}
@@ -0,0 +1,446 @@
+/*
+ * pet-input-server: src/config.rs
+ */
+
+use crate::labels::Locales;
+use crate::rename::RenameProfiles;
+use crate::ActionKind;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Config schema version understood by this build. When a key is renamed or removed,
+/// bump this and list the old key in `RENAMED_KEYS` or `REMOVED_KEYS` so existing
+/// files keep loading (or fail with a clear explanation).
+pub const CONFIG_VERSION: u32 = 1;
+
+/// `(old dotted key, new dotted key)` pairs that are moved automatically, with a notice.
+const RENAMED_KEYS: &[(&str, &str)] = &[];
+
+/// `(old dotted key, explanation)` for keys that no longer have an equivalent.
+const REMOVED_KEYS: &[(&str, &str)] = &[];
+
+/// Settings read from the optional `--config` TOML file.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Schema version the file was written for; see `CONFIG_VERSION`.
+    pub version: u32,
+    /// Named screen rectangles that emit RegionEnter/RegionExit.
+    pub regions: BTreeMap<String, RegionRect>,
+    /// How far (in pixels) the cursor must leave a region before it counts as an exit.
+    pub region_hysteresis_px: f64,
+    /// Minimum time between broadcast MouseMove events; `--mouse-throttle-ms` overrides it.
+    pub mouse_throttle_ms: u64,
+    /// Shared secret for the HTTP endpoints; `--token` overrides it.
+    pub token: Option<String>,
+    /// Independent client groups, joined via `ws://host:port/<room>` or the room's token.
+    pub rooms: BTreeMap<String, RoomConfig>,
+    /// Key-to-note mapping for instrument mode; reloaded when the file changes.
+    pub instrument: InstrumentConfig,
+    /// The derived `Intensity` activity value.
+    pub intensity: IntensityConfig,
+    /// The derived `Mood` hint.
+    pub mood: MoodConfig,
+    /// The derived per-key `KeyHeat` map.
+    pub key_heat: KeyHeatConfig,
+    /// Global kind renames, e.g. `KeyboardPress = "keydown"`.
+    pub rename_kinds: BTreeMap<String, String>,
+    /// Named rename overlays that clients pick with `?profile=<name>`.
+    pub rename_profiles: BTreeMap<String, BTreeMap<String, String>>,
+    /// Locale for key labels when a client doesn't pass `?locale=`; unset means no labels.
+    pub locale: Option<String>,
+    /// Extra or overridden key labels per locale, e.g. `[locales.de] MetaLeft = "Win"`.
+    pub locales: BTreeMap<String, BTreeMap<String, String>>,
+    /// Per-kind switch, e.g. `KeyboardRelease = false`; unlisted kinds are enabled.
+    pub events: BTreeMap<String, bool>,
+    /// Combos swallowed in `--grab` mode.
+    pub grab: GrabConfig,
+    /// Animation cues that authenticated clients may broadcast.
+    pub cues: CueConfig,
+    /// Key chatter filter; `--debounce-ms` overrides `ms`.
+    pub debounce: DebounceConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            regions: BTreeMap::new(),
+            region_hysteresis_px: 4.0,
+            mouse_throttle_ms: 16,
+            token: None,
+            rooms: BTreeMap::new(),
+            instrument: InstrumentConfig::default(),
+            intensity: IntensityConfig::default(),
+            mood: MoodConfig::default(),
+            key_heat: KeyHeatConfig::default(),
+            rename_kinds: BTreeMap::new(),
+            rename_profiles: BTreeMap::new(),
+            locale: None,
+            locales: BTreeMap::new(),
+            events: BTreeMap::new(),
+            grab: GrabConfig::default(),
+            cues: CueConfig::default(),
+            debounce: DebounceConfig::default(),
+        }
+    }
+}
+
+/// Which coordinate space a region rectangle is written in.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionUnits {
+    /// Fractions of the display size (0.0..=1.0).
+    #[default]
+    Normalized,
+    /// Screen pixels, as reported by the input listener.
+    Pixels,
+}
+
+/// A rectangle from the `[regions]` table, e.g. `panic = {x=0.95,y=0.0,w=0.05,h=0.05}`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RegionRect {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+    #[serde(default)]
+    pub units: RegionUnits,
+}
+
+/// One entry of the `[rooms]` table, e.g. `work = {token="abc"}`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomConfig {
+    /// Required to join the room; also selects the room when connecting to `/`.
+    pub token: Option<String>,
+    /// Whether live input events are delivered to this room.
+    pub input: bool,
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        RoomConfig {
+            token: None,
+            input: true,
+        }
+    }
+}
+
+/// The `[grab]` section, used with `--grab`:
+///
+/// ```toml
+/// [grab]
+/// consume = ["F13", "Control+KeyQ"]
+/// escape = "Control+Alt+Escape"
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrabConfig {
+    /// Combos kept from the OS: protocol key names joined with `+`, the key last.
+    pub consume: Vec<String>,
+    /// Never swallowed; pressing it turns swallowing off until restart.
+    pub escape: String,
+}
+
+impl Default for GrabConfig {
+    fn default() -> Self {
+        GrabConfig {
+            consume: Vec::new(),
+            escape: "Control+Alt+Escape".to_string(),
+        }
+    }
+}
+
+/// The `[cues]` section. Clients that connected with the server token may send
+/// `{"cmd":"cue","name":"confetti","data":{...}}`, rebroadcast to everyone as `Cue`:
+///
+/// ```toml
+/// [cues]
+/// allow = ["confetti", "wave"]
+/// max_data_bytes = 1024
+/// per_second = 1.0
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CueConfig {
+    /// Cue names that are accepted; empty turns cues off.
+    pub allow: Vec<String>,
+    /// Size cap on the serialized `data` object.
+    pub max_data_bytes: usize,
+    /// Cues per second one connection may send (one second of burst).
+    pub per_second: f64,
+}
+
+impl Default for CueConfig {
+    fn default() -> Self {
+        CueConfig {
+            allow: Vec::new(),
+            max_data_bytes: 1024,
+            per_second: 1.0,
+        }
+    }
+}
+
+/// The `[instrument]` section:
+///
+/// ```toml
+/// [instrument]
+/// octave_shift = {Shift = 1, Control = -1}
+/// [instrument.notes]
+/// KeyA = "C4"
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstrumentConfig {
+    /// Protocol key name to note identifier, e.g. `KeyA = "C4"`.
+    pub notes: HashMap<String, String>,
+    /// Keys that shift every note by this many octaves while held.
+    pub octave_shift: HashMap<String, i32>,
+    pub velocity_min: u8,
+    pub velocity_max: u8,
+    /// Presses per second at which velocity reaches `velocity_max`.
+    pub velocity_full_rate: f64,
+}
+
+impl Default for InstrumentConfig {
+    fn default() -> Self {
+        InstrumentConfig {
+            notes: HashMap::new(),
+            octave_shift: HashMap::new(),
+            velocity_min: 48,
+            velocity_max: 127,
+            velocity_full_rate: 10.0,
+        }
+    }
+}
+
+/// The `[intensity]` section. Off unless `enabled = true`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntensityConfig {
+    pub enabled: bool,
+    /// How often the value is checked and (if it changed) broadcast.
+    pub rate_hz: f64,
+    /// Fraction of the remaining headroom one weight-1.0 event adds.
+    pub attack: f64,
+    /// Exponential decay time constant, in seconds.
+    pub decay_secs: f64,
+    /// Smallest change worth broadcasting.
+    pub min_change: f64,
+    pub weights: IntensityWeights,
+}
+
+impl Default for IntensityConfig {
+    fn default() -> Self {
+        IntensityConfig {
+            enabled: false,
+            rate_hz: 5.0,
+            attack: 0.15,
+            decay_secs: 1.5,
+            min_change: 0.02,
+            weights: IntensityWeights::default(),
+        }
+    }
+}
+
+/// The `[mood]` section. Off unless `enabled = true`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MoodConfig {
+    pub enabled: bool,
+    /// How often the mood is re-evaluated.
+    pub rate_hz: f64,
+    /// Seconds without input before the mood turns "sleepy".
+    pub sleepy_secs: f64,
+    /// Seconds without input before the mood turns "away".
+    pub away_secs: f64,
+    /// Typing speed, averaged over `typing_window_secs`, that counts as "focused".
+    pub focused_keys_per_sec: f64,
+    pub typing_window_secs: f64,
+    /// Direction changes within `shake_window_ms` that make a shake.
+    pub shake_reversals: usize,
+    pub shake_window_ms: u64,
+    /// Shortest horizontal stroke, in pixels, that counts towards a shake.
+    pub shake_min_px: f64,
+    /// How long "playful" lasts after a shake.
+    pub playful_secs: f64,
+}
+
+impl Default for MoodConfig {
+    fn default() -> Self {
+        MoodConfig {
+            enabled: false,
+            rate_hz: 4.0,
+            sleepy_secs: 60.0,
+            away_secs: 600.0,
+            focused_keys_per_sec: 4.0,
+            typing_window_secs: 5.0,
+            shake_reversals: 4,
+            shake_window_ms: 800,
+            shake_min_px: 40.0,
+            playful_secs: 5.0,
+        }
+    }
+}
+
+/// The `[key_heat]` section. Off unless `enabled = true`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyHeatConfig {
+    pub enabled: bool,
+    /// How often the warm keys are broadcast.
+    pub rate_hz: f64,
+    /// Heat one press adds; a key's heat never exceeds 1.0.
+    pub increment: f64,
+    /// Exponential decay time constant, in seconds.
+    pub decay_secs: f64,
+    /// Keys cooler than this are left out (and forgotten).
+    pub threshold: f64,
+}
+
+impl Default for KeyHeatConfig {
+    fn default() -> Self {
+        KeyHeatConfig {
+            enabled: false,
+            rate_hz: 10.0,
+            increment: 0.35,
+            decay_secs: 1.0,
+            threshold: 0.05,
+        }
+    }
+}
+
+/// The `[debounce]` section. Off while every window is 0.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebounceConfig {
+    /// A press this soon after the same key's release is chatter.
+    pub ms: u64,
+    /// Per-key windows by protocol key name, e.g. `KeyW = 0` for a key double-tapped on purpose.
+    pub keys: BTreeMap<String, u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntensityWeights {
+    pub key: f64,
+    pub click: f64,
+    pub scroll: f64,
+}
+
+impl Default for IntensityWeights {
+    fn default() -> Self {
+        IntensityWeights {
+            key: 1.0,
+            click: 1.5,
+            scroll: 0.5,
+        }
+    }
+}
+
+impl Config {
+    /// Reads and parses a config file, returning a printable error on failure.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        let mut table: toml::Table = toml::from_str(&text)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        migrate(&mut table).map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        for (name, rect) in &config.regions {
+            if rect.w <= 0.0 || rect.h <= 0.0 {
+                return Err(format!("Region '{}' must have a positive width and height", name));
+            }
+        }
+        for name in config.rooms.keys() {
+            let valid = !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                return Err(format!("Room name '{}' may only use letters, digits, '-' and '_'", name));
+            }
+        }
+        if let Some(unknown) = config.events.keys().find(|kind| kind.parse::<ActionKind>().is_err()) {
+            return Err(format!("Unknown kind '{}' in [events] (known: {})", unknown, ActionKind::names().join(", ")));
+        }
+        if let Some(locale) = &config.locale {
+            let locales = Locales::build(&config.locales);
+            if locales.get(locale).is_none() {
+                return Err(format!("Unknown locale '{}' (known: {})", locale, locales.names().join(", ")));
+            }
+        }
+        if config.cues.per_second <= 0.0 {
+            return Err("[cues] per_second must be positive".to_string());
+        }
+        RenameProfiles::build(&config.rename_kinds, &config.rename_profiles)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+}
+
+/// Brings an older config up to `CONFIG_VERSION`: renamed keys are moved (with a logged
+/// notice), removed keys are hard errors, and files from a newer build are rejected.
+/// Files without a `version` key are treated as the current version.
+fn migrate(table: &mut toml::Table) -> Result<(), String> {
+    let version = match table.get("version") {
+        None => CONFIG_VERSION as i64,
+        Some(toml::Value::Integer(v)) => *v,
+        Some(_) => return Err("'version' must be an integer".to_string()),
+    };
+    if version > CONFIG_VERSION as i64 {
+        return Err(format!(
+            "config version {} is newer than this server supports ({}); please upgrade",
+            version, CONFIG_VERSION
+        ));
+    }
+
+    for (old, reason) in REMOVED_KEYS {
+        if take_key(table, old).is_some() {
+            return Err(format!("'{}' is no longer supported: {}", old, reason));
+        }
+    }
+    for (old, new) in RENAMED_KEYS {
+        if let Some(value) = take_key(table, old) {
+            println!("Config notice: '{}' has been renamed to '{}'; please update your file.", old, new);
+            put_key(table, new, value)?;
+        }
+    }
+    table.insert("version".to_string(), toml::Value::Integer(CONFIG_VERSION as i64));
+    Ok(())
+}
+
+/// Removes a dotted key such as `intensity.rate_hz`, returning its value.
+fn take_key(table: &mut toml::Table, dotted: &str) -> Option<toml::Value> {
+    match dotted.split_once('.') {
+        None => table.remove(dotted),
+        Some((head, rest)) => match table.get_mut(head) {
+            Some(toml::Value::Table(inner)) => take_key(inner, rest),
+            _ => None,
+        },
+    }
+}
+
+fn put_key(table: &mut toml::Table, dotted: &str, value: toml::Value) -> Result<(), String> {
+    match dotted.split_once('.') {
+        None => {
+            if table.contains_key(dotted) {
+                return Err(format!("both the old and new name of '{}' are set", dotted));
+            }
+            table.insert(dotted.to_string(), value);
+            Ok(())
+        }
+        Some((head, rest)) => {
+            let inner = table
+                .entry(head.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            match inner {
+                toml::Value::Table(inner) => put_key(inner, rest, value),
+                _ => Err(format!("'{}' must be a table", head)),
+            }
+        }
+    }
+}
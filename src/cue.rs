@@ -0,0 +1,31 @@
+/*
+ * pet-input-server: src/cue.rs
+ *
+ * Animation cues sent by a client (a stream deck, a chat bot) through the
+ * socket the pet already listens on:
+ *
+ *   client → {"cmd":"cue","name":"confetti","data":{...}}
+ *   all    ← Cue {"name":"confetti","data":{...}}
+ *
+ * Only connections that presented the server token may send them, the name
+ * must be in `[cues] allow`, and `data` is capped in size. `Cue` is its own
+ * kind, so a frontend never mistakes one for real input.
+ */
+
+use crate::config::CueConfig;
+use crate::{Action, ActionKind, ActionValue};
+use serde_json::json;
+
+/// Checks a cue request against the config and builds the `Cue` to rebroadcast.
+pub fn build(config: &CueConfig, name: Option<&str>, data: Option<&serde_json::Value>) -> Result<Action, String> {
+    let name = name.ok_or("missing name")?;
+    if !config.allow.iter().any(|allowed| allowed == name) {
+        return Err(format!("'{}' is not in [cues] allow", name));
+    }
+    let data = data.cloned().unwrap_or(serde_json::Value::Null);
+    let bytes = serde_json::to_string(&data).map_or(0, |text| text.len());
+    if bytes > config.max_data_bytes {
+        return Err(format!("data is {} bytes, over the {}-byte limit", bytes, config.max_data_bytes));
+    }
+    Ok(Action::new(ActionKind::Cue, ActionValue::Json(json!({ "name": name, "data": data }))))
+}
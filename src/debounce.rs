@@ -0,0 +1,170 @@
+/*
+ * pet-input-server: src/debounce.rs
+ *
+ * Key chatter filter for worn keyboards (`[debounce]`, `--debounce-ms`). A
+ * switch that chatters turns one press into press, release, press, release
+ * within a few milliseconds, and the pet double-taps. A KeyboardPress that
+ * comes within the window after the same key's release is dropped, and so is
+ * the release that ends it.
+ *
+ * Only a press after a release is checked: auto-repeat presses (no release in
+ * between) always pass, and if one arrives while a dropped press is still
+ * down, the key really is held and it goes through. Fast legitimate double
+ * taps (gaming keys) can be exempted per key:
+ *
+ * ```toml
+ * [debounce]
+ * ms = 40
+ * keys = { KeyW = 0, Space = 60 }
+ * ```
+ */
+
+use crate::config::DebounceConfig;
+use once_cell::sync::{Lazy, OnceCell};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct KeyState {
+    down: bool,
+    last_release: Option<Instant>,
+    /// The press that made the key `down` was dropped.
+    dropped: bool,
+}
+
+static CONFIG: OnceCell<DebounceConfig> = OnceCell::new();
+static KEYS: Lazy<Mutex<HashMap<String, KeyState>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Turns the filter on if any window (default or per key) is non-zero.
+pub fn configure(config: &DebounceConfig) {
+    if config.ms == 0 && config.keys.values().all(|ms| *ms == 0) {
+        return;
+    }
+    let _ = CONFIG.set(config.clone());
+    println!(
+        "Debouncing key presses within {}ms of a release ({} per-key overrides).",
+        config.ms,
+        config.keys.len()
+    );
+}
+
+pub fn enabled() -> bool {
+    CONFIG.get().is_some()
+}
+
+fn window(config: &DebounceConfig, key: &str) -> Duration {
+    Duration::from_millis(config.keys.get(key).copied().unwrap_or(config.ms))
+}
+
+/// Called for every key press and release of `key` at `at`; `true` means drop it.
+pub fn drops(key: &str, pressed: bool, at: Instant) -> bool {
+    let Some(config) = CONFIG.get() else { return false };
+    let dropped = decide(&mut KEYS.lock().unwrap(), config, key, pressed, at);
+    if dropped && pressed {
+        crate::metrics::debounced();
+    }
+    dropped
+}
+
+fn decide(
+    keys: &mut HashMap<String, KeyState>,
+    config: &DebounceConfig,
+    key: &str,
+    pressed: bool,
+    at: Instant,
+) -> bool {
+    let state = keys.entry(key.to_string()).or_default();
+    if pressed {
+        if state.down {
+            // Auto-repeat: the key really is held.
+            state.dropped = false;
+            return false;
+        }
+        state.down = true;
+        let window = window(config, key);
+        let chatter = state
+            .last_release
+            .is_some_and(|released| !window.is_zero() && at.saturating_duration_since(released) < window);
+        state.dropped = chatter;
+        chatter
+    } else {
+        state.down = false;
+        state.last_release = Some(at);
+        std::mem::take(&mut state.dropped)
+    }
+}
+
+/// Settings for `/status`; `null` when off.
+pub fn to_json() -> serde_json::Value {
+    match CONFIG.get() {
+        None => serde_json::Value::Null,
+        Some(config) => json!({
+            "ms": config.ms,
+            "keys": config.keys,
+            "dropped": crate::metrics::debounced_total(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `(key, pressed, ms)` events and returns which were dropped.
+    fn run(config: &DebounceConfig, events: &[(&str, bool, u64)]) -> Vec<bool> {
+        let start = Instant::now();
+        let mut keys = HashMap::new();
+        events
+            .iter()
+            .map(|(key, pressed, ms)| decide(&mut keys, config, key, *pressed, start + Duration::from_millis(*ms)))
+            .collect()
+    }
+
+    fn config(ms: u64, keys: &[(&str, u64)]) -> DebounceConfig {
+        DebounceConfig {
+            ms,
+            keys: keys.iter().map(|(key, ms)| (key.to_string(), *ms)).collect(),
+        }
+    }
+
+    #[test]
+    fn chatter_and_the_release_that_ends_it_are_dropped() {
+        let events = [("KeyA", true, 0), ("KeyA", false, 50), ("KeyA", true, 55), ("KeyA", false, 60)];
+        assert_eq!(run(&config(40, &[]), &events), [false, false, true, true]);
+        // A second press after the window is a real one.
+        let events = [("KeyA", true, 0), ("KeyA", false, 50), ("KeyA", true, 90), ("KeyA", false, 150)];
+        assert_eq!(run(&config(40, &[]), &events), [false; 4]);
+    }
+
+    #[test]
+    fn auto_repeat_passes_and_revives_a_dropped_press() {
+        let events = [
+            ("KeyA", true, 0),
+            ("KeyA", false, 50),
+            ("KeyA", true, 55),
+            // Still down well after the bounce: it is held, so the repeat and its release go out.
+            ("KeyA", true, 500),
+            ("KeyA", false, 700),
+        ];
+        assert_eq!(run(&config(40, &[]), &events), [false, false, true, false, false]);
+    }
+
+    #[test]
+    fn per_key_windows_override_the_default() {
+        let config = config(40, &[("KeyW", 0), ("Space", 100)]);
+        let events = [
+            ("KeyW", true, 0),
+            ("KeyW", false, 10),
+            ("KeyW", true, 15),
+            ("Space", true, 0),
+            ("Space", false, 10),
+            ("Space", true, 90),
+            // Keys are tracked separately.
+            ("KeyA", false, 85),
+            ("KeyA", true, 100),
+        ];
+        assert_eq!(run(&config, &events), [false, false, false, false, false, true, false, true]);
+    }
+}
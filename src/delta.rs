@@ -0,0 +1,87 @@
+/*
+ * pet-input-server: src/delta.rs
+ *
+ * Delta-encoded mouse path for high-frequency capture. After an absolute
+ * MouseMove "keyframe", moves are sent as MouseDelta {dx, dy} in whole pixels
+ * until the next keyframe. Clients still track absolute position: summing the
+ * deltas onto the last keyframe gives the rounded cursor position exactly.
+ */
+
+use crate::{Action, ActionKind, ActionValue, Coords, DeltaCoords};
+
+/// Deltas larger than this force a keyframe so every delta fits in an `i8`.
+const MAX_DELTA: i64 = 127;
+
+/// Per-connection encoder state.
+pub struct MouseDeltaEncoder {
+    keyframe_every: u32,
+    /// Rounded position the client currently believes the cursor is at.
+    last: Option<(i64, i64)>,
+    since_keyframe: u32,
+}
+
+impl MouseDeltaEncoder {
+    pub fn new(keyframe_every: u32) -> Self {
+        MouseDeltaEncoder {
+            keyframe_every: keyframe_every.max(1),
+            last: None,
+            since_keyframe: 0,
+        }
+    }
+
+    /// Rewrites MouseMove actions into keyframes or deltas; other actions pass through.
+    pub fn encode(&mut self, action: Action) -> Option<Action> {
+        let ActionValue::Coords(Coords { x, y }) = &action.value else {
+            return Some(action);
+        };
+        if action.kind != ActionKind::MouseMove {
+            return Some(action);
+        }
+        let (x, y) = (x.round() as i64, y.round() as i64);
+
+        let delta = self.last.map(|(lx, ly)| (x - lx, y - ly));
+        match delta {
+            Some((0, 0)) => None,
+            Some((dx, dy))
+                if self.since_keyframe < self.keyframe_every
+                    && dx.abs() <= MAX_DELTA
+                    && dy.abs() <= MAX_DELTA =>
+            {
+                self.last = Some((x, y));
+                self.since_keyframe += 1;
+                Some(Action {
+                    source: action.source,
+                    timestamp_ms: action.timestamp_ms,
+                    mono_ns: action.mono_ns,
+                    os_timestamp_ms: action.os_timestamp_ms,
+                    hook_timestamp_ms: action.hook_timestamp_ms,
+                    ..Action::new(
+                        ActionKind::MouseDelta,
+                        ActionValue::Delta(DeltaCoords {
+                            dx: dx as i32,
+                            dy: dy as i32,
+                        }),
+                    )
+                })
+            }
+            _ => {
+                self.last = Some((x, y));
+                self.since_keyframe = 0;
+                Some(Action {
+                    source: action.source,
+                    timestamp_ms: action.timestamp_ms,
+                    mono_ns: action.mono_ns,
+                    os_timestamp_ms: action.os_timestamp_ms,
+                    hook_timestamp_ms: action.hook_timestamp_ms,
+                    ..Action::new(
+                        ActionKind::MouseMove,
+                        ActionValue::Coords(Coords {
+                            x: x as f64,
+                            y: y as f64,
+                        }),
+                    )
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+/*
+ * pet-input-server: src/discovery.rs
+ *
+ * Discovery file for local clients: `{"port","pid","started_ms","url"}`
+ * written at startup to a per-user path ($XDG_RUNTIME_DIR on Linux,
+ * %LOCALAPPDATA% on Windows, the temp dir otherwise), so they can find a
+ * server started with `--port 0`. Any existing file is assumed stale and
+ * replaced; on graceful shutdown it is removed again, but only if it still
+ * describes this process.
+ */
+
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FILE_NAME: &str = "bongocat-server.json";
+
+pub fn default_path() -> PathBuf {
+    let dir = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA")
+    } else {
+        std::env::var_os("XDG_RUNTIME_DIR")
+    };
+    dir.map(PathBuf::from).unwrap_or_else(std::env::temp_dir).join(FILE_NAME)
+}
+
+/// Removes the file when dropped.
+pub struct DiscoveryFile {
+    path: PathBuf,
+}
+
+pub fn write(path: PathBuf, port: u16) -> Result<DiscoveryFile, String> {
+    if path.exists() {
+        println!("Replacing stale discovery file {}.", path.display());
+    }
+    let info = json!({
+        "port": port,
+        "pid": std::process::id(),
+        "started_ms": SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+        "url": format!("ws://127.0.0.1:{}", port),
+    });
+    std::fs::write(&path, info.to_string())
+        .map_err(|e| format!("Failed to write discovery file {}: {}", path.display(), e))?;
+    println!("Wrote discovery file {}.", path.display());
+    Ok(DiscoveryFile { path })
+}
+
+impl Drop for DiscoveryFile {
+    fn drop(&mut self) {
+        // Another server may have taken the file over since we started.
+        let ours = std::fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+            .is_some_and(|info| info["pid"] == std::process::id());
+        if ours {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
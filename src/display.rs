@@ -0,0 +1,59 @@
+/*
+ * pet-input-server: src/display.rs
+ *
+ * The one place that asks for the display size. `rdev::display_size()` fails
+ * on Wayland and headless machines, so callers get an explicit `Option` and
+ * `--screen-size WxH` can stand in when detection doesn't work.
+ */
+
+use once_cell::sync::{Lazy, OnceCell};
+use std::sync::Mutex;
+
+/// Set by `--screen-size`; always wins over detection.
+static OVERRIDE: OnceCell<(u64, u64)> = OnceCell::new();
+/// The size seen by the last `refresh()`, for callers on the event path.
+static CACHED: Lazy<Mutex<Option<(u64, u64)>>> = Lazy::new(|| Mutex::new(None));
+
+/// Parses `--screen-size` values such as `2560x1440`.
+pub fn parse_size(value: &str) -> Result<(u64, u64), String> {
+    let parsed = value
+        .split_once(['x', 'X'])
+        .and_then(|(w, h)| Some((w.trim().parse::<u64>().ok()?, h.trim().parse::<u64>().ok()?)));
+    match parsed {
+        Some((w, h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(format!("expected WIDTHxHEIGHT (e.g. 2560x1440), got '{}'", value)),
+    }
+}
+
+pub fn set_override(size: (u64, u64)) {
+    let _ = OVERRIDE.set(size);
+}
+
+/// The current display size in pixels, or `None` if it is unknown.
+pub fn size() -> Option<(u64, u64)> {
+    OVERRIDE.get().copied().or_else(|| rdev::display_size().ok())
+}
+
+/// Queries the display size and remembers it for `cached()`.
+pub fn refresh() -> Option<(u64, u64)> {
+    let current = size();
+    *CACHED.lock().unwrap() = current;
+    current
+}
+
+/// The last size seen by `refresh()`; cheap enough to call per event.
+pub fn cached() -> Option<(u64, u64)> {
+    *CACHED.lock().unwrap()
+}
+
+/// Reports the display size at startup and warns when it can't be determined.
+pub fn probe() {
+    match (refresh(), OVERRIDE.get()) {
+        (Some((w, h)), Some(_)) => println!("Display size: {}x{} (from --screen-size)", w, h),
+        (Some((w, h)), None) => println!("Display size: {}x{}", w, h),
+        (None, _) => eprintln!(
+            "Could not detect the display size (Wayland or headless?); features that need it \
+             stay disabled. Pass --screen-size WIDTHxHEIGHT to set it."
+        ),
+    }
+}
@@ -0,0 +1,180 @@
+/*
+ * pet-input-server: src/fixtures.rs
+ *
+ * The `fixtures` subcommand: writes one sample payload per action kind,
+ * protocol version and wire format, encoded by the same `Serializer`s the
+ * live sinks use, plus a `manifest.json` listing them. Meant for frontend
+ * work without a running server; the serializer tests round-trip the same
+ * samples:
+ *
+ *   <out>/v1/json/KeyboardPress.json
+ *   <out>/v1/msgpack/KeyboardPress.msgpack
+ *   <out>/v1/plaintext/KeyboardPress.txt
+ */
+
+use crate::serializer::{self, EncodedFrame, FORMATS};
+use crate::{Action, ActionKind, ActionValue, Coords, DeltaCoords, NoteValue, SUPPORTED_PROTOCOL_VERSIONS};
+use serde_json::json;
+use std::path::Path;
+
+/// A typical value for `kind`, shaped like what the server really sends.
+/// No catch-all arm, so a new kind doesn't build until it has a sample here.
+fn sample_value(kind: ActionKind) -> ActionValue {
+    match kind {
+        ActionKind::MouseMove => ActionValue::Coords(Coords { x: 640.0, y: 360.0 }),
+        ActionKind::MouseDelta => ActionValue::Delta(DeltaCoords { dx: 12, dy: -3 }),
+        ActionKind::MousePress | ActionKind::MouseRelease => ActionValue::String("Mouse1".to_string()),
+        ActionKind::KeyboardPress | ActionKind::KeyboardRelease | ActionKind::KeyLongPress => {
+            ActionValue::String("KeyA".to_string())
+        }
+        ActionKind::RegionEnter | ActionKind::RegionExit => ActionValue::String("taskbar".to_string()),
+        ActionKind::Note => ActionValue::Note(NoteValue {
+            note: "C4".to_string(),
+            velocity: 100,
+        }),
+        ActionKind::Intensity => ActionValue::Number(0.42),
+        ActionKind::Backpressure => ActionValue::String("high".to_string()),
+        ActionKind::ProtocolError => ActionValue::Json(json!({
+            "code": "unsupported_protocol",
+            "requested": "9",
+            "supported": SUPPORTED_PROTOCOL_VERSIONS,
+            "message": "protocol version '9' is not supported",
+        })),
+        ActionKind::InputFailure => ActionValue::Json(json!({ "error": "input listener stopped" })),
+        ActionKind::Rates => ActionValue::Json(json!({ "MouseMove": 58, "KeyboardPress": 4, "total": 62 })),
+        ActionKind::Oversized => {
+            ActionValue::Json(json!({ "kind": "ClipboardChange", "bytes": 70000, "limit": 65536 }))
+        }
+        ActionKind::InputFlood => ActionValue::Json(json!({ "rate": 2400 })),
+        ActionKind::Prefs => ActionValue::Json(json!({ "format": "json", "kinds": "KeyboardPress,MousePress" })),
+        ActionKind::ShoutingDetected => ActionValue::Json(json!(true)),
+        ActionKind::TimeSyncProbe => ActionValue::Json(json!({ "probe": 0, "server_ms": 1700000000000.0 })),
+        ActionKind::TimeSync => ActionValue::Json(json!({ "offset_ms": -3.5, "rtt_ms": 1.2 })),
+        ActionKind::MediaKey => ActionValue::String("PlayPause".to_string()),
+        ActionKind::ModifierTap => ActionValue::String("Shift".to_string()),
+        ActionKind::SystemSuspend => ActionValue::Json(json!({ "at_ms": 1700000000000u64 })),
+        ActionKind::SystemResume => ActionValue::Json(json!({ "at_ms": 1700000600000u64, "suspended_s": 600 })),
+        ActionKind::ButtonChord => ActionValue::Json(json!(["Mouse1", "Mouse2"])),
+        ActionKind::MousePan | ActionKind::MousePanEnd => {
+            ActionValue::Json(json!({ "dx": -120.0, "dy": 45.0, "distance": 131.0 }))
+        }
+        ActionKind::Status => ActionValue::Json(json!({ "uptime_s": 3600, "time_ms": 1700000000000u64 })),
+        ActionKind::WindowBounds => ActionValue::Json(json!({ "x": 100, "y": 80, "w": 1280, "h": 720 })),
+        ActionKind::Chord => ActionValue::Json(json!(["KeyS", "KeyT", "KeyA"])),
+        ActionKind::CapturePaused => ActionValue::Json(json!(true)),
+        ActionKind::CoordPrivacy => ActionValue::Json(json!({ "grid_px": 64.0 })),
+        ActionKind::Mood => ActionValue::String("focused".to_string()),
+        ActionKind::RawEvent => ActionValue::Json(json!({
+            "event_type": "KeyPress(Unknown(255))",
+            "name": null,
+            "time": 1700000000000.0,
+        })),
+        ActionKind::Cue => ActionValue::Json(json!({ "name": "confetti", "data": { "colors": ["pink", "gold"] } })),
+        ActionKind::ServerNotice => ActionValue::Json(json!({
+            "code": "input_flood",
+            "severity": "warn",
+            "message": "Input flood: 2400 events/s (ceiling 2000/s)",
+            "active": true,
+        })),
+        ActionKind::Snapshot => ActionValue::Json(json!({
+            "cursor": { "x": 640.0, "y": 360.0 },
+            "keys": ["KeyA", "Shift"],
+            "buttons": [],
+            "modifiers": { "shift": true, "control": false, "alt": false, "meta": false },
+            "intensity": 0.42,
+            "mood": "focused",
+            "events": 7,
+        })),
+        ActionKind::KeyHeat => ActionValue::Json(json!({ "KeyA": 0.8, "Space": 0.2 })),
+        ActionKind::Disconnect => ActionValue::Json(json!({
+            "reason": "shutdown",
+            "code": 4000,
+            "message": "server shutting down",
+            "retry_after_ms": 7300,
+        })),
+        // No payload.
+        ActionKind::Inactive | ActionKind::ClipboardChange => ActionValue::Json(serde_json::Value::Null),
+    }
+}
+
+pub fn sample(kind: ActionKind) -> Action {
+    Action::new(kind, sample_value(kind))
+}
+
+/// Writes every fixture under `out` and returns how many files were written.
+pub fn write_all(out: &Path) -> Result<usize, String> {
+    let mut entries = Vec::new();
+    for version in SUPPORTED_PROTOCOL_VERSIONS {
+        for format in FORMATS {
+            let serializer = serializer::by_name(format).expect("FORMATS entries have serializers");
+            let dir = out.join(format!("v{}", version)).join(format);
+            std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+            for kind in ActionKind::ALL {
+                let bytes = match serializer.encode(&sample(kind)) {
+                    EncodedFrame::Text(text) => text.into_bytes(),
+                    EncodedFrame::Binary(bytes) => bytes,
+                };
+                let extension = match *format {
+                    "plaintext" => "txt",
+                    other => other,
+                };
+                let relative = format!("v{}/{}/{}.{}", version, format, kind, extension);
+                let path = out.join(&relative);
+                std::fs::write(&path, &bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+                entries.push(json!({
+                    "kind": kind,
+                    "protocol": version,
+                    "format": format,
+                    "path": relative,
+                    "bytes": bytes.len(),
+                }));
+            }
+        }
+    }
+
+    let manifest = json!({
+        "server_version": env!("CARGO_PKG_VERSION"),
+        "protocol_versions": SUPPORTED_PROTOCOL_VERSIONS,
+        "formats": FORMATS,
+        "kinds": ActionKind::names(),
+        "files": entries,
+    });
+    let path = out.join("manifest.json");
+    let text = serde_json::to_string_pretty(&manifest).expect("manifest serializes");
+    std::fs::write(&path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_one_file_per_kind_version_and_format_plus_a_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let written = write_all(dir.path()).unwrap();
+        assert_eq!(written, SUPPORTED_PROTOCOL_VERSIONS.len() * FORMATS.len() * ActionKind::ALL.len());
+
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("manifest.json")).unwrap()).unwrap();
+        let files = manifest["files"].as_array().unwrap();
+        assert_eq!(files.len(), written);
+        for entry in files {
+            let bytes = std::fs::read(dir.path().join(entry["path"].as_str().unwrap())).unwrap();
+            assert_eq!(bytes.len() as u64, entry["bytes"].as_u64().unwrap(), "{}", entry);
+        }
+        assert_eq!(manifest["kinds"].as_array().unwrap().len(), ActionKind::ALL.len());
+    }
+
+    #[test]
+    fn json_fixtures_name_their_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        write_all(dir.path()).unwrap();
+        for kind in ActionKind::ALL {
+            let path = dir.path().join(format!("v1/json/{}.json", kind));
+            let value: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+            assert_eq!(value["kind"], kind.as_str());
+            assert!(value.get("value").is_some(), "{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,108 @@
+/*
+ * pet-input-server: src/flood.rs
+ *
+ * Input flood alarm (`--flood-ceiling`). Counts raw input callbacks per
+ * second; when the rate passes the ceiling (a stuck key, a faulty device),
+ * broadcasts one `InputFlood` and logs it. It re-arms only after the rate
+ * falls below `--flood-rearm-rate`. While flooding, mouse moves can be
+ * throttled harder with `--flood-throttle-multiplier`.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static EVENTS: AtomicU64 = AtomicU64::new(0);
+static FLOODING: AtomicBool = AtomicBool::new(false);
+static MULTIPLIER: AtomicU32 = AtomicU32::new(1);
+
+/// Called from the hot path for every raw input event.
+pub fn count() {
+    if ENABLED.load(Ordering::Relaxed) {
+        EVENTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The mouse-move throttle to use right now.
+pub fn throttle(base: Duration) -> Duration {
+    if FLOODING.load(Ordering::Relaxed) {
+        base * MULTIPLIER.load(Ordering::Relaxed)
+    } else {
+        base
+    }
+}
+
+/// The flooding state after a second with `rate` events, if it changes: on above
+/// `ceiling`, off again only below `rearm`.
+fn transition(flooding: bool, rate: u64, ceiling: u64, rearm: u64) -> Option<bool> {
+    if !flooding && rate > ceiling {
+        Some(true)
+    } else if flooding && rate < rearm {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Starts the once-a-second check; rates are events per second.
+pub fn spawn(ceiling: u64, rearm: u64, multiplier: u32, broadcast_tx: broadcast::Sender<Action>) {
+    MULTIPLIER.store(multiplier.max(1), Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let rate = EVENTS.swap(0, Ordering::Relaxed);
+            match transition(FLOODING.load(Ordering::Relaxed), rate, ceiling, rearm) {
+                Some(true) => {
+                    FLOODING.store(true, Ordering::Relaxed);
+                    eprintln!("Input flood: {} events/s (ceiling {}/s).", rate, ceiling);
+                    let message = format!("Input flood: {} events/s (ceiling {}/s)", rate, ceiling);
+                    crate::notice::raise(crate::notice::Code::InputFlood, &message);
+                    let _ = broadcast_tx.send(Action::new(
+                        ActionKind::InputFlood,
+                        ActionValue::Json(serde_json::json!({ "rate": rate })),
+                    ));
+                }
+                Some(false) => {
+                    FLOODING.store(false, Ordering::Relaxed);
+                    println!("Input flood over ({} events/s).", rate);
+                    let message = format!("Input flood over ({} events/s)", rate);
+                    crate::notice::clear(crate::notice::Code::InputFlood, &message);
+                }
+                None => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alarm_fires_once_and_rearms_below_the_rearm_rate() {
+        let (ceiling, rearm) = (2000, 500);
+        let mut flooding = false;
+        let mut changes = Vec::new();
+        for rate in [100, 2000, 2001, 5000, 1999, 500, 499, 3000] {
+            if let Some(now) = transition(flooding, rate, ceiling, rearm) {
+                flooding = now;
+                changes.push((rate, now));
+            }
+        }
+        assert_eq!(changes, [(2001, true), (499, false), (3000, true)]);
+    }
+
+    #[test]
+    fn flooding_multiplies_the_throttle() {
+        let base = Duration::from_millis(16);
+        MULTIPLIER.store(4, Ordering::Relaxed);
+        assert_eq!(throttle(base), base);
+        FLOODING.store(true, Ordering::Relaxed);
+        assert_eq!(throttle(base), base * 4);
+        FLOODING.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,104 @@
+/*
+ * pet-input-server: src/grab.rs
+ *
+ * `--grab` (`grab` feature): takes input through rdev's grab API instead of
+ * `listen`, so key combos listed in `[grab] consume` are swallowed before
+ * the OS sees them. Every event is still broadcast; swallowed ones carry
+ * `"consumed":true`. The release of a swallowed press is swallowed too.
+ *
+ * The `[grab] escape` combo is never swallowed, and pressing it stops all
+ * swallowing until the server restarts, so a bad list can't lock you out.
+ */
+
+use crate::combo::Combo;
+use crate::config::GrabConfig;
+use crate::source::{EventSource, RawInputEvent, CONSUMING};
+use once_cell::sync::OnceCell;
+use rdev::EventType;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+struct Rules {
+    consume: Vec<Combo>,
+    escape: Combo,
+}
+
+static RULES: OnceCell<Rules> = OnceCell::new();
+/// Cleared for good by the escape combo.
+static ACTIVE: AtomicBool = AtomicBool::new(true);
+
+#[derive(Default)]
+struct Keys {
+    held: HashSet<String>,
+    /// Keys whose press was swallowed, so their release is swallowed as well.
+    swallowed: HashSet<String>,
+}
+
+static KEYS: Mutex<Option<Keys>> = Mutex::new(None);
+
+/// Checks the `[grab]` section; must be called before the source runs.
+pub fn configure(config: &GrabConfig) -> Result<(), String> {
+    let consume = config.consume.iter().map(|combo| Combo::parse(combo)).collect::<Result<Vec<_>, _>>()?;
+    let escape = Combo::parse(&config.escape)?;
+    println!(
+        "Grab mode: swallowing {} combo(s); {} stops swallowing.",
+        consume.len(),
+        config.escape
+    );
+    let _ = RULES.set(Rules { consume, escape });
+    Ok(())
+}
+
+/// Whether `event` should be kept from the OS.
+fn swallow(event: &EventType) -> bool {
+    let Some(rules) = RULES.get() else { return false };
+    let mut keys = KEYS.lock().unwrap();
+    let keys = keys.get_or_insert_with(Keys::default);
+    match event {
+        EventType::KeyPress(key) => {
+            let Some(name) = crate::map_key(*key) else { return false };
+            keys.held.insert(name.clone());
+            if rules.escape.matches(&name, &keys.held) {
+                if ACTIVE.swap(false, Ordering::Relaxed) {
+                    println!("Grab escape combo pressed: no longer swallowing any keys.");
+                }
+                return false;
+            }
+            let swallowed = ACTIVE.load(Ordering::Relaxed)
+                && (keys.swallowed.contains(&name) || rules.consume.iter().any(|combo| combo.matches(&name, &keys.held)));
+            if swallowed {
+                keys.swallowed.insert(name);
+            }
+            swallowed
+        }
+        EventType::KeyRelease(key) => {
+            let Some(name) = crate::map_key(*key) else { return false };
+            keys.held.remove(&name);
+            keys.swallowed.remove(&name)
+        }
+        _ => false,
+    }
+}
+
+/// The OS hook in grab mode.
+#[derive(Clone, Copy)]
+pub struct GrabSource;
+
+impl EventSource for GrabSource {
+    fn run(self, sink: impl Fn(RawInputEvent) + 'static) -> Result<(), String> {
+        println!("Input grab thread started. Listening for global input...");
+        rdev::grab(move |event| {
+            // Nothing is forwarded any more, so nothing may be kept from the OS either.
+            if crate::shutting_down() {
+                return Some(event);
+            }
+            let swallowed = swallow(&event.event_type);
+            CONSUMING.store(swallowed, Ordering::Relaxed);
+            sink(event.clone());
+            CONSUMING.store(false, Ordering::Relaxed);
+            (!swallowed).then_some(event)
+        })
+        .map_err(|error| format!("{:?}", error))
+    }
+}
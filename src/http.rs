@@ -0,0 +1,394 @@
+/*
+ * pet-input-server: src/http.rs
+ *
+ * A tiny HTTP/1.1 responder that shares the WebSocket port. Connections are
+ * peeked first: WebSocket upgrades go to tungstenite, everything else lands here.
+ */
+
+use crate::settings::{SettingsPatch, SETTINGS};
+use crate::{latency, timestamp, Action, ActionKind, ServerState};
+use std::sync::atomic::Ordering;
+use serde_json::json;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::fs::File;
+use tokio::net::TcpStream;
+
+const MAX_HEAD_BYTES: usize = 8 * 1024;
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        query_param(&self.query, name)
+    }
+}
+
+/// Looks up `name` in a raw `a=1&b=2` query string.
+pub fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+/// Peeks at the request head without consuming it and reports whether it asks for a
+/// WebSocket upgrade. Gives up (treating it as plain HTTP) after a short wait.
+pub async fn is_websocket_upgrade(stream: &TcpStream) -> bool {
+    let mut buf = vec![0u8; MAX_HEAD_BYTES];
+    for _ in 0..50 {
+        let n = match stream.peek(&mut buf).await {
+            Ok(0) | Err(_) => return false,
+            Ok(n) => n,
+        };
+        let head = String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase();
+        if head.contains("\r\n\r\n") || n == buf.len() {
+            return head
+                .lines()
+                .any(|line| line.starts_with("upgrade:") && line.contains("websocket"));
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    false
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            return None;
+        }
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    let mut request = Request {
+        method,
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        body: buf[head_end + 4..].to_vec(),
+    };
+    let length: usize = request
+        .header("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY_BYTES {
+        return None;
+    }
+    while request.body.len() < length {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        request.body.extend_from_slice(&chunk[..n]);
+    }
+    request.body.truncate(length);
+    Some(request)
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &serde_json::Value) {
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Accepts the token from `Authorization: Bearer <token>` or a `?token=` query parameter.
+/// Guarded endpoints stay closed entirely when the server has no token configured.
+fn authorized(request: &Request, state: &ServerState) -> bool {
+    let Some(expected) = state.token.as_deref() else {
+        return false;
+    };
+    let presented = request
+        .header("authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| request.query_param("token"));
+    token_eq(presented, Some(expected))
+}
+
+/// Compares a presented token with the expected one without stopping at the first differing
+/// byte, so response timing doesn't reveal how much of a guess was right. Two `None`s match.
+pub fn token_eq(presented: Option<&str>, expected: Option<&str>) -> bool {
+    let (Some(presented), Some(expected)) = (presented, expected) else {
+        return presented.is_none() && expected.is_none();
+    };
+    let (a, b) = (presented.as_bytes(), expected.as_bytes());
+    let diff = (0..a.len().max(b.len())).fold(a.len() ^ b.len(), |acc, i| {
+        acc | usize::from(a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0))
+    });
+    std::hint::black_box(diff) == 0
+}
+
+/// Serves one plain HTTP request and closes the connection.
+pub async fn handle(mut stream: TcpStream, state: &ServerState) {
+    let request = match tokio::time::timeout(state.handshake_timeout, read_request(&mut stream)).await {
+        Ok(Some(request)) => request,
+        Ok(None) => {
+            respond(&mut stream, "400 Bad Request", &json!({"error": "malformed request"})).await;
+            return;
+        }
+        Err(_) => {
+            crate::metrics::handshake_timed_out();
+            return;
+        }
+    };
+
+    let guarded = matches!(request.path.as_str(), "/config" | "/recording" | "/status" | "/prefs" | "/qr")
+        || request.path.starts_with("/rooms/")
+        || request.path.starts_with("/prefs/");
+    match (request.method.as_str(), request.path.as_str()) {
+        _ if guarded && !authorized(&request, state) => {
+            respond(&mut stream, "401 Unauthorized", &json!({"error": "missing or invalid token"})).await;
+        }
+        ("GET", "/config") => {
+            respond(&mut stream, "200 OK", &config_json(state)).await;
+        }
+        ("PATCH", "/config") => {
+            let (status, body) = patch_config(&request.body, state);
+            respond(&mut stream, status, &body).await;
+        }
+        (_, "/config") => {
+            respond(&mut stream, "405 Method Not Allowed", &json!({"error": "use GET or PATCH"})).await;
+        }
+        ("GET", "/status") => {
+            let status = json!({
+                "clients": state.clients.load(Ordering::SeqCst),
+                "client_sessions": state.sessions.len(),
+                "latency": latency::to_json(),
+                "hook_delay": timestamp::to_json(),
+                "raw_queue": crate::rawqueue::to_json(),
+                "privacy": crate::privacy::to_json(),
+                "realtime": crate::realtime::to_json(),
+                "handshakes": crate::metrics::handshakes_json(state.pending_handshakes.load(Ordering::SeqCst)),
+                "shedding": crate::shedding::to_json(),
+                "debounce": crate::debounce::to_json(),
+                "suspended_s": crate::power::suspended_total().as_secs(),
+            });
+            respond(&mut stream, "200 OK", &status).await;
+        }
+        ("GET", "/prefs") => {
+            respond(&mut stream, "200 OK", &state.prefs.to_json()).await;
+        }
+        ("DELETE", path) if path.starts_with("/prefs/") => {
+            let name = &path["/prefs/".len()..];
+            if state.prefs.delete(name) {
+                println!("Deleted saved preferences for client '{}'.", name);
+                respond(&mut stream, "200 OK", &json!({"deleted": name})).await;
+            } else {
+                respond(&mut stream, "404 Not Found", &json!({"error": "no saved preferences"})).await;
+            }
+        }
+        ("GET", "/keymap") => {
+            respond(&mut stream, "200 OK", &crate::keymap_json()).await;
+        }
+        ("GET", "/capabilities") => {
+            respond(&mut stream, "200 OK", &capabilities_json(state)).await;
+        }
+        ("GET", "/qr") => {
+            // Printed on the server's terminal again too, for the person standing at it.
+            let url = state.connection_url();
+            crate::qr::print(&url);
+            match crate::qr::render(&url) {
+                Ok(code) => respond(&mut stream, "200 OK", &json!({"url": url, "qr": code})).await,
+                Err(e) => respond(&mut stream, "500 Internal Server Error", &json!({"error": e})).await,
+            }
+        }
+        ("GET", "/recording") => {
+            send_recording(&mut stream, state).await;
+        }
+        ("POST", path) if path.starts_with("/rooms/") && path.ends_with("/inject") => {
+            let room = &path["/rooms/".len()..path.len() - "/inject".len()];
+            let (status, body) = inject(room, &request.body, state);
+            respond(&mut stream, status, &body).await;
+        }
+        _ => {
+            respond(&mut stream, "404 Not Found", &json!({"error": "not found"})).await;
+        }
+    }
+}
+
+fn config_json(state: &ServerState) -> serde_json::Value {
+    json!({
+        "runtime": SETTINGS.to_json(),
+        "read_only": read_only_json(state),
+    })
+}
+
+/// Everything else in effect, which only a restart changes; `PATCH /config` rejects these keys.
+fn read_only_json(state: &ServerState) -> serde_json::Value {
+    json!({
+        "bind": state.bind_addr,
+        "port": state.port,
+        "token_required": state.token.is_some(),
+        "recording": state.recording.as_ref().map(|recorder| recorder.path().display().to_string()),
+        "default_locale": state.default_locale,
+        "default_format": state.default_format,
+        "mouse_delta": state.mouse_delta,
+        "mouse_keyframe_every": state.mouse_keyframe_every,
+        "coord_precision": state.coord_precision,
+        "polar_coords": state.polar_coords,
+        "max_message_bytes": state.max_message_bytes,
+        "backpressure_high": state.backpressure_high,
+        "backpressure_low": state.backpressure_low,
+        "queue_policy": state.queue_policy.as_str(),
+        "queue_block_timeout_ms": state.queue_block_timeout.as_millis() as u64,
+        "envelope": state.envelope,
+        "snapshot_mode": state.snapshot_mode,
+        "snapshot_interval_ms": state.snapshot_interval.as_millis() as u64,
+        "client_idle_timeout_secs": state.client_idle_timeout.map(|timeout| timeout.as_secs()),
+        "client_session_ttl_secs": state.sessions.ttl().as_secs(),
+        "handshake_timeout_secs": state.handshake_timeout.as_secs(),
+        "max_pending_handshakes": state.max_pending_handshakes,
+    })
+}
+
+/// What this build and its startup options support, for clients to adapt to; `/config`
+/// reports the tunable values instead.
+fn capabilities_json(state: &ServerState) -> serde_json::Value {
+    let kinds = ActionKind::ALL.iter().filter(|kind| crate::subscriptions::enabled(**kind));
+    json!({
+        "server": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol_versions": crate::SUPPORTED_PROTOCOL_VERSIONS,
+        "subprotocols": crate::subprotocol_names(),
+        "stream_paths": crate::role::PATHS,
+        "formats": crate::serializer::FORMATS,
+        "queue_policies": crate::clientqueue::QueuePolicy::names(),
+        "kinds": kinds.map(|kind| kind.as_str()).collect::<Vec<_>>(),
+        "opt_in_kinds": crate::OPT_IN_KINDS.iter().map(|kind| kind.as_str()).collect::<Vec<_>>(),
+        "notice_codes": crate::notice::codes_json(),
+        "close_codes": crate::close_codes_json(),
+        "locales": state.locales.names(),
+        // Cargo features compiled into this binary.
+        "features": {
+            "mqtt": cfg!(feature = "mqtt"),
+            "clipboard": cfg!(feature = "clipboard"),
+            "uinput": cfg!(feature = "uinput"),
+            "window_bounds": cfg!(feature = "window-bounds"),
+            "monitors": cfg!(feature = "monitors"),
+            "grab": cfg!(feature = "grab"),
+        },
+        // What this run was started with.
+        "runtime": {
+            "token_required": state.token.is_some(),
+            "inject": state.token.is_some(),
+            "cues": state.token.is_some() && crate::subscriptions::enabled(ActionKind::Cue),
+            "admin_path": state.token.is_some(),
+            "raw_stream": crate::rawstream::enabled(),
+            "recording": state.recording.is_some(),
+            "client_sessions": !state.sessions.ttl().is_zero(),
+            "mouse_delta_default": state.mouse_delta,
+            "polar_coords": state.polar_coords,
+            "default_format": state.default_format,
+            "default_queue_policy": state.queue_policy.as_str(),
+            "envelope_default": state.envelope,
+            "snapshot_default": state.snapshot_mode,
+            "snapshot_interval_ms": state.snapshot_interval.as_millis() as u64,
+            "max_message_bytes": state.max_message_bytes,
+        },
+    })
+}
+
+fn patch_config(body: &[u8], state: &ServerState) -> (&'static str, serde_json::Value) {
+    let value: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(e) => return ("400 Bad Request", json!({"error": format!("invalid JSON: {}", e)})),
+    };
+    let read_only = read_only_json(state);
+    if let Some(key) = value.as_object().and_then(|obj| obj.keys().find(|k| read_only.get(k.as_str()).is_some())) {
+        return ("400 Bad Request", json!({"error": format!("'{}' is read-only", key)}));
+    }
+    let patch: SettingsPatch = match serde_json::from_value(value) {
+        Ok(p) => p,
+        Err(e) => return ("400 Bad Request", json!({"error": e.to_string()})),
+    };
+    match SETTINGS.apply(&patch) {
+        Ok(()) => {
+            println!("Runtime config updated: {}", SETTINGS.to_json());
+            ("200 OK", config_json(state))
+        }
+        Err(e) => ("400 Bad Request", json!({"error": e})),
+    }
+}
+
+/// Sends a client-supplied Action to one room only; live input is unaffected.
+fn inject(room: &str, body: &[u8], state: &ServerState) -> (&'static str, serde_json::Value) {
+    let Some(room) = state.rooms.get(room) else {
+        return ("404 Not Found", json!({"error": "unknown room"}));
+    };
+    let action: Action = match serde_json::from_slice(body) {
+        Ok(a) => a,
+        Err(e) => return ("400 Bad Request", json!({"error": format!("invalid action: {}", e)})),
+    };
+    println!("Injecting into room '{}': {:?}", room.name, action);
+    let delivered = room.tx.send(action).unwrap_or(0);
+    ("200 OK", json!({"room": room.name, "delivered": delivered}))
+}
+
+/// Streams the recording made so far. The writer is flushed first and only the bytes
+/// written up to that point are sent, read through a separate read-only handle.
+async fn send_recording(stream: &mut TcpStream, state: &ServerState) {
+    let Some(recorder) = &state.recording else {
+        respond(stream, "404 Not Found", &json!({"error": "not recording (start with --record)"})).await;
+        return;
+    };
+    let opened = match recorder.flushed_len() {
+        Ok(len) => File::open(recorder.path()).await.map(|file| (file, len)),
+        Err(e) => Err(e),
+    };
+    let (file, len) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            respond(stream, "500 Internal Server Error", &json!({"error": e.to_string()})).await;
+            return;
+        }
+    };
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        recorder.format().content_type(),
+        len
+    );
+    if stream.write_all(head.as_bytes()).await.is_ok() {
+        let _ = tokio::io::copy(&mut file.take(len), stream).await;
+    }
+    let _ = stream.shutdown().await;
+}
@@ -0,0 +1,103 @@
+/*
+ * pet-input-server: src/instrument.rs
+ *
+ * Optional "instrument mode": key presses listed in `[instrument.notes]` also
+ * emit a Note action, so a frontend can play bongo/piano sounds per key.
+ */
+
+use crate::config::InstrumentConfig;
+use crate::{Action, ActionKind, ActionValue, NoteValue};
+use once_cell::sync::Lazy;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Typing speed is measured over this trailing window.
+const SPEED_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Instrument {
+    config: InstrumentConfig,
+    /// Octave-shift keys currently held down.
+    held_shifts: HashSet<String>,
+    recent_presses: VecDeque<Instant>,
+}
+
+static INSTRUMENT: Lazy<Mutex<Instrument>> = Lazy::new(|| Mutex::new(Instrument::default()));
+
+/// Installs (or hot-swaps) the note mapping. Held keys and typing speed carry over.
+pub fn configure(config: &InstrumentConfig) {
+    INSTRUMENT.lock().unwrap().config = config.clone();
+}
+
+/// Tracks typing speed and octave-shift keys, and sends a Note if `key` is mapped.
+pub fn on_key_press(key: &str, broadcast_tx: &broadcast::Sender<Action>) {
+    let mut inst = INSTRUMENT.lock().unwrap();
+    if inst.config.notes.is_empty() {
+        return;
+    }
+
+    let now = Instant::now();
+    inst.recent_presses.push_back(now);
+    while let Some(&oldest) = inst.recent_presses.front() {
+        if now.duration_since(oldest) <= SPEED_WINDOW {
+            break;
+        }
+        inst.recent_presses.pop_front();
+    }
+
+    if inst.config.octave_shift.contains_key(key) {
+        inst.held_shifts.insert(key.to_string());
+    }
+
+    let Some(note) = inst.config.notes.get(key) else {
+        return;
+    };
+    let shift: i32 = inst
+        .held_shifts
+        .iter()
+        .filter_map(|k| inst.config.octave_shift.get(k))
+        .sum();
+    let action = Action::new(
+        ActionKind::Note,
+        ActionValue::Note(NoteValue {
+            note: shift_octave(note, shift),
+            velocity: inst.velocity(),
+        }),
+    );
+    crate::rates::count(ActionKind::Note);
+    let _ = broadcast_tx.send(action);
+}
+
+pub fn on_key_release(key: &str) {
+    let mut inst = INSTRUMENT.lock().unwrap();
+    inst.held_shifts.remove(key);
+}
+
+impl Instrument {
+    /// Scales linearly from `velocity_min` when typing slowly up to `velocity_max`
+    /// at `velocity_full_rate` presses per second.
+    fn velocity(&self) -> u8 {
+        let c = &self.config;
+        let rate = self.recent_presses.len() as f64 / SPEED_WINDOW.as_secs_f64();
+        let t = (rate / c.velocity_full_rate.max(f64::EPSILON)).min(1.0);
+        let (lo, hi) = (c.velocity_min as f64, c.velocity_max.max(c.velocity_min) as f64);
+        (lo + (hi - lo) * t).round() as u8
+    }
+}
+
+/// Moves a note like "C#4" by `octaves`; notes without a trailing octave number are left alone.
+fn shift_octave(note: &str, octaves: i32) -> String {
+    if octaves == 0 {
+        return note.to_string();
+    }
+    let split = note
+        .rfind(|c: char| !c.is_ascii_digit() && c != '-')
+        .map_or(0, |i| i + 1);
+    let (pitch, octave) = note.split_at(split);
+    match octave.parse::<i32>() {
+        Ok(octave) if !pitch.is_empty() => format!("{}{}", pitch, octave + octaves),
+        _ => note.to_string(),
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ * pet-input-server: src/intensity.rs
+ *
+ * A single 0.0..1.0 "how busy is the user" value, so every client doesn't have
+ * to smooth typing speed itself. Input events push the value up, time decays it.
+ */
+
+use crate::config::IntensityConfig;
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+#[derive(Clone, Copy)]
+pub enum Activity {
+    Key,
+    Click,
+    Scroll,
+}
+
+struct Meter {
+    config: IntensityConfig,
+    value: f64,
+    updated: Instant,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static METER: Lazy<Mutex<Meter>> = Lazy::new(|| {
+    Mutex::new(Meter {
+        config: IntensityConfig::default(),
+        value: 0.0,
+        updated: Instant::now(),
+    })
+});
+
+impl Meter {
+    /// Applies exponential decay for the time elapsed since the last update.
+    fn decay_to(&mut self, now: Instant) {
+        let dt = now.duration_since(self.updated).as_secs_f64();
+        self.value *= (-dt / self.config.decay_secs.max(f64::EPSILON)).exp();
+        self.updated = now;
+    }
+
+    /// Each event closes `attack * weight` of the remaining gap to 1.0.
+    fn push(&mut self, activity: Activity, now: Instant) {
+        self.decay_to(now);
+        let weights = &self.config.weights;
+        let weight = match activity {
+            Activity::Key => weights.key,
+            Activity::Click => weights.click,
+            Activity::Scroll => weights.scroll,
+        };
+        let step = (self.config.attack * weight).clamp(0.0, 1.0);
+        self.value += (1.0 - self.value) * step;
+    }
+}
+
+/// The value to broadcast after `last_sent`, if any: values under `min_change` snap to zero,
+/// so clients see a definite "idle", and smaller moves than `min_change` are skipped.
+fn to_send(value: f64, last_sent: f64, min_change: f64) -> Option<f64> {
+    let value = if value < min_change { 0.0 } else { value };
+    let reached_idle = value == 0.0 && last_sent != 0.0;
+    ((value - last_sent).abs() >= min_change || reached_idle).then_some(value)
+}
+
+/// Called from the input callback; a no-op unless the feature is enabled.
+pub fn record(activity: Activity) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    METER.lock().unwrap().push(activity, Instant::now());
+}
+
+/// Enables the meter and starts the ticker that broadcasts `Intensity` at `rate_hz`,
+/// but only when the value moved by at least `min_change` since the last broadcast.
+pub fn spawn(config: &IntensityConfig, broadcast_tx: broadcast::Sender<Action>) {
+    let period = Duration::from_secs_f64(1.0 / config.rate_hz.max(0.1));
+    let min_change = config.min_change;
+    METER.lock().unwrap().config = config.clone();
+    ENABLED.store(true, Ordering::Relaxed);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        let mut last_sent = 0.0f64;
+        loop {
+            interval.tick().await;
+            let value = {
+                let mut meter = METER.lock().unwrap();
+                meter.decay_to(Instant::now());
+                meter.value
+            };
+            let Some(value) = to_send(value, last_sent, min_change) else {
+                continue;
+            };
+            last_sent = value;
+            let _ = broadcast_tx.send(Action::new(
+                ActionKind::Intensity,
+                ActionValue::Number((value * 1000.0).round() / 1000.0),
+            ));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meter(now: Instant) -> Meter {
+        Meter {
+            config: IntensityConfig::default(),
+            value: 0.0,
+            updated: now,
+        }
+    }
+
+    #[test]
+    fn events_close_part_of_the_gap_to_one() {
+        let t = Instant::now();
+        let mut meter = meter(t);
+        let step = meter.config.attack * meter.config.weights.key;
+        meter.push(Activity::Key, t);
+        assert!((meter.value - step).abs() < 1e-9);
+        meter.push(Activity::Key, t);
+        assert!((meter.value - (step + (1.0 - step) * step)).abs() < 1e-9);
+        for _ in 0..1000 {
+            meter.push(Activity::Click, t);
+        }
+        assert!(meter.value <= 1.0 && meter.value > 0.99);
+    }
+
+    #[test]
+    fn value_decays_with_the_time_constant() {
+        let t = Instant::now();
+        let mut meter = meter(t);
+        meter.value = 0.8;
+        let tau = Duration::from_secs_f64(meter.config.decay_secs);
+        meter.decay_to(t + tau);
+        assert!((meter.value - 0.8 / std::f64::consts::E).abs() < 1e-6);
+        meter.decay_to(t + tau * 20);
+        assert!(meter.value < 1e-6);
+    }
+
+    #[test]
+    fn weights_scale_each_activity() {
+        let t = Instant::now();
+        let config = IntensityConfig::default();
+        for (activity, weight) in [
+            (Activity::Key, config.weights.key),
+            (Activity::Click, config.weights.click),
+            (Activity::Scroll, config.weights.scroll),
+        ] {
+            let mut meter = meter(t);
+            meter.push(activity, t);
+            assert!((meter.value - (config.attack * weight).clamp(0.0, 1.0)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn only_changes_of_min_change_are_sent() {
+        assert_eq!(to_send(0.5, 0.0, 0.02), Some(0.5));
+        assert_eq!(to_send(0.51, 0.5, 0.02), None);
+        assert_eq!(to_send(0.53, 0.5, 0.02), Some(0.53));
+        // Fading below min_change snaps to zero, once.
+        assert_eq!(to_send(0.015, 0.03, 0.02), Some(0.0));
+        assert_eq!(to_send(0.01, 0.0, 0.02), None);
+        assert_eq!(to_send(0.0, 0.0, 0.02), None);
+    }
+}
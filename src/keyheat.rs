@@ -0,0 +1,160 @@
+/*
+ * pet-input-server: src/keyheat.rs
+ *
+ * Per-key "heat" for clients that draw a glow on each key and fade it out:
+ * a press adds `increment` (capped at 1.0) and the heat decays exponentially
+ * with `decay_secs`. At `rate_hz` the keys above `threshold` are broadcast:
+ *
+ *   {"kind":"KeyHeat","value":{"KeyA":0.8,"Space":0.2}}
+ *
+ * A key whose heat falls below the threshold is removed, so the map only
+ * holds recently pressed (mapped) keys and a tick costs O(warm keys). Once the
+ * last key has cooled an empty map is sent, then nothing until the next press.
+ */
+
+use crate::config::KeyHeatConfig;
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+struct Heat {
+    config: KeyHeatConfig,
+    /// Warm keys by mapped name.
+    keys: HashMap<String, f64>,
+    updated: Instant,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static HEAT: Lazy<Mutex<Heat>> = Lazy::new(|| {
+    Mutex::new(Heat {
+        config: KeyHeatConfig::default(),
+        keys: HashMap::new(),
+        updated: Instant::now(),
+    })
+});
+
+impl Heat {
+    /// Decays every warm key to `now` and drops the ones that cooled below the threshold.
+    fn decay_to(&mut self, now: Instant) {
+        let dt = now.duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        let factor = (-dt / self.config.decay_secs.max(f64::EPSILON)).exp();
+        let threshold = self.config.threshold;
+        self.keys.retain(|_, heat| {
+            *heat *= factor;
+            *heat >= threshold
+        });
+    }
+
+    fn press(&mut self, name: &str, now: Instant) {
+        self.decay_to(now);
+        let increment = self.config.increment;
+        let value = self.keys.entry(name.to_string()).or_insert(0.0);
+        *value = (*value + increment).min(1.0);
+    }
+}
+
+/// Called for each key press (by mapped name); a no-op unless the feature is enabled.
+pub fn on_key_press(name: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    HEAT.lock().unwrap().press(name, Instant::now());
+}
+
+fn action(keys: serde_json::Map<String, serde_json::Value>) -> Action {
+    Action::new(ActionKind::KeyHeat, ActionValue::Json(serde_json::Value::Object(keys)))
+}
+
+/// Enables tracking and starts the ticker that broadcasts `KeyHeat` at `rate_hz`.
+pub fn spawn(config: &KeyHeatConfig, broadcast_tx: broadcast::Sender<Action>) {
+    let period = Duration::from_secs_f64(1.0 / config.rate_hz.max(0.1));
+    {
+        let mut heat = HEAT.lock().unwrap();
+        heat.config = config.clone();
+        heat.updated = Instant::now();
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        let mut was_warm = false;
+        loop {
+            interval.tick().await;
+            let keys: serde_json::Map<_, _> = {
+                let mut heat = HEAT.lock().unwrap();
+                heat.decay_to(Instant::now());
+                heat.keys
+                    .iter()
+                    .map(|(key, heat)| (key.clone(), ((heat * 1000.0).round() / 1000.0).into()))
+                    .collect()
+            };
+            if keys.is_empty() && !was_warm {
+                continue;
+            }
+            was_warm = !keys.is_empty();
+            let _ = broadcast_tx.send(action(keys));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heat(start: Instant) -> Heat {
+        Heat {
+            config: KeyHeatConfig {
+                increment: 0.4,
+                decay_secs: 2.0,
+                threshold: 0.1,
+                ..KeyHeatConfig::default()
+            },
+            keys: HashMap::new(),
+            updated: start,
+        }
+    }
+
+    #[test]
+    fn heat_decays_exponentially() {
+        let start = Instant::now();
+        let mut heat = heat(start);
+        heat.config.threshold = 0.01;
+        heat.press("KeyA", start);
+        heat.decay_to(start + Duration::from_secs(2));
+        assert!((heat.keys["KeyA"] - 0.4 * (-1.0f64).exp()).abs() < 1e-9);
+        // Decay compounds the same however often it is applied.
+        heat.decay_to(start + Duration::from_secs(3));
+        heat.decay_to(start + Duration::from_secs(4));
+        assert!((heat.keys["KeyA"] - 0.4 * (-2.0f64).exp()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn keys_below_the_threshold_are_forgotten() {
+        let start = Instant::now();
+        let mut heat = heat(start);
+        heat.press("KeyA", start);
+        heat.press("Space", start + Duration::from_secs(2));
+        // KeyA: 0.4·e^-2 ≈ 0.054, below 0.1; Space: 0.4·e^-1 ≈ 0.147.
+        heat.decay_to(start + Duration::from_secs(4));
+        assert_eq!(heat.keys.keys().collect::<Vec<_>>(), ["Space"]);
+        heat.decay_to(start + Duration::from_secs(6));
+        assert!(heat.keys.is_empty());
+    }
+
+    #[test]
+    fn heat_is_capped_at_one() {
+        let start = Instant::now();
+        let mut heat = heat(start);
+        for _ in 0..5 {
+            heat.press("KeyA", start);
+        }
+        assert_eq!(heat.keys["KeyA"], 1.0);
+        heat.press("KeyA", start + Duration::from_secs(2));
+        assert_eq!(heat.keys["KeyA"], ((-1.0f64).exp() + 0.4).min(1.0));
+    }
+}
@@ -0,0 +1,169 @@
+/*
+ * pet-input-server: src/keypad.rs
+ *
+ * Keypad keys rdev can't tell apart on Windows (`--numpad-distinct`). rdev
+ * maps a low-level hook event by its virtual key alone, so keypad Enter
+ * arrives as Return, and with NumLock off the keypad's digits and decimal
+ * arrive as the navigation cluster (Insert, End, DownArrow, ...). Windows
+ * tells them apart by the scan code and the extended-key flag (the E0
+ * prefix): keypad Enter has it and Return doesn't, the navigation cluster has
+ * it and the keypad doesn't.
+ *
+ * With `--numpad-distinct` a second WH_KEYBOARD_LL hook is installed on
+ * rdev's listener thread, from inside rdev's own callback so that it sits in
+ * front of rdev's hook. It records each event's (vk, scan, flags), and
+ * `restore` rewrites the key rdev reports before the event is queued. The
+ * NumLock-off keys have no rdev `Key`, so they get `Key::Unknown` codes above
+ * any real key code.
+ */
+
+use rdev::Key;
+
+/// `KBDLLHOOKSTRUCT::flags` bit for keys sent with the E0 scan code prefix.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub const LLKHF_EXTENDED: u32 = 0x01;
+
+/// Base of the `Key::Unknown` codes for NumLock-off keypad keys; real codes stay below 256.
+const KEYPAD_NAV_BASE: u32 = 0x1_0000;
+
+/// Virtual key and scan code of keypad Enter, which shares both with Return.
+#[cfg_attr(not(windows), allow(dead_code))]
+const ENTER: (u32, u32) = (0x0D, 0x1C);
+
+/// NumLock-off keypad keys: virtual key, scan code, the key rdev reports for it and the
+/// name sent with `--numpad-distinct`.
+const NAV: &[(u32, u32, Key, &str)] = &[
+    (0x2D, 0x52, Key::Insert, "NumpadInsert"),
+    (0x23, 0x4F, Key::End, "NumpadEnd"),
+    (0x28, 0x50, Key::DownArrow, "NumpadDownArrow"),
+    (0x22, 0x51, Key::PageDown, "NumpadPageDown"),
+    (0x25, 0x4B, Key::LeftArrow, "NumpadLeftArrow"),
+    (0x0C, 0x4C, Key::Unknown(0x0C), "NumpadClear"),
+    (0x27, 0x4D, Key::RightArrow, "NumpadRightArrow"),
+    (0x24, 0x47, Key::Home, "NumpadHome"),
+    (0x26, 0x48, Key::UpArrow, "NumpadUpArrow"),
+    (0x21, 0x49, Key::PageUp, "NumpadPageUp"),
+    (0x2E, 0x53, Key::Delete, "NumpadDelete"),
+];
+
+const fn nav_key(vk: u32) -> Key {
+    Key::Unknown(KEYPAD_NAV_BASE + vk)
+}
+
+/// The keypad key a hook event really was, if rdev reports it as something else.
+/// Injected events without a scan code are left alone.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn distinct_key(vk: u32, scan: u32, flags: u32) -> Option<Key> {
+    let extended = flags & LLKHF_EXTENDED != 0;
+    if (vk, scan) == ENTER {
+        return extended.then_some(Key::KpReturn);
+    }
+    if extended {
+        return None;
+    }
+    NAV.iter().find(|(v, s, _, _)| (*v, *s) == (vk, scan)).map(|(vk, ..)| nav_key(*vk))
+}
+
+/// The NumLock-off keypad keys and their `--numpad-distinct` names, for the keymap.
+pub fn nav_names() -> impl Iterator<Item = (Key, &'static str)> {
+    NAV.iter().map(|(vk, _, _, name)| (nav_key(*vk), *name))
+}
+
+/// The navigation key a NumLock-off keypad key folds into without `--numpad-distinct`.
+pub fn folded(key: Key) -> Option<Key> {
+    NAV.iter().find(|(vk, ..)| nav_key(*vk) == key).map(|(_, _, nav, _)| *nav)
+}
+
+/// Rewrites a key event rdev folded, using what the hook recorded for it. Called from
+/// rdev's callback, on its thread.
+#[cfg(windows)]
+pub fn restore(event: &mut rdev::Event) {
+    let (rdev::EventType::KeyPress(key) | rdev::EventType::KeyRelease(key)) = &mut event.event_type else {
+        return;
+    };
+    let Some((vk, scan, flags)) = hook::last_key() else {
+        return;
+    };
+    let Some(distinct) = distinct_key(vk, scan, flags) else {
+        return;
+    };
+    // The record must be for this event: the key rdev reported is the one it folds into.
+    let reported = if distinct == Key::KpReturn { Some(Key::Return) } else { folded(distinct) };
+    if reported == Some(*key) {
+        *key = distinct;
+    }
+}
+
+#[cfg(windows)]
+mod hook {
+    use std::cell::Cell;
+    use std::ptr::null;
+    use winapi::shared::minwindef::{LPARAM, LRESULT, WPARAM};
+    use winapi::um::libloaderapi::GetModuleHandleW;
+    use winapi::um::winuser::{CallNextHookEx, SetWindowsHookExW, HC_ACTION, KBDLLHOOKSTRUCT, WH_KEYBOARD_LL};
+
+    thread_local! {
+        static INSTALLED: Cell<bool> = const { Cell::new(false) };
+        static LAST: Cell<Option<(u32, u32, u32)>> = const { Cell::new(None) };
+    }
+
+    unsafe extern "system" fn hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        if code == HC_ACTION {
+            let info = &*(lparam as *const KBDLLHOOKSTRUCT);
+            LAST.with(|last| last.set(Some((info.vkCode, info.scanCode, info.flags))));
+        }
+        CallNextHookEx(std::ptr::null_mut(), code, wparam, lparam)
+    }
+
+    /// The (vk, scan, flags) of the key event being handled on this thread. The first call
+    /// on a listener thread installs the hook, so only that one event goes unrecorded; a
+    /// restarted listener runs on a new thread and installs it again.
+    pub fn last_key() -> Option<(u32, u32, u32)> {
+        if !INSTALLED.with(|installed| installed.replace(true)) {
+            let hook = unsafe { SetWindowsHookExW(WH_KEYBOARD_LL, Some(hook_proc), GetModuleHandleW(null()), 0) };
+            if hook.is_null() {
+                eprintln!("Could not install the keypad hook; keypad keys stay merged with --numpad-distinct.");
+            }
+            return None;
+        }
+        LAST.with(Cell::take)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (vk, scan, flags) from a Windows low-level hook; key-up events add LLKHF_UP (0x80).
+    #[test]
+    fn recorded_hook_events_map_to_the_key_pressed() {
+        let recorded: &[(u32, u32, u32, Option<&str>)] = &[
+            (0x0D, 0x1C, 0x01, Some("NumpadEnter")),
+            (0x0D, 0x1C, 0x81, Some("NumpadEnter")),
+            (0x0D, 0x1C, 0x00, None),
+            (0x24, 0x47, 0x00, Some("NumpadHome")),
+            (0x24, 0x47, 0x01, None),
+            (0x25, 0x4B, 0x80, Some("NumpadLeftArrow")),
+            (0x25, 0x4B, 0x81, None),
+            (0x2D, 0x52, 0x00, Some("NumpadInsert")),
+            (0x2E, 0x53, 0x00, Some("NumpadDelete")),
+            (0x2E, 0x53, 0x01, None),
+            (0x0C, 0x4C, 0x00, Some("NumpadClear")),
+            // NumLock on: rdev already reports Kp4.
+            (0x64, 0x4B, 0x00, None),
+            // Injected by SendInput with only a virtual key.
+            (0x25, 0x00, 0x10, None),
+        ];
+        for (vk, scan, flags, expected) in recorded {
+            let name = distinct_key(*vk, *scan, *flags).and_then(|key| crate::map_key_with(key, false, true));
+            assert_eq!(name.as_deref(), *expected, "({:#x}, {:#x}, {:#x})", vk, scan, flags);
+        }
+    }
+
+    #[test]
+    fn keypad_nav_keys_fold_into_the_nav_cluster_by_default() {
+        let key = distinct_key(0x24, 0x47, 0x00).unwrap();
+        assert_eq!(crate::map_key_with(key, false, false).as_deref(), Some("Home"));
+        assert_eq!(folded(nav_key(0x28)), Some(Key::DownArrow));
+    }
+}
@@ -0,0 +1,199 @@
+/*
+ * pet-input-server: src/kind.rs
+ *
+ * Every kind the server emits, as an enum inside the pipeline and as its name
+ * on the wire. Per-kind tables (counters, subscription masks) are arrays
+ * indexed by `ActionKind::index`, and `as_str` is an exhaustive match, so a
+ * new kind can't be added without giving it a name.
+ *
+ * Names only turn into strings at the edges: serialization (where a client's
+ * renames apply), config and query parsing, and reports.
+ */
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ActionKind {
+    MouseMove,
+    MouseDelta,
+    MousePress,
+    MouseRelease,
+    KeyboardPress,
+    KeyboardRelease,
+    RegionEnter,
+    RegionExit,
+    Note,
+    Intensity,
+    Backpressure,
+    ProtocolError,
+    InputFailure,
+    Rates,
+    Oversized,
+    Inactive,
+    KeyLongPress,
+    InputFlood,
+    ClipboardChange,
+    Prefs,
+    ShoutingDetected,
+    TimeSyncProbe,
+    TimeSync,
+    MediaKey,
+    SystemSuspend,
+    SystemResume,
+    ButtonChord,
+    MousePan,
+    MousePanEnd,
+    Status,
+    WindowBounds,
+    Chord,
+    CapturePaused,
+    Cue,
+    CoordPrivacy,
+    Mood,
+    RawEvent,
+    ServerNotice,
+    Snapshot,
+    KeyHeat,
+    Disconnect,
+    ModifierTap,
+}
+
+/// Number of kinds; the length of per-kind tables.
+pub const COUNT: usize = ActionKind::ALL.len();
+
+impl ActionKind {
+    /// Every kind, in declaration order (which `index` follows).
+    pub const ALL: [ActionKind; 42] = [
+        ActionKind::MouseMove,
+        ActionKind::MouseDelta,
+        ActionKind::MousePress,
+        ActionKind::MouseRelease,
+        ActionKind::KeyboardPress,
+        ActionKind::KeyboardRelease,
+        ActionKind::RegionEnter,
+        ActionKind::RegionExit,
+        ActionKind::Note,
+        ActionKind::Intensity,
+        ActionKind::Backpressure,
+        ActionKind::ProtocolError,
+        ActionKind::InputFailure,
+        ActionKind::Rates,
+        ActionKind::Oversized,
+        ActionKind::Inactive,
+        ActionKind::KeyLongPress,
+        ActionKind::InputFlood,
+        ActionKind::ClipboardChange,
+        ActionKind::Prefs,
+        ActionKind::ShoutingDetected,
+        ActionKind::TimeSyncProbe,
+        ActionKind::TimeSync,
+        ActionKind::MediaKey,
+        ActionKind::SystemSuspend,
+        ActionKind::SystemResume,
+        ActionKind::ButtonChord,
+        ActionKind::MousePan,
+        ActionKind::MousePanEnd,
+        ActionKind::Status,
+        ActionKind::WindowBounds,
+        ActionKind::Chord,
+        ActionKind::CapturePaused,
+        ActionKind::Cue,
+        ActionKind::CoordPrivacy,
+        ActionKind::Mood,
+        ActionKind::RawEvent,
+        ActionKind::ServerNotice,
+        ActionKind::Snapshot,
+        ActionKind::KeyHeat,
+        ActionKind::Disconnect,
+        ActionKind::ModifierTap,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ActionKind::MouseMove => "MouseMove",
+            ActionKind::MouseDelta => "MouseDelta",
+            ActionKind::MousePress => "MousePress",
+            ActionKind::MouseRelease => "MouseRelease",
+            ActionKind::KeyboardPress => "KeyboardPress",
+            ActionKind::KeyboardRelease => "KeyboardRelease",
+            ActionKind::RegionEnter => "RegionEnter",
+            ActionKind::RegionExit => "RegionExit",
+            ActionKind::Note => "Note",
+            ActionKind::Intensity => "Intensity",
+            ActionKind::Backpressure => "Backpressure",
+            ActionKind::ProtocolError => "ProtocolError",
+            ActionKind::InputFailure => "InputFailure",
+            ActionKind::Rates => "Rates",
+            ActionKind::Oversized => "Oversized",
+            ActionKind::Inactive => "Inactive",
+            ActionKind::KeyLongPress => "KeyLongPress",
+            ActionKind::InputFlood => "InputFlood",
+            ActionKind::ClipboardChange => "ClipboardChange",
+            ActionKind::Prefs => "Prefs",
+            ActionKind::ShoutingDetected => "ShoutingDetected",
+            ActionKind::TimeSyncProbe => "TimeSyncProbe",
+            ActionKind::TimeSync => "TimeSync",
+            ActionKind::MediaKey => "MediaKey",
+            ActionKind::SystemSuspend => "SystemSuspend",
+            ActionKind::SystemResume => "SystemResume",
+            ActionKind::ButtonChord => "ButtonChord",
+            ActionKind::MousePan => "MousePan",
+            ActionKind::MousePanEnd => "MousePanEnd",
+            ActionKind::Status => "Status",
+            ActionKind::WindowBounds => "WindowBounds",
+            ActionKind::Chord => "Chord",
+            ActionKind::CapturePaused => "CapturePaused",
+            ActionKind::Cue => "Cue",
+            ActionKind::CoordPrivacy => "CoordPrivacy",
+            ActionKind::Mood => "Mood",
+            ActionKind::RawEvent => "RawEvent",
+            ActionKind::ServerNotice => "ServerNotice",
+            ActionKind::Snapshot => "Snapshot",
+            ActionKind::KeyHeat => "KeyHeat",
+            ActionKind::Disconnect => "Disconnect",
+            ActionKind::ModifierTap => "ModifierTap",
+        }
+    }
+
+    /// Position in `ALL`, for per-kind arrays and bitmasks.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Every kind's name, for error messages and the fixtures index.
+    pub fn names() -> Vec<&'static str> {
+        ActionKind::ALL.iter().map(|kind| kind.as_str()).collect()
+    }
+}
+
+impl FromStr for ActionKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ActionKind, String> {
+        ActionKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == name)
+            .ok_or_else(|| format!("unknown kind '{}'", name))
+    }
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl Serialize for ActionKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ActionKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ActionKind, D::Error> {
+        let name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,164 @@
+/*
+ * pet-input-server: src/labels.rs
+ *
+ * Display labels for keys, per locale: a client that connects with
+ * `?locale=de` (or every client, with `locale = "de"` in the config) gets a
+ * `label` such as "Strg" on keyboard events. The `value` key name never
+ * changes, so frontend logic keeps matching on it.
+ *
+ * A key's label is looked up in the locale's table, then under its unsided
+ * name (`ControlLeft` → `Control`), then in the labels every locale shares
+ * (letters, digits, arrows); a key none of them knows is labelled with its
+ * canonical name. Built-in tables exist for en, de, fr and ja, and
+ * `[locales.<name>]` in the config extends or adds to them.
+ */
+
+use crate::ActionKind;
+use std::collections::{BTreeMap, HashMap};
+
+/// Kinds whose value is a key name and so get a label.
+const LABELLED_KINDS: &[ActionKind] =
+    &[ActionKind::KeyboardPress, ActionKind::KeyboardRelease, ActionKind::KeyLongPress];
+
+const SHARED: &[(&str, &str)] = &[
+    ("UpArrow", "↑"),
+    ("DownArrow", "↓"),
+    ("LeftArrow", "←"),
+    ("RightArrow", "→"),
+];
+
+const EN: &[(&str, &str)] = &[
+    ("Control", "Ctrl"),
+    ("Escape", "Esc"),
+    ("Return", "Enter"),
+    ("CapsLock", "Caps Lock"),
+    ("Delete", "Del"),
+    ("Insert", "Ins"),
+    ("PageUp", "Page Up"),
+    ("PageDown", "Page Down"),
+    ("PrintScreen", "Print Screen"),
+    ("ScrollLock", "Scroll Lock"),
+    ("NumLock", "Num Lock"),
+];
+
+const DE: &[(&str, &str)] = &[
+    ("Control", "Strg"),
+    ("Shift", "Umschalt"),
+    ("AltRight", "Alt Gr"),
+    ("Escape", "Esc"),
+    ("Return", "Eingabe"),
+    ("Backspace", "Rücktaste"),
+    ("Tab", "Tab"),
+    ("CapsLock", "Feststell"),
+    ("Space", "Leertaste"),
+    ("Delete", "Entf"),
+    ("Insert", "Einfg"),
+    ("Home", "Pos1"),
+    ("End", "Ende"),
+    ("PageUp", "Bild auf"),
+    ("PageDown", "Bild ab"),
+    ("PrintScreen", "Druck"),
+    ("ScrollLock", "Rollen"),
+    ("NumLock", "Num"),
+    ("Menu", "Menü"),
+];
+
+const FR: &[(&str, &str)] = &[
+    ("Control", "Ctrl"),
+    ("Shift", "Maj"),
+    ("AltRight", "Alt Gr"),
+    ("Escape", "Échap"),
+    ("Return", "Entrée"),
+    ("Backspace", "Retour arrière"),
+    ("CapsLock", "Verr. maj"),
+    ("Space", "Espace"),
+    ("Delete", "Suppr"),
+    ("Insert", "Inser"),
+    ("Home", "Début"),
+    ("End", "Fin"),
+    ("PageUp", "Page préc."),
+    ("PageDown", "Page suiv."),
+    ("PrintScreen", "Impr. écran"),
+    ("ScrollLock", "Arrêt défil"),
+    ("NumLock", "Verr. num"),
+];
+
+const JA: &[(&str, &str)] = &[
+    ("Control", "コントロール"),
+    ("Shift", "シフト"),
+    ("Alt", "オルト"),
+    ("Escape", "エスケープ"),
+    ("Return", "エンター"),
+    ("Backspace", "バックスペース"),
+    ("Tab", "タブ"),
+    ("CapsLock", "キャプスロック"),
+    ("Space", "スペース"),
+    ("Delete", "削除"),
+    ("Insert", "挿入"),
+    ("Home", "ホーム"),
+    ("End", "エンド"),
+    ("PageUp", "ページアップ"),
+    ("PageDown", "ページダウン"),
+    ("PrintScreen", "プリントスクリーン"),
+    ("Menu", "メニュー"),
+];
+
+const BUILT_IN: &[(&str, &[(&str, &str)])] = &[("en", EN), ("de", DE), ("fr", FR), ("ja", JA)];
+
+/// One locale's key → label table.
+#[derive(Default, Debug)]
+pub struct KeyLabels(HashMap<String, String>);
+
+impl KeyLabels {
+    pub fn label(&self, key: &str) -> String {
+        let unsided = key.strip_suffix("Left").or_else(|| key.strip_suffix("Right"));
+        if let Some(label) = self.0.get(key).or_else(|| unsided.and_then(|base| self.0.get(base))) {
+            return label.clone();
+        }
+        if let Some((_, label)) = SHARED.iter().find(|(name, _)| *name == key) {
+            return label.to_string();
+        }
+        // KeyA → A, Num1 → 1
+        match key.strip_prefix("Key").or_else(|| key.strip_prefix("Num")) {
+            Some(rest) if rest.len() == 1 => rest.to_string(),
+            _ => key.to_string(),
+        }
+    }
+
+    /// Whether `kind` carries a key name that this table labels.
+    pub fn applies_to(kind: ActionKind) -> bool {
+        LABELLED_KINDS.contains(&kind)
+    }
+}
+
+/// Every locale, built-in and from `[locales]`.
+#[derive(Default, Debug)]
+pub struct Locales(HashMap<String, KeyLabels>);
+
+impl Locales {
+    /// Built-in tables with the config's `[locales.<name>]` entries laid over them.
+    pub fn build(extra: &BTreeMap<String, BTreeMap<String, String>>) -> Locales {
+        let mut locales: HashMap<String, KeyLabels> = BUILT_IN
+            .iter()
+            .map(|(name, table)| {
+                let labels = table.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+                (name.to_string(), KeyLabels(labels))
+            })
+            .collect();
+        for (name, table) in extra {
+            let labels = locales.entry(name.clone()).or_default();
+            labels.0.extend(table.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        Locales(locales)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&KeyLabels> {
+        self.0.get(name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.0.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
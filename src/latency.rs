@@ -0,0 +1,131 @@
+/*
+ * pet-input-server: src/latency.rs
+ *
+ * Diagnostics-only latency probe (`--latency-probe`). Times two stages:
+ * the rdev callback up to the broadcast send, and each WebSocket write.
+ * Recent samples feed the p50/p99 figures in GET /status, and a callback
+ * over `--latency-budget-us` is logged, since it means something blocking
+ * has crept into the hot path.
+ */
+
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Samples kept per stage for the percentiles.
+const WINDOW: usize = 4096;
+/// Minimum time between two over-budget warnings.
+const WARN_EVERY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy)]
+pub enum Stage {
+    /// From entering the rdev callback until the action is on the broadcast channel.
+    Callback,
+    /// One WebSocket write to a client.
+    WsSend,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BUDGET_NS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Default)]
+struct Samples {
+    callback: VecDeque<u64>,
+    ws_send: VecDeque<u64>,
+    over_budget: u64,
+    last_warning: Option<Instant>,
+}
+
+static SAMPLES: Lazy<Mutex<Samples>> = Lazy::new(|| Mutex::new(Samples::default()));
+
+pub fn configure(budget: Duration) {
+    BUDGET_NS.store(budget.as_nanos() as u64, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+    println!("Latency probe enabled (callback budget {}µs).", budget.as_micros());
+}
+
+/// Starts timing a stage; `None` (and no work) while the probe is off.
+pub fn start() -> Option<Instant> {
+    ENABLED.load(Ordering::Relaxed).then(Instant::now)
+}
+
+pub fn record(stage: Stage, started: Option<Instant>) {
+    let Some(started) = started else { return };
+    let ns = started.elapsed().as_nanos() as u64;
+    let mut samples = SAMPLES.lock().unwrap();
+    let window = match stage {
+        Stage::Callback => &mut samples.callback,
+        Stage::WsSend => &mut samples.ws_send,
+    };
+    if window.len() == WINDOW {
+        window.pop_front();
+    }
+    window.push_back(ns);
+
+    let budget = BUDGET_NS.load(Ordering::Relaxed);
+    if matches!(stage, Stage::Callback) && ns > budget {
+        samples.over_budget += 1;
+        if samples.last_warning.is_none_or(|at| at.elapsed() >= WARN_EVERY) {
+            samples.last_warning = Some(Instant::now());
+            eprintln!(
+                "Input callback took {}µs (budget {}µs); something is blocking the hot path.",
+                ns / 1000,
+                budget / 1000
+            );
+        }
+    }
+}
+
+/// Percentiles in microseconds for GET /status, or `null` when the probe is off.
+pub fn to_json() -> serde_json::Value {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return serde_json::Value::Null;
+    }
+    let samples = SAMPLES.lock().unwrap();
+    json!({
+        "budget_us": BUDGET_NS.load(Ordering::Relaxed) as f64 / 1000.0,
+        "callback": stage_json(&samples.callback),
+        "ws_send": stage_json(&samples.ws_send),
+        "callbacks_over_budget": samples.over_budget,
+    })
+}
+
+fn stage_json(window: &VecDeque<u64>) -> serde_json::Value {
+    let mut sorted: Vec<u64> = window.iter().copied().collect();
+    sorted.sort_unstable();
+    let percentile = |p: f64| {
+        let index = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+        sorted.get(index).map(|ns| *ns as f64 / 1000.0)
+    };
+    json!({
+        "samples": sorted.len(),
+        "p50_us": percentile(0.50),
+        "p99_us": percentile(0.99),
+        "max_us": sorted.last().map(|ns| *ns as f64 / 1000.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_are_read_from_the_sorted_window() {
+        let window: VecDeque<u64> = (1..=100).rev().map(|us| us * 1000).collect();
+        let figures = stage_json(&window);
+        assert_eq!(figures["samples"], 100);
+        assert_eq!(figures["p50_us"], 51.0);
+        assert_eq!(figures["p99_us"], 99.0);
+        assert_eq!(figures["max_us"], 100.0);
+    }
+
+    #[test]
+    fn an_empty_stage_has_no_figures() {
+        let figures = stage_json(&VecDeque::new());
+        assert_eq!(figures["samples"], 0);
+        assert!(figures["p50_us"].is_null() && figures["max_us"].is_null());
+    }
+}
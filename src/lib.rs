@@ -0,0 +1,3501 @@
+/*
+ * pet-input-server: src/lib.rs
+ *
+ * The server itself; src/main.rs only calls `run`. Unit tests live next to
+ * the code, and tests/ serves through `run_with` with a scripted input
+ * source and real WebSocket clients.
+ */
+
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use rdev::{EventType, Key};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{header, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Error as WsError;
+use tokio_tungstenite::{accept_hdr_async_with_config, tungstenite::Message};
+use clap::Parser;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{oneshot, watch};
+
+mod backpressure;
+mod chords;
+mod combo;
+mod clientqueue;
+#[cfg(feature = "clipboard")]
+mod clipboard;
+mod config;
+mod cue;
+mod debounce;
+mod delta;
+mod discovery;
+mod display;
+mod fixtures;
+mod flood;
+#[cfg(feature = "grab")]
+mod grab;
+mod http;
+mod instrument;
+mod intensity;
+mod keyheat;
+mod keypad;
+mod kind;
+mod labels;
+mod latency;
+mod longpress;
+mod metrics;
+mod modtap;
+mod mood;
+#[cfg(feature = "monitors")]
+mod monitors;
+mod netsim;
+mod notice;
+mod overload;
+mod pan;
+mod pause;
+mod power;
+mod prefs;
+mod privacy;
+mod qr;
+mod rates;
+mod ratelimit;
+mod rawqueue;
+mod rawstream;
+mod realtime;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(windows)]
+mod namedpipe;
+mod recording;
+mod regions;
+mod reliable;
+mod rename;
+mod rng;
+mod role;
+mod rooms;
+mod schedule;
+mod serializer;
+mod sessions;
+mod settings;
+mod shedding;
+mod shouting;
+mod snapshot;
+mod source;
+mod stats;
+mod steno;
+mod subscriptions;
+mod timesync;
+mod timestamp;
+#[cfg(all(target_os = "linux", feature = "uinput"))]
+mod uinput_mirror;
+#[cfg(feature = "window-bounds")]
+mod window;
+
+use settings::{SettingsPatch, SETTINGS};
+pub use kind::ActionKind;
+pub use source::{EventSource, MockSource, RawInputEvent};
+
+// --- Protocol Definition ---
+// The value structs deny unknown fields so that decoding the untagged `ActionValue`
+// only picks them for an exact match; `{"x":1,"y":2,"w":3}` stays `Json`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Coords {
+    #[serde(serialize_with = "serialize_coord")]
+    x: f64,
+    #[serde(serialize_with = "serialize_coord")]
+    y: f64,
+}
+
+impl Coords {
+    /// Rounds both axes to `decimals` places.
+    fn rounded(&self, decimals: u32) -> Coords {
+        Coords {
+            x: round_to(self.x, decimals),
+            y: round_to(self.y, decimals),
+        }
+    }
+}
+
+fn round_to(value: f64, decimals: u32) -> f64 {
+    let scale = 10f64.powi(decimals as i32);
+    (value * scale).round() / scale
+}
+
+/// Writes whole coordinates as integers (`12` rather than `12.0`).
+fn serialize_coord<S: serde::Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if value.fract() == 0.0 && value.abs() < (1u64 << 53) as f64 {
+        serializer.serialize_i64(*value as i64)
+    } else {
+        serializer.serialize_f64(*value)
+    }
+}
+
+/// Cursor position relative to the screen center (`--polar-coords`).
+/// `angle` is in radians, counter-clockwise from the +x axis (0 = right, π/2 = up).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PolarCoords {
+    #[serde(serialize_with = "serialize_coord")]
+    angle: f64,
+    #[serde(serialize_with = "serialize_coord")]
+    radius: f64,
+}
+
+impl Coords {
+    fn to_polar(&self, (width, height): (u64, u64)) -> PolarCoords {
+        let dx = self.x - width as f64 / 2.0;
+        // Screen y grows downwards; flip it so "up" is a positive angle.
+        let dy = height as f64 / 2.0 - self.y;
+        PolarCoords {
+            angle: dy.atan2(dx),
+            radius: dx.hypot(dy),
+        }
+    }
+}
+
+/// Largest `--coord-precision` / `?precision=` accepted.
+const MAX_COORD_PRECISION: u32 = 6;
+
+/// Events the broadcast channel holds before the slowest client starts losing them.
+const BROADCAST_CAPACITY: usize = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DeltaCoords {
+    dx: i32,
+    dy: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NoteValue {
+    note: String,
+    velocity: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum ActionValue {
+    String(String),
+    Coords(Coords),
+    Delta(DeltaCoords),
+    Note(NoteValue),
+    Polar(PolarCoords),
+    Number(f64),
+    /// Structured payloads that don't warrant their own type.
+    Json(serde_json::Value),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Action {
+    kind: ActionKind,
+    value: ActionValue,
+    /// Which machine the action came from (`--source-name`), kept as-is when relayed.
+    #[serde(default)]
+    source: Option<String>,
+    /// With `--shifted-symbols`, what a keyboard event types given the Shift state ("!" for Shift+Num1).
+    #[serde(default)]
+    shifted: Option<String>,
+    /// With `?locale=` or the config's `locale`, how the key is written for display ("Strg").
+    #[serde(default)]
+    label: Option<String>,
+    /// With `--timestamps`, when the input happened (ms since the epoch, OS time where trustworthy).
+    #[serde(default)]
+    timestamp_ms: Option<f64>,
+    /// With `--monotonic-ns`, when the hook saw the input, in ns since the server started.
+    /// Monotonic, so only comparable with other `mono_ns` values from the same run.
+    #[serde(default)]
+    mono_ns: Option<u64>,
+    /// With `--os-timestamps`, rdev's own event time as given, ms since the epoch (even if implausible).
+    #[serde(default)]
+    os_timestamp_ms: Option<f64>,
+    /// With `--os-timestamps`, when our hook callback received the event, ms since the epoch.
+    #[serde(default)]
+    hook_timestamp_ms: Option<f64>,
+    /// Per-connection sequence number, only for clients in `?reliable=1` mode.
+    #[serde(default)]
+    seq: Option<u64>,
+    /// With `--grab`, set on events that were swallowed before the OS saw them.
+    #[serde(default)]
+    consumed: bool,
+}
+
+/// How an action is laid out on the wire, under `kind`: its own name, or a client's rename.
+#[derive(Serialize)]
+pub struct ActionView<'a> {
+    kind: &'a str,
+    value: &'a ActionValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shifted: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mono_ns: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    os_timestamp_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hook_timestamp_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    consumed: bool,
+}
+
+/// `?envelope=1` layout: what happened under `event`, when and where it was seen under `meta`.
+///
+/// ```text
+/// {"event": {"kind", "value", "shifted"?, "label"?, "consumed"?},
+///  "meta":  {"source"?, "timestamp_ms"?, "mono_ns"?, "os_timestamp_ms"?, "hook_timestamp_ms"?, "seq"?}}
+/// ```
+///
+/// Both objects are always present; fields marked `?` are left out when unset, as in the flat form.
+#[derive(Serialize)]
+pub struct EnvelopeView<'a> {
+    event: EventView<'a>,
+    meta: MetaView<'a>,
+}
+
+#[derive(Serialize)]
+struct EventView<'a> {
+    kind: &'a str,
+    value: &'a ActionValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shifted: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<&'a str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    consumed: bool,
+}
+
+#[derive(Serialize)]
+struct MetaView<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mono_ns: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    os_timestamp_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hook_timestamp_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+}
+
+impl Serialize for Action {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.view(self.kind.as_str()).serialize(serializer)
+    }
+}
+
+/// `key_filter` in the runtime settings.
+fn key_allowed(name: &str) -> bool {
+    SETTINGS.current().key_filter.as_ref().is_none_or(|filter| filter.is_match(name))
+}
+
+/// `only_button` in the runtime settings.
+fn button_allowed(name: &str) -> bool {
+    SETTINGS.current().only_button.as_deref().is_none_or(|only| only == name)
+}
+
+/// Set by `--source-name`.
+static SOURCE_NAME: once_cell::sync::OnceCell<String> = once_cell::sync::OnceCell::new();
+
+impl Action {
+    /// An action with only its kind and value; the optional fields start unset and are
+    /// filled in by whoever knows them (timestamps, labels, `seq`, ...).
+    pub fn new(kind: ActionKind, value: ActionValue) -> Action {
+        Action {
+            kind,
+            value,
+            source: None,
+            shifted: None,
+            label: None,
+            timestamp_ms: None,
+            mono_ns: None,
+            os_timestamp_ms: None,
+            hook_timestamp_ms: None,
+            seq: None,
+            consumed: false,
+        }
+    }
+
+    /// Rounds coordinate values to `decimals` places; other values are untouched.
+    fn round_coords(&mut self, decimals: u32) {
+        match &self.value {
+            ActionValue::Coords(coords) => self.value = ActionValue::Coords(coords.rounded(decimals)),
+            ActionValue::Polar(polar) => {
+                self.value = ActionValue::Polar(PolarCoords {
+                    // The angle needs a few more places than pixels to stay accurate far from the center.
+                    angle: round_to(polar.angle, decimals + 3),
+                    radius: round_to(polar.radius, decimals),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    /// Rewrites a MouseMove into polar form; left as-is while the display size is unknown.
+    fn make_polar(&mut self) {
+        if self.kind != ActionKind::MouseMove {
+            return;
+        }
+        if let (ActionValue::Coords(coords), Some(display)) = (&self.value, display::cached()) {
+            self.value = ActionValue::Polar(coords.to_polar(display));
+        }
+    }
+
+    /// The action for serializing under the wire name `kind`.
+    pub fn view<'a>(&'a self, kind: &'a str) -> ActionView<'a> {
+        ActionView {
+            kind,
+            value: &self.value,
+            source: self.source.as_deref(),
+            shifted: self.shifted.as_deref(),
+            label: self.label.as_deref(),
+            timestamp_ms: self.timestamp_ms,
+            mono_ns: self.mono_ns,
+            os_timestamp_ms: self.os_timestamp_ms,
+            hook_timestamp_ms: self.hook_timestamp_ms,
+            seq: self.seq,
+            consumed: self.consumed,
+        }
+    }
+
+    /// The enveloped form of `view`.
+    pub fn envelope<'a>(&'a self, kind: &'a str) -> EnvelopeView<'a> {
+        EnvelopeView {
+            event: EventView {
+                kind,
+                value: &self.value,
+                shifted: self.shifted.as_deref(),
+                label: self.label.as_deref(),
+                consumed: self.consumed,
+            },
+            meta: MetaView {
+                source: self.source.as_deref(),
+                timestamp_ms: self.timestamp_ms,
+                mono_ns: self.mono_ns,
+                os_timestamp_ms: self.os_timestamp_ms,
+                hook_timestamp_ms: self.hook_timestamp_ms,
+                seq: self.seq,
+            },
+        }
+    }
+
+    /// Tags the action with this server's `--source-name` unless an upstream already did.
+    fn stamp_source(&mut self) {
+        if self.source.is_none() {
+            self.source = SOURCE_NAME.get().cloned();
+        }
+    }
+}
+
+/// Versions a client may ask for with `?protocol=<n>`.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[u32] = &[1];
+
+/// Prefix of the WebSocket subprotocols: `bongocat.v1`, or `bongocat.v1.msgpack` to pick a format too.
+const SUBPROTOCOL_PREFIX: &str = "bongocat.v";
+
+/// Every subprotocol the server accepts, for error messages.
+fn subprotocol_names() -> Vec<String> {
+    SUPPORTED_PROTOCOL_VERSIONS
+        .iter()
+        .flat_map(|version| {
+            std::iter::once(format!("{}{}", SUBPROTOCOL_PREFIX, version))
+                .chain(serializer::FORMATS.iter().map(move |format| format!("{}{}.{}", SUBPROTOCOL_PREFIX, version, format)))
+        })
+        .collect()
+}
+
+/// The format a supported subprotocol selects (`None` for the default), or `Err` if unsupported.
+fn parse_subprotocol(name: &str) -> Result<Option<&'static str>, ()> {
+    let rest = name.strip_prefix(SUBPROTOCOL_PREFIX).ok_or(())?;
+    let (version, format) = match rest.split_once('.') {
+        Some((version, format)) => (version, Some(format)),
+        None => (rest, None),
+    };
+    if !version.parse::<u32>().is_ok_and(|v| SUPPORTED_PROTOCOL_VERSIONS.contains(&v)) {
+        return Err(());
+    }
+    match format {
+        None => Ok(None),
+        Some(format) => serializer::FORMATS.iter().find(|known| **known == format).map(|known| Some(*known)).ok_or(()),
+    }
+}
+
+
+/// Control messages a client may send, e.g. `{"claim_active":true}`.
+#[derive(Deserialize, Debug)]
+struct ClientMessage {
+    /// `true` makes this client the only one in its room that receives events; `false` gives that up.
+    claim_active: Option<bool>,
+    /// Events per second this client can handle; mouse moves are dropped first. `0` removes the cap.
+    max_rate: Option<f64>,
+    /// `true` switches this client to the low-rate preview feed (also `?preview=1`); `false` back.
+    preview: Option<bool>,
+    /// `"save_prefs"` stores this connection's options under its `?name=`; `"timesync"` starts
+    /// a clock offset estimate, answered with `"timesync_echo"` messages; `"cue"` broadcasts
+    /// an animation cue.
+    cmd: Option<String>,
+    /// The cue to broadcast, and what to send along with it.
+    name: Option<String>,
+    data: Option<serde_json::Value>,
+    /// The probe a `timesync_echo` answers, and the client's clock when it did.
+    probe: Option<u32>,
+    client_ms: Option<f64>,
+    /// Reliable mode: every `seq` up to this one has arrived.
+    ack: Option<u64>,
+    /// Reliable mode: send the buffered events from this `seq` on again.
+    resend_from: Option<u64>,
+}
+
+/// Why the server closed a connection, sent as an application close code (4000-4999).
+#[derive(Clone, Copy, Debug)]
+enum CloseReason {
+    Shutdown,
+    UnsupportedProtocol,
+    MessageTooLarge,
+    IdleTimeout,
+    QueueStalled,
+}
+
+impl CloseReason {
+    const ALL: [CloseReason; 5] = [
+        CloseReason::Shutdown,
+        CloseReason::UnsupportedProtocol,
+        CloseReason::MessageTooLarge,
+        CloseReason::IdleTimeout,
+        CloseReason::QueueStalled,
+    ];
+
+    fn code(self) -> u16 {
+        match self {
+            CloseReason::Shutdown => 4000,
+            CloseReason::UnsupportedProtocol => 4001,
+            CloseReason::MessageTooLarge => 4002,
+            CloseReason::IdleTimeout => 4003,
+            CloseReason::QueueStalled => 4004,
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            CloseReason::Shutdown => "server shutting down",
+            CloseReason::UnsupportedProtocol => "unsupported protocol version",
+            CloseReason::MessageTooLarge => "message too large",
+            CloseReason::IdleTimeout => "client idle limit reached",
+            CloseReason::QueueStalled => "client queue full",
+        }
+    }
+
+    /// Machine-readable name, for the `Disconnect` message.
+    fn slug(self) -> &'static str {
+        match self {
+            CloseReason::Shutdown => "shutdown",
+            CloseReason::UnsupportedProtocol => "unsupported_protocol",
+            CloseReason::MessageTooLarge => "message_too_large",
+            CloseReason::IdleTimeout => "idle_timeout",
+            CloseReason::QueueStalled => "queue_stalled",
+        }
+    }
+
+    /// How long the client should wait before reconnecting; `None` means retrying
+    /// unchanged will fail again. A restart gets a long, jittered wait so the
+    /// clients don't all come back in the same instant; a stalled queue is usually
+    /// a passing hiccup.
+    fn retry_after_ms(self) -> Option<u64> {
+        match self {
+            CloseReason::Shutdown => Some(5000 + (rng::fraction() * 5000.0) as u64),
+            CloseReason::UnsupportedProtocol => None,
+            CloseReason::MessageTooLarge | CloseReason::IdleTimeout => Some(1000),
+            CloseReason::QueueStalled => Some(250),
+        }
+    }
+
+    /// `{"kind":"Disconnect",...}`, sent just before the close frame.
+    fn hint(self) -> Action {
+        Action::new(
+            ActionKind::Disconnect,
+            ActionValue::Json(serde_json::json!({
+                "reason": self.slug(),
+                "code": self.code(),
+                "message": self.reason(),
+                "retry_after_ms": self.retry_after_ms(),
+            })),
+        )
+    }
+
+    fn frame(self) -> Message {
+        Message::Close(Some(CloseFrame {
+            code: CloseCode::from(self.code()),
+            reason: self.reason().into(),
+        }))
+    }
+}
+
+/// The close codes the server sends, for `/capabilities`.
+fn close_codes_json() -> serde_json::Value {
+    CloseReason::ALL
+        .iter()
+        .map(|reason| {
+            serde_json::json!({
+                "code": reason.code(),
+                "reason": reason.slug(),
+                "message": reason.reason(),
+                "retry": reason.retry_after_ms().is_some(),
+            })
+        })
+        .collect()
+}
+
+/// Closes the connection for `reason`, telling the client whether and when to reconnect first.
+/// The hint goes out whatever the client's `?kinds=`, since it is about the connection itself.
+async fn close_with(ws_sender: &mut WsSender, reason: CloseReason, wire: &Wire<'_>) {
+    let _ = send_action(ws_sender, reason.hint(), wire).await;
+    let _ = ws_sender.send(reason.frame()).await;
+}
+
+/// Kinds a client only receives when it lists them in `?kinds=`.
+pub const OPT_IN_KINDS: &[ActionKind] = &[ActionKind::Rates];
+
+// --- Mouse Move Throttling ---
+use once_cell::sync::Lazy;
+/// The throttle's clock; `LAST_MOUSE_MOVE` holds milliseconds since this instant
+/// (at least 1, so 0 means no move has been sent yet).
+static THROTTLE_EPOCH: Lazy<Instant> = Lazy::new(Instant::now);
+static LAST_MOUSE_MOVE: AtomicU64 = AtomicU64::new(0);
+
+/// Claims the next MouseMove slot without taking a lock; if callbacks race, one of them wins.
+fn mouse_move_due(throttle: Duration) -> bool {
+    let now = (THROTTLE_EPOCH.elapsed().as_millis() as u64).max(1);
+    let last = LAST_MOUSE_MOVE.load(Ordering::Relaxed);
+    if last != 0 && now.saturating_sub(last) < throttle.as_millis() as u64 {
+        metrics::throttled();
+        return false;
+    }
+    // Losing the race means another thread just sent a move; that's not the throttle's doing.
+    LAST_MOUSE_MOVE
+        .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+}
+/// Last cursor position seen (throttled or not), replayed to clients when they connect.
+static LAST_CURSOR: Lazy<Mutex<Option<(f64, f64)>>> = Lazy::new(|| Mutex::new(None));
+
+/// Startup values shared with every connection task.
+pub struct ServerState {
+    /// Shared secret required by the HTTP endpoints.
+    pub token: Option<String>,
+    pub bind_addr: String,
+    /// The port actually listened on (the OS's pick with `--port 0`).
+    pub port: u16,
+    /// `--qr-host`: the host put in the QR code instead of the detected LAN address.
+    pub qr_host: Option<String>,
+    pub rooms: rooms::Rooms,
+    pub renames: rename::RenameProfiles,
+    pub locales: labels::Locales,
+    /// The config's `locale`: used when a client doesn't pass `?locale=`.
+    pub default_locale: Option<String>,
+    /// Set while `--record` is active.
+    pub recording: Option<Arc<recording::Recorder>>,
+    /// Whether connections get delta-encoded mouse moves unless they ask otherwise.
+    pub mouse_delta: bool,
+    pub mouse_keyframe_every: u32,
+    /// Default decimal places for coordinates sent to clients.
+    pub coord_precision: u32,
+    /// Whether mouse moves are sent in polar form.
+    pub polar_coords: bool,
+    /// Per-message size limit, in both directions.
+    pub max_message_bytes: usize,
+    /// Saved per-client-name connection options.
+    pub prefs: prefs::PrefsStore,
+    /// Options remembered per `?client_id=`.
+    pub sessions: sessions::Sessions,
+    /// Set by `--simulate-network` (debug builds only).
+    pub simulate_network: Option<netsim::SimulatedNetwork>,
+    /// Per-client queue depths that switch the Backpressure notice on and off.
+    pub backpressure_high: usize,
+    pub backpressure_low: usize,
+    /// `--queue-policy`: used when a client doesn't pass `?queue=`.
+    pub queue_policy: clientqueue::QueuePolicy,
+    pub queue_block_timeout: Duration,
+    /// `--envelope`: used when a client doesn't pass `?envelope=`.
+    pub envelope: bool,
+    /// `--snapshot-mode`: used when a client doesn't pass `?snapshot=`.
+    pub snapshot_mode: bool,
+    pub snapshot_interval: Duration,
+    /// `--format`: used when a client doesn't pass `?format=`.
+    pub default_format: String,
+    /// Set by `--client-idle-timeout-secs`.
+    pub client_idle_timeout: Option<Duration>,
+    /// The config's `[cues]` section.
+    pub cues: config::CueConfig,
+    /// `--handshake-timeout-secs`: how long a new connection has to finish its request or handshake.
+    pub handshake_timeout: Duration,
+    /// `--max-pending-handshakes`.
+    pub max_pending_handshakes: usize,
+    /// Connections accepted but not yet past the WebSocket handshake.
+    pub pending_handshakes: AtomicUsize,
+    /// Number of connected WebSocket clients.
+    pub clients: AtomicUsize,
+    next_client_id: AtomicU64,
+    /// When the last client left (or the server started, if none has connected yet).
+    idle_since: Mutex<Instant>,
+    /// Flips to `true` once graceful shutdown begins.
+    pub shutdown: watch::Sender<bool>,
+    /// Set when shutdown was caused by a failure, so the process exits non-zero.
+    pub failed: AtomicBool,
+}
+
+/// Keeps `ServerState::clients` accurate for the lifetime of one connection.
+struct ClientGuard<'a>(&'a ServerState);
+
+/// Holds one of the `--max-pending-handshakes` slots until the handshake ends either way.
+struct HandshakeGuard<'a>(&'a ServerState);
+
+impl ServerState {
+    /// Counts the new connection and gives it a server-unique id.
+    fn client_connected(&self) -> (ClientGuard<'_>, u64) {
+        let concurrent = self.clients.fetch_add(1, Ordering::SeqCst) + 1;
+        metrics::connected(concurrent);
+        (ClientGuard(self), self.next_client_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Takes a pre-handshake slot, or `None` (counted as a rejection) when they're all in use.
+    fn handshake_started(&self) -> Option<HandshakeGuard<'_>> {
+        let taken = self
+            .pending_handshakes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < self.max_pending_handshakes).then_some(n + 1));
+        match taken {
+            Ok(_) => Some(HandshakeGuard(self)),
+            Err(_) => {
+                metrics::handshake_rejected();
+                None
+            }
+        }
+    }
+
+    /// The URL a LAN client scans from the QR code: the default room, with its token if it has one.
+    pub fn connection_url(&self) -> String {
+        let token = self.rooms.get(rooms::DEFAULT_ROOM).and_then(rooms::Room::token);
+        qr::url(self.qr_host.as_deref(), self.port, token)
+    }
+
+    /// Moves the idle clock forward past time the machine spent asleep.
+    fn skip_idle(&self, slept: Duration) {
+        *self.idle_since.lock().unwrap() += slept;
+    }
+
+    /// Starts graceful shutdown; safe to call more than once.
+    pub fn request_shutdown(&self, reason: &str) {
+        if !*self.shutdown.borrow() {
+            println!("Shutting down: {}", reason);
+            SHUTTING_DOWN.store(true, Ordering::SeqCst);
+            let _ = self.shutdown.send(true);
+        }
+    }
+}
+
+impl Drop for HandshakeGuard<'_> {
+    fn drop(&mut self) {
+        self.0.pending_handshakes.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for ClientGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.clients.fetch_sub(1, Ordering::SeqCst) == 1 {
+            *self.0.idle_since.lock().unwrap() = Instant::now();
+        }
+    }
+}
+
+// --- Command Line Argument Definition ---
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Summarize a --record file: events by kind, typing speed, top keys, idle gaps, mouse distance
+    Analyze {
+        file: PathBuf,
+        /// Width of the KPM/WPM/CPS timeline buckets, in seconds
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        bucket_secs: u64,
+        /// Also write the full report to this JSON file
+        #[arg(long)]
+        json: Option<PathBuf>,
+        /// Also write the timeline to this CSV file
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+    /// Write one sample payload per action kind, protocol version and format, plus a manifest
+    Fixtures {
+        /// Directory to write into (created if missing)
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Rewrite a --record file as JSON lines or binary; by default, the format it isn't
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        /// Format to write
+        #[arg(long, value_enum)]
+        to: Option<recording::RecordFormat>,
+    },
+}
+
+/// The command line; build one with `Cli::parse_from` to call `run_with`.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The port to listen on; 0 lets the OS pick one (see the discovery file)
+    #[arg(short, long, default_value_t = 8080)]
+    port: u16,
+
+    /// Where to write the discovery file [default: $XDG_RUNTIME_DIR or %LOCALAPPDATA%/bongocat-server.json]
+    #[arg(long)]
+    discovery_file: Option<PathBuf>,
+
+    /// Don't write a discovery file
+    #[arg(long, conflicts_with = "discovery_file")]
+    no_discovery_file: bool,
+
+    /// Print the connection URL (LAN address, port, room token) as a QR code at startup
+    #[arg(long)]
+    qr: bool,
+
+    /// Host or IP to put in the QR code instead of the detected LAN address
+    #[arg(long)]
+    qr_host: Option<String>,
+
+    /// Wire format for clients that don't pass ?format= (json, msgpack or plaintext)
+    #[arg(long, default_value = "json", value_parser = clap::builder::PossibleValuesParser::new(serializer::FORMATS))]
+    format: String,
+
+    /// Path to an optional TOML config file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Minimum milliseconds between MouseMove events (default 16, ~60fps)
+    #[arg(long)]
+    mouse_throttle_ms: Option<u64>,
+
+    /// Drop a key press this many ms after the same key's release, as switch chatter (overrides `[debounce] ms`)
+    #[arg(long)]
+    debounce_ms: Option<u64>,
+
+    /// Shared secret required by the HTTP endpoints (e.g. /config)
+    #[arg(long)]
+    token: Option<String>,
+
+    /// Send mouse moves as small dx/dy deltas between absolute keyframes
+    /// (clients can also opt in or out with `?mouse=delta` / `?mouse=absolute`)
+    #[arg(long)]
+    mouse_delta: bool,
+
+    /// In delta mode, send an absolute keyframe after this many deltas
+    #[arg(long, default_value_t = 60)]
+    mouse_keyframe_every: u32,
+
+    /// Exit after this many seconds with no connected clients (default: run forever)
+    #[arg(long)]
+    shutdown_after_idle_secs: Option<u64>,
+
+    /// On graceful shutdown, write the session stats (totals, rate histogram, drops, activity) to this JSON file
+    #[arg(long, value_name = "PATH")]
+    stats_out: Option<PathBuf>,
+
+    /// Broadcast a Status event (uptime, wall-clock time) this often; off by default
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    status_interval_secs: Option<u64>,
+
+    /// Close (code 4003) a client that has sent nothing, not even a pong, for this long; off by default
+    #[arg(long)]
+    client_idle_timeout_secs: Option<u64>,
+
+    /// Drop a connection that hasn't sent its HTTP request or finished the WebSocket handshake within this many seconds
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    handshake_timeout_secs: u64,
+
+    /// Connections allowed in that pre-handshake state at once; further ones are closed straight away
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u64).range(1..))]
+    max_pending_handshakes: u64,
+
+    /// Queued events per client at which it is sent {"kind":"Backpressure","value":"high"}
+    #[arg(long, default_value_t = 256)]
+    backpressure_high: usize,
+
+    /// Queued events per client at which it is sent {"kind":"Backpressure","value":"normal"} again
+    #[arg(long, default_value_t = 32)]
+    backpressure_low: usize,
+
+    /// What a client's full queue does with new input (per client: `?queue=`)
+    #[arg(long, value_enum, default_value_t = clientqueue::QueuePolicy::DropOldest)]
+    queue_policy: clientqueue::QueuePolicy,
+
+    /// With `block`, how long to wait for room before closing the client's connection
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    queue_block_timeout_ms: u64,
+
+    /// Send actions as {"event":{...},"meta":{...}} instead of flat (per client: `?envelope=0|1`)
+    #[arg(long)]
+    envelope: bool,
+
+    /// Send clients one Snapshot of cursor, held keys and activity per tick instead of events (`?snapshot=0|1`)
+    #[arg(long)]
+    snapshot_mode: bool,
+
+    /// Milliseconds between snapshots for clients in snapshot mode
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    snapshot_interval: u64,
+
+    /// Emit ControlLeft/ControlRight etc. instead of collapsing modifiers to "Control"
+    #[arg(long)]
+    preserve_side: bool,
+
+    /// Also send an empty ButtonChord when a mouse button chord is released
+    #[arg(long)]
+    button_chord_exit: bool,
+
+    /// Cursor travel (px) with the middle button held before MousePan events start
+    #[arg(long, default_value_t = 8.0)]
+    pan_threshold_px: f64,
+
+    /// Chording mode: keys pressed within this many ms of a chord's first key are sent as one Chord once all are released [default: 30]
+    #[arg(long, num_args = 0..=1, default_missing_value = "30", value_parser = clap::value_parser!(u64).range(1..))]
+    chord_window_ms: Option<u64>,
+
+    /// With --chord-window-ms, send only Chord events, not the individual key presses and releases
+    #[arg(long, requires = "chord_window_ms")]
+    chord_only: bool,
+
+    /// Emit KeyLongPress once a key has been held this long (ms); 0 disables it
+    #[arg(long, default_value_t = 1000)]
+    long_press_ms: u64,
+
+    /// Add a "shifted" field to keyboard events with the US-layout symbol when Shift is held
+    #[arg(long)]
+    shifted_symbols: bool,
+
+    /// Keyboard layout whose Shift symbols --shifted-symbols reports
+    #[arg(long, value_enum, default_value = "us", value_name = "LAYOUT")]
+    shifted_layout: ShiftedLayout,
+
+    /// Only broadcast MouseMove while this modifier is held, for pets that follow the cursor on demand
+    #[arg(long, value_enum, value_name = "NAME")]
+    move_requires_modifier: Option<Modifier>,
+
+    /// Emit ModifierTap when a modifier is pressed and released on its own within this many ms
+    #[arg(long, value_name = "MS")]
+    modifier_tap_ms: Option<u64>,
+
+    /// Keys that count for --modifier-tap-ms (comma-separated mapped names)
+    #[arg(long, value_delimiter = ',', default_value = modtap::DEFAULT_KEYS)]
+    modifier_tap_keys: Vec<String>,
+
+    /// Add `timestamp_ms` (when the OS saw the event, ms since the epoch) to input events
+    #[arg(long)]
+    timestamps: bool,
+
+    /// Add `mono_ns` (ns since server start on a monotonic clock, only comparable within one run) to input events
+    #[arg(long)]
+    monotonic_ns: bool,
+
+    /// Add `os_timestamp_ms` (rdev's event time, unchecked) and `hook_timestamp_ms` (when our callback ran) to input events
+    #[arg(long)]
+    os_timestamps: bool,
+
+    /// Emit `ShoutingDetected` after this many uppercase letters in a row (0 disables)
+    #[arg(long, default_value_t = 8)]
+    shouting_threshold: u32,
+
+    /// Emit Numpad0..Numpad9, NumpadEnter etc. instead of folding keypad keys into the main keys
+    #[arg(long)]
+    numpad_distinct: bool,
+
+    /// Record every broadcast event to this file (JSON lines unless --record-format binary)
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// How --record files are written; `binary` is zstd-compressed MessagePack, far smaller
+    #[arg(long, value_enum, default_value_t = recording::RecordFormat::Jsonl)]
+    record_format: recording::RecordFormat,
+
+    /// Only write these kinds to the --record file (comma-separated), e.g. MouseMove,MousePress,MouseRelease
+    #[arg(long, value_delimiter = ',')]
+    record_kinds: Option<Vec<String>>,
+
+    /// Start a new --record file once the current one reaches a size (100MB) or age (1h); old ones become <path>.1, .2, ...
+    #[arg(long, value_parser = recording::parse_rotate)]
+    record_rotate: Option<recording::RotateAt>,
+
+    /// Gzip rotated recording files in the background
+    #[arg(long)]
+    record_gzip: bool,
+
+    /// Keep only this many rotated recording files
+    #[arg(long)]
+    record_keep: Option<usize>,
+
+    /// Validate the config file and command line, report any problems, and exit
+    #[arg(long)]
+    check_config: bool,
+
+    /// Print the detected display size, virtual-desktop bounds and monitors, and exit
+    #[arg(long)]
+    probe: bool,
+
+    /// Replay captured input into a virtual uinput keyboard/mouse (Linux, `uinput` feature)
+    #[arg(long)]
+    mirror_uinput: bool,
+
+    /// What to do once the input listener has died and cannot be restarted
+    #[arg(long, value_enum, default_value_t = InputFailurePolicy::Warn)]
+    on_input_failure: InputFailurePolicy,
+
+    /// Also publish every action as JSON to this MQTT broker, e.g. mqtt://localhost:1883 (`mqtt` feature)
+    #[arg(long)]
+    mqtt_broker: Option<String>,
+
+    /// Topic used with --mqtt-broker
+    #[arg(long, default_value = "pet-input-server/events")]
+    mqtt_topic: String,
+
+    /// Also write every action as a JSON line to clients of the named pipe \\.\pipe\<NAME> (Windows only)
+    #[arg(long, value_name = "NAME")]
+    named_pipe: Option<String>,
+
+    /// Broadcast `ClipboardChange` (without the contents) when the clipboard text changes (`clipboard` feature)
+    #[arg(long)]
+    watch_clipboard: bool,
+
+    /// Key combo that pauses and resumes broadcasting input; "none" disables it
+    #[arg(long, default_value = "Control+Alt+Shift+KeyP")]
+    pause_hotkey: String,
+
+    /// Capture input with rdev's grab API and swallow the `[grab] consume` combos (`grab` feature; needs --i-understand-grab)
+    #[arg(long, requires = "i_understand_grab", conflicts_with = "replay")]
+    grab: bool,
+
+    /// Confirms --grab: a wrong `[grab]` list can swallow keys you need (the `[grab] escape` combo always works)
+    #[arg(long)]
+    i_understand_grab: bool,
+
+    /// Broadcast `WindowBounds` when the focused window moves or resizes (`window-bounds` feature; X11 and Windows)
+    #[arg(long)]
+    track_window_bounds: bool,
+
+    /// Enable the overload breaker: trips when mouse callbacks average more than this many ms apart
+    #[arg(long)]
+    overload_gap_ms: Option<u64>,
+
+    /// Mouse-move throttle (ms) used while the overload breaker is tripped
+    #[arg(long, default_value_t = 100)]
+    overload_throttle_ms: u64,
+
+    /// Shed mouse moves while the slowest client is this % of the broadcast buffer behind, sparing clicks and keys
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    shed_at_percent: Option<u8>,
+
+    /// Broadcast an `InputFlood` alarm when raw input exceeds this many events per second (0 disables)
+    #[arg(long, default_value_t = 2000)]
+    flood_ceiling: u64,
+
+    /// Events per second the rate must fall below before the flood alarm can fire again
+    #[arg(long, default_value_t = 1000)]
+    flood_rearm_rate: u64,
+
+    /// Multiply the mouse-move throttle by this while an input flood lasts (1 leaves it alone)
+    #[arg(long, default_value_t = 1)]
+    flood_throttle_multiplier: u32,
+
+    /// Replay the input from a --record file instead of capturing live input
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Tag every emitted action with `"source": <name>` (upstream tags are preserved)
+    #[arg(long)]
+    source_name: Option<String>,
+
+    /// Only broadcast keyboard events whose key name matches this regex, e.g. '^F[0-9]+$' (`key_filter` in /config)
+    #[arg(long)]
+    key_filter: Option<String>,
+
+    /// Only broadcast mouse button events (and chords and pans) for this button (`only_button` in /config)
+    #[arg(long, value_name = "NAME", value_parser = ["Mouse1", "Mouse2", "Mouse3"])]
+    only_button: Option<String>,
+
+    /// Largest WebSocket message sent or accepted; bigger actions are replaced by an `Oversized` notice
+    #[arg(long, default_value_t = 64 * 1024, value_parser = clap::value_parser!(u64).range(1024..))]
+    max_message_bytes: u64,
+
+    /// Display size to assume when it can't be detected (Wayland, headless), e.g. 2560x1440
+    #[arg(long, value_parser = display::parse_size)]
+    screen_size: Option<(u64, u64)>,
+
+    /// JSON file that keeps the options clients save with {"cmd":"save_prefs"} across restarts
+    #[arg(long)]
+    prefs_file: Option<PathBuf>,
+
+    /// How long options are remembered for a client that connected with ?client_id= after it leaves (0 disables)
+    #[arg(long, default_value_t = 900)]
+    client_session_ttl_secs: u64,
+
+    /// TESTING ONLY (debug builds): delay every outgoing frame by DELAY_MS and drop LOSS_PCT percent, e.g. 150,5
+    #[arg(long, value_name = "DELAY_MS,LOSS_PCT", value_parser = netsim::parse)]
+    simulate_network: Option<netsim::SimulatedNetwork>,
+
+    /// Time the input pipeline and report p50/p99 stage latencies in GET /status
+    #[arg(long)]
+    latency_probe: bool,
+
+    /// With --latency-probe, warn when one input callback takes longer than this (µs)
+    #[arg(long, default_value_t = 100)]
+    latency_budget_us: u64,
+
+    /// Send mouse moves as {"angle": radians, "radius": px} from the screen center
+    #[arg(long)]
+    polar_coords: bool,
+
+    /// Privacy for public streams: snap cursor positions to the centre of N-pixel grid cells
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "privacy_jitter_px")]
+    privacy_grid_px: Option<u64>,
+
+    /// Raise the input listener and translation threads' priority (falls back with a warning if the OS refuses)
+    #[arg(long)]
+    realtime_input: bool,
+
+    /// Pin the input listener and translation threads to this CPU
+    #[arg(long, value_name = "N")]
+    input_cpu: Option<usize>,
+
+    /// Allow `?stream=raw`: unmapped rdev events for debugging, bypassing privacy and filters (needs a token)
+    #[arg(long)]
+    debug_stream: bool,
+
+    /// Privacy for public streams: move cursor positions by up to N pixels at random
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    privacy_jitter_px: Option<u64>,
+
+    /// Decimal places sent for mouse coordinates (clients may override with ?precision=N)
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(0..=MAX_COORD_PRECISION as i64))]
+    coord_precision: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum InputFailurePolicy {
+    /// Keep serving clients and broadcast an `InputFailure` warning
+    Warn,
+    /// Shut down and exit with status 1 so a supervisor (e.g. systemd) restarts us
+    Exit,
+}
+
+/// How many times a failed input listener is restarted before giving up.
+const LISTENER_RESTARTS: u32 = 3;
+
+/// Set once graceful shutdown starts. rdev's thread can't be stopped and keeps
+/// calling back until the process exits; from here on its events are ignored, so
+/// derived state (counters, long presses, moods) stops changing while it is torn down.
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+pub fn shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
+/// The "Hot Path" callback, run by the OS hook. It only queues the event for the
+/// translation thread; everything else happens in `translate_event`.
+fn event_callback(event: source::RawInputEvent) {
+    if shutting_down() {
+        return;
+    }
+    #[cfg(windows)]
+    let event = {
+        let mut event = event;
+        if NUMPAD_DISTINCT.load(Ordering::Relaxed) {
+            keypad::restore(&mut event);
+        }
+        event
+    };
+    rawqueue::push(event, source::CONSUMING.load(Ordering::Relaxed));
+}
+
+/// Turns one queued raw event into Actions. Runs on the translation thread.
+fn translate_event(queued: rawqueue::Queued, broadcast_tx: &broadcast::Sender<Action>) {
+    // Events queued just before shutdown began are dropped too.
+    if shutting_down() {
+        return;
+    }
+    let rawqueue::Queued { event, consumed, at } = queued;
+    #[cfg(all(target_os = "linux", feature = "uinput"))]
+    if uinput_mirror::is_echo(&event.event_type) {
+        return;
+    }
+    rawstream::publish(&event);
+
+    flood::count();
+    let occurred = timestamp::event_time(event.time);
+    let os_time = timestamp::epoch_ms(event.time);
+    let event = privacy::apply(event.event_type);
+    match event {
+        EventType::KeyPress(key) => track_modifier(key, true),
+        EventType::KeyRelease(key) => track_modifier(key, false),
+        _ => {}
+    }
+    if pause::on_event(&event, broadcast_tx) || pause::paused() {
+        return;
+    }
+    if let (true, EventType::KeyPress(key) | EventType::KeyRelease(key)) = (debounce::enabled(), event) {
+        let pressed = matches!(event, EventType::KeyPress(_));
+        if map_key(key).is_some_and(|name| debounce::drops(&name, pressed, at)) {
+            return;
+        }
+    }
+    // Nobody is listening for keys: don't even map them.
+    if matches!(event, EventType::KeyPress(_) | EventType::KeyRelease(_)) && !subscriptions::keyboard_wanted() {
+        return;
+    }
+
+    let probe = latency::start();
+    // Derived events are the first thing to go when the breaker trips.
+    let derived = !overload::tripped();
+    let action = match event {
+        EventType::MouseMove { x, y } => {
+            *LAST_CURSOR.lock().unwrap() = Some((x, y));
+            overload::on_mouse_move();
+            if derived {
+                regions::on_mouse_move(x, y, broadcast_tx);
+                mood::on_mouse_move(x);
+            }
+            if move_unlocked() && mouse_move_due(flood::throttle(overload::throttle(SETTINGS.mouse_throttle()))) {
+                Some(Action::new(ActionKind::MouseMove, ActionValue::Coords(Coords { x, y })))
+            } else {
+                None
+            }
+        }
+        EventType::ButtonPress(button) => {
+            modtap::interrupt();
+            if derived {
+                intensity::record(intensity::Activity::Click);
+                mood::on_activity();
+            }
+            button_allowed(&map_button(button))
+                .then(|| Action::new(ActionKind::MousePress, ActionValue::String(map_button(button))))
+        }
+        EventType::ButtonRelease(button) => button_allowed(&map_button(button))
+            .then(|| Action::new(ActionKind::MouseRelease, ActionValue::String(map_button(button)))),
+        EventType::KeyPress(key) if media_key(key).is_some() => {
+            media_key(key).map(|name| Action::new(ActionKind::MediaKey, ActionValue::String(name.to_string())))
+        }
+        // The press alone is reported for media keys.
+        EventType::KeyRelease(key) if media_key(key).is_some() => None,
+        EventType::KeyPress(key) => map_key(key).and_then(|val| {
+            if derived {
+                intensity::record(intensity::Activity::Key);
+                mood::on_key_press();
+                instrument::on_key_press(&val, broadcast_tx);
+            }
+            let allowed = key_allowed(&val);
+            if allowed {
+                longpress::on_key_press(&val);
+            }
+            modtap::on_key_press(&val, allowed, other_modifiers_held(key));
+            if derived && allowed {
+                keyheat::on_key_press(&val);
+            }
+            if derived {
+                shouting::on_key_press(&val, modifier_held(Modifier::Shift), broadcast_tx);
+            }
+            (allowed && !steno::suppresses_keys()).then(|| Action {
+                shifted: shifted_symbol(key, &val),
+                ..Action::new(ActionKind::KeyboardPress, ActionValue::String(val))
+            })
+        }),
+        EventType::KeyRelease(key) => map_key(key).and_then(|val| {
+            instrument::on_key_release(&val);
+            longpress::on_key_release(&val);
+            modtap::on_key_release(&val, broadcast_tx);
+            (key_allowed(&val) && !steno::suppresses_keys()).then(|| Action {
+                shifted: shifted_symbol(key, &val),
+                ..Action::new(ActionKind::KeyboardRelease, ActionValue::String(val))
+            })
+        }),
+        EventType::Wheel { .. } => {
+            modtap::interrupt();
+            if derived {
+                intensity::record(intensity::Activity::Scroll);
+                mood::on_activity();
+            }
+            None
+        }
+    };
+
+    let action = action.filter(|act| subscriptions::enabled(act.kind) && shedding::admit(broadcast_tx, act.kind));
+    let move_sent = matches!(&action, Some(act) if act.kind == ActionKind::MouseMove);
+    if let Some(mut act) = action {
+        if timestamp::ENABLED.load(Ordering::Relaxed) {
+            act.timestamp_ms = Some(occurred);
+        }
+        if timestamp::MONOTONIC.load(Ordering::Relaxed) {
+            act.mono_ns = Some(timestamp::monotonic_ns(at));
+        }
+        if timestamp::OS_TIMES.load(Ordering::Relaxed) {
+            act.os_timestamp_ms = Some(os_time);
+            act.hook_timestamp_ms = Some(timestamp::hook_ms(at));
+        }
+        act.consumed = consumed;
+        rates::count(act.kind);
+        // We only do the fast `send` operation.
+        let _ = broadcast_tx.send(act);
+    }
+    // Chords and pans go out after the event that made, moved or broke them.
+    match event {
+        EventType::ButtonPress(button) | EventType::ButtonRelease(button) if !button_allowed(&map_button(button)) => {}
+        EventType::ButtonPress(button) => {
+            chords::on_button_press(&map_button(button), broadcast_tx);
+            if button == rdev::Button::Middle {
+                pan::on_press(*LAST_CURSOR.lock().unwrap());
+            }
+        }
+        EventType::ButtonRelease(button) => {
+            chords::on_button_release(&map_button(button), broadcast_tx);
+            if button == rdev::Button::Middle {
+                pan::on_release(broadcast_tx);
+            }
+        }
+        EventType::MouseMove { x, y } if derived => pan::on_mouse_move(x, y, move_sent, broadcast_tx),
+        EventType::KeyPress(key) if steno::enabled() && media_key(key).is_none() => {
+            if let Some(name) = map_key(key) {
+                steno::on_key_press(&name);
+            }
+        }
+        EventType::KeyRelease(key) if steno::enabled() && media_key(key).is_none() => {
+            if let Some(name) = map_key(key) {
+                steno::on_key_release(&name, broadcast_tx);
+            }
+        }
+        _ => {}
+    }
+    latency::record(latency::Stage::Callback, probe);
+}
+
+/// Parses the command line and runs the server (or a subcommand) until it stops.
+pub async fn run() {
+    // 1. Parse command-line arguments
+    let cli = Cli::parse();
+    match &cli.command {
+        Some(Command::Analyze { file, bucket_secs, json, csv }) => {
+            if let Err(e) = stats::analyze(file, *bucket_secs, json.as_deref(), csv.as_deref()) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Fixtures { out }) => {
+            match fixtures::write_all(out) {
+                Ok(count) => println!("Wrote {} fixtures and manifest.json to {}", count, out.display()),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Convert { input, output, to }) => {
+            match recording::convert(input, output, *to) {
+                Ok((format, count)) => println!("Wrote {} records to {} ({:?})", count, output.display(), format),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        None => {}
+    }
+    match cli.replay.clone() {
+        Some(path) => match source::ReplaySource::open(&path) {
+            Ok(replay) => run_with(cli, replay).await,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => run_with(cli, source::RdevSource).await,
+    }
+}
+
+/// Serves with `cli`'s options (its subcommand is ignored) on input from `source`, until
+/// shutdown. `--grab` puts the grabbing hook in place of `source`, unless replaying.
+pub async fn run_with<S>(cli: Cli, source: S)
+where
+    S: EventSource + Clone + Send + 'static,
+{
+    let port = cli.port;
+    PRESERVE_SIDE.store(cli.preserve_side, Ordering::Relaxed);
+    NUMPAD_DISTINCT.store(cli.numpad_distinct, Ordering::Relaxed);
+    if cli.shifted_symbols {
+        let _ = SHIFTED_LAYOUT.set(cli.shifted_layout);
+    }
+    MOVE_MODIFIER.store(cli.move_requires_modifier.map_or(0, Modifier::mask), Ordering::Relaxed);
+    if let Some(ms) = cli.modifier_tap_ms {
+        modtap::configure(Duration::from_millis(ms), cli.modifier_tap_keys.clone());
+    }
+    shouting::configure(cli.shouting_threshold);
+    chords::configure(cli.button_chord_exit);
+    pan::configure(cli.pan_threshold_px);
+    steno::configure(cli.chord_window_ms.unwrap_or(0), cli.chord_only);
+    if cli.pause_hotkey != "none" {
+        if let Err(e) = pause::configure(&cli.pause_hotkey) {
+            eprintln!("Invalid --pause-hotkey: {}", e);
+            std::process::exit(1);
+        }
+    }
+    timestamp::ENABLED.store(cli.timestamps, Ordering::Relaxed);
+    timestamp::MONOTONIC.store(cli.monotonic_ns, Ordering::Relaxed);
+    timestamp::OS_TIMES.store(cli.os_timestamps, Ordering::Relaxed);
+    timestamp::start_monotonic();
+    if let Some(name) = &cli.source_name {
+        let _ = SOURCE_NAME.set(name.clone());
+    }
+    let config = match &cli.config {
+        Some(path) => match config::Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => config::Config::default(),
+    };
+    subscriptions::configure_enabled(&config.events);
+    let initial = SettingsPatch {
+        mouse_throttle_ms: Some(cli.mouse_throttle_ms.unwrap_or(config.mouse_throttle_ms)),
+        region_hysteresis_px: Some(config.region_hysteresis_px),
+        key_filter: Some(cli.key_filter.clone()),
+        only_button: Some(cli.only_button.clone()),
+    };
+    if let Err(e) = SETTINGS.apply(&initial) {
+        eprintln!("Invalid settings: {}", e);
+        std::process::exit(1);
+    }
+    if cli.check_config {
+        match &cli.config {
+            Some(path) => println!("Config OK: {} (version {})", path.display(), config.version),
+            None => println!("No config file given; command-line options OK."),
+        }
+        return;
+    }
+    if let Some(size) = cli.screen_size {
+        display::set_override(size);
+    }
+    if cli.realtime_input || cli.input_cpu.is_some() {
+        realtime::configure(realtime::Request {
+            priority: cli.realtime_input,
+            cpu: cli.input_cpu,
+        });
+    }
+    if let Some(cell) = cli.privacy_grid_px {
+        privacy::configure(privacy::Mode::Grid(cell as f64));
+        notice::raise(notice::Code::CoordPrivacy, &format!("Cursor coordinates snap to a {}px grid.", cell));
+    }
+    if let Some(bound) = cli.privacy_jitter_px {
+        privacy::configure(privacy::Mode::Jitter(bound as f64));
+        notice::raise(notice::Code::CoordPrivacy, &format!("Cursor coordinates are jittered by up to {}px.", bound));
+    }
+    display::probe();
+    if cli.probe {
+        print_monitors();
+        return;
+    }
+    regions::init(&config.regions);
+    if !config.regions.is_empty() || cli.polar_coords {
+        spawn_display_watcher();
+    }
+    instrument::configure(&config.instrument);
+    debounce::configure(&config::DebounceConfig {
+        ms: cli.debounce_ms.unwrap_or(config.debounce.ms),
+        keys: config.debounce.keys.clone(),
+    });
+    if let Some(network) = &cli.simulate_network {
+        if cfg!(not(debug_assertions)) {
+            eprintln!("--simulate-network is a testing tool and only exists in debug builds.");
+            std::process::exit(1);
+        }
+        eprintln!("WARNING: simulating a bad network ({:?}); do not use this in production.", network);
+    }
+    if cli.latency_probe {
+        latency::configure(Duration::from_micros(cli.latency_budget_us));
+    }
+    if let Some(gap) = cli.overload_gap_ms {
+        overload::configure(Duration::from_millis(gap), Duration::from_millis(cli.overload_throttle_ms));
+    }
+    if let Some(path) = cli.config.clone() {
+        spawn_config_reloader(path);
+    }
+
+    // 2. Create the broadcast channel.
+    let (broadcast_tx, _rx) = broadcast::channel::<Action>(BROADCAST_CAPACITY);
+    notice::init(broadcast_tx.clone());
+    if let Some(percent) = cli.shed_at_percent {
+        shedding::configure(BROADCAST_CAPACITY, percent);
+    }
+
+    if config.intensity.enabled {
+        intensity::spawn(&config.intensity, broadcast_tx.clone());
+    }
+    if config.mood.enabled {
+        mood::spawn(&config.mood, broadcast_tx.clone());
+    }
+    if config.key_heat.enabled {
+        keyheat::spawn(&config.key_heat, broadcast_tx.clone());
+    }
+    rates::spawn(broadcast_tx.clone());
+    if cli.flood_ceiling > 0 {
+        flood::spawn(
+            cli.flood_ceiling,
+            cli.flood_rearm_rate,
+            cli.flood_throttle_multiplier,
+            broadcast_tx.clone(),
+        );
+    }
+    if cli.long_press_ms > 0 {
+        longpress::spawn(Duration::from_millis(cli.long_press_ms), broadcast_tx.clone());
+    }
+
+    let record_kinds = cli.record_kinds.as_ref().map(|kinds| {
+        kinds
+            .iter()
+            .map(|kind| {
+                kind.parse::<ActionKind>().unwrap_or_else(|_| {
+                    eprintln!("Unknown kind '{}' in --record-kinds.", kind);
+                    std::process::exit(1);
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+    let rotation = recording::Rotation {
+        at: cli.record_rotate,
+        gzip: cli.record_gzip,
+        keep: cli.record_keep,
+    };
+    let recording = cli.record.as_deref().map(|path| match recording::Recorder::create(path, record_kinds, cli.record_format, rotation) {
+        Ok(recorder) => Arc::new(recorder),
+        Err(e) => {
+            eprintln!("Failed to create recording {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    });
+    if let Some(recorder) = &recording {
+        subscriptions::pin(subscriptions::mask_of(|kind| recorder.records(kind)));
+        tokio::spawn(recording::run(recorder.clone(), broadcast_tx.subscribe()));
+    }
+
+    if cli.mirror_uinput {
+        subscriptions::pin_all();
+        start_uinput_mirror(broadcast_tx.subscribe());
+    }
+    if cli.watch_clipboard {
+        start_clipboard_watcher(broadcast_tx.clone());
+    }
+    if cli.track_window_bounds {
+        start_window_tracker(broadcast_tx.clone());
+    }
+    if let Some(broker) = &cli.mqtt_broker {
+        subscriptions::pin_all();
+        start_mqtt(broker, &cli.mqtt_topic, broadcast_tx.subscribe());
+    }
+    if let Some(name) = &cli.named_pipe {
+        subscriptions::pin_all();
+        start_named_pipe(name, broadcast_tx.clone());
+    }
+
+    // 3. Spawn a separate OS thread for the input source (normally `rdev`).
+    let listener_failed = if cli.grab && cli.replay.is_none() {
+        start_grab(&config.grab, broadcast_tx.clone())
+    } else {
+        spawn_input_listener(source, broadcast_tx.clone())
+    };
+
+    // 4. Spawn a NEW, dedicated async task just for logging.
+    let mut logging_rx = broadcast_tx.subscribe();
+    tokio::spawn(async move {
+        while let Ok(action) = logging_rx.recv().await {
+            if !matches!(action.kind, ActionKind::MouseMove | ActionKind::Intensity | ActionKind::Rates) {
+                // This println! is now safe and won't block the input.
+                println!("Broadcasting action: {:?}", action);
+            }
+        }
+    });
+
+    // 5. Start the WebSocket server
+    let addr = format!("0.0.0.0:{}", port);
+    let listener = TcpListener::bind(&addr).await.expect("Failed to bind");
+    // With --port 0 the OS picked the port.
+    let port = listener.local_addr().map_or(port, |bound| bound.port());
+    println!("WebSocket server started on: ws://0.0.0.0:{}", port);
+    let discovery = if cli.no_discovery_file {
+        None
+    } else {
+        let path = cli.discovery_file.clone().unwrap_or_else(discovery::default_path);
+        discovery::write(path, port)
+            .map_err(|e| eprintln!("{} (continuing without it)", e))
+            .ok()
+    };
+    let prefs = match prefs::PrefsStore::load(cli.prefs_file.clone()) {
+        Ok(prefs) => prefs,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let token = cli.token.or(config.token);
+    if cli.debug_stream {
+        if token.is_none() {
+            eprintln!("--debug-stream exposes unfiltered input and needs a token (--token or `token` in the config).");
+            std::process::exit(1);
+        }
+        rawstream::enable();
+        println!("Debug stream enabled: clients with the token can connect with ?stream=raw.");
+    }
+    let state = Arc::new(ServerState {
+        token,
+        bind_addr: addr,
+        port,
+        qr_host: cli.qr_host.clone(),
+        rooms: rooms::Rooms::from_config(&config.rooms),
+        renames: rename::RenameProfiles::build(&config.rename_kinds, &config.rename_profiles)
+            .expect("rename profiles are validated when the config is loaded"),
+        locales: labels::Locales::build(&config.locales),
+        default_locale: config.locale.clone(),
+        recording,
+        mouse_delta: cli.mouse_delta,
+        mouse_keyframe_every: cli.mouse_keyframe_every,
+        coord_precision: cli.coord_precision,
+        polar_coords: cli.polar_coords,
+        max_message_bytes: cli.max_message_bytes as usize,
+        simulate_network: cli.simulate_network,
+        prefs,
+        sessions: sessions::Sessions::new(Duration::from_secs(cli.client_session_ttl_secs)),
+        backpressure_high: cli.backpressure_high,
+        backpressure_low: cli.backpressure_low,
+        queue_policy: cli.queue_policy,
+        queue_block_timeout: Duration::from_millis(cli.queue_block_timeout_ms),
+        envelope: cli.envelope,
+        snapshot_mode: cli.snapshot_mode,
+        snapshot_interval: Duration::from_millis(cli.snapshot_interval),
+        client_idle_timeout: cli.client_idle_timeout_secs.map(Duration::from_secs),
+        default_format: cli.format.clone(),
+        cues: config.cues.clone(),
+        handshake_timeout: Duration::from_secs(cli.handshake_timeout_secs),
+        max_pending_handshakes: cli.max_pending_handshakes as usize,
+        pending_handshakes: AtomicUsize::new(0),
+        clients: AtomicUsize::new(0),
+        next_client_id: AtomicU64::new(1),
+        idle_since: Mutex::new(Instant::now()),
+        shutdown: watch::channel(false).0,
+        failed: AtomicBool::new(false),
+    });
+    if cli.qr {
+        qr::print(&state.connection_url());
+    }
+    tokio::spawn(supervise_input_listener(
+        listener_failed,
+        cli.on_input_failure,
+        broadcast_tx.clone(),
+        state.clone(),
+    ));
+
+    metrics::start(broadcast_tx.subscribe());
+    let ctrl_c_state = state.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            ctrl_c_state.request_shutdown("Ctrl-C received");
+        }
+    });
+    if let Some(secs) = cli.shutdown_after_idle_secs {
+        spawn_idle_shutdown(state.clone(), Duration::from_secs(secs));
+    }
+    if let Some(secs) = cli.status_interval_secs {
+        spawn_status_ticker(Duration::from_secs(secs), broadcast_tx.clone());
+    }
+    let power_state = state.clone();
+    power::spawn(broadcast_tx.clone(), move |slept| {
+        power_state.skip_idle(slept);
+        restart_input_listener();
+    });
+
+    // 6. Accept new connections until shutdown begins
+    let mut shutdown_rx = state.shutdown.subscribe();
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(_) => break,
+            },
+            _ = shutdown_rx.changed() => break,
+        };
+        tokio::spawn(handle_connection(stream, broadcast_tx.clone(), state.clone()));
+    }
+
+    // 7. Give connected clients a moment to receive their Close frames.
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while state.clients.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    if let Some(recorder) = &state.recording {
+        let _ = recorder.finish();
+    }
+    drop(discovery);
+    metrics::print_report();
+    if let Some(path) = &cli.stats_out {
+        match metrics::write_json(path) {
+            Ok(()) => println!("Wrote session stats to {}", path.display()),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    println!("Server stopped.");
+    if state.failed.load(Ordering::SeqCst) {
+        std::process::exit(1);
+    }
+}
+
+/// What the listener supervisor hears about.
+enum ListenerEvent {
+    /// The run with this generation returned or panicked.
+    Ended(u64, std::thread::Result<Result<(), String>>),
+    /// Start over, from `restart_input_listener`.
+    Restart,
+}
+
+/// The current listener run. Events from earlier runs that haven't stopped are ignored.
+static LISTENER_GENERATION: AtomicU64 = AtomicU64::new(0);
+static LISTENER_CONTROL: once_cell::sync::OnceCell<std::sync::mpsc::Sender<ListenerEvent>> =
+    once_cell::sync::OnceCell::new();
+/// How long a restart waits for the old run to return before starting the new one anyway.
+const LISTENER_STOP_WAIT: Duration = Duration::from_secs(1);
+
+/// Starts the input source over, if it is one that wants that after a resume.
+fn restart_input_listener() {
+    if let Some(control) = LISTENER_CONTROL.get() {
+        let _ = control.send(ListenerEvent::Restart);
+    }
+}
+
+/// Runs `source` on its own thread, restarting it with backoff if it errors or panics,
+/// and starts the translation thread that turns its events into Actions.
+/// The returned channel fires once with the last error when restarts run out; a source
+/// that simply ends (a finished replay) closes it instead. Sources with
+/// `RESTART_ON_RESUME` are also started over by `restart_input_listener`.
+fn spawn_input_listener<S>(source: S, broadcast_tx: broadcast::Sender<Action>) -> oneshot::Receiver<String>
+where
+    S: EventSource + Clone + Send + 'static,
+{
+    let (failed_tx, failed_rx) = oneshot::channel();
+    let (control_tx, control) = std::sync::mpsc::channel();
+    if S::RESTART_ON_RESUME {
+        let _ = LISTENER_CONTROL.set(control_tx.clone());
+    }
+    rawqueue::spawn(move |queued| translate_event(queued, &broadcast_tx));
+    std::thread::spawn(move || {
+        let mut restarts = 0;
+        let reason = loop {
+            let generation = LISTENER_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+            let (source, ended) = (source.clone(), control_tx.clone());
+            std::thread::spawn(move || {
+                realtime::elevate("listener");
+                let sink = move |event| {
+                    if LISTENER_GENERATION.load(Ordering::Relaxed) == generation {
+                        event_callback(event);
+                    }
+                };
+                let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| source.run(sink)));
+                let _ = ended.send(ListenerEvent::Ended(generation, outcome));
+            });
+            let outcome = loop {
+                match control.recv() {
+                    Ok(ListenerEvent::Ended(ended, outcome)) if ended == generation => break Some(outcome),
+                    // A run replaced earlier has finally returned.
+                    Ok(ListenerEvent::Ended(..)) => {}
+                    Ok(ListenerEvent::Restart) => break None,
+                    Err(_) => unreachable!("the supervisor holds a sender"),
+                }
+            };
+            let reason = match outcome {
+                None => {
+                    println!("Restarting input listener after resume.");
+                    source::interrupt();
+                    let deadline = Instant::now() + LISTENER_STOP_WAIT;
+                    while let Ok(event) = control.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                        if matches!(event, ListenerEvent::Ended(ended, _) if ended == generation) {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                Some(Ok(Ok(()))) => return,
+                Some(Ok(Err(error))) => error,
+                Some(Err(panic)) => match panic.downcast_ref::<&str>() {
+                    Some(message) => format!("panicked: {}", message),
+                    None => match panic.downcast_ref::<String>() {
+                        Some(message) => format!("panicked: {}", message),
+                        None => "panicked".to_string(),
+                    },
+                },
+            };
+            eprintln!("Error listening to input: {}", reason);
+            if restarts == LISTENER_RESTARTS {
+                break reason;
+            }
+            restarts += 1;
+            let backoff = Duration::from_secs(1 << (restarts - 1));
+            eprintln!(
+                "Restarting input listener in {}s (attempt {}/{})",
+                backoff.as_secs(),
+                restarts,
+                LISTENER_RESTARTS
+            );
+            std::thread::sleep(backoff);
+        };
+        let _ = failed_tx.send(reason);
+    });
+    failed_rx
+}
+
+/// Applies `--on-input-failure` once the listener thread has given up.
+async fn supervise_input_listener(
+    failed: oneshot::Receiver<String>,
+    policy: InputFailurePolicy,
+    broadcast_tx: broadcast::Sender<Action>,
+    state: Arc<ServerState>,
+) {
+    let Ok(reason) = failed.await else { return };
+    match policy {
+        InputFailurePolicy::Warn => {
+            eprintln!("Input capture has stopped; still serving clients.");
+            notice::raise(notice::Code::InputListenerDown, &format!("Input capture has stopped: {}", reason));
+            let _ = broadcast_tx.send(Action::new(
+                ActionKind::InputFailure,
+                ActionValue::Json(serde_json::json!({ "error": reason })),
+            ));
+        }
+        InputFailurePolicy::Exit => {
+            state.failed.store(true, Ordering::SeqCst);
+            state.request_shutdown("input listener failed");
+        }
+    }
+}
+
+/// Resolves region bounds now, then re-resolves (and refreshes the cached display size)
+/// whenever the display size changes.
+fn spawn_display_watcher() {
+    let mut last = display::cached();
+    if last.is_none() && regions::needs_display_size() {
+        eprintln!("Could not detect display size; normalized regions are inactive until it is known.");
+    }
+    regions::resolve(last);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            let current = display::refresh();
+            if current != last {
+                println!("Display size changed to {:?}; re-resolving regions.", current);
+                regions::resolve(current);
+                last = current;
+            }
+        }
+    });
+}
+
+#[cfg(all(target_os = "linux", feature = "uinput"))]
+fn start_uinput_mirror(broadcast_rx: broadcast::Receiver<Action>) {
+    if let Err(e) = uinput_mirror::spawn(broadcast_rx, display::size()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "uinput")))]
+fn start_uinput_mirror(_broadcast_rx: broadcast::Receiver<Action>) {
+    eprintln!("--mirror-uinput needs a Linux build with the `uinput` feature (cargo build --features uinput).");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "clipboard")]
+fn start_clipboard_watcher(broadcast_tx: broadcast::Sender<Action>) {
+    if let Err(e) = clipboard::spawn(broadcast_tx) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn start_clipboard_watcher(_broadcast_tx: broadcast::Sender<Action>) {
+    eprintln!("--watch-clipboard needs a build with the `clipboard` feature (cargo build --features clipboard).");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "grab")]
+fn start_grab(config: &config::GrabConfig, broadcast_tx: broadcast::Sender<Action>) -> oneshot::Receiver<String> {
+    if let Err(e) = grab::configure(config) {
+        eprintln!("Invalid [grab] config: {}", e);
+        std::process::exit(1);
+    }
+    spawn_input_listener(grab::GrabSource, broadcast_tx)
+}
+
+#[cfg(not(feature = "grab"))]
+fn start_grab(_config: &config::GrabConfig, _broadcast_tx: broadcast::Sender<Action>) -> oneshot::Receiver<String> {
+    eprintln!("--grab needs a build with the `grab` feature (cargo build --features grab).");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "window-bounds")]
+fn start_window_tracker(broadcast_tx: broadcast::Sender<Action>) {
+    if let Err(e) = window::spawn(broadcast_tx) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "window-bounds"))]
+fn start_window_tracker(_broadcast_tx: broadcast::Sender<Action>) {
+    eprintln!("--track-window-bounds needs a build with the `window-bounds` feature (cargo build --features window-bounds).");
+    std::process::exit(1);
+}
+
+/// `--probe`: the virtual desktop and each monitor, indexed as the platform reports them.
+#[cfg(feature = "monitors")]
+fn print_monitors() {
+    let list = match monitors::list() {
+        Ok(list) => list,
+        Err(e) => {
+            eprintln!("Could not list monitors: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some((x, y, w, h)) = monitors::virtual_bounds(&list) {
+        println!("Virtual desktop: {}x{} at ({}, {})", w, h, x, y);
+    }
+    for (index, monitor) in list.iter().enumerate() {
+        println!(
+            "Monitor {}: {}x{} at ({}, {}){}",
+            index,
+            monitor.w,
+            monitor.h,
+            monitor.x,
+            monitor.y,
+            if monitor.primary == Some(true) { " primary" } else { "" }
+        );
+    }
+}
+
+#[cfg(not(feature = "monitors"))]
+fn print_monitors() {
+    println!("Virtual-desktop bounds and the monitor list need a build with the `monitors` feature (cargo build --features monitors).");
+}
+
+#[cfg(feature = "mqtt")]
+fn start_mqtt(broker: &str, topic: &str, broadcast_rx: broadcast::Receiver<Action>) {
+    if let Err(e) = mqtt::spawn(broker, topic, broadcast_rx) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn start_mqtt(_broker: &str, _topic: &str, _broadcast_rx: broadcast::Receiver<Action>) {
+    eprintln!("--mqtt-broker needs a build with the `mqtt` feature (cargo build --features mqtt).");
+    std::process::exit(1);
+}
+
+#[cfg(windows)]
+fn start_named_pipe(name: &str, broadcast_tx: broadcast::Sender<Action>) {
+    if let Err(e) = namedpipe::spawn(name, broadcast_tx) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(windows))]
+fn start_named_pipe(_name: &str, _broadcast_tx: broadcast::Sender<Action>) {
+    eprintln!("--named-pipe is only available on Windows; on other systems connect over WebSocket.");
+    std::process::exit(1);
+}
+
+/// Requests shutdown once no client has been connected for `idle`.
+fn spawn_idle_shutdown(state: Arc<ServerState>, idle: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let idle_for = state.idle_since.lock().unwrap().elapsed();
+            if state.clients.load(Ordering::SeqCst) == 0 && idle_for >= idle {
+                state.request_shutdown(&format!("no clients for {}s", idle.as_secs()));
+                break;
+            }
+        }
+    });
+}
+
+/// Broadcasts `Status` {"uptime_s", "time_ms"} every `interval`.
+fn spawn_status_ticker(interval: Duration, broadcast_tx: broadcast::Sender<Action>) {
+    let started = Instant::now();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let time_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+            let _ = broadcast_tx.send(Action::new(
+                ActionKind::Status,
+                ActionValue::Json(serde_json::json!({
+                    "uptime_s": started.elapsed().as_secs(),
+                    "time_ms": time_ms,
+                })),
+            ));
+        }
+    });
+}
+
+/// Polls the config file and re-applies the hot-reloadable sections (`[instrument]`) when it changes.
+/// A file that fails to parse is reported and the previous settings stay in effect.
+fn spawn_config_reloader(path: PathBuf) {
+    let modified = |path: &PathBuf| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last = modified(&path);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(2));
+        loop {
+            interval.tick().await;
+            let current = modified(&path);
+            if current == last {
+                continue;
+            }
+            last = current;
+            match config::Config::load(&path) {
+                Ok(config) => {
+                    instrument::configure(&config.instrument);
+                    println!("Reloaded config from {}.", path.display());
+                }
+                Err(e) => eprintln!("{} (keeping previous settings)", e),
+            }
+        }
+    });
+}
+
+/// Handles a single client connection (WebSocket, or plain HTTP for the endpoints)
+async fn handle_connection(
+    stream: TcpStream,
+    broadcast_tx: broadcast::Sender<Action>,
+    state: Arc<ServerState>,
+) {
+    // A peer that connects and then says nothing (a port scanner) must not hold a task forever.
+    let Some(handshake) = state.handshake_started() else {
+        return;
+    };
+    let deadline = tokio::time::Instant::now() + state.handshake_timeout;
+    let Ok(upgrade) = tokio::time::timeout_at(deadline, http::is_websocket_upgrade(&stream)).await else {
+        metrics::handshake_timed_out();
+        return;
+    };
+    if !upgrade {
+        drop(handshake);
+        http::handle(stream, &state).await;
+        return;
+    }
+
+    // The room is picked during the handshake so unknown rooms and bad tokens get a proper HTTP error.
+    let mut room = None;
+    let mut renames = None;
+    let mut precision = None;
+    let mut locale = None;
+    let mut format = None;
+    let mut queue_policy = None;
+    let mut envelope = None;
+    let mut quiet = None;
+    let mut role = None;
+    let mut query = String::new();
+    let mut stored_prefs = None;
+    #[allow(clippy::result_large_err)] // the signature is fixed by tungstenite's `Callback`
+    let pick_room = |req: &Request, mut resp: Response| -> Result<Response, ErrorResponse> {
+        let raw_query = req.uri().query().unwrap_or("");
+        // Options remembered for `?client_id=`, then saved ones for `?name=`, fill in
+        // whatever the client didn't pass this time.
+        let resumed = match http::query_param(raw_query, "client_id") {
+            Some(id) if id.len() > sessions::MAX_ID_LEN => {
+                return Err(error_response(StatusCode::BAD_REQUEST, "client_id is too long"));
+            }
+            Some(id) => state.sessions.apply(id, raw_query).0,
+            None => raw_query.to_string(),
+        };
+        (query, stored_prefs) = match http::query_param(&resumed, "name") {
+            Some(name) => state.prefs.apply(name, &resumed),
+            None => (resumed, None),
+        };
+        let token = http::query_param(&query, "token");
+        if http::query_param(&query, "stream") == Some("raw") {
+            if !rawstream::enabled() {
+                return Err(error_response(StatusCode::FORBIDDEN, "the raw stream needs --debug-stream"));
+            }
+            if !http::token_eq(token, state.token.as_deref()) {
+                return Err(error_response(StatusCode::UNAUTHORIZED, "the raw stream needs the server token"));
+            }
+        }
+        let (picked_role, room_path) = role::Role::from_path(req.uri().path());
+        let admin_token_ok = state.token.is_some() && http::token_eq(token, state.token.as_deref());
+        if picked_role == role::Role::Admin && !admin_token_ok {
+            return Err(error_response(StatusCode::UNAUTHORIZED, "the admin path needs the server token"));
+        }
+        role = Some(picked_role);
+        renames = state.renames.profile(http::query_param(&query, "profile"));
+        if renames.is_none() {
+            return Err(error_response(StatusCode::NOT_FOUND, "unknown rename profile"));
+        }
+        precision = match http::query_param(&query, "precision") {
+            None => Some(state.coord_precision),
+            Some(p) => p.parse().ok().filter(|p| *p <= MAX_COORD_PRECISION),
+        };
+        if precision.is_none() {
+            return Err(error_response(StatusCode::BAD_REQUEST, "invalid precision"));
+        }
+        locale = match http::query_param(&query, "locale").or(state.default_locale.as_deref()) {
+            None => Some(None),
+            Some(name) => state.locales.get(name).map(Some),
+        };
+        if locale.is_none() {
+            let reason = format!("unknown locale (known: {})", state.locales.names().join(", "));
+            return Err(error_response(StatusCode::BAD_REQUEST, &reason));
+        }
+        // `Sec-WebSocket-Protocol` offers, in the client's order of preference; the first we support wins.
+        let offered: Vec<&str> = req
+            .headers()
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+        let mut subprotocol_format = None;
+        if !offered.is_empty() {
+            let Some((name, picked)) = offered
+                .iter()
+                .find_map(|name| parse_subprotocol(name).ok().map(|format| (*name, format)))
+            else {
+                let reason = format!("unsupported subprotocol (supported: {})", subprotocol_names().join(", "));
+                return Err(error_response(StatusCode::BAD_REQUEST, &reason));
+            };
+            let value = header::HeaderValue::from_str(name).expect("subprotocol names are valid header values");
+            resp.headers_mut().insert(header::SEC_WEBSOCKET_PROTOCOL, value);
+            subprotocol_format = picked;
+        }
+        format = serializer::by_name(
+            subprotocol_format
+                .or(http::query_param(&query, "format"))
+                .unwrap_or(&state.default_format),
+        );
+        if format.is_none() {
+            let reason = format!("unsupported format (supported: {})", serializer::FORMATS.join(", "));
+            return Err(error_response(StatusCode::BAD_REQUEST, &reason));
+        }
+        queue_policy = match http::query_param(&query, "queue") {
+            None => Some(state.queue_policy),
+            Some(name) => clientqueue::QueuePolicy::parse(name),
+        };
+        if queue_policy.is_none() {
+            let reason = format!("unknown queue policy (known: {})", clientqueue::QueuePolicy::names().join(", "));
+            return Err(error_response(StatusCode::BAD_REQUEST, &reason));
+        }
+        envelope = match http::query_param(&query, "envelope") {
+            None => Some(state.envelope),
+            Some("1") => Some(true),
+            Some("0") => Some(false),
+            Some(_) => None,
+        };
+        if envelope.is_none() {
+            return Err(error_response(StatusCode::BAD_REQUEST, "invalid envelope (expected 0 or 1)"));
+        }
+        quiet = match schedule::QuietHours::from_query(&query) {
+            Ok(quiet) => Some(quiet),
+            Err(e) => return Err(error_response(StatusCode::BAD_REQUEST, &format!("invalid quiet hours: {}", e))),
+        };
+        match state.rooms.resolve(room_path, token) {
+            Ok(r) => {
+                room = Some(r);
+                Ok(resp)
+            }
+            Err(rooms::RoomError::NotFound) => Err(error_response(StatusCode::NOT_FOUND, "unknown room")),
+            Err(rooms::RoomError::Unauthorized) => {
+                Err(error_response(StatusCode::UNAUTHORIZED, "missing or invalid room token"))
+            }
+        }
+    };
+    let ws_config = WebSocketConfig {
+        max_message_size: Some(state.max_message_bytes),
+        max_frame_size: Some(state.max_message_bytes),
+        ..Default::default()
+    };
+    let accept = accept_hdr_async_with_config(stream, pick_room, Some(ws_config));
+    let handshake_result = tokio::time::timeout_at(deadline, accept).await;
+    drop(handshake);
+    let ws_stream = match handshake_result {
+        Ok(Ok(ws)) => ws,
+        Ok(Err(e)) => {
+            println!("WebSocket handshake error: {}", e);
+            return;
+        }
+        Err(_) => {
+            metrics::handshake_timed_out();
+            return;
+        }
+    };
+    let (
+        Some(room),
+        Some(renames),
+        Some(precision),
+        Some(labels),
+        Some(serializer),
+        Some(queue_policy),
+        Some(envelope),
+        Some(mut quiet),
+        Some(role),
+    ) = (room, renames, precision, locale, format, queue_policy, envelope, quiet, role)
+    else {
+        return;
+    };
+    let wire = Wire {
+        renames,
+        labels,
+        serializer,
+        envelope,
+        max_bytes: state.max_message_bytes,
+        network: state.simulate_network,
+    };
+    let (_client, client_id) = state.client_connected();
+    match role {
+        role::Role::Combined => println!("Client connected to room '{}'.", room.name),
+        role => println!("Client connected to room '{}' ({} stream).", room.name, role.as_str()),
+    }
+
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+
+    if let Some(requested) = http::query_param(&query, "protocol") {
+        let supported = requested
+            .parse::<u32>()
+            .is_ok_and(|v| SUPPORTED_PROTOCOL_VERSIONS.contains(&v));
+        if !supported {
+            println!("Client requested unsupported protocol version '{}'.", requested);
+            let error = Action::new(
+                ActionKind::ProtocolError,
+                ActionValue::Json(serde_json::json!({
+                    "code": "unsupported_protocol",
+                    "requested": requested,
+                    "supported": SUPPORTED_PROTOCOL_VERSIONS,
+                    "message": format!("protocol version '{}' is not supported", requested),
+                })),
+            );
+            let _ = send_action(&mut ws_sender, error, &wire).await;
+            close_with(&mut ws_sender, CloseReason::UnsupportedProtocol, &wire).await;
+            return;
+        }
+    }
+    if let Some(notice) = privacy::notice().filter(|_| role.carries(ActionKind::CoordPrivacy)) {
+        if send_action(&mut ws_sender, notice, &wire).await.is_err() {
+            return;
+        }
+    }
+    if let Some(prefs) = stored_prefs.filter(|_| role.carries(ActionKind::Prefs)) {
+        let notice = Action::new(ActionKind::Prefs, ActionValue::Json(prefs));
+        if send_action(&mut ws_sender, notice, &wire).await.is_err() {
+            return;
+        }
+    }
+    let client_name = http::query_param(&query, "name");
+    // `?stream=raw` swaps the live input for the unfiltered debug stream.
+    let raw_stream = http::query_param(&query, "stream") == Some("raw");
+    let live_input = room.input && !raw_stream;
+    let input_rx = if raw_stream {
+        rawstream::subscribe()
+    } else {
+        live_input.then(|| broadcast_tx.subscribe())
+    };
+    let mut input_rx =
+        input_rx.map(|rx| clientqueue::ClientQueue::new(rx, queue_policy, state.queue_block_timeout));
+    let mut room_rx = room.tx.subscribe();
+    let wants_delta = match http::query_param(&query, "mouse") {
+        Some("delta") => true,
+        Some("absolute") => false,
+        _ => state.mouse_delta,
+    };
+    let mut delta = wants_delta.then(|| delta::MouseDeltaEncoder::new(state.mouse_keyframe_every));
+    let mut backpressure =
+        backpressure::BackpressureMonitor::new(state.backpressure_high, state.backpressure_low);
+    // `?kinds=A,B` limits the client to those kinds; otherwise it gets everything but the opt-in ones.
+    let kinds: Option<Vec<ActionKind>> = http::query_param(&query, "kinds")
+        .map(|list| list.split(',').filter_map(|kind| kind.parse().ok()).collect());
+    let wants = |kind: ActionKind| {
+        role.carries(kind)
+            && subscriptions::enabled(kind)
+            && match &kinds {
+                Some(kinds) => kinds.contains(&kind),
+                None => role.by_default(kind),
+            }
+    };
+    // `?snapshot=1` folds input into one Snapshot per tick, whatever `?kinds=` says.
+    let snapshot_mode = live_input
+        && role.carries(ActionKind::Snapshot)
+        && match http::query_param(&query, "snapshot") {
+            Some("1") => true,
+            Some("0") => false,
+            _ => state.snapshot_mode,
+        };
+    let mut snapshot = snapshot_mode.then(|| snapshot::Snapshotter::new(state.snapshot_interval));
+    let captures = |kind: ActionKind| wants(kind) || (snapshot_mode && snapshot::tracks(kind));
+    let _rates = wants(ActionKind::Rates).then(rates::subscribe);
+    let _subscription = live_input.then(|| subscriptions::register(client_id, subscriptions::mask_of(captures)));
+    let prepare = |action: &mut Action| {
+        if state.polar_coords {
+            action.make_polar();
+        }
+        action.round_coords(precision);
+    };
+
+    // A cursor-following pet looks broken until the first move, so start it at the last known position.
+    let last_cursor = *LAST_CURSOR.lock().unwrap();
+    if let Some((x, y)) = last_cursor.filter(|_| live_input && captures(ActionKind::MouseMove)) {
+        let mut action = Action::new(ActionKind::MouseMove, ActionValue::Coords(Coords { x, y }));
+        prepare(&mut action);
+        let action = match (&mut snapshot, &mut delta) {
+            (Some(snapshot), _) => {
+                snapshot.fold(&action);
+                None
+            }
+            (None, Some(encoder)) => encoder.encode(action),
+            (None, None) => Some(action),
+        };
+        if let Some(action) = action {
+            if send_action(&mut ws_sender, action, &wire).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    if let Some(mood) = mood::current().filter(|_| live_input && captures(ActionKind::Mood)) {
+        if let Some(snapshot) = &mut snapshot {
+            snapshot.fold(&mood);
+        } else if send_action(&mut ws_sender, mood, &wire).await.is_err() {
+            return;
+        }
+    }
+    if live_input && wants(ActionKind::ServerNotice) {
+        for notice in notice::active() {
+            if send_action(&mut ws_sender, notice, &wire).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    let mut active_rx = room.active.subscribe();
+    let mut reliable = (http::query_param(&query, "reliable") == Some("1")).then(reliable::ResendBuffer::new);
+    let mut timesync = None;
+    if http::query_param(&query, "timesync") == Some("1") {
+        let (exchange, probe) = timesync::Exchange::start();
+        timesync = Some(exchange);
+        if send_action(&mut ws_sender, probe, &wire).await.is_err() {
+            return;
+        }
+    }
+    let mut rate_limit = http::query_param(&query, "max_rate")
+        .and_then(|rate| rate.parse::<f64>().ok())
+        .filter(|rate| *rate >= 1.0)
+        .map(ratelimit::TokenBucket::new);
+    let mut preview = (http::query_param(&query, "preview") == Some("1")).then(ratelimit::TokenBucket::preview);
+    // Cues need the server token, the same secret the HTTP endpoints use; `/admin` checked it already.
+    let cue_sender = match role {
+        role::Role::Admin => true,
+        role::Role::Combined => {
+            state.token.is_some() && http::token_eq(http::query_param(&query, "token"), state.token.as_deref())
+        }
+        role::Role::Events | role::Role::Meta => false,
+    };
+    let mut cue_rate = ratelimit::TokenBucket::new(state.cues.per_second);
+    let mut shutdown_rx = state.shutdown.subscribe();
+    let mut last_heard = tokio::time::Instant::now();
+    'connection: loop {
+        let received = tokio::select! {
+            r = recv_optional(&mut input_rx) => r,
+            r = room_rx.recv() => r,
+            notice = quiet_transition(&mut quiet) => {
+                if !wants(ActionKind::ServerNotice) {
+                    continue;
+                }
+                if send_action(&mut ws_sender, notice, &wire).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            _ = tick_optional(&mut snapshot) => {
+                let Some(snapshot) = &mut snapshot else { continue };
+                if send_action(&mut ws_sender, snapshot.take(), &wire).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            // Reading is what notices a client that went away while no events were flowing.
+            incoming = ws_receiver.next() => match incoming {
+                Some(Err(WsError::Capacity(e))) => {
+                    println!("Closing client connection: {}", e);
+                    close_with(&mut ws_sender, CloseReason::MessageTooLarge, &wire).await;
+                    break;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(Message::Text(text))) => {
+                    last_heard = tokio::time::Instant::now();
+                    match serde_json::from_str::<ClientMessage>(&text) {
+                        Ok(message) => {
+                            match message.claim_active {
+                                Some(true) => room.claim_active(client_id),
+                                Some(false) => room.release_active(client_id),
+                                None => {}
+                            }
+                            match message.max_rate {
+                                Some(0.0) => rate_limit = None,
+                                Some(rate) if rate >= 1.0 => rate_limit = Some(ratelimit::TokenBucket::new(rate)),
+                                Some(rate) => println!("Ignoring max_rate {}: must be 0 or at least 1.", rate),
+                                None => {}
+                            }
+                            match message.preview {
+                                Some(true) if preview.is_none() => preview = Some(ratelimit::TokenBucket::preview()),
+                                Some(false) => preview = None,
+                                _ => {}
+                            }
+                            if let (Some(buffer), Some(seq)) = (&mut reliable, message.ack) {
+                                buffer.ack(seq);
+                            }
+                            let resends = match (&reliable, message.resend_from) {
+                                (Some(buffer), Some(seq)) => buffer.resend_from(seq),
+                                _ => Vec::new(),
+                            };
+                            for action in resends {
+                                if send_action(&mut ws_sender, action, &wire).await.is_err() {
+                                    break 'connection;
+                                }
+                            }
+                            match (message.cmd.as_deref(), client_name) {
+                                (Some("save_prefs"), Some(name)) => {
+                                    let current = current_options(&query, &rate_limit, preview.is_some());
+                                    let notice = Action::new(
+                                        ActionKind::Prefs,
+                                        ActionValue::Json(state.prefs.save(name, &current)),
+                                    );
+                                    println!("Saved preferences for client '{}'.", name);
+                                    if send_action(&mut ws_sender, notice, &wire).await.is_err() {
+                                        break;
+                                    }
+                                }
+                                (Some("save_prefs"), None) => println!("Ignoring save_prefs from a client without ?name=."),
+                                (Some("timesync"), _) => {
+                                    let (exchange, probe) = timesync::Exchange::start();
+                                    timesync = Some(exchange);
+                                    if send_action(&mut ws_sender, probe, &wire).await.is_err() {
+                                        break;
+                                    }
+                                }
+                                (Some("timesync_echo"), _) => {
+                                    let reply = match (&mut timesync, message.probe, message.client_ms) {
+                                        (Some(exchange), Some(probe), Some(client_ms)) => exchange.on_echo(probe, client_ms),
+                                        _ => None,
+                                    };
+                                    let Some(reply) = reply else {
+                                        println!("Ignoring timesync_echo that answers no outstanding probe.");
+                                        continue;
+                                    };
+                                    if send_action(&mut ws_sender, reply, &wire).await.is_err() {
+                                        break;
+                                    }
+                                }
+                                (Some("cue"), _) => {
+                                    let cue = if !cue_sender {
+                                        Err("cues need the server token, on / or /admin".to_string())
+                                    } else if !subscriptions::enabled(ActionKind::Cue) {
+                                        Err("Cue is disabled in [events]".to_string())
+                                    } else {
+                                        cue::build(&state.cues, message.name.as_deref(), message.data.as_ref())
+                                    };
+                                    let cue = cue.and_then(|cue| {
+                                        cue_rate.admit(ActionKind::Cue).then_some(cue).ok_or_else(|| "rate limited".to_string())
+                                    });
+                                    match cue {
+                                        Ok(cue) => {
+                                            println!("Cue from client {}: {:?}", client_id, cue.value);
+                                            rates::count(ActionKind::Cue);
+                                            for room in state.rooms.all() {
+                                                let _ = room.tx.send(cue.clone());
+                                            }
+                                        }
+                                        Err(e) => println!("Ignoring cue from client {}: {}", client_id, e),
+                                    }
+                                }
+                                (Some(cmd), _) => println!("Ignoring unknown client command {:?}.", cmd),
+                                (None, _) => {}
+                            }
+                        }
+                        Err(e) => println!("Ignoring client message {:?}: {}", text, e),
+                    }
+                    continue;
+                }
+                Some(Ok(_)) => {
+                    last_heard = tokio::time::Instant::now();
+                    continue;
+                }
+            },
+            Ok(()) = active_rx.changed() => {
+                let claimed_by_other = active_rx.borrow_and_update().is_some_and(|active| active != client_id);
+                if claimed_by_other && role.carries(ActionKind::Inactive) {
+                    let notice = Action::new(ActionKind::Inactive, ActionValue::Json(serde_json::Value::Null));
+                    if send_action(&mut ws_sender, notice, &wire).await.is_err() {
+                        break;
+                    }
+                }
+                continue;
+            },
+            _ = async { drop(shutdown_rx.wait_for(|stopping| *stopping).await) } => {
+                close_with(&mut ws_sender, CloseReason::Shutdown, &wire).await;
+                break;
+            }
+            _ = idle_deadline(state.client_idle_timeout, last_heard) => {
+                println!(
+                    "Closing client {} by idle policy: nothing received for {}s.",
+                    client_id,
+                    last_heard.elapsed().as_secs()
+                );
+                close_with(&mut ws_sender, CloseReason::IdleTimeout, &wire).await;
+                break;
+            }
+        };
+        let (action, newly_dropped) = match received {
+            Ok(action) => (Some(action), 0),
+            // A lagging client loses the oldest events but stays connected.
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                metrics::lagged(n);
+                (None, n)
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                if input_rx.as_ref().is_some_and(clientqueue::ClientQueue::stalled) {
+                    println!("Closing client {}: its queue stayed full under the block policy.", client_id);
+                    close_with(&mut ws_sender, CloseReason::QueueStalled, &wire).await;
+                }
+                break;
+            }
+        };
+        let pending = input_rx.as_ref().map_or(0, |rx| rx.len()) + room_rx.len();
+        if let Some(level) = backpressure.observe(pending, newly_dropped) {
+            println!(
+                "Client backpressure {} (pending {}, dropped {}).",
+                level, pending, backpressure.dropped
+            );
+            let notice = Action::new(ActionKind::Backpressure, ActionValue::String(level.to_string()));
+            if role.carries(ActionKind::Backpressure) && send_action(&mut ws_sender, notice, &wire).await.is_err() {
+                break;
+            }
+        }
+        let Some(mut action) = action else { continue };
+        if room.excludes(client_id) {
+            continue;
+        }
+        if let Some(snapshot) = snapshot.as_mut().filter(|_| snapshot::covers(action.kind)) {
+            prepare(&mut action);
+            snapshot.fold(&action);
+            continue;
+        }
+        if !wants(action.kind) {
+            continue;
+        }
+        if rate_limit.as_mut().is_some_and(|bucket| !bucket.admit(action.kind)) {
+            continue;
+        }
+        if preview.as_mut().is_some_and(|bucket| !bucket.admit(action.kind)) {
+            continue;
+        }
+        if quiet.as_mut().is_some_and(|quiet| !quiet.admit(action.kind)) {
+            continue;
+        }
+        prepare(&mut action);
+        let mut action = match &mut delta {
+            Some(encoder) => match encoder.encode(action) {
+                Some(action) => action,
+                None => continue,
+            },
+            None => action,
+        };
+        if let Some(buffer) = &mut reliable {
+            buffer.stamp(&mut action);
+        }
+        if send_action(&mut ws_sender, action, &wire).await.is_err() {
+            break;
+        }
+    }
+    room.release_active(client_id);
+    if let Some(id) = http::query_param(&query, "client_id") {
+        state.sessions.remember(id, &current_options(&query, &rate_limit, preview.is_some()));
+    }
+    println!("Client disconnected.");
+}
+
+/// The connection's query options as in effect now: `max_rate` and `preview` may have been
+/// changed over the socket since it connected.
+fn current_options(query: &str, rate_limit: &Option<ratelimit::TokenBucket>, preview: bool) -> String {
+    let mut current: Vec<String> = query
+        .split('&')
+        .filter(|pair| !pair.starts_with("max_rate=") && !pair.starts_with("preview="))
+        .map(str::to_string)
+        .collect();
+    if let Some(bucket) = rate_limit {
+        current.push(format!("max_rate={}", bucket.rate()));
+    }
+    if preview {
+        current.push("preview=1".to_string());
+    }
+    current.join("&")
+}
+
+type WsSender = futures_util::stream::SplitSink<tokio_tungstenite::WebSocketStream<TcpStream>, Message>;
+
+/// How one connection's actions are put on the wire.
+struct Wire<'a> {
+    renames: &'a rename::KindRenames,
+    /// Set when the client gets key labels.
+    labels: Option<&'a labels::KeyLabels>,
+    serializer: Box<dyn serializer::Serializer>,
+    /// Set for `?envelope=1`.
+    envelope: bool,
+    max_bytes: usize,
+    network: Option<netsim::SimulatedNetwork>,
+}
+
+impl Wire<'_> {
+    fn encode(&self, action: &Action, kind: &str) -> serializer::EncodedFrame {
+        if self.envelope {
+            self.serializer.encode_enveloped(action, kind)
+        } else {
+            self.serializer.encode_as(action, kind)
+        }
+    }
+
+    /// The message for `action`: labelled, renamed and encoded, or an `Oversized` notice in
+    /// its place if it is over the size limit. `None` if even the notice doesn't fit.
+    fn frame(&self, mut action: Action) -> Option<serializer::EncodedFrame> {
+        if let (Some(labels), ActionValue::String(key)) = (self.labels, &action.value) {
+            if labels::KeyLabels::applies_to(action.kind) {
+                action.label = Some(labels.label(key));
+            }
+        }
+        let wire_kind = self.renames.apply(action.kind.as_str());
+        action.stamp_source();
+        let mut frame = self.encode(&action, wire_kind);
+        // An oversized message would make the client drop the connection, so send a summary instead.
+        if frame.len() > self.max_bytes {
+            eprintln!(
+                "Dropping {} action of {} bytes (limit {}); sending an Oversized notice instead.",
+                action.kind,
+                frame.len(),
+                self.max_bytes
+            );
+            let mut notice = Action {
+                source: action.source,
+                ..Action::new(
+                    ActionKind::Oversized,
+                    ActionValue::Json(serde_json::json!({
+                        "kind": wire_kind,
+                        "bytes": frame.len(),
+                        "limit": self.max_bytes,
+                    })),
+                )
+            };
+            notice.stamp_source();
+            frame = self.encode(&notice, self.renames.apply(notice.kind.as_str()));
+            if frame.len() > self.max_bytes {
+                return None;
+            }
+        }
+        Some(frame)
+    }
+}
+
+/// Labels, renames, serializes and sends one action.
+async fn send_action(
+    ws_sender: &mut WsSender,
+    action: Action,
+    wire: &Wire<'_>,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let Some(frame) = wire.frame(action) else {
+        return Ok(());
+    };
+    let message = match frame {
+        serializer::EncodedFrame::Text(text) => Message::Text(text),
+        serializer::EncodedFrame::Binary(bytes) => Message::Binary(bytes),
+    };
+    if let Some(network) = &wire.network {
+        if !network.transmit().await {
+            return Ok(());
+        }
+    }
+    let started = latency::start();
+    let sent = ws_sender.send(message).await;
+    latency::record(latency::Stage::WsSend, started);
+    sent
+}
+
+/// Receives from an optional channel; a missing channel simply never yields.
+async fn recv_optional(
+    rx: &mut Option<clientqueue::ClientQueue>,
+) -> Result<Action, broadcast::error::RecvError> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for the next snapshot tick; never, for a client not in snapshot mode.
+async fn tick_optional(snapshot: &mut Option<snapshot::Snapshotter>) {
+    match snapshot {
+        Some(snapshot) => snapshot.tick().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for the client to enter or leave its quiet hours; never, without `?quiet=`.
+async fn quiet_transition(quiet: &mut Option<schedule::QuietHours>) -> Action {
+    match quiet {
+        Some(quiet) => quiet.transition().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Fires `timeout` after `last_heard`; never, when the idle policy is off.
+async fn idle_deadline(timeout: Option<Duration>, last_heard: tokio::time::Instant) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep_until(last_heard + timeout).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn error_response(status: StatusCode, reason: &str) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(reason.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+// --- Key/Button Mapping Utilities ---
+
+fn map_button(button: rdev::Button) -> String {
+    match button {
+        rdev::Button::Left => "Mouse1".to_string(),
+        // --- THIS LINE IS FIXED ---
+        rdev::Button::Right => "Mouse2".to_string(),
+        // ---
+        rdev::Button::Middle => "Mouse3".to_string(),
+        _ => "Mouse1".to_string(),
+    }
+}
+
+static KEY_MAP: Lazy<HashMap<Key, &'static str>> = Lazy::new(|| {
+    let mut m = HashMap::new();
+    m.insert(Key::Space, "Space");
+    m.insert(Key::Alt, "Alt");
+    m.insert(Key::AltGr, "Alt");
+    m.insert(Key::ControlLeft, "Control");
+    m.insert(Key::ControlRight, "Control");
+    m.insert(Key::ShiftLeft, "Shift");
+    m.insert(Key::ShiftRight, "Shift");
+    m.insert(Key::MetaLeft, "Meta");
+    m.insert(Key::MetaRight, "Meta");
+    m.insert(Key::Escape, "Escape");
+    m.insert(Key::F1, "F1");
+    m.insert(Key::F2, "F2");
+    m.insert(Key::F3, "F3");
+    m.insert(Key::F4, "F4");
+    m.insert(Key::F5, "F5");
+    m.insert(Key::F6, "F6");
+    m.insert(Key::F7, "F7");
+    m.insert(Key::F8, "F8");
+    m.insert(Key::F9, "F9");
+    m.insert(Key::F10, "F10");
+    m.insert(Key::F11, "F11");
+    m.insert(Key::F12, "F12");
+    m.insert(Key::Tab, "Tab");
+    m.insert(Key::Return, "Return");
+    m.insert(Key::Backspace, "Backspace");
+    m.insert(Key::CapsLock, "CapsLock");
+    m.insert(Key::Insert, "Insert");
+    m.insert(Key::Delete, "Delete");
+    m.insert(Key::Home, "Home");
+    m.insert(Key::End, "End");
+    m.insert(Key::PageUp, "PageUp");
+    m.insert(Key::PageDown, "PageDown");
+    m.insert(Key::UpArrow, "UpArrow");
+    m.insert(Key::DownArrow, "DownArrow");
+    m.insert(Key::LeftArrow, "LeftArrow");
+    m.insert(Key::RightArrow, "RightArrow");
+    m.insert(Key::PrintScreen, "PrintScreen");
+    m.insert(Key::ScrollLock, "ScrollLock");
+    m.insert(Key::Pause, "Pause");
+    m.insert(Key::NumLock, "NumLock");
+    m.insert(Key::Comma, ",");
+    m.insert(Key::Dot, ".");
+    m.insert(Key::Slash, "/");
+    m.insert(Key::SemiColon, ";");
+    m.insert(Key::Quote, "'");
+    m.insert(Key::LeftBracket, "[");
+    m.insert(Key::RightBracket, "]");
+    m.insert(Key::BackSlash, "\\");
+    m.insert(Key::Minus, "-");
+    m.insert(Key::Equal, "=");
+    m.insert(Key::BackQuote, "`");
+    m.insert(Key::KeyQ, "KeyQ");
+    m.insert(Key::KeyW, "KeyW");
+    m.insert(Key::KeyE, "KeyE");
+    m.insert(Key::KeyR, "KeyR");
+    m.insert(Key::KeyT, "KeyT");
+    m.insert(Key::KeyY, "KeyY");
+    m.insert(Key::KeyU, "KeyU");
+    m.insert(Key::KeyI, "KeyI");
+    m.insert(Key::KeyO, "KeyO");
+    m.insert(Key::KeyP, "KeyP");
+    m.insert(Key::KeyA, "KeyA");
+    m.insert(Key::KeyS, "KeyS");
+    m.insert(Key::KeyD, "KeyD");
+    m.insert(Key::KeyF, "KeyF");
+    m.insert(Key::KeyG, "KeyG");
+    m.insert(Key::KeyH, "KeyH");
+    m.insert(Key::KeyJ, "KeyJ");
+    m.insert(Key::KeyK, "KeyK");
+    m.insert(Key::KeyL, "KeyL");
+    m.insert(Key::KeyZ, "KeyZ");
+    m.insert(Key::KeyX, "KeyX");
+    m.insert(Key::KeyC, "KeyC");
+    m.insert(Key::KeyV, "KeyV");
+    m.insert(Key::KeyB, "KeyB");
+    m.insert(Key::KeyN, "KeyN");
+    m.insert(Key::KeyM, "KeyM");
+    m.insert(Key::Num1, "Num1");
+    m.insert(Key::Num2, "Num2");
+    m.insert(Key::Num3, "Num3");
+    m.insert(Key::Num4, "Num4");
+    m.insert(Key::Num5, "Num5");
+    m.insert(Key::Num6, "Num6");
+    m.insert(Key::Num7, "Num7");
+    m.insert(Key::Num8, "Num8");
+    m.insert(Key::Num9, "Num9");
+    m.insert(Key::Num0, "Num0");
+    m.insert(Key::Kp0, "Num0");
+    m.insert(Key::Kp1, "Num1");
+    m.insert(Key::Kp2, "Num2");
+    m.insert(Key::Kp3, "Num3");
+    m.insert(Key::Kp4, "Num4");
+    m.insert(Key::Kp5, "Num5");
+    m.insert(Key::Kp6, "Num6");
+    m.insert(Key::Kp7, "Num7");
+    m.insert(Key::Kp8, "Num8");
+    m.insert(Key::Kp9, "Num9");
+    m
+});
+
+/// Set by `--preserve-side`: keep left/right modifiers distinct.
+static PRESERVE_SIDE: AtomicBool = AtomicBool::new(false);
+
+fn sided_key_name(key: Key) -> Option<&'static str> {
+    match key {
+        Key::Alt => Some("AltLeft"),
+        Key::AltGr => Some("AltRight"),
+        Key::ControlLeft => Some("ControlLeft"),
+        Key::ControlRight => Some("ControlRight"),
+        Key::ShiftLeft => Some("ShiftLeft"),
+        Key::ShiftRight => Some("ShiftRight"),
+        Key::MetaLeft => Some("MetaLeft"),
+        Key::MetaRight => Some("MetaRight"),
+        _ => None,
+    }
+}
+
+/// Set by `--numpad-distinct`: keypad keys keep their own names.
+static NUMPAD_DISTINCT: AtomicBool = AtomicBool::new(false);
+
+/// Keypad names used with `--numpad-distinct`. rdev reports the physical key on Linux and
+/// macOS; on Windows keypad Enter and the NumLock-off keypad keys are told apart by
+/// `keypad::restore`, and the latter are named by `keypad::nav_names`.
+const NUMPAD_KEYS: &[(Key, &str)] = &[
+    (Key::Kp0, "Numpad0"),
+    (Key::Kp1, "Numpad1"),
+    (Key::Kp2, "Numpad2"),
+    (Key::Kp3, "Numpad3"),
+    (Key::Kp4, "Numpad4"),
+    (Key::Kp5, "Numpad5"),
+    (Key::Kp6, "Numpad6"),
+    (Key::Kp7, "Numpad7"),
+    (Key::Kp8, "Numpad8"),
+    (Key::Kp9, "Numpad9"),
+    (Key::KpReturn, "NumpadEnter"),
+    (Key::KpMinus, "NumpadSubtract"),
+    (Key::KpPlus, "NumpadAdd"),
+    (Key::KpMultiply, "NumpadMultiply"),
+    (Key::KpDivide, "NumpadDivide"),
+    (Key::KpDelete, "NumpadDecimal"),
+];
+
+/// The effective key mapping for GET /keymap: rdev key → name the server sends for it,
+/// given `--preserve-side` and `--numpad-distinct`, plus media keys and shifted symbols.
+pub fn keymap_json() -> serde_json::Value {
+    let unknown_codes = UNKNOWN_KEY_CODES.iter().map(|(code, _)| *code).chain(MENU_KEY_CODE);
+    let keys: serde_json::Map<String, serde_json::Value> = KEY_MAP
+        .keys()
+        .copied()
+        .chain(NUMPAD_KEYS.iter().map(|(key, _)| *key))
+        .chain(keypad::nav_names().map(|(key, _)| key))
+        .chain(unknown_codes.map(Key::Unknown))
+        .filter_map(|key| Some((format!("{:?}", key), map_key(key)?.into())))
+        .collect();
+    let media_keys: serde_json::Map<String, serde_json::Value> = MEDIA_KEY_CODES
+        .iter()
+        .map(|(code, name)| (format!("{:?}", Key::Unknown(*code)), (*name).into()))
+        .collect();
+    let layout = SHIFTED_LAYOUT.get().copied();
+    let shifted: Option<serde_json::Map<String, serde_json::Value>> = layout
+        .map(|layout| layout.table().iter().map(|(name, symbol)| (name.to_string(), (*symbol).into())).collect());
+    serde_json::json!({
+        "keys": keys,
+        "media_keys": media_keys,
+        "shifted": shifted,
+        "shifted_layout": layout.map(ShiftedLayout::as_str),
+    })
+}
+
+fn map_key(key: rdev::Key) -> Option<String> {
+    map_key_with(key, PRESERVE_SIDE.load(Ordering::Relaxed), NUMPAD_DISTINCT.load(Ordering::Relaxed))
+}
+
+/// `map_key` with `--preserve-side` and `--numpad-distinct` passed in.
+fn map_key_with(key: rdev::Key, preserve_side: bool, numpad_distinct: bool) -> Option<String> {
+    if preserve_side {
+        if let Some(name) = sided_key_name(key) {
+            return Some(name.to_string());
+        }
+    }
+    if numpad_distinct {
+        let mut names = NUMPAD_KEYS.iter().copied().chain(keypad::nav_names());
+        if let Some((_, name)) = names.find(|(k, _)| *k == key) {
+            return Some(name.to_string());
+        }
+    }
+    if let Some(nav) = keypad::folded(key) {
+        return map_key_with(nav, preserve_side, numpad_distinct);
+    }
+    if let Some(mapped) = KEY_MAP.get(&key) {
+        return Some(mapped.to_string());
+    }
+    if let Key::Unknown(code) = key {
+        if Some(code) == MENU_KEY_CODE {
+            return Some("Menu".to_string());
+        }
+        UNKNOWN_KEY_CODES
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, name)| name.to_string())
+    } else {
+        None
+    }
+}
+
+/// Punctuation that rdev only reports as `Key::Unknown(code)`.
+const UNKNOWN_KEY_CODES: &[(u32, &str)] = &[
+    (188, ","),
+    (190, "."),
+    (191, "/"),
+    (186, ";"),
+    (222, "'"),
+    (219, "["),
+    (221, "]"),
+    (220, "\\"),
+    (189, "-"),
+    (187, "="),
+];
+
+/// Set by `--shifted-symbols` to the `--shifted-layout`; unset means no `shifted` field.
+static SHIFTED_LAYOUT: once_cell::sync::OnceCell<ShiftedLayout> = once_cell::sync::OnceCell::new();
+
+/// Keyboard layouts whose Shift symbols are known.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum ShiftedLayout {
+    Us,
+}
+
+impl ShiftedLayout {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShiftedLayout::Us => "us",
+        }
+    }
+
+    /// Plain key name → what Shift turns it into.
+    fn table(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            ShiftedLayout::Us => US_SHIFTED,
+        }
+    }
+}
+
+/// What Shift turns each digit and punctuation key into on a US layout.
+const US_SHIFTED: &[(&str, &str)] = &[
+    ("Num1", "!"),
+    ("Num2", "@"),
+    ("Num3", "#"),
+    ("Num4", "$"),
+    ("Num5", "%"),
+    ("Num6", "^"),
+    ("Num7", "&"),
+    ("Num8", "*"),
+    ("Num9", "("),
+    ("Num0", ")"),
+    ("-", "_"),
+    ("=", "+"),
+    ("[", "{"),
+    ("]", "}"),
+    ("\\", "|"),
+    (";", ":"),
+    ("'", "\""),
+    (",", "<"),
+    (".", ">"),
+    ("/", "?"),
+    ("`", "~"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Modifier {
+    Shift,
+    #[value(alias = "ctrl")]
+    Control,
+    Alt,
+    /// The Windows / Command key
+    #[value(alias = "super", alias = "cmd")]
+    Meta,
+}
+
+impl Modifier {
+    /// Its bits in `MODIFIERS_HELD`, one per side.
+    fn mask(self) -> u8 {
+        match self {
+            Modifier::Shift => 0b0000_0011,
+            Modifier::Control => 0b0000_1100,
+            Modifier::Alt => 0b0011_0000,
+            Modifier::Meta => 0b1100_0000,
+        }
+    }
+}
+
+/// The modifier keys held down, one bit per physical key (see `modifier_bit`).
+/// Tracked before pausing and key filtering, so a release is never missed.
+static MODIFIERS_HELD: AtomicU8 = AtomicU8::new(0);
+/// Set by `--move-requires-modifier`: the modifier's mask, or 0 to always send moves.
+static MOVE_MODIFIER: AtomicU8 = AtomicU8::new(0);
+
+fn modifier_bit(key: Key) -> Option<u8> {
+    let bit = match key {
+        Key::ShiftLeft => 0,
+        Key::ShiftRight => 1,
+        Key::ControlLeft => 2,
+        Key::ControlRight => 3,
+        Key::Alt => 4,
+        Key::AltGr => 5,
+        Key::MetaLeft => 6,
+        Key::MetaRight => 7,
+        _ => return None,
+    };
+    Some(1 << bit)
+}
+
+fn track_modifier(key: Key, down: bool) {
+    if let Some(bit) = modifier_bit(key) {
+        if down {
+            MODIFIERS_HELD.fetch_or(bit, Ordering::Relaxed);
+        } else {
+            MODIFIERS_HELD.fetch_and(!bit, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Whether a modifier other than `key` itself is held.
+fn other_modifiers_held(key: Key) -> bool {
+    MODIFIERS_HELD.load(Ordering::Relaxed) & !modifier_bit(key).unwrap_or(0) != 0
+}
+
+/// Whether either side of `modifier` is held.
+fn modifier_held(modifier: Modifier) -> bool {
+    MODIFIERS_HELD.load(Ordering::Relaxed) & modifier.mask() != 0
+}
+
+/// Whether cursor moves may be broadcast: always, unless `--move-requires-modifier` names
+/// a modifier that isn't held.
+fn move_unlocked() -> bool {
+    let required = MOVE_MODIFIER.load(Ordering::Relaxed);
+    required == 0 || MODIFIERS_HELD.load(Ordering::Relaxed) & required != 0
+}
+
+/// The `shifted` field for a keyboard event: the shifted symbol while Shift is held,
+/// otherwise (or for keys without one) the plain key name. `None` unless enabled.
+fn shifted_symbol(key: Key, name: &str) -> Option<String> {
+    let layout = SHIFTED_LAYOUT.get()?;
+    Some(shift_symbol(*layout, key, name, modifier_held(Modifier::Shift)).to_string())
+}
+
+/// What `key` (sent as `name`) types on `layout`, with or without Shift.
+fn shift_symbol(layout: ShiftedLayout, key: Key, name: &str, shift: bool) -> &str {
+    // Keypad digits share the "NumN" names but Shift doesn't turn them into symbols.
+    let keypad = NUMPAD_KEYS.iter().any(|(k, _)| *k == key);
+    layout
+        .table()
+        .iter()
+        .find(|(plain, _)| shift && !keypad && *plain == name)
+        .map_or(name, |(_, symbol)| symbol)
+}
+
+/// The application/context-menu key has no rdev variant, and its raw code differs per platform.
+#[cfg(target_os = "windows")]
+const MENU_KEY_CODE: Option<u32> = Some(93); // VK_APPS
+#[cfg(target_os = "linux")]
+const MENU_KEY_CODE: Option<u32> = Some(135); // X11 keycode of Menu
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+const MENU_KEY_CODE: Option<u32> = None;
+
+/// Media keys, which rdev only reports as `Key::Unknown(code)`. macOS delivers them as
+/// system events the hook never sees, and Fn is handled by keyboard firmware on most
+/// laptops, so neither can be detected.
+#[cfg(target_os = "windows")]
+const MEDIA_KEY_CODES: &[(u32, &str)] = &[
+    (173, "Mute"), // VK_VOLUME_MUTE
+    (174, "VolumeDown"),
+    (175, "VolumeUp"),
+    (176, "NextTrack"),
+    (177, "PrevTrack"),
+    (178, "Stop"),
+    (179, "PlayPause"),
+];
+#[cfg(target_os = "linux")]
+const MEDIA_KEY_CODES: &[(u32, &str)] = &[
+    (121, "Mute"), // X11 keycodes (evdev + 8)
+    (122, "VolumeDown"),
+    (123, "VolumeUp"),
+    (171, "NextTrack"),
+    (172, "PlayPause"),
+    (173, "PrevTrack"),
+    (174, "Stop"),
+];
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+const MEDIA_KEY_CODES: &[(u32, &str)] = &[];
+
+fn media_key(key: Key) -> Option<&'static str> {
+    let Key::Unknown(code) = key else { return None };
+    MEDIA_KEY_CODES.iter().find(|(c, _)| *c == code).map(|(_, name)| *name)
+}
+
+fn media_key_for_name(name: &str) -> Option<Key> {
+    MEDIA_KEY_CODES
+        .iter()
+        .find(|(_, n)| *n == name)
+        .map(|(code, _)| Key::Unknown(*code))
+}
+
+/// The inverse of `map_key`, used when replaying recorded input.
+/// Names shared by several keys (e.g. "Control") resolve to the left-hand one.
+fn key_for_name(name: &str) -> Option<Key> {
+    let sided = [
+        Key::Alt,
+        Key::AltGr,
+        Key::ControlLeft,
+        Key::ControlRight,
+        Key::ShiftLeft,
+        Key::ShiftRight,
+        Key::MetaLeft,
+        Key::MetaRight,
+    ];
+    if let Some(key) = sided.into_iter().find(|key| sided_key_name(*key) == Some(name)) {
+        return Some(key);
+    }
+    if let Some(key) = sided.into_iter().find(|key| KEY_MAP.get(key) == Some(&name)) {
+        return Some(key);
+    }
+    if let Some((key, _)) = NUMPAD_KEYS.iter().copied().chain(keypad::nav_names()).find(|(_, n)| *n == name) {
+        return Some(key);
+    }
+    let mut keys: Vec<Key> = KEY_MAP.iter().filter(|(_, n)| **n == name).map(|(k, _)| *k).collect();
+    // Prefer the main-row digit over its keypad twin.
+    keys.sort_by_key(|key| format!("{:?}", key).starts_with("Kp"));
+    if name == "Menu" {
+        return MENU_KEY_CODE.map(Key::Unknown);
+    }
+    keys.first().copied().or_else(|| {
+        UNKNOWN_KEY_CODES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(code, _)| Key::Unknown(*code))
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serializer::{EncodedFrame, Serializer};
+
+    #[test]
+    fn us_layout_shifts_every_digit_and_punctuation_key() {
+        let expected = [
+            (Key::Num1, "!"),
+            (Key::Num2, "@"),
+            (Key::Num3, "#"),
+            (Key::Num4, "$"),
+            (Key::Num5, "%"),
+            (Key::Num6, "^"),
+            (Key::Num7, "&"),
+            (Key::Num8, "*"),
+            (Key::Num9, "("),
+            (Key::Num0, ")"),
+            (Key::Minus, "_"),
+            (Key::Equal, "+"),
+            (Key::LeftBracket, "{"),
+            (Key::RightBracket, "}"),
+            (Key::BackSlash, "|"),
+            (Key::SemiColon, ":"),
+            (Key::Quote, "\""),
+            (Key::Comma, "<"),
+            (Key::Dot, ">"),
+            (Key::Slash, "?"),
+            (Key::BackQuote, "~"),
+        ];
+        assert_eq!(expected.len(), US_SHIFTED.len());
+        for (key, symbol) in expected {
+            let name = map_key(key).unwrap();
+            assert_eq!(shift_symbol(ShiftedLayout::Us, key, &name, true), symbol, "{:?}", key);
+            assert_eq!(shift_symbol(ShiftedLayout::Us, key, &name, false), name, "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn us_layout_covers_punctuation_reported_as_raw_codes() {
+        for (code, name) in UNKNOWN_KEY_CODES {
+            let key = Key::Unknown(*code);
+            assert_ne!(shift_symbol(ShiftedLayout::Us, key, name, true), *name, "{}", name);
+        }
+    }
+
+    #[test]
+    fn shift_leaves_keypad_and_letters_alone() {
+        // Both the shared "Num1" name and the --numpad-distinct one.
+        for (key, distinct) in NUMPAD_KEYS {
+            for name in map_key(*key).into_iter().chain(Some(distinct.to_string())) {
+                assert_eq!(shift_symbol(ShiftedLayout::Us, *key, &name, true), name, "{:?}", key);
+            }
+        }
+        assert_eq!(shift_symbol(ShiftedLayout::Us, Key::KeyA, "KeyA", true), "KeyA");
+        assert_eq!(shift_symbol(ShiftedLayout::Us, Key::Space, "Space", true), "Space");
+    }
+
+    fn bytes_of(frame: EncodedFrame) -> Vec<u8> {
+        match frame {
+            EncodedFrame::Text(text) => text.into_bytes(),
+            EncodedFrame::Binary(bytes) => bytes,
+        }
+    }
+
+    fn json(action: &Action) -> String {
+        String::from_utf8(bytes_of(serializer::Json.encode(action))).unwrap()
+    }
+
+    fn mouse_move(x: f64, y: f64) -> Action {
+        Action::new(ActionKind::MouseMove, ActionValue::Coords(Coords { x, y }))
+    }
+
+    #[test]
+    fn coordinates_are_rounded_to_the_precision() {
+        let expected = [
+            (0, r#"{"kind":"MouseMove","value":{"x":123,"y":-1}}"#),
+            (1, r#"{"kind":"MouseMove","value":{"x":123.5,"y":-0.5}}"#),
+            (2, r#"{"kind":"MouseMove","value":{"x":123.46,"y":-0.5}}"#),
+            (3, r#"{"kind":"MouseMove","value":{"x":123.457,"y":-0.5}}"#),
+            (MAX_COORD_PRECISION, r#"{"kind":"MouseMove","value":{"x":123.456789,"y":-0.5}}"#),
+        ];
+        for (decimals, text) in expected {
+            let mut action = mouse_move(123.456789, -0.5);
+            action.round_coords(decimals);
+            assert_eq!(json(&action), text, "{}", decimals);
+        }
+        let mut action = mouse_move(123.456789, 7.0);
+        action.round_coords(1);
+        assert_eq!(bytes_of(serializer::Plaintext.encode(&action)), b"MouseMove 123.5 7\n");
+    }
+
+    #[test]
+    fn whole_coordinates_are_written_as_integers() {
+        assert_eq!(json(&mouse_move(1920.0, 0.0)), r#"{"kind":"MouseMove","value":{"x":1920,"y":0}}"#);
+        assert_eq!(json(&mouse_move(-3.0, 2.5)), r#"{"kind":"MouseMove","value":{"x":-3,"y":2.5}}"#);
+    }
+
+    #[test]
+    fn polar_angles_keep_three_more_places() {
+        let mut action = Action::new(
+            ActionKind::MouseMove,
+            ActionValue::Polar(PolarCoords {
+                angle: 0.123456789,
+                radius: 512.345,
+            }),
+        );
+        action.round_coords(1);
+        assert_eq!(json(&action), r#"{"kind":"MouseMove","value":{"angle":0.1235,"radius":512.3}}"#);
+        // Other values are left alone.
+        let mut intensity = fixtures::sample(ActionKind::Intensity);
+        intensity.round_coords(0);
+        assert_eq!(json(&intensity), r#"{"kind":"Intensity","value":0.42}"#);
+    }
+
+    fn wire<'a>(renames: &'a rename::KindRenames, format: &str, max_bytes: usize) -> Wire<'a> {
+        Wire {
+            renames,
+            labels: None,
+            serializer: serializer::by_name(format).unwrap(),
+            envelope: false,
+            max_bytes,
+            network: None,
+        }
+    }
+
+    fn big_cue() -> Action {
+        Action::new(
+            ActionKind::Cue,
+            ActionValue::Json(serde_json::json!({ "name": "confetti", "data": "x".repeat(500) })),
+        )
+    }
+
+    #[test]
+    fn actions_under_the_limit_are_sent_as_is() {
+        let renames = rename::KindRenames::default();
+        let frame = wire(&renames, "json", 600).frame(big_cue()).unwrap();
+        assert_eq!(bytes_of(frame), bytes_of(serializer::Json.encode(&big_cue())));
+    }
+
+    #[test]
+    fn oversized_actions_become_a_notice() {
+        let renames = rename::KindRenames::default();
+        let full = serializer::Json.encode(&big_cue()).len();
+        let frame = wire(&renames, "json", 200).frame(big_cue()).unwrap();
+        let notice: serde_json::Value = serde_json::from_slice(&bytes_of(frame)).unwrap();
+        assert_eq!(
+            notice,
+            serde_json::json!({ "kind": "Oversized", "value": { "kind": "Cue", "bytes": full, "limit": 200 } })
+        );
+
+        let frame = wire(&renames, "msgpack", 200).frame(big_cue()).unwrap();
+        assert!(frame.len() <= 200);
+        let EncodedFrame::Binary(bytes) = frame else { panic!("msgpack is binary") };
+        let notice: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(notice["kind"], "Oversized");
+    }
+
+    #[test]
+    fn nothing_is_sent_when_even_the_notice_is_too_big() {
+        let renames = rename::KindRenames::default();
+        assert!(wire(&renames, "json", 20).frame(big_cue()).is_none());
+    }
+
+    #[test]
+    fn numpad_distinct_names_every_keypad_key() {
+        for (key, name) in NUMPAD_KEYS {
+            assert_eq!(map_key_with(*key, false, true).as_deref(), Some(*name));
+            // Replaying a recording brings back the keypad key.
+            assert_eq!(key_for_name(name), Some(*key));
+        }
+        assert_eq!(map_key_with(Key::Num1, false, true).as_deref(), Some("Num1"));
+        assert_eq!(map_key_with(Key::Return, false, true).as_deref(), Some("Return"));
+        assert_eq!(map_key_with(Key::Delete, false, true).as_deref(), Some("Delete"));
+    }
+
+    #[test]
+    fn keypad_digits_fold_into_the_main_row_by_default() {
+        for (n, (keypad, _)) in NUMPAD_KEYS.iter().take(10).enumerate() {
+            assert_eq!(map_key_with(*keypad, false, false), Some(format!("Num{}", n)));
+        }
+        assert_eq!(key_for_name("Num1"), Some(Key::Num1));
+    }
+
+    #[test]
+    fn preserve_side_keeps_left_and_right_apart() {
+        assert_eq!(map_key_with(Key::ShiftRight, false, false).as_deref(), Some("Shift"));
+        assert_eq!(map_key_with(Key::ShiftRight, true, false).as_deref(), Some("ShiftRight"));
+        assert_eq!(map_key_with(Key::AltGr, true, true).as_deref(), Some("AltRight"));
+        assert_eq!(key_for_name("ControlRight"), Some(Key::ControlRight));
+    }
+
+    /// Runs raw events through `translate_event`, draining the channel after each one
+    /// so the backlog never looks like a slow consumer.
+    fn translate_all(events: impl IntoIterator<Item = EventType>) -> Vec<Action> {
+        let (tx, mut rx) = broadcast::channel(64);
+        let mut actions = Vec::new();
+        for event_type in events {
+            let event = source::RawInputEvent {
+                time: SystemTime::now(),
+                name: None,
+                event_type,
+            };
+            translate_event(rawqueue::Queued { event, consumed: false, at: Instant::now() }, &tx);
+            while let Ok(action) = rx.try_recv() {
+                actions.push(action);
+            }
+        }
+        actions
+    }
+
+    #[test]
+    fn input_after_shutdown_is_ignored() {
+        let _serial = subscriptions::tests::SERIAL.lock().unwrap();
+        let _all = subscriptions::register(u64::MAX, subscriptions::mask_of(|_| true));
+        SHUTTING_DOWN.store(true, Ordering::SeqCst);
+        let after = translate_all([
+            EventType::KeyPress(Key::KeyA),
+            EventType::KeyRelease(Key::KeyA),
+            EventType::ButtonPress(rdev::Button::Left),
+            EventType::ButtonRelease(rdev::Button::Left),
+        ]);
+        SHUTTING_DOWN.store(false, Ordering::SeqCst);
+        assert!(after.is_empty());
+        // The same events go through once the flag is clear.
+        let before = translate_all([EventType::KeyPress(Key::KeyA), EventType::KeyRelease(Key::KeyA)]);
+        let kinds: Vec<ActionKind> = before.iter().map(|action| action.kind).collect();
+        assert_eq!(kinds, [ActionKind::KeyboardPress, ActionKind::KeyboardRelease]);
+    }
+
+    /// Micro-benchmark against the `Mutex<Instant>` throttle this replaced:
+    /// `cargo test --release -- --ignored --nocapture mouse_move_due`
+    #[test]
+    #[ignore]
+    fn mouse_move_due_against_a_mutex() {
+        static LAST: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+        fn mutex_due(throttle: Duration) -> bool {
+            let mut last = LAST.lock().unwrap();
+            if last.elapsed() < throttle {
+                metrics::throttled();
+                return false;
+            }
+            *last = Instant::now();
+            true
+        }
+        fn per_call(due: fn(Duration) -> bool) -> Duration {
+            const THREADS: u32 = 4;
+            const CALLS: u32 = 1_000_000;
+            let started = Instant::now();
+            let threads: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    std::thread::spawn(move || {
+                        (0..CALLS).filter(|_| due(std::hint::black_box(Duration::from_millis(16)))).count()
+                    })
+                })
+                .collect();
+            let sent: usize = threads.into_iter().map(|thread| thread.join().unwrap()).sum();
+            let elapsed = started.elapsed();
+            // The threads share one slot per window, whichever implementation hands it out.
+            assert!(sent as u128 <= elapsed.as_millis() / 16 + 1, "{} sent in {:?}", sent, elapsed);
+            elapsed / (THREADS * CALLS)
+        }
+        let _serial = subscriptions::tests::SERIAL.lock().unwrap();
+        let atomic = per_call(mouse_move_due);
+        let mutex = per_call(mutex_due);
+        println!("mouse_move_due: atomic {:?}/call, mutex {:?}/call", atomic, mutex);
+    }
+
+    #[test]
+    fn mock_input_becomes_actions_with_moves_throttled() {
+        use source::{EventSource, MockSource};
+        let _serial = subscriptions::tests::SERIAL.lock().unwrap();
+        let _all = subscriptions::register(u64::MAX, subscriptions::mask_of(|_| true));
+        let throttle = SETTINGS.mouse_throttle();
+        let ms = Duration::from_millis;
+        let source = MockSource {
+            events: vec![
+                (ms(0), EventType::MouseMove { x: 1.0, y: 1.0 }),
+                // Inside the throttle window of the first move.
+                (ms(1), EventType::MouseMove { x: 2.0, y: 2.0 }),
+                (ms(2), EventType::MouseMove { x: 3.0, y: 3.0 }),
+                (ms(3), EventType::ButtonPress(rdev::Button::Left)),
+                (ms(4), EventType::KeyPress(Key::KeyA)),
+                (ms(5), EventType::KeyRelease(Key::KeyA)),
+                (ms(6), EventType::ButtonRelease(rdev::Button::Left)),
+                (throttle * 3, EventType::MouseMove { x: 4.0, y: 4.0 }),
+            ],
+        };
+        // Whatever moved the cursor last is well outside the window.
+        std::thread::sleep(throttle);
+        let (tx, mut rx) = broadcast::channel(64);
+        source
+            .run(move |event| translate_event(rawqueue::Queued { event, consumed: false, at: Instant::now() }, &tx))
+            .unwrap();
+        let mut actions = Vec::new();
+        while let Ok(action) = rx.try_recv() {
+            actions.push(serde_json::to_value(&action).unwrap());
+        }
+        assert_eq!(
+            actions,
+            [
+                serde_json::json!({"kind": "MouseMove", "value": {"x": 1, "y": 1}}),
+                serde_json::json!({"kind": "MousePress", "value": "Mouse1"}),
+                serde_json::json!({"kind": "KeyboardPress", "value": "KeyA"}),
+                serde_json::json!({"kind": "KeyboardRelease", "value": "KeyA"}),
+                serde_json::json!({"kind": "MouseRelease", "value": "Mouse1"}),
+                serde_json::json!({"kind": "MouseMove", "value": {"x": 4, "y": 4}}),
+            ]
+        );
+    }
+
+    #[test]
+    fn ten_thousand_events_stay_within_the_callback_budget() {
+        let _serial = subscriptions::tests::SERIAL.lock().unwrap();
+        let _all = subscriptions::register(u64::MAX, subscriptions::mask_of(|_| true));
+        let budget = Duration::from_micros(100);
+        latency::configure(budget);
+        let (tx, mut rx) = broadcast::channel(64);
+        let mut took = Vec::with_capacity(10_000);
+        for n in 0..10_000 {
+            let event_type = match n % 4 {
+                0 => EventType::KeyPress(Key::KeyA),
+                1 => EventType::KeyRelease(Key::KeyA),
+                _ => EventType::MouseMove { x: n as f64, y: 0.0 },
+            };
+            let event = source::RawInputEvent {
+                time: SystemTime::now(),
+                name: None,
+                event_type,
+            };
+            let started = Instant::now();
+            translate_event(rawqueue::Queued { event, consumed: false, at: started }, &tx);
+            took.push(started.elapsed());
+            while rx.try_recv().is_ok() {}
+        }
+        // A percentile, so one preempted call on a busy machine doesn't fail the run.
+        took.sort_unstable();
+        assert!(took[9_899] < budget, "p99 {:?}, max {:?}", took[9_899], took[9_999]);
+        // The probe's own figures, over its last window, agree.
+        let status = latency::to_json();
+        assert_eq!(status["callback"]["samples"], 4096);
+        let p99 = status["callback"]["p99_us"].as_f64().unwrap();
+        assert!(p99 < budget.as_micros() as f64, "probe p99 {}µs", p99);
+    }
+}
@@ -0,0 +1,63 @@
+/*
+ * pet-input-server: src/longpress.rs
+ *
+ * Emits `KeyLongPress` once when a key has been held for `--long-press-ms`.
+ * Auto-repeat presses don't restart the hold; only a release re-arms the key.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+struct Hold {
+    since: Instant,
+    fired: bool,
+}
+
+/// Keys currently held down, by mapped name.
+static HELD: Lazy<Mutex<HashMap<String, Hold>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn on_key_press(name: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    HELD.lock().unwrap().entry(name.to_string()).or_insert_with(|| Hold {
+        since: Instant::now(),
+        fired: false,
+    });
+}
+
+pub fn on_key_release(name: &str) {
+    if ENABLED.load(Ordering::Relaxed) {
+        HELD.lock().unwrap().remove(name);
+    }
+}
+
+/// Starts the timer that checks held keys against `threshold`.
+pub fn spawn(threshold: Duration, broadcast_tx: broadcast::Sender<Action>) {
+    ENABLED.store(true, Ordering::Relaxed);
+    // Checking ten times per threshold keeps the event within 10% of the configured time.
+    let period = (threshold / 10).max(Duration::from_millis(10));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let mut due = Vec::new();
+            for (name, hold) in HELD.lock().unwrap().iter_mut() {
+                if !hold.fired && hold.since.elapsed() >= threshold {
+                    hold.fired = true;
+                    due.push(name.clone());
+                }
+            }
+            for name in due {
+                let _ = broadcast_tx.send(Action::new(ActionKind::KeyLongPress, ActionValue::String(name)));
+            }
+        }
+    });
+}
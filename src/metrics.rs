@@ -0,0 +1,253 @@
+/*
+ * pet-input-server: src/metrics.rs
+ *
+ * Session totals that are always counted, unlike the opt-in `Rates`: events
+ * emitted by kind, connections served, peak concurrent clients, lag
+ * incidents (a client falling behind the broadcast buffer), events shed to
+ * avoid them, debounced key chatter, and connections dropped before their
+ * handshake. Printed as a report on graceful shutdown, and written as JSON
+ * with `--stats-out`, along with a histogram of events per second and how
+ * many mouse moves the throttle held back. The JSON also carries an
+ * `activity` report built by `stats::Stats` from everything broadcast, the
+ * same code `analyze` runs over a recording, so the two always agree.
+ */
+
+use crate::kind::{ActionKind, COUNT};
+use crate::stats::Stats;
+use crate::Action;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+static STARTED: Lazy<Instant> = Lazy::new(Instant::now);
+/// One counter per kind, by `ActionKind::index`.
+static EVENTS: [AtomicU64; COUNT] = [const { AtomicU64::new(0) }; COUNT];
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static PEAK_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+static LAG_INCIDENTS: AtomicU64 = AtomicU64::new(0);
+static LAGGED_EVENTS: AtomicU64 = AtomicU64::new(0);
+static HANDSHAKE_TIMEOUTS: AtomicU64 = AtomicU64::new(0);
+static HANDSHAKE_REJECTIONS: AtomicU64 = AtomicU64::new(0);
+static DEBOUNCED: AtomicU64 = AtomicU64::new(0);
+/// Events dropped by `--shed-at-percent`, by `ActionKind::index`.
+static SHED: [AtomicU64; COUNT] = [const { AtomicU64::new(0) }; COUNT];
+static THROTTLED: AtomicU64 = AtomicU64::new(0);
+
+/// Lower bounds of the events-per-second histogram buckets.
+const RATE_BUCKETS: [u64; 8] = [0, 1, 10, 50, 100, 250, 500, 1000];
+/// Events emitted so far in the current second.
+static SECOND_EVENTS: AtomicU64 = AtomicU64::new(0);
+/// Seconds of the session, by the `RATE_BUCKETS` bucket their event count fell in.
+static RATE_SECONDS: [AtomicU64; RATE_BUCKETS.len()] = [const { AtomicU64::new(0) }; RATE_BUCKETS.len()];
+static PEAK_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// Bucket size of the live activity timeline, `analyze`'s default.
+const ACTIVITY_BUCKET_MS: u64 = 60_000;
+/// Everything broadcast this session, timed like a recording's `t`.
+static ACTIVITY: Lazy<Mutex<Stats>> = Lazy::new(|| Mutex::new(Stats::new(ACTIVITY_BUCKET_MS)));
+
+/// Starts the uptime clock, the once-a-second sampler for the rate histogram and the
+/// activity feed from `broadcast_rx`.
+pub fn start(mut broadcast_rx: broadcast::Receiver<Action>) {
+    Lazy::force(&STARTED);
+    tokio::spawn(async move {
+        loop {
+            match broadcast_rx.recv().await {
+                Ok(action) => {
+                    let t = STARTED.elapsed().as_millis() as u64;
+                    ACTIVITY.lock().unwrap().add(t, &action);
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        // The first tick is immediate.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let n = SECOND_EVENTS.swap(0, Ordering::Relaxed);
+            let bucket = RATE_BUCKETS.iter().rposition(|min| n >= *min).unwrap_or(0);
+            RATE_SECONDS[bucket].fetch_add(1, Ordering::Relaxed);
+            PEAK_RATE.fetch_max(n, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Counts one emitted event.
+pub fn count(kind: ActionKind) {
+    EVENTS[kind.index()].fetch_add(1, Ordering::Relaxed);
+    SECOND_EVENTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts one mouse move held back by the throttle.
+pub fn throttled() {
+    THROTTLED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a new connection; `concurrent` includes it.
+pub fn connected(concurrent: usize) {
+    CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+    PEAK_CLIENTS.fetch_max(concurrent, Ordering::Relaxed);
+}
+
+/// Counts one time a client lagged and lost `dropped` events.
+pub fn lagged(dropped: u64) {
+    LAG_INCIDENTS.fetch_add(1, Ordering::Relaxed);
+    LAGGED_EVENTS.fetch_add(dropped, Ordering::Relaxed);
+}
+
+/// Counts a connection dropped for missing the handshake deadline.
+pub fn handshake_timed_out() {
+    HANDSHAKE_TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a connection closed because too many were already mid-handshake.
+pub fn handshake_rejected() {
+    HANDSHAKE_REJECTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts one key press dropped as chatter.
+pub fn debounced() {
+    DEBOUNCED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn debounced_total() -> u64 {
+    DEBOUNCED.load(Ordering::Relaxed)
+}
+
+/// Counts one event dropped before the broadcast channel to protect discrete events.
+pub fn shed(kind: ActionKind) {
+    SHED[kind.index()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Shed events by kind, for `/status`.
+pub fn shed_json() -> serde_json::Value {
+    serde_json::Value::Object(per_kind_json(&SHED))
+}
+
+/// Pre-handshake connection counts for `/status`; `pending` is the current number.
+pub fn handshakes_json(pending: usize) -> serde_json::Value {
+    json!({
+        "pending": pending,
+        "timed_out": HANDSHAKE_TIMEOUTS.load(Ordering::Relaxed),
+        "rejected": HANDSHAKE_REJECTIONS.load(Ordering::Relaxed),
+    })
+}
+
+/// Prints the session summary.
+pub fn print_report() {
+    let uptime = STARTED.elapsed().as_secs();
+    println!("Session summary:");
+    println!(
+        "  Uptime: {}h {:02}m {:02}s",
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60
+    );
+    println!("  Connections served: {}", CONNECTIONS.load(Ordering::Relaxed));
+    println!("  Peak concurrent clients: {}", PEAK_CLIENTS.load(Ordering::Relaxed));
+    println!(
+        "  Lag incidents: {} ({} events dropped)",
+        LAG_INCIDENTS.load(Ordering::Relaxed),
+        LAGGED_EVENTS.load(Ordering::Relaxed)
+    );
+    println!(
+        "  Handshakes dropped: {} timed out, {} over the pending limit",
+        HANDSHAKE_TIMEOUTS.load(Ordering::Relaxed),
+        HANDSHAKE_REJECTIONS.load(Ordering::Relaxed)
+    );
+    let shed: u64 = SHED.iter().map(|counter| counter.load(Ordering::Relaxed)).sum();
+    if shed > 0 {
+        println!("  Events shed under backpressure: {}", shed);
+    }
+    let debounced = DEBOUNCED.load(Ordering::Relaxed);
+    if debounced > 0 {
+        println!("  Key presses debounced: {}", debounced);
+    }
+    println!("  Mouse moves throttled: {}", THROTTLED.load(Ordering::Relaxed));
+    let mut total = 0;
+    println!("  Events by kind:");
+    for (kind, counter) in ActionKind::ALL.iter().zip(EVENTS.iter()) {
+        let n = counter.load(Ordering::Relaxed);
+        if n > 0 {
+            println!("    {:<20} {}", kind, n);
+            total += n;
+        }
+    }
+    println!("    {:<20} {}", "total", total);
+}
+
+fn per_kind_json(counters: &[AtomicU64; COUNT]) -> serde_json::Map<String, serde_json::Value> {
+    ActionKind::ALL
+        .iter()
+        .zip(counters.iter())
+        .map(|(kind, counter)| (kind.to_string(), counter.load(Ordering::Relaxed)))
+        .filter(|(_, n)| *n > 0)
+        .map(|(kind, n)| (kind, json!(n)))
+        .collect()
+}
+
+/// The session summary as JSON, for `--stats-out`.
+pub fn to_json() -> serde_json::Value {
+    let uptime = STARTED.elapsed();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let histogram: Vec<serde_json::Value> = RATE_BUCKETS
+        .iter()
+        .enumerate()
+        .map(|(i, min)| {
+            let range = match (min, RATE_BUCKETS.get(i + 1)) {
+                (0, _) => "0".to_string(),
+                (min, Some(next)) => format!("{}-{}", min, next - 1),
+                (min, None) => format!("{}+", min),
+            };
+            json!({ "events_per_s": range, "seconds": RATE_SECONDS[i].load(Ordering::Relaxed) })
+        })
+        .collect();
+    let total: u64 = EVENTS.iter().map(|counter| counter.load(Ordering::Relaxed)).sum();
+    json!({
+        "started_ms": now.saturating_sub(uptime).as_millis() as u64,
+        "ended_ms": now.as_millis() as u64,
+        "duration_s": (uptime.as_secs_f64() * 1000.0).round() / 1000.0,
+        "connections": CONNECTIONS.load(Ordering::Relaxed),
+        "peak_clients": PEAK_CLIENTS.load(Ordering::Relaxed),
+        "events_by_kind": per_kind_json(&EVENTS),
+        "events_total": total,
+        "rate_histogram": histogram,
+        "peak_events_per_s": PEAK_RATE.load(Ordering::Relaxed),
+        "dropped": {
+            "mouse_moves_throttled": THROTTLED.load(Ordering::Relaxed),
+            "lag_incidents": LAG_INCIDENTS.load(Ordering::Relaxed),
+            "lagged_events": LAGGED_EVENTS.load(Ordering::Relaxed),
+            "shed": per_kind_json(&SHED),
+            "debounced": DEBOUNCED.load(Ordering::Relaxed),
+            "handshake_timeouts": HANDSHAKE_TIMEOUTS.load(Ordering::Relaxed),
+            "handshake_rejections": HANDSHAKE_REJECTIONS.load(Ordering::Relaxed),
+        },
+        "activity": ACTIVITY.lock().unwrap().to_json(),
+    })
+}
+
+/// Writes `to_json()` to `path` through a temporary file in the same directory and a
+/// rename, so a crash part way leaves the previous file (or none), never a truncated one.
+pub fn write_json(path: &Path) -> Result<(), String> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let text = serde_json::to_string_pretty(&to_json()).expect("stats serialize");
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp)?;
+        std::io::Write::write_all(&mut file, text.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)
+    };
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        format!("Failed to write {}: {}", path.display(), e)
+    })
+}
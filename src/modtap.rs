@@ -0,0 +1,208 @@
+/*
+ * pet-input-server: src/modtap.rs
+ *
+ * Sticky-key style taps for accessibility overlays (`--modifier-tap-ms`):
+ * a modifier pressed and released on its own, within the window, emits
+ *
+ *   {"kind":"ModifierTap","value":"Shift"}
+ *
+ * on release. Holding it through another key press, a click or a scroll
+ * (Shift+A, Ctrl+click, Ctrl+wheel) is using it as a modifier and emits
+ * nothing extra, as does pressing it while another modifier is already held
+ * (Ctrl+Shift). Auto-repeat presses while it is held don't restart the
+ * window. Two taps in a row are two events; a client wanting double taps
+ * times them itself.
+ *
+ * `--modifier-tap-keys` lists the eligible keys by mapped name (default
+ * Shift,Control,Alt,Meta); with `--preserve-side`, `Shift` also covers
+ * ShiftLeft and ShiftRight, and the event names the side.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+pub const DEFAULT_KEYS: &str = "Shift,Control,Alt,Meta";
+
+/// The modifier that may turn out to be a tap.
+struct Candidate {
+    name: String,
+    since: Instant,
+    /// Something happened while it was held, so its release is not a tap.
+    spoiled: bool,
+}
+
+struct Taps {
+    window: Duration,
+    keys: Vec<String>,
+    candidate: Option<Candidate>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TAPS: Lazy<Mutex<Taps>> = Lazy::new(|| {
+    Mutex::new(Taps {
+        window: Duration::ZERO,
+        keys: Vec::new(),
+        candidate: None,
+    })
+});
+
+impl Taps {
+    fn eligible(&self, name: &str) -> bool {
+        self.keys.iter().any(|key| {
+            name.strip_prefix(key.as_str())
+                .is_some_and(|side| matches!(side, "" | "Left" | "Right"))
+        })
+    }
+
+    fn press(&mut self, name: &str, allowed: bool, other_modifiers: bool, now: Instant) {
+        match &mut self.candidate {
+            // Auto-repeat.
+            Some(candidate) if candidate.name == name => return,
+            Some(candidate) => candidate.spoiled = true,
+            None => {}
+        }
+        if self.candidate.is_none() && allowed && !other_modifiers && self.eligible(name) {
+            self.candidate = Some(Candidate {
+                name: name.to_string(),
+                since: now,
+                spoiled: false,
+            });
+        }
+    }
+
+    fn interrupt(&mut self) {
+        if let Some(candidate) = &mut self.candidate {
+            candidate.spoiled = true;
+        }
+    }
+
+    /// True when releasing `name` completes a tap.
+    fn release(&mut self, name: &str, now: Instant) -> bool {
+        if self.candidate.as_ref().is_none_or(|candidate| candidate.name != name) {
+            return false;
+        }
+        let window = self.window;
+        self.candidate
+            .take()
+            .is_some_and(|candidate| !candidate.spoiled && now.duration_since(candidate.since) <= window)
+    }
+}
+
+pub fn configure(window: Duration, keys: Vec<String>) {
+    let mut taps = TAPS.lock().unwrap();
+    taps.window = window;
+    taps.keys = keys;
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Called for each key press (by mapped name). `allowed` is false for keys
+/// `--key-filter` hides, which may spoil a tap but never become one;
+/// `other_modifiers` says whether a different modifier is already down.
+pub fn on_key_press(name: &str, allowed: bool, other_modifiers: bool) {
+    if ENABLED.load(Ordering::Relaxed) {
+        TAPS.lock().unwrap().press(name, allowed, other_modifiers, Instant::now());
+    }
+}
+
+/// A click or scroll: the held modifier is being used with the mouse.
+pub fn interrupt() {
+    if ENABLED.load(Ordering::Relaxed) {
+        TAPS.lock().unwrap().interrupt();
+    }
+}
+
+/// Called for each key release; sends `ModifierTap` when it completes a tap.
+pub fn on_key_release(name: &str, broadcast_tx: &broadcast::Sender<Action>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let tapped = TAPS.lock().unwrap().release(name, Instant::now());
+    if tapped {
+        let _ = broadcast_tx.send(Action::new(ActionKind::ModifierTap, ActionValue::String(name.to_string())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(300);
+
+    fn taps() -> Taps {
+        Taps {
+            window: WINDOW,
+            keys: DEFAULT_KEYS.split(',').map(str::to_string).collect(),
+            candidate: None,
+        }
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn press_and_release_alone_is_a_tap() {
+        let (mut taps, t) = (taps(), Instant::now());
+        taps.press("Shift", true, false, t);
+        assert!(taps.release("Shift", ms(t, 120)));
+        // With --preserve-side the names carry the side.
+        taps.press("ControlRight", true, false, t);
+        assert!(taps.release("ControlRight", ms(t, 50)));
+    }
+
+    #[test]
+    fn holding_through_another_key_is_not_a_tap() {
+        let (mut taps, t) = (taps(), Instant::now());
+        taps.press("Shift", true, false, t);
+        taps.press("KeyA", true, true, ms(t, 40));
+        assert!(!taps.release("KeyA", ms(t, 60)));
+        assert!(!taps.release("Shift", ms(t, 80)));
+    }
+
+    #[test]
+    fn hidden_keys_and_mouse_use_spoil_the_tap() {
+        let (mut taps, t) = (taps(), Instant::now());
+        taps.press("Control", true, false, t);
+        taps.press("KeyC", false, true, ms(t, 10));
+        assert!(!taps.release("Control", ms(t, 20)));
+
+        taps.press("Control", true, false, t);
+        taps.interrupt();
+        assert!(!taps.release("Control", ms(t, 20)));
+    }
+
+    #[test]
+    fn tap_tap_is_two_taps() {
+        let (mut taps, t) = (taps(), Instant::now());
+        taps.press("Alt", true, false, t);
+        assert!(taps.release("Alt", ms(t, 80)));
+        taps.press("Alt", true, false, ms(t, 150));
+        assert!(taps.release("Alt", ms(t, 230)));
+    }
+
+    #[test]
+    fn auto_repeat_does_not_restart_the_window() {
+        let (mut taps, t) = (taps(), Instant::now());
+        taps.press("Meta", true, false, t);
+        taps.press("Meta", true, false, ms(t, 250));
+        taps.press("Meta", true, false, ms(t, 280));
+        assert!(!taps.release("Meta", ms(t, 400)));
+    }
+
+    #[test]
+    fn slow_releases_second_modifiers_and_other_keys_are_not_taps() {
+        let (mut taps, t) = (taps(), Instant::now());
+        taps.press("Shift", true, false, t);
+        assert!(!taps.release("Shift", ms(t, 301)));
+        // Shift pressed while Control is held: part of Ctrl+Shift.
+        taps.press("Shift", true, true, t);
+        assert!(!taps.release("Shift", ms(t, 10)));
+        taps.press("KeyA", true, false, t);
+        assert!(!taps.release("KeyA", ms(t, 10)));
+        assert!(!taps.eligible("ShiftLock"));
+    }
+}
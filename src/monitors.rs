@@ -0,0 +1,112 @@
+/*
+ * pet-input-server: src/monitors.rs
+ *
+ * Per-monitor geometry for `--probe` (`monitors` feature): each monitor's
+ * rectangle in virtual-desktop pixels, in the order the platform reports
+ * them. X11 asks Xinerama (one monitor covering the root window when it is
+ * inactive); Windows uses `EnumDisplayMonitors`.
+ */
+
+#[derive(Clone, Copy, Debug)]
+pub struct Monitor {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+    /// `None` where the platform has no notion of a primary monitor.
+    pub primary: Option<bool>,
+}
+
+#[cfg(target_os = "linux")]
+pub fn list() -> Result<Vec<Monitor>, String> {
+    use std::os::raw::c_int;
+    use x11::{xinerama, xlib};
+
+    unsafe {
+        let display = xlib::XOpenDisplay(std::ptr::null());
+        if display.is_null() {
+            return Err("cannot open the X display (monitor listing needs X11)".to_string());
+        }
+        let mut monitors = Vec::new();
+        if xinerama::XineramaIsActive(display) != 0 {
+            let mut count: c_int = 0;
+            let screens = xinerama::XineramaQueryScreens(display, &mut count);
+            if !screens.is_null() {
+                for screen in std::slice::from_raw_parts(screens, count.max(0) as usize) {
+                    monitors.push(Monitor {
+                        x: screen.x_org.into(),
+                        y: screen.y_org.into(),
+                        w: screen.width.max(0) as u32,
+                        h: screen.height.max(0) as u32,
+                        primary: None,
+                    });
+                }
+                xlib::XFree(screens.cast());
+            }
+        }
+        if monitors.is_empty() {
+            let screen = xlib::XDefaultScreen(display);
+            monitors.push(Monitor {
+                x: 0,
+                y: 0,
+                w: xlib::XDisplayWidth(display, screen).max(0) as u32,
+                h: xlib::XDisplayHeight(display, screen).max(0) as u32,
+                primary: None,
+            });
+        }
+        xlib::XCloseDisplay(display);
+        Ok(monitors)
+    }
+}
+
+#[cfg(windows)]
+pub fn list() -> Result<Vec<Monitor>, String> {
+    use winapi::shared::minwindef::{BOOL, LPARAM, TRUE};
+    use winapi::shared::windef::{HDC, HMONITOR, LPRECT};
+    use winapi::um::winuser::{EnumDisplayMonitors, GetMonitorInfoW, MONITORINFO, MONITORINFOF_PRIMARY};
+
+    unsafe extern "system" fn collect(monitor: HMONITOR, _: HDC, _: LPRECT, data: LPARAM) -> BOOL {
+        let monitors = &mut *(data as *mut Vec<Monitor>);
+        let mut info: MONITORINFO = std::mem::zeroed();
+        info.cbSize = std::mem::size_of::<MONITORINFO>() as u32;
+        if GetMonitorInfoW(monitor, &mut info) != 0 {
+            let rect = info.rcMonitor;
+            monitors.push(Monitor {
+                x: rect.left,
+                y: rect.top,
+                w: (rect.right - rect.left).max(0) as u32,
+                h: (rect.bottom - rect.top).max(0) as u32,
+                primary: Some(info.dwFlags & MONITORINFOF_PRIMARY != 0),
+            });
+        }
+        TRUE
+    }
+
+    let mut monitors: Vec<Monitor> = Vec::new();
+    let ok = unsafe {
+        EnumDisplayMonitors(
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            Some(collect),
+            &mut monitors as *mut Vec<Monitor> as LPARAM,
+        )
+    };
+    if ok == 0 {
+        return Err("EnumDisplayMonitors failed".to_string());
+    }
+    Ok(monitors)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn list() -> Result<Vec<Monitor>, String> {
+    Err("monitor listing is only implemented for X11 and Windows".to_string())
+}
+
+/// The smallest rectangle covering every monitor, as (x, y, w, h).
+pub fn virtual_bounds(monitors: &[Monitor]) -> Option<(i32, i32, u32, u32)> {
+    let left = monitors.iter().map(|m| m.x).min()?;
+    let top = monitors.iter().map(|m| m.y).min()?;
+    let right = monitors.iter().map(|m| m.x + m.w as i32).max()?;
+    let bottom = monitors.iter().map(|m| m.y + m.h as i32).max()?;
+    Some((left, top, (right - left) as u32, (bottom - top) as u32))
+}
@@ -0,0 +1,247 @@
+/*
+ * pet-input-server: src/mood.rs
+ *
+ * A coarse `Mood` hint for pets that don't want to derive one themselves:
+ * "away" after a long idle, "sleepy" after a short one, "playful" for a while
+ * after the mouse is shaken, "focused" while typing fast, otherwise "calm".
+ * Checked a few times a second and broadcast only when it changes.
+ */
+
+use crate::config::MoodConfig;
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+struct Tracker {
+    config: MoodConfig,
+    last_input: Instant,
+    /// Recent key presses, for typing speed.
+    keys: VecDeque<Instant>,
+    /// Recent horizontal direction changes of the cursor, for shake detection.
+    reversals: VecDeque<Instant>,
+    /// x where the current stroke started, and its direction (-1, 0 or 1).
+    stroke: Option<(f64, f64, i8)>,
+    playful_until: Option<Instant>,
+    current: Option<&'static str>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACKER: Lazy<Mutex<Tracker>> = Lazy::new(|| Mutex::new(Tracker::new(MoodConfig::default(), Instant::now())));
+
+impl Tracker {
+    fn new(config: MoodConfig, now: Instant) -> Tracker {
+        Tracker {
+            config,
+            last_input: now,
+            keys: VecDeque::new(),
+            reversals: VecDeque::new(),
+            stroke: None,
+            playful_until: None,
+            current: None,
+        }
+    }
+
+    fn on_key(&mut self, now: Instant) {
+        self.last_input = now;
+        self.keys.push_back(now);
+    }
+
+    /// A stroke is a run of motion in one horizontal direction; when one longer
+    /// than `shake_min_px` turns around, that's a reversal.
+    fn on_move(&mut self, x: f64, now: Instant) {
+        self.last_input = now;
+        let Some((start, last, direction)) = self.stroke else {
+            self.stroke = Some((x, x, 0));
+            return;
+        };
+        let step = if x > last { 1 } else if x < last { -1 } else { 0 };
+        if step == 0 {
+            return;
+        }
+        if direction != 0 && step != direction {
+            if (last - start).abs() >= self.config.shake_min_px {
+                self.reversals.push_back(now);
+            }
+            self.stroke = Some((last, x, step));
+        } else {
+            self.stroke = Some((start, x, step));
+        }
+        let window = Duration::from_millis(self.config.shake_window_ms);
+        while self.reversals.front().is_some_and(|t| now.duration_since(*t) > window) {
+            self.reversals.pop_front();
+        }
+        if self.reversals.len() >= self.config.shake_reversals {
+            self.reversals.clear();
+            self.playful_until = Some(now + Duration::from_secs_f64(self.config.playful_secs));
+        }
+    }
+
+    fn evaluate(&mut self, now: Instant) -> &'static str {
+        let window = Duration::from_secs_f64(self.config.typing_window_secs.max(0.1));
+        while self.keys.front().is_some_and(|t| now.duration_since(*t) > window) {
+            self.keys.pop_front();
+        }
+        let idle = now.duration_since(self.last_input).as_secs_f64();
+        let keys_per_sec = self.keys.len() as f64 / window.as_secs_f64();
+        if idle >= self.config.away_secs {
+            "away"
+        } else if idle >= self.config.sleepy_secs {
+            "sleepy"
+        } else if self.playful_until.is_some_and(|until| now < until) {
+            "playful"
+        } else if keys_per_sec >= self.config.focused_keys_per_sec {
+            "focused"
+        } else {
+            "calm"
+        }
+    }
+}
+
+/// Called for each key press; a no-op unless the feature is enabled.
+pub fn on_key_press() {
+    if ENABLED.load(Ordering::Relaxed) {
+        TRACKER.lock().unwrap().on_key(Instant::now());
+    }
+}
+
+/// Called for each cursor move; a no-op unless the feature is enabled.
+pub fn on_mouse_move(x: f64) {
+    if ENABLED.load(Ordering::Relaxed) {
+        TRACKER.lock().unwrap().on_move(x, Instant::now());
+    }
+}
+
+/// Called for clicks and scrolling, which only count as not being idle.
+pub fn on_activity() {
+    if ENABLED.load(Ordering::Relaxed) {
+        TRACKER.lock().unwrap().last_input = Instant::now();
+    }
+}
+
+fn action(mood: &str) -> Action {
+    Action::new(ActionKind::Mood, ActionValue::String(mood.to_string()))
+}
+
+/// The current mood, sent to clients as they connect so they needn't wait for a change.
+pub fn current() -> Option<Action> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    TRACKER.lock().unwrap().current.map(action)
+}
+
+/// Enables tracking and starts the ticker that broadcasts `Mood` when it changes.
+pub fn spawn(config: &MoodConfig, broadcast_tx: broadcast::Sender<Action>) {
+    let period = Duration::from_secs_f64(1.0 / config.rate_hz.max(0.1));
+    {
+        let mut tracker = TRACKER.lock().unwrap();
+        tracker.config = config.clone();
+        tracker.last_input = Instant::now();
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let changed = {
+                let mut tracker = TRACKER.lock().unwrap();
+                let mood = tracker.evaluate(Instant::now());
+                (tracker.current != Some(mood)).then(|| {
+                    tracker.current = Some(mood);
+                    mood
+                })
+            };
+            if let Some(mood) = changed {
+                let _ = broadcast_tx.send(action(mood));
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(n: f64) -> Duration {
+        Duration::from_secs_f64(n)
+    }
+
+    #[test]
+    fn idle_turns_sleepy_then_away_until_the_next_input() {
+        let config = MoodConfig {
+            sleepy_secs: 2.0,
+            away_secs: 10.0,
+            ..MoodConfig::default()
+        };
+        let start = Instant::now();
+        let mut tracker = Tracker::new(config, start);
+        assert_eq!(tracker.evaluate(start + secs(1.9)), "calm");
+        assert_eq!(tracker.evaluate(start + secs(2.0)), "sleepy");
+        assert_eq!(tracker.evaluate(start + secs(9.9)), "sleepy");
+        assert_eq!(tracker.evaluate(start + secs(10.0)), "away");
+        tracker.on_key(start + secs(11.0));
+        assert_eq!(tracker.evaluate(start + secs(11.0)), "calm");
+    }
+
+    #[test]
+    fn fast_typing_is_focused_while_it_lasts() {
+        let config = MoodConfig {
+            focused_keys_per_sec: 3.0,
+            typing_window_secs: 1.0,
+            ..MoodConfig::default()
+        };
+        let start = Instant::now();
+        let mut tracker = Tracker::new(config, start);
+        for n in 0..2 {
+            tracker.on_key(start + secs(0.1 * n as f64));
+        }
+        assert_eq!(tracker.evaluate(start + secs(0.2)), "calm");
+        tracker.on_key(start + secs(0.3));
+        assert_eq!(tracker.evaluate(start + secs(0.3)), "focused");
+        // The first presses fall out of the window.
+        assert_eq!(tracker.evaluate(start + secs(1.05)), "calm");
+    }
+
+    #[test]
+    fn shaking_the_mouse_is_playful_for_a_while() {
+        let config = MoodConfig {
+            shake_reversals: 2,
+            shake_min_px: 40.0,
+            playful_secs: 5.0,
+            ..MoodConfig::default()
+        };
+        let start = Instant::now();
+        let mut tracker = Tracker::new(config, start);
+        for (n, x) in [0.0, 100.0, 0.0, 100.0].into_iter().enumerate() {
+            tracker.on_move(x, start + Duration::from_millis(50 * n as u64));
+        }
+        assert_eq!(tracker.evaluate(start + secs(0.2)), "playful");
+        assert_eq!(tracker.evaluate(start + secs(5.2)), "calm");
+    }
+
+    #[test]
+    fn jitter_and_slow_shakes_are_not_playful() {
+        let config = MoodConfig {
+            shake_reversals: 2,
+            shake_window_ms: 500,
+            shake_min_px: 40.0,
+            ..MoodConfig::default()
+        };
+        let start = Instant::now();
+        let mut tracker = Tracker::new(config.clone(), start);
+        for (n, x) in [0.0, 10.0, 0.0, 10.0, 0.0].into_iter().enumerate() {
+            tracker.on_move(x, start + Duration::from_millis(50 * n as u64));
+        }
+        assert_eq!(tracker.evaluate(start + secs(0.3)), "calm");
+        let mut tracker = Tracker::new(config, start);
+        for (n, x) in [0.0, 100.0, 0.0, 100.0].into_iter().enumerate() {
+            tracker.on_move(x, start + Duration::from_millis(600 * n as u64));
+        }
+        assert_eq!(tracker.evaluate(start + secs(1.9)), "calm");
+    }
+}
@@ -0,0 +1,108 @@
+/*
+ * pet-input-server: src/mqtt.rs
+ *
+ * `--mqtt-broker <url>` (`mqtt` feature): publishes every broadcast action as
+ * JSON to `--mqtt-topic`, alongside the WebSocket clients. Events that occur
+ * while the broker is unreachable are dropped rather than replayed later.
+ */
+
+use crate::serializer::{self, EncodedFrame, Serializer};
+use crate::Action;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+const DEFAULT_PORT: u16 = 1883;
+/// Longest wait between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Accepts `mqtt://host[:port]`, `tcp://host[:port]` or a bare `host[:port]`.
+fn parse_broker(url: &str) -> Result<(String, u16), String> {
+    let rest = url
+        .strip_prefix("mqtt://")
+        .or_else(|| url.strip_prefix("tcp://"))
+        .unwrap_or(url)
+        .trim_end_matches('/');
+    if rest.contains("://") {
+        return Err(format!("Unsupported MQTT broker URL '{}' (use mqtt://host:port)", url));
+    }
+    let (host, port) = match rest.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| format!("Invalid port in MQTT broker URL '{}'", url))?;
+            (host, port)
+        }
+        None => (rest, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        return Err(format!("Missing host in MQTT broker URL '{}'", url));
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Validates the options and starts the connection and publishing tasks.
+pub fn spawn(broker: &str, topic: &str, broadcast_rx: broadcast::Receiver<Action>) -> Result<(), String> {
+    let (host, port) = parse_broker(broker)?;
+    if topic.is_empty() || topic.contains(['+', '#']) {
+        return Err(format!("Invalid MQTT topic '{}' (wildcards are not allowed)", topic));
+    }
+    let mut options = MqttOptions::new(format!("pet-input-server-{}", std::process::id()), host.clone(), port);
+    options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut eventloop) = AsyncClient::new(options, 256);
+    let connected = Arc::new(AtomicBool::new(false));
+
+    // rumqttc reconnects on the next poll after an error; we only pace the retries.
+    let connection = connected.clone();
+    tokio::spawn(async move {
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    println!("Connected to MQTT broker {}:{}", host, port);
+                    connection.store(true, Ordering::Relaxed);
+                    backoff = Duration::from_secs(1);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if connection.swap(false, Ordering::Relaxed) || backoff == Duration::from_secs(1) {
+                        eprintln!("MQTT broker {}:{} unavailable: {}; retrying.", host, port, e);
+                    }
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    });
+
+    let topic = topic.to_string();
+    tokio::spawn(publish(client, topic, connected, broadcast_rx));
+    Ok(())
+}
+
+async fn publish(
+    client: AsyncClient,
+    topic: String,
+    connected: Arc<AtomicBool>,
+    mut broadcast_rx: broadcast::Receiver<Action>,
+) {
+    loop {
+        let mut action = match broadcast_rx.recv().await {
+            Ok(action) => action,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if !connected.load(Ordering::Relaxed) || !crate::subscriptions::enabled(action.kind) {
+            continue;
+        }
+        action.stamp_source();
+        let payload = match serializer::Json.encode(&action) {
+            EncodedFrame::Text(text) => text.into_bytes(),
+            EncodedFrame::Binary(bytes) => bytes,
+        };
+        // A full request queue means the broker is slow; drop rather than stall.
+        let _ = client.try_publish(topic.as_str(), QoS::AtMostOnce, false, payload);
+    }
+}
@@ -0,0 +1,84 @@
+/*
+ * pet-input-server: src/namedpipe.rs
+ *
+ * `--named-pipe <name>` (Windows): serves the live input as newline-delimited
+ * JSON on `\\.\pipe\<name>`, for pet apps that can open a pipe more easily
+ * than a WebSocket. Each connected pipe client gets every broadcast action,
+ * one `{"kind":...,"value":...}` line each, like the `json` format; there is
+ * no handshake, filtering or reading from the client. A client that falls a
+ * whole buffer behind loses the oldest lines, as a WebSocket client would.
+ */
+
+use crate::serializer::{self, EncodedFrame, Serializer};
+use crate::Action;
+use tokio::io::AsyncWriteExt;
+use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+use tokio::sync::broadcast;
+
+const PIPE_PREFIX: &str = r"\\.\pipe\";
+
+/// `bongocat` becomes `\\.\pipe\bongocat`; a full pipe path is used as is.
+fn pipe_path(name: &str) -> String {
+    if name.starts_with(PIPE_PREFIX) {
+        name.to_string()
+    } else {
+        format!("{}{}", PIPE_PREFIX, name)
+    }
+}
+
+/// Creates the pipe and starts accepting clients. Fails if another process already owns the name.
+pub fn spawn(name: &str, broadcast_tx: broadcast::Sender<Action>) -> Result<(), String> {
+    let path = pipe_path(name);
+    let first = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&path)
+        .map_err(|e| format!("Failed to create named pipe {}: {}", path, e))?;
+    println!("Serving actions as JSON lines on named pipe {}", path);
+    tokio::spawn(accept(path, first, broadcast_tx));
+    Ok(())
+}
+
+/// A pipe instance serves one client, so a fresh one is created for the next before serving each.
+async fn accept(path: String, mut server: NamedPipeServer, broadcast_tx: broadcast::Sender<Action>) {
+    loop {
+        if let Err(e) = server.connect().await {
+            eprintln!("Named pipe {}: connection failed: {}", path, e);
+        } else {
+            println!("Named pipe client connected.");
+            tokio::spawn(serve(server, broadcast_tx.subscribe()));
+        }
+        server = match ServerOptions::new().create(&path) {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("Named pipe {}: cannot accept more clients: {}", path, e);
+                return;
+            }
+        };
+    }
+}
+
+async fn serve(mut pipe: NamedPipeServer, mut broadcast_rx: broadcast::Receiver<Action>) {
+    loop {
+        let mut action = match broadcast_rx.recv().await {
+            Ok(action) => action,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                crate::metrics::lagged(n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if !crate::subscriptions::enabled(action.kind) {
+            continue;
+        }
+        action.stamp_source();
+        let mut line = match serializer::Json.encode(&action) {
+            EncodedFrame::Text(text) => text.into_bytes(),
+            EncodedFrame::Binary(bytes) => bytes,
+        };
+        line.push(b'\n');
+        if pipe.write_all(&line).await.is_err() {
+            break;
+        }
+    }
+    println!("Named pipe client disconnected.");
+}
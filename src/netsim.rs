@@ -0,0 +1,42 @@
+/*
+ * pet-input-server: src/netsim.rs
+ *
+ * `--simulate-network DELAY_MS,LOSS_PCT`: a testing tool for client
+ * developers, NOT for production. Every outgoing WebSocket frame waits
+ * DELAY_MS before it is written (as on a slow link, so a busy stream falls
+ * behind) and LOSS_PCT percent of frames are silently dropped.
+ */
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SimulatedNetwork {
+    delay: Duration,
+    /// Drop probability, 0.0..=1.0.
+    loss: f64,
+}
+
+/// Parses `--simulate-network` values such as `150,5`.
+pub fn parse(value: &str) -> Result<SimulatedNetwork, String> {
+    let parsed = value
+        .split_once(',')
+        .and_then(|(delay, loss)| Some((delay.trim().parse::<u64>().ok()?, loss.trim().parse::<f64>().ok()?)));
+    match parsed {
+        Some((delay, loss)) if (0.0..=100.0).contains(&loss) => Ok(SimulatedNetwork {
+            delay: Duration::from_millis(delay),
+            loss: loss / 100.0,
+        }),
+        _ => Err(format!("expected DELAY_MS,LOSS_PCT (e.g. 150,5), got '{}'", value)),
+    }
+}
+
+impl SimulatedNetwork {
+    /// Applies the delay; `false` means this frame is lost and must not be sent.
+    pub async fn transmit(&self) -> bool {
+        if crate::rng::fraction() < self.loss {
+            return false;
+        }
+        tokio::time::sleep(self.delay).await;
+        true
+    }
+}
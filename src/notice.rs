@@ -0,0 +1,154 @@
+/*
+ * pet-input-server: src/notice.rs
+ *
+ * `ServerNotice`: one envelope for server-side conditions a frontend may want
+ * to show as a status badge instead of leaving them in the server console:
+ *
+ *   {"kind":"ServerNotice","value":{"code":"input_flood","severity":"warn",
+ *    "message":"...","active":true}}
+ *
+ * Subsystems call `raise` when a condition starts and `clear` when it ends
+ * (`active` false). Conditions still active are sent to clients as they
+ * connect. A few codes concern one client only (`quiet_hours`) and are sent
+ * to it directly, built with `action`. The older specific kinds (InputFailure, InputFlood, CoordPrivacy,
+ * CapturePaused) are still sent alongside for existing clients. The codes are
+ * listed in `/capabilities` under `notice_codes`.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::{Lazy, OnceCell};
+use serde_json::json;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
+    InputListenerDown,
+    InputFlood,
+    InputOverloaded,
+    BroadcastShedding,
+    CapturePaused,
+    CoordPrivacy,
+    RealtimeRefused,
+    QuietHours,
+}
+
+impl Code {
+    pub const ALL: [Code; 8] = [
+        Code::InputListenerDown,
+        Code::InputFlood,
+        Code::InputOverloaded,
+        Code::BroadcastShedding,
+        Code::CapturePaused,
+        Code::CoordPrivacy,
+        Code::RealtimeRefused,
+        Code::QuietHours,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::InputListenerDown => "input_listener_down",
+            Code::InputFlood => "input_flood",
+            Code::InputOverloaded => "input_overloaded",
+            Code::BroadcastShedding => "broadcast_shedding",
+            Code::CapturePaused => "capture_paused",
+            Code::CoordPrivacy => "coord_privacy",
+            Code::RealtimeRefused => "realtime_refused",
+            Code::QuietHours => "quiet_hours",
+        }
+    }
+
+    /// `error`: input has stopped; `warn`: input is degraded; `info`: working as configured.
+    pub fn severity(self) -> &'static str {
+        match self {
+            Code::InputListenerDown => "error",
+            Code::InputFlood | Code::InputOverloaded | Code::BroadcastShedding | Code::RealtimeRefused => "warn",
+            Code::CapturePaused | Code::CoordPrivacy | Code::QuietHours => "info",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Code::InputListenerDown => "the input listener died and could not be restarted; no more input events",
+            Code::InputFlood => "raw input is above --flood-ceiling; mouse moves are throttled harder",
+            Code::InputOverloaded => "input callbacks are arriving late; derived events are paused",
+            Code::BroadcastShedding => "a client is far behind; mouse moves are shed (--shed-at-percent)",
+            Code::CapturePaused => "capture was paused with the pause hotkey",
+            Code::CoordPrivacy => "cursor coordinates are coarsened or jittered",
+            Code::RealtimeRefused => "--realtime-input was requested but the OS refused it",
+            Code::QuietHours => "this client's ?quiet= hours are in effect; some updates are thinned out",
+        }
+    }
+}
+
+static TX: OnceCell<broadcast::Sender<Action>> = OnceCell::new();
+/// Conditions raised and not yet cleared, with their notices.
+static ACTIVE: Lazy<Mutex<Vec<(Code, Action)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Where notices go; raised before this, they only reach clients as they connect.
+pub fn init(broadcast_tx: broadcast::Sender<Action>) {
+    let _ = TX.set(broadcast_tx);
+}
+
+/// The notice for `code`; `active` false says the condition is over.
+pub fn action(code: Code, message: &str, active: bool) -> Action {
+    Action::new(
+        ActionKind::ServerNotice,
+        ActionValue::Json(json!({
+            "code": code.as_str(),
+            "severity": if active { code.severity() } else { "info" },
+            "message": message,
+            "active": active,
+        })),
+    )
+}
+
+fn send(action: Action) {
+    if let Some(tx) = TX.get() {
+        crate::rates::count(ActionKind::ServerNotice);
+        let _ = tx.send(action);
+    }
+}
+
+/// `code` has started (or changed); `message` says how, for people.
+pub fn raise(code: Code, message: &str) {
+    let action = action(code, message, true);
+    {
+        let mut active = ACTIVE.lock().unwrap();
+        active.retain(|(c, _)| *c != code);
+        active.push((code, action.clone()));
+    }
+    send(action);
+}
+
+/// `code` is over; a no-op if it wasn't raised.
+pub fn clear(code: Code, message: &str) {
+    let was_active = {
+        let mut active = ACTIVE.lock().unwrap();
+        let before = active.len();
+        active.retain(|(c, _)| *c != code);
+        active.len() != before
+    };
+    if was_active {
+        send(action(code, message, false));
+    }
+}
+
+/// Notices for conditions still in effect, sent to each client as it connects.
+pub fn active() -> Vec<Action> {
+    ACTIVE.lock().unwrap().iter().map(|(_, action)| action.clone()).collect()
+}
+
+/// The documented codes, for `/capabilities`.
+pub fn codes_json() -> serde_json::Value {
+    Code::ALL
+        .iter()
+        .map(|code| {
+            json!({
+                "code": code.as_str(),
+                "severity": code.severity(),
+                "description": code.description(),
+            })
+        })
+        .collect()
+}
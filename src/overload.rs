@@ -0,0 +1,96 @@
+/*
+ * pet-input-server: src/overload.rs
+ *
+ * Optional circuit breaker (`--overload-gap-ms`). While the mouse is moving,
+ * rdev callbacks normally arrive every few milliseconds; when the average gap
+ * between them grows past the threshold, the machine is struggling to deliver
+ * input, so we throttle mouse moves harder and skip derived work (regions,
+ * instrument notes, intensity) until the gap recovers.
+ */
+
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Gaps longer than this mean the mouse stopped, not that delivery is slow.
+const IDLE_GAP: Duration = Duration::from_millis(500);
+/// Weight of the newest gap in the moving average.
+const SMOOTHING: f64 = 0.1;
+
+/// Average gap (µs) that trips the breaker; 0 means disabled.
+static THRESHOLD_US: AtomicU64 = AtomicU64::new(0);
+static THROTTLE_MS: AtomicU64 = AtomicU64::new(0);
+static TRIPPED: AtomicBool = AtomicBool::new(false);
+
+struct Gaps {
+    last: Option<Instant>,
+    average_us: f64,
+}
+
+static GAPS: Lazy<Mutex<Gaps>> = Lazy::new(|| {
+    Mutex::new(Gaps {
+        last: None,
+        average_us: 0.0,
+    })
+});
+
+/// Enables the breaker; `throttle` is the minimum mouse-move interval while it is tripped.
+pub fn configure(threshold: Duration, throttle: Duration) {
+    THROTTLE_MS.store(throttle.as_millis() as u64, Ordering::Relaxed);
+    THRESHOLD_US.store(threshold.as_micros().max(1) as u64, Ordering::Relaxed);
+    println!(
+        "Overload breaker armed: trips when mouse callbacks average more than {}ms apart.",
+        threshold.as_millis()
+    );
+}
+
+/// True while the breaker is tripped and derived work should be skipped.
+pub fn tripped() -> bool {
+    TRIPPED.load(Ordering::Relaxed)
+}
+
+/// The mouse-move throttle to use right now.
+pub fn throttle(base: Duration) -> Duration {
+    if tripped() {
+        base.max(Duration::from_millis(THROTTLE_MS.load(Ordering::Relaxed)))
+    } else {
+        base
+    }
+}
+
+/// Called from the hot path for every raw mouse move.
+pub fn on_mouse_move() {
+    let threshold = THRESHOLD_US.load(Ordering::Relaxed);
+    if threshold == 0 {
+        return;
+    }
+    let now = Instant::now();
+    let mut gaps = GAPS.lock().unwrap();
+    let Some(last) = gaps.last.replace(now) else { return };
+    let gap = now.duration_since(last);
+    if gap >= IDLE_GAP {
+        return;
+    }
+    gaps.average_us += (gap.as_micros() as f64 - gaps.average_us) * SMOOTHING;
+
+    // Recover at half the threshold so the breaker doesn't flap.
+    let average = gaps.average_us;
+    if !tripped() && average > threshold as f64 {
+        TRIPPED.store(true, Ordering::Relaxed);
+        let message = format!(
+            "Input callbacks are lagging ({:.1}ms apart); throttling and pausing derived events.",
+            average / 1000.0
+        );
+        eprintln!("{}", message);
+        crate::notice::raise(crate::notice::Code::InputOverloaded, &message);
+    } else if tripped() && average < threshold as f64 / 2.0 {
+        TRIPPED.store(false, Ordering::Relaxed);
+        let message = format!(
+            "Input callbacks recovered ({:.1}ms apart); resuming normal processing.",
+            average / 1000.0
+        );
+        println!("{}", message);
+        crate::notice::clear(crate::notice::Code::InputOverloaded, &message);
+    }
+}
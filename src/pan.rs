@@ -0,0 +1,80 @@
+/*
+ * pet-input-server: src/pan.rs
+ *
+ * Middle-button drag ("pan", as in CAD tools). While the middle button is
+ * held, cursor travel is summed; once it passes `--pan-threshold-px` every
+ * sent MouseMove is followed by `MousePan` with the offset and path length
+ * since the press, and the release sends `MousePanEnd` with the totals.
+ * A middle click that jitters less than the threshold sends neither.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Path length (px) before a middle drag counts as a pan, stored as f64 bits.
+static THRESHOLD: AtomicU64 = AtomicU64::new(0);
+
+struct Pan {
+    start: (f64, f64),
+    last: (f64, f64),
+    distance: f64,
+}
+
+impl Pan {
+    fn value(&self) -> ActionValue {
+        ActionValue::Json(json!({
+            "dx": (self.last.0 - self.start.0).round(),
+            "dy": (self.last.1 - self.start.1).round(),
+            "distance": self.distance.round(),
+        }))
+    }
+
+    fn panning(&self) -> bool {
+        self.distance >= f64::from_bits(THRESHOLD.load(Ordering::Relaxed))
+    }
+}
+
+static PAN: Lazy<Mutex<Option<Pan>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn configure(threshold_px: f64) {
+    THRESHOLD.store(threshold_px.to_bits(), Ordering::Relaxed);
+}
+
+fn notice(kind: ActionKind, value: ActionValue) -> Action {
+    crate::rates::count(kind);
+    Action::new(kind, value)
+}
+
+/// Middle button pressed at `cursor` (the last known position, if any).
+pub fn on_press(cursor: Option<(f64, f64)>) {
+    let start = cursor.unwrap_or_default();
+    *PAN.lock().unwrap() = Some(Pan {
+        start,
+        last: start,
+        distance: 0.0,
+    });
+}
+
+/// Middle button released.
+pub fn on_release(broadcast_tx: &broadcast::Sender<Action>) {
+    if let Some(pan) = PAN.lock().unwrap().take() {
+        if pan.panning() {
+            let _ = broadcast_tx.send(notice(ActionKind::MousePanEnd, pan.value()));
+        }
+    }
+}
+
+/// Every raw mouse move; `sent` says whether it went out as a MouseMove.
+pub fn on_mouse_move(x: f64, y: f64, sent: bool, broadcast_tx: &broadcast::Sender<Action>) {
+    let mut pan = PAN.lock().unwrap();
+    let Some(pan) = pan.as_mut() else { return };
+    pan.distance += (x - pan.last.0).hypot(y - pan.last.1);
+    pan.last = (x, y);
+    if sent && pan.panning() {
+        let _ = broadcast_tx.send(notice(ActionKind::MousePan, pan.value()));
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * pet-input-server: src/pause.rs
+ *
+ * Pausing capture with a hotkey (`--pause-hotkey`, default
+ * Control+Alt+Shift+KeyP). The input listener keeps running while paused;
+ * only broadcasting stops, so the hotkey is still seen and can resume.
+ * Each toggle broadcasts `CapturePaused` true/false. The hotkey's own key
+ * press and release are never broadcast.
+ */
+
+use crate::combo::Combo;
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::OnceCell;
+use rdev::EventType;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+static HOTKEY: OnceCell<Combo> = OnceCell::new();
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
+struct Keys {
+    held: HashSet<String>,
+    /// The hotkey's key while it is down, so its release is dropped too.
+    hotkey_down: Option<String>,
+}
+
+static KEYS: Mutex<Option<Keys>> = Mutex::new(None);
+
+impl Keys {
+    /// Tracks one key event. `Some(toggle)` means it belongs to the hotkey (and is not
+    /// broadcast); `toggle` is set on the press that completes the combo.
+    fn see(&mut self, hotkey: &Combo, name: String, pressed: bool) -> Option<bool> {
+        if !pressed {
+            self.held.remove(&name);
+            return self.hotkey_down.take_if(|down| *down == name).map(|_| false);
+        }
+        self.held.insert(name.clone());
+        if self.hotkey_down.as_ref() == Some(&name) {
+            // Auto-repeat of the hotkey.
+            return Some(false);
+        }
+        if !hotkey.matches(&name, &self.held) {
+            return None;
+        }
+        self.hotkey_down = Some(name);
+        Some(true)
+    }
+}
+
+pub fn configure(hotkey: &str) -> Result<(), String> {
+    let _ = HOTKEY.set(Combo::parse(hotkey)?);
+    Ok(())
+}
+
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+/// Sees every key event, paused or not. `true` means the event belongs to the
+/// hotkey and must not be broadcast.
+pub fn on_event(event: &EventType, broadcast_tx: &broadcast::Sender<Action>) -> bool {
+    let Some(hotkey) = HOTKEY.get() else { return false };
+    let (key, pressed) = match event {
+        EventType::KeyPress(key) => (*key, true),
+        EventType::KeyRelease(key) => (*key, false),
+        _ => return false,
+    };
+    let Some(name) = crate::map_key(key) else { return false };
+    let seen = KEYS.lock().unwrap().get_or_insert_with(Keys::default).see(hotkey, name, pressed);
+    match seen {
+        None => return false,
+        Some(false) => return true,
+        Some(true) => {}
+    }
+    let paused = !PAUSED.fetch_xor(true, Ordering::Relaxed);
+    println!("Capture {} by hotkey.", if paused { "paused" } else { "resumed" });
+    if paused {
+        crate::notice::raise(crate::notice::Code::CapturePaused, "Capture paused by hotkey.");
+    } else {
+        crate::notice::clear(crate::notice::Code::CapturePaused, "Capture resumed by hotkey.");
+    }
+    let _ = broadcast_tx.send(Action::new(ActionKind::CapturePaused, ActionValue::Json(paused.into())));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `(key, pressed)` events past the default hotkey.
+    fn run(events: &[(&str, bool)]) -> Vec<Option<bool>> {
+        let hotkey = Combo::parse("Control+Alt+Shift+KeyP").unwrap();
+        let mut keys = Keys::default();
+        events.iter().map(|(name, pressed)| keys.see(&hotkey, name.to_string(), *pressed)).collect()
+    }
+
+    #[test]
+    fn the_hotkey_toggles_once_and_is_never_broadcast() {
+        let events = [
+            ("Control", true),
+            ("Alt", true),
+            ("Shift", true),
+            ("KeyP", true),
+            ("KeyP", true),
+            ("KeyP", false),
+            ("Shift", false),
+        ];
+        assert_eq!(run(&events), [None, None, None, Some(true), Some(false), Some(false), None]);
+    }
+
+    #[test]
+    fn the_key_alone_or_with_some_modifiers_passes() {
+        let events = [
+            ("KeyP", true),
+            ("KeyP", false),
+            ("Control", true),
+            ("Alt", true),
+            ("KeyP", true),
+            ("KeyP", false),
+        ];
+        assert_eq!(run(&events), [None; 6]);
+    }
+
+    #[test]
+    fn releasing_a_modifier_first_still_swallows_the_key_release() {
+        let events = [
+            ("Control", true),
+            ("Alt", true),
+            ("Shift", true),
+            ("KeyP", true),
+            ("Control", false),
+            ("KeyP", false),
+            // Pressing it again with Control up is just a key.
+            ("KeyP", true),
+        ];
+        assert_eq!(run(&events), [None, None, None, Some(true), None, Some(false), None]);
+    }
+}
@@ -0,0 +1,393 @@
+/*
+ * pet-input-server: src/power.rs
+ *
+ * System sleep/wake detection. Where the platform says so ahead of time,
+ * `SystemSuspend` goes out before the machine sleeps: on Linux from logind's
+ * `PrepareForSleep` signal (read through `gdbus monitor`, with a
+ * `systemd-inhibit` delay lock so clients get the notice before the
+ * suspend), on Windows from `WM_POWERBROADCAST` to a hidden window.
+ * `SystemResume` (with how long it slept) follows on wake, the slept
+ * interval is taken out of the idle clock, and the caller restarts the input
+ * listener.
+ *
+ * Without a subscription (no system bus, no window) a once-a-second ticker
+ * compares a clock that keeps counting through sleep with one that doesn't;
+ * a wall clock step or a stalled runtime moves both or neither, so only a
+ * real suspend shows up, though only after the fact.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+const TICK: Duration = Duration::from_secs(1);
+/// How much of a tick the machine has to have slept through to count as a suspend.
+const SUSPEND_GAP: Duration = Duration::from_secs(5);
+/// How long connections get to write `SystemSuspend` out before the machine is let go.
+#[cfg_attr(not(any(target_os = "linux", windows)), allow(dead_code))]
+const NOTICE_GRACE: Duration = Duration::from_millis(250);
+
+/// Total time spent suspended since startup, in ms.
+static SUSPENDED_MS: AtomicU64 = AtomicU64::new(0);
+
+pub fn suspended_total() -> Duration {
+    Duration::from_millis(SUSPENDED_MS.load(Ordering::Relaxed))
+}
+
+fn epoch_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+fn notice(kind: ActionKind, value: serde_json::Value) -> Action {
+    Action::new(kind, ActionValue::Json(value))
+}
+
+/// One reading of every clock involved.
+#[derive(Clone, Copy, Debug)]
+struct Reading {
+    wall: SystemTime,
+    instant: Instant,
+    /// Time since boot, counting sleep.
+    total: Duration,
+    /// Time since boot, not counting sleep.
+    awake: Duration,
+}
+
+impl Reading {
+    /// `None` where there is no pair of clocks to tell sleep apart with.
+    fn now() -> Option<Reading> {
+        let (total, awake) = sleep_clocks()?;
+        Some(Reading {
+            wall: SystemTime::now(),
+            instant: Instant::now(),
+            total,
+            awake,
+        })
+    }
+
+    /// How long the machine slept between `self` and `later`.
+    fn slept_until(&self, later: &Reading) -> Duration {
+        let total = later.total.saturating_sub(self.total);
+        total.saturating_sub(later.awake.saturating_sub(self.awake))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn sleep_clocks() -> Option<(Duration, Duration)> {
+    Some((clock(libc::CLOCK_BOOTTIME)?, clock(libc::CLOCK_MONOTONIC)?))
+}
+
+#[cfg(target_os = "macos")]
+fn sleep_clocks() -> Option<(Duration, Duration)> {
+    Some((clock(libc::CLOCK_MONOTONIC)?, clock(libc::CLOCK_UPTIME_RAW)?))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn clock(id: libc::clockid_t) -> Option<Duration> {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: `now` is a valid timespec for the call to fill in.
+    if unsafe { libc::clock_gettime(id, &mut now) } != 0 {
+        return None;
+    }
+    Some(Duration::new(now.tv_sec as u64, now.tv_nsec as u32))
+}
+
+#[cfg(windows)]
+fn sleep_clocks() -> Option<(Duration, Duration)> {
+    use winapi::um::realtimeapiset::QueryUnbiasedInterruptTime;
+    use winapi::um::sysinfoapi::GetTickCount64;
+    let mut unbiased = 0;
+    // SAFETY: both only write the value they return.
+    let (ticks, ok) = unsafe { (GetTickCount64(), QueryUnbiasedInterruptTime(&mut unbiased)) };
+    // The unbiased interrupt time is in 100ns units.
+    (ok != 0).then(|| (Duration::from_millis(ticks), Duration::from_nanos(unbiased * 100)))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", windows)))]
+fn sleep_clocks() -> Option<(Duration, Duration)> {
+    None
+}
+
+/// Turns suspend and resume into notices, idle-clock adjustments and the resume callback.
+struct Watcher {
+    broadcast_tx: broadcast::Sender<Action>,
+    on_resume: Box<dyn Fn(Duration) + Send + Sync>,
+    /// Set between `suspending` and `resumed`.
+    asleep: Mutex<Option<Reading>>,
+}
+
+impl Watcher {
+    /// The machine is about to sleep; the notice goes out now.
+    #[cfg_attr(not(any(target_os = "linux", windows)), allow(dead_code))]
+    fn suspending(&self) {
+        let reading = Reading::now();
+        *self.asleep.lock().unwrap() = reading;
+        println!("System suspending.");
+        let at = reading.map_or_else(SystemTime::now, |reading| reading.wall);
+        let _ = self.broadcast_tx.send(notice(ActionKind::SystemSuspend, json!({ "at_ms": epoch_ms(at) })));
+    }
+
+    /// The machine woke up after `suspending`.
+    #[cfg_attr(not(any(target_os = "linux", windows)), allow(dead_code))]
+    fn resumed(&self) {
+        let Some(before) = self.asleep.lock().unwrap().take() else {
+            // No reading to measure against; still say so, and restart the listener.
+            (self.on_resume)(Duration::ZERO);
+            let _ = self.broadcast_tx.send(notice(
+                ActionKind::SystemResume,
+                json!({ "at_ms": epoch_ms(SystemTime::now()), "suspended_s": 0 }),
+            ));
+            return;
+        };
+        match Reading::now() {
+            Some(after) => self.woke(&before, &after, before.slept_until(&after)),
+            None => self.woke(&before, &before, Duration::ZERO),
+        }
+    }
+
+    /// Reports a suspend from `before` to `after`; `slept` is how long it lasted.
+    fn woke(&self, before: &Reading, after: &Reading, slept: Duration) {
+        SUSPENDED_MS.fetch_add(slept.as_millis() as u64, Ordering::Relaxed);
+        // The part of the sleep `Instant` counted (none where it stops during sleep).
+        let instant_elapsed = after.instant.duration_since(before.instant);
+        let awake_elapsed = after.awake.saturating_sub(before.awake);
+        (self.on_resume)(instant_elapsed.saturating_sub(awake_elapsed));
+        println!("System resumed after {}s asleep.", slept.as_secs());
+        let _ = self.broadcast_tx.send(notice(
+            ActionKind::SystemResume,
+            json!({ "at_ms": epoch_ms(after.wall), "suspended_s": slept.as_secs() }),
+        ));
+    }
+
+    /// The fallback: reports a suspend found between two ticks, after the fact.
+    async fn poll(&self) {
+        let Some(mut last) = Reading::now() else {
+            eprintln!("No power events or sleep-aware clocks on this platform; suspends go unnoticed.");
+            return;
+        };
+        loop {
+            tokio::time::sleep(TICK).await;
+            let Some(now) = Reading::now() else { return };
+            let slept = last.slept_until(&now);
+            if slept >= SUSPEND_GAP {
+                let suspended = notice(ActionKind::SystemSuspend, json!({ "at_ms": epoch_ms(last.wall) }));
+                let _ = self.broadcast_tx.send(suspended);
+                self.woke(&last, &now, slept);
+            }
+            last = now;
+        }
+    }
+}
+
+/// Starts watching. `on_resume` runs on every wake with the part of the suspend the
+/// monotonic clock counted (zero where it stops during sleep), so idle timers can skip it.
+pub fn spawn(broadcast_tx: broadcast::Sender<Action>, on_resume: impl Fn(Duration) + Send + Sync + 'static) {
+    let watcher = Arc::new(Watcher {
+        broadcast_tx,
+        on_resume: Box::new(on_resume),
+        asleep: Mutex::new(None),
+    });
+    tokio::spawn(async move {
+        if !subscribe(&watcher).await {
+            watcher.poll().await;
+        }
+    });
+}
+
+/// Whether `PrepareForSleep` is in a line of `gdbus monitor` output, and with which value:
+/// `/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep (true,)`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn prepare_for_sleep(line: &str) -> Option<bool> {
+    let (_, args) = line.split_once("org.freedesktop.login1.Manager.PrepareForSleep (")?;
+    match args.split([',', ')']).next()? {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Follows logind until the monitor goes away. `false` if it never got going.
+#[cfg(target_os = "linux")]
+async fn subscribe(watcher: &Watcher) -> bool {
+    use std::process::Stdio;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::process::Command;
+
+    let monitor = Command::new("gdbus")
+        .args(["monitor", "--system", "--dest", "org.freedesktop.login1"])
+        .args(["--object-path", "/org/freedesktop/login1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let Ok(mut monitor) = monitor else {
+        return false;
+    };
+    let mut lines = BufReader::new(monitor.stdout.take().expect("piped")).lines();
+    // gdbus prints this first once it is connected to the system bus.
+    match lines.next_line().await {
+        Ok(Some(line)) if line.starts_with("Monitoring") => {}
+        _ => return false,
+    }
+    println!("Watching logind for sleep and wake.");
+    let mut inhibitor = inhibit();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match prepare_for_sleep(&line) {
+            Some(true) => {
+                watcher.suspending();
+                tokio::time::sleep(NOTICE_GRACE).await;
+                // Dropping the lock lets the suspend go ahead.
+                inhibitor = None;
+            }
+            Some(false) => {
+                watcher.resumed();
+                inhibitor = inhibit();
+            }
+            None => {}
+        }
+    }
+    drop(inhibitor);
+    eprintln!("Lost the logind monitor; falling back to watching the clocks.");
+    true
+}
+
+/// Holds a logind delay lock for as long as the returned process runs, so a suspend
+/// waits (up to logind's `InhibitDelayMaxSec`) for `SystemSuspend` to go out.
+#[cfg(target_os = "linux")]
+fn inhibit() -> Option<tokio::process::Child> {
+    tokio::process::Command::new("systemd-inhibit")
+        .args(["--what=sleep", "--mode=delay", "--who=pet-input-server"])
+        .args(["--why=Telling clients about the suspend", "cat"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .ok()
+}
+
+/// Runs a hidden window for `WM_POWERBROADCAST` on its own thread; `false` if it couldn't
+/// be created. A suspend waits for the window procedure, so the notice goes out first.
+#[cfg(windows)]
+async fn subscribe(watcher: &Arc<Watcher>) -> bool {
+    let (created_tx, created) = tokio::sync::oneshot::channel();
+    let watcher = watcher.clone();
+    std::thread::spawn(move || window::run(watcher, created_tx));
+    created.await.unwrap_or(false)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+async fn subscribe(_watcher: &Watcher) -> bool {
+    false
+}
+
+#[cfg(windows)]
+mod window {
+    use super::{Watcher, NOTICE_GRACE};
+    use once_cell::sync::OnceCell;
+    use std::ptr::{null, null_mut};
+    use std::sync::Arc;
+    use winapi::shared::minwindef::{LPARAM, LRESULT, TRUE, UINT, WPARAM};
+    use winapi::shared::windef::HWND;
+    use winapi::um::libloaderapi::GetModuleHandleW;
+    use winapi::um::winuser::{
+        CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, RegisterClassW, TranslateMessage, MSG,
+        PBT_APMRESUMEAUTOMATIC, PBT_APMSUSPEND, WM_POWERBROADCAST, WNDCLASSW,
+    };
+
+    static WATCHER: OnceCell<Arc<Watcher>> = OnceCell::new();
+
+    unsafe extern "system" fn window_proc(hwnd: HWND, message: UINT, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        if message != WM_POWERBROADCAST {
+            return DefWindowProcW(hwnd, message, wparam, lparam);
+        }
+        if let Some(watcher) = WATCHER.get() {
+            match wparam {
+                PBT_APMSUSPEND => {
+                    watcher.suspending();
+                    std::thread::sleep(NOTICE_GRACE);
+                }
+                PBT_APMRESUMEAUTOMATIC => watcher.resumed(),
+                _ => {}
+            }
+        }
+        TRUE as LRESULT
+    }
+
+    pub fn run(watcher: Arc<Watcher>, created: tokio::sync::oneshot::Sender<bool>) {
+        let _ = WATCHER.set(watcher);
+        let name: Vec<u16> = "PetInputServerPower\0".encode_utf16().collect();
+        // SAFETY: plain Win32 calls; `name` outlives the window, which lives as long as the thread.
+        unsafe {
+            let mut class: WNDCLASSW = std::mem::zeroed();
+            class.lpfnWndProc = Some(window_proc);
+            class.hInstance = GetModuleHandleW(null());
+            class.lpszClassName = name.as_ptr();
+            // Not message-only: those don't get broadcasts.
+            let window = if RegisterClassW(&class) == 0 {
+                null_mut()
+            } else {
+                let (parent, menu, param) = (null_mut(), null_mut(), null_mut());
+                CreateWindowExW(0, name.as_ptr(), name.as_ptr(), 0, 0, 0, 0, 0, parent, menu, class.hInstance, param)
+            };
+            let _ = created.send(!window.is_null());
+            if window.is_null() {
+                return;
+            }
+            println!("Watching WM_POWERBROADCAST for sleep and wake.");
+            let mut message: MSG = std::mem::zeroed();
+            while GetMessageW(&mut message, null_mut(), 0, 0) > 0 {
+                TranslateMessage(&message);
+                DispatchMessageW(&message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(wall_s: u64, total_s: u64, awake_s: u64) -> Reading {
+        Reading {
+            wall: UNIX_EPOCH + Duration::from_secs(wall_s),
+            instant: Instant::now(),
+            total: Duration::from_secs(total_s),
+            awake: Duration::from_secs(awake_s),
+        }
+    }
+
+    #[test]
+    fn only_time_asleep_counts_as_a_suspend() {
+        let before = reading(1_000, 100, 100);
+        // Asleep for a minute: the boot clock ran on, the awake clock didn't.
+        assert_eq!(before.slept_until(&reading(1_061, 161, 101)), Duration::from_secs(60));
+        // The runtime stalled for ten seconds: both clocks ran.
+        assert_eq!(before.slept_until(&reading(1_010, 110, 110)), Duration::ZERO);
+        // NTP stepped the wall clock an hour forward: neither clock noticed.
+        assert_eq!(before.slept_until(&reading(4_601, 101, 101)), Duration::ZERO);
+    }
+
+    #[test]
+    fn the_clocks_here_agree_while_awake() {
+        let Some(before) = Reading::now() else { return };
+        std::thread::sleep(Duration::from_millis(50));
+        let after = Reading::now().unwrap();
+        assert!(before.slept_until(&after) < Duration::from_millis(20), "{:?} {:?}", before, after);
+    }
+
+    #[test]
+    fn prepare_for_sleep_is_read_from_gdbus_monitor_lines() {
+        let line = |value: &str| {
+            format!("/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep ({},)", value)
+        };
+        assert_eq!(prepare_for_sleep(&line("true")), Some(true));
+        assert_eq!(prepare_for_sleep(&line("false")), Some(false));
+        let other = "/org/freedesktop/login1: org.freedesktop.login1.Manager.SessionNew ('3', objectpath '/x')";
+        assert_eq!(prepare_for_sleep(other), None);
+        assert_eq!(prepare_for_sleep("Monitoring signals on object /org/freedesktop/login1"), None);
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * pet-input-server: src/prefs.rs
+ *
+ * Stored connection preferences per client name (`?name=`). A client that
+ * sends `{"cmd":"save_prefs"}` has its current query options saved; on its
+ * next connection they apply as if it had passed them again (anything it
+ * does pass still wins). Clients sharing a name share preferences.
+ * Kept in the `--prefs-file` JSON file when given, otherwise in memory.
+ */
+
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Query options that can be stored.
+pub const KEYS: &[&str] = &[
+    "format", "kinds", "mouse", "precision", "max_rate", "preview", "queue", "envelope", "snapshot", "quiet",
+    "quiet_mouse_ms", "quiet_intensity_ms", "quiet_stats_ms",
+];
+
+pub type Prefs = BTreeMap<String, String>;
+
+/// The storable options in `query`.
+pub fn options_of(query: &str) -> Prefs {
+    KEYS.iter()
+        .filter_map(|key| Some((key.to_string(), crate::http::query_param(query, key)?.to_string())))
+        .collect()
+}
+
+/// `query` extended with the options in `prefs` that it doesn't set itself.
+pub fn fill_in(query: &str, prefs: &Prefs) -> String {
+    let mut merged = query.to_string();
+    for (key, value) in prefs {
+        if crate::http::query_param(query, key).is_none() {
+            if !merged.is_empty() {
+                merged.push('&');
+            }
+            merged.push_str(&format!("{}={}", key, value));
+        }
+    }
+    merged
+}
+
+pub struct PrefsStore {
+    path: Option<PathBuf>,
+    entries: Mutex<BTreeMap<String, Prefs>>,
+}
+
+impl PrefsStore {
+    pub fn load(path: Option<PathBuf>) -> Result<PrefsStore, String> {
+        let entries = match &path {
+            Some(path) if path.exists() => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read prefs file {}: {}", path.display(), e))?;
+                serde_json::from_str(&text)
+                    .map_err(|e| format!("Invalid prefs file {}: {}", path.display(), e))?
+            }
+            _ => BTreeMap::new(),
+        };
+        Ok(PrefsStore {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Extends `query` with the stored options for `name` that it doesn't set itself.
+    /// Returns the new query and the stored preferences, if there were any.
+    pub fn apply(&self, name: &str, query: &str) -> (String, Option<serde_json::Value>) {
+        let entries = self.entries.lock().unwrap();
+        let Some(prefs) = entries.get(name) else {
+            return (query.to_string(), None);
+        };
+        (fill_in(query, prefs), Some(json!(prefs)))
+    }
+
+    /// Stores the preference options found in `query` under `name`.
+    pub fn save(&self, name: &str, query: &str) -> serde_json::Value {
+        let prefs = options_of(query);
+        let saved = json!(prefs);
+        self.entries.lock().unwrap().insert(name.to_string(), prefs);
+        self.persist();
+        saved
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!(*self.entries.lock().unwrap())
+    }
+
+    /// Forgets `name`; `false` if nothing was stored for it.
+    pub fn delete(&self, name: &str) -> bool {
+        let removed = self.entries.lock().unwrap().remove(name).is_some();
+        if removed {
+            self.persist();
+        }
+        removed
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else { return };
+        let text = serde_json::to_string_pretty(&*self.entries.lock().unwrap()).expect("prefs serialize");
+        if let Err(e) = std::fs::write(path, text) {
+            eprintln!("Failed to write prefs file {}: {}", path.display(), e);
+        }
+    }
+}
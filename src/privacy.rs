@@ -0,0 +1,59 @@
+/*
+ * pet-input-server: src/privacy.rs
+ *
+ * Coarse cursor positions for public streams, where the exact position can
+ * give away what is on screen. Off by default. `--privacy-grid-px N` snaps
+ * every cursor position to the centre of its N-pixel cell;
+ * `--privacy-jitter-px N` moves it by up to N pixels in each axis at random.
+ *
+ * It is applied to the raw event, before anything else sees it, so the
+ * broadcast, recordings, regions, pans and the position replayed to new
+ * clients all agree. Clicks carry no position; frontends place them at the
+ * last MouseMove, which has been reduced the same way. Clients are told
+ * with a `CoordPrivacy` notice when they connect.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::OnceCell;
+use rdev::EventType;
+use serde_json::json;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Mode {
+    Grid(f64),
+    Jitter(f64),
+}
+
+static MODE: OnceCell<Mode> = OnceCell::new();
+
+pub fn configure(mode: Mode) {
+    let _ = MODE.set(mode);
+}
+
+/// `event` with its cursor position reduced; other events pass through.
+pub fn apply(event: EventType) -> EventType {
+    match (event, MODE.get()) {
+        (EventType::MouseMove { x, y }, Some(Mode::Grid(cell))) => EventType::MouseMove {
+            x: ((x / cell).floor() + 0.5) * cell,
+            y: ((y / cell).floor() + 0.5) * cell,
+        },
+        (EventType::MouseMove { x, y }, Some(Mode::Jitter(bound))) => EventType::MouseMove {
+            x: x + (crate::rng::fraction() * 2.0 - 1.0) * bound,
+            y: y + (crate::rng::fraction() * 2.0 - 1.0) * bound,
+        },
+        (event, _) => event,
+    }
+}
+
+/// What is in effect, for `/status` and the connect notice; `None` when off.
+pub fn to_json() -> Option<serde_json::Value> {
+    MODE.get().map(|mode| match mode {
+        Mode::Grid(cell) => json!({ "grid_px": cell }),
+        Mode::Jitter(bound) => json!({ "jitter_px": bound }),
+    })
+}
+
+/// The `CoordPrivacy` notice sent to each client on connect, if privacy is on.
+pub fn notice() -> Option<Action> {
+    to_json().map(|value| Action::new(ActionKind::CoordPrivacy, ActionValue::Json(value)))
+}
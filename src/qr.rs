@@ -0,0 +1,85 @@
+/*
+ * pet-input-server: src/qr.rs
+ *
+ * `--qr`: prints the connection URL as a terminal QR code at startup, so a
+ * phone overlay can scan it instead of typing `ws://192.168.1.x:8080/?token=`.
+ * `GET /qr` (guarded) renders it again, for a terminal that has scrolled away.
+ *
+ * The host is the address this machine uses for its default route, which on
+ * a machine with several interfaces (Wi-Fi plus Ethernet, Docker bridges) is
+ * the one the rest of the LAN can normally reach. A VPN that takes the
+ * default route wins too; `--qr-host` overrides the guess.
+ */
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+
+/// The source address the OS picks for traffic to `remote`. Connecting a UDP
+/// socket only consults the routing table; nothing is sent.
+fn route_source(bind: IpAddr, remote: IpAddr) -> Option<IpAddr> {
+    let socket = UdpSocket::bind((bind, 0)).ok()?;
+    socket.connect((remote, 9)).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    let usable = !ip.is_loopback() && !ip.is_unspecified() && !is_link_local(ip);
+    usable.then_some(ip)
+}
+
+fn is_link_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+/// The best guess at this machine's LAN address: IPv4 first, since that is
+/// what phones on a home network resolve, then IPv6.
+pub fn lan_ip() -> Option<IpAddr> {
+    route_source(Ipv4Addr::UNSPECIFIED.into(), Ipv4Addr::new(192, 0, 2, 1).into())
+        .or_else(|| route_source(Ipv6Addr::UNSPECIFIED.into(), Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into()))
+}
+
+/// `ws://host:port/`, plus `?token=` when the default room needs one.
+pub fn connection_url(host: &str, port: u16, token: Option<&str>) -> String {
+    let host = match host.parse::<Ipv6Addr>() {
+        Ok(_) => format!("[{}]", host),
+        Err(_) => host.to_string(),
+    };
+    match token {
+        Some(token) => format!("ws://{}:{}/?token={}", host, port, token),
+        None => format!("ws://{}:{}/", host, port),
+    }
+}
+
+/// The URL for this run, with `host` (`--qr-host`) or the detected LAN address.
+pub fn url(host: Option<&str>, port: u16, token: Option<&str>) -> String {
+    let host = match host {
+        Some(host) => host.to_string(),
+        None => lan_ip().map_or_else(
+            || {
+                eprintln!("Could not find a LAN address for the QR code; using 127.0.0.1 (see --qr-host).");
+                Ipv4Addr::LOCALHOST.to_string()
+            },
+            |ip| ip.to_string(),
+        ),
+    };
+    connection_url(&host, port, token)
+}
+
+/// `text` as a QR code in Unicode half blocks, light on dark so it scans from a dark terminal.
+pub fn render(text: &str) -> Result<String, String> {
+    let code = QrCode::new(text.as_bytes()).map_err(|e| format!("Cannot encode {} as a QR code: {}", text, e))?;
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
+
+/// Prints the banner for `--qr`.
+pub fn print(url: &str) {
+    match render(url) {
+        Ok(code) => println!("Scan to connect to {}:\n{}", url, code),
+        Err(e) => eprintln!("{}", e),
+    }
+}
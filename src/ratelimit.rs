@@ -0,0 +1,59 @@
+/*
+ * pet-input-server: src/ratelimit.rs
+ *
+ * Per-client event rate cap, requested with `{"max_rate":60}`. A token
+ * bucket refilled at `max_rate` per second (one second of burst). Mouse
+ * moves need a whole token and are the first to be dropped; other events
+ * may run the bucket up to one burst into debt before they are dropped too.
+ *
+ * The preview feed (`{"preview":true}` or `?preview=1`) is the same bucket at
+ * a fixed `PREVIEW_RATE`, on top of any `max_rate`: a lightweight overview
+ * for dashboards that don't need every event.
+ */
+
+use crate::ActionKind;
+use std::time::Instant;
+
+/// Events per second in the preview feed.
+const PREVIEW_RATE: f64 = 4.0;
+
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64) -> Self {
+        TokenBucket {
+            rate,
+            tokens: rate,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// A bucket for the preview feed.
+    pub fn preview() -> Self {
+        Self::new(PREVIEW_RATE)
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Takes a token for an event of `kind`; `false` means the event should be dropped.
+    pub fn admit(&mut self, kind: ActionKind) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+
+        let floor = if kind == ActionKind::MouseMove { 1.0 } else { 1.0 - self.rate };
+        if self.tokens >= floor {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,70 @@
+/*
+ * pet-input-server: src/rates.rs
+ *
+ * Opt-in "Rates" ticker: once a second, broadcasts how many input-derived
+ * events of each kind were emitted during the last second, plus a total.
+ * Clients subscribe with `?kinds=Rates,...`; while nobody has, nothing is
+ * counted and the ticker stays idle.
+ */
+
+use crate::kind::COUNT;
+use crate::{Action, ActionKind, ActionValue};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+static SUBSCRIBERS: AtomicUsize = AtomicUsize::new(0);
+/// One counter per kind, by `ActionKind::index`.
+static COUNTS: [AtomicU64; COUNT] = [const { AtomicU64::new(0) }; COUNT];
+
+/// Counts one emitted event. Called from the hot path, so it bails out early when unused;
+/// the session totals in `metrics` are kept either way.
+pub fn count(kind: ActionKind) {
+    crate::metrics::count(kind);
+    if SUBSCRIBERS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    COUNTS[kind.index()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Keeps counting enabled for as long as it is held by a connection.
+pub struct Subscription(());
+
+pub fn subscribe() -> Subscription {
+    if SUBSCRIBERS.fetch_add(1, Ordering::Relaxed) == 0 {
+        // Forget anything left over from the previous subscriber.
+        for counter in COUNTS.iter() {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+    Subscription(())
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        SUBSCRIBERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn spawn(broadcast_tx: broadcast::Sender<Action>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if SUBSCRIBERS.load(Ordering::Relaxed) == 0 {
+                continue;
+            }
+            let mut rates = serde_json::Map::new();
+            let mut total = 0;
+            for (kind, counter) in ActionKind::ALL.iter().zip(COUNTS.iter()) {
+                let n = counter.swap(0, Ordering::Relaxed);
+                if n > 0 {
+                    rates.insert(kind.to_string(), n.into());
+                    total += n;
+                }
+            }
+            rates.insert("total".to_string(), total.into());
+            let _ = broadcast_tx.send(Action::new(ActionKind::Rates, ActionValue::Json(rates.into())));
+        }
+    });
+}
@@ -0,0 +1,169 @@
+/*
+ * pet-input-server: src/rawqueue.rs
+ *
+ * Hand-off between the OS hook and the pipeline. The hook callback only
+ * pushes the raw event into a bounded queue; one translation thread drains
+ * it and does the mapping, throttling and broadcasting, so a slow pipeline
+ * stage can't delay the OS's input delivery.
+ *
+ * When the queue is full the oldest queued MouseMove is dropped to make
+ * room: a later move supersedes it anyway. With no move queued, the oldest
+ * event goes instead. Drops are counted and shown in `/status`.
+ */
+
+use crate::source::RawInputEvent;
+use once_cell::sync::Lazy;
+use rdev::EventType;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+
+/// Events the queue holds before it starts dropping.
+const CAPACITY: usize = 4096;
+
+/// A raw event and whether a grabbing source kept it from the OS.
+pub struct Queued {
+    pub event: RawInputEvent,
+    pub consumed: bool,
+    /// When the hook queued it, for `--monotonic-ns`.
+    pub at: Instant,
+}
+
+static QUEUE: Lazy<Mutex<VecDeque<Queued>>> = Lazy::new(|| Mutex::new(VecDeque::with_capacity(CAPACITY)));
+static READY: Condvar = Condvar::new();
+static DROPPED_MOVES: AtomicU64 = AtomicU64::new(0);
+static DROPPED_OTHER: AtomicU64 = AtomicU64::new(0);
+static HIGH_WATER: AtomicU64 = AtomicU64::new(0);
+
+/// Queues one event; called from the OS hook, so it never blocks on the pipeline.
+pub fn push(event: RawInputEvent, consumed: bool) {
+    let at = Instant::now();
+    let mut queue = QUEUE.lock().unwrap();
+    match make_room(&mut queue) {
+        Some(true) => DROPPED_MOVES.fetch_add(1, Ordering::Relaxed),
+        Some(false) => DROPPED_OTHER.fetch_add(1, Ordering::Relaxed),
+        None => 0,
+    };
+    queue.push_back(Queued { event, consumed, at });
+    HIGH_WATER.fetch_max(queue.len() as u64, Ordering::Relaxed);
+    drop(queue);
+    READY.notify_one();
+}
+
+/// Drops one event if the queue is full: the oldest MouseMove, else the oldest event.
+/// Returns whether a move was dropped, or `None` if there was room.
+fn make_room(queue: &mut VecDeque<Queued>) -> Option<bool> {
+    if queue.len() < CAPACITY {
+        return None;
+    }
+    match queue.iter().position(|q| matches!(q.event.event_type, EventType::MouseMove { .. })) {
+        Some(index) => {
+            queue.remove(index);
+            Some(true)
+        }
+        None => {
+            queue.pop_front();
+            Some(false)
+        }
+    }
+}
+
+/// Starts the translation thread, which runs `process` on every queued event in order.
+/// A panic while processing one event is logged and the thread carries on with the next.
+pub fn spawn(process: impl Fn(Queued) + Send + 'static) {
+    std::thread::spawn(move || {
+        crate::realtime::elevate("translation");
+        loop {
+            let next = {
+                let mut queue = QUEUE.lock().unwrap();
+                loop {
+                    match queue.pop_front() {
+                        Some(next) => break next,
+                        None => queue = READY.wait(queue).unwrap(),
+                    }
+                }
+            };
+            if std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| process(next))).is_err() {
+                eprintln!("Translating an input event panicked; skipping it.");
+            }
+        }
+    });
+}
+
+/// Queue depth and drop counters for `/status`.
+pub fn to_json() -> serde_json::Value {
+    json!({
+        "depth": QUEUE.lock().unwrap().len(),
+        "capacity": CAPACITY,
+        "high_water": HIGH_WATER.load(Ordering::Relaxed),
+        "dropped_mouse_moves": DROPPED_MOVES.load(Ordering::Relaxed),
+        "dropped_other": DROPPED_OTHER.load(Ordering::Relaxed),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdev::{Event, Key};
+    use std::sync::mpsc;
+    use std::time::{Duration, SystemTime};
+
+    fn raw(event_type: EventType) -> RawInputEvent {
+        Event {
+            time: SystemTime::now(),
+            name: None,
+            event_type,
+        }
+    }
+
+    fn queued(event_type: EventType) -> Queued {
+        Queued {
+            event: raw(event_type),
+            consumed: false,
+            at: Instant::now(),
+        }
+    }
+
+    fn key(n: usize) -> EventType {
+        EventType::KeyPress(Key::Unknown(n as u32))
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest_move_first() {
+        let mut queue: VecDeque<Queued> = (0..CAPACITY).map(|n| queued(key(n))).collect();
+        queue[10] = queued(EventType::MouseMove { x: 1.0, y: 1.0 });
+        queue[20] = queued(EventType::MouseMove { x: 2.0, y: 2.0 });
+        assert_eq!(make_room(&mut queue), Some(true));
+        assert_eq!(queue.len(), CAPACITY - 1);
+        assert!(matches!(queue[19].event.event_type, EventType::MouseMove { x: 2.0, .. }));
+        // Room now: nothing else goes.
+        assert_eq!(make_room(&mut queue), None);
+    }
+
+    #[test]
+    fn without_moves_the_oldest_event_goes() {
+        let mut queue: VecDeque<Queued> = (0..CAPACITY).map(|n| queued(key(n))).collect();
+        assert_eq!(make_room(&mut queue), Some(false));
+        assert!(matches!(queue[0].event.event_type, EventType::KeyPress(Key::Unknown(1))));
+    }
+
+    /// The only test that uses the global queue, since the translation thread it starts never ends.
+    #[test]
+    fn translation_thread_keeps_order_and_survives_panics() {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        spawn(move |queued| {
+            if let EventType::KeyPress(Key::Unknown(n)) = queued.event.event_type {
+                assert_ne!(n, 3, "a panicking stage");
+                tx.lock().unwrap().send(n).unwrap();
+            }
+        });
+        for n in 0..6 {
+            push(raw(key(n)), false);
+        }
+        let seen: Vec<u32> = (0..5).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        assert_eq!(seen, [0, 1, 2, 4, 5]);
+    }
+}
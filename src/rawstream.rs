@@ -0,0 +1,69 @@
+/*
+ * pet-input-server: src/rawstream.rs
+ *
+ * The `?stream=raw` debug stream, for chasing platform-specific reports:
+ * every event exactly as rdev delivered it, before key mapping, filtering,
+ * pausing or coordinate privacy, as
+ *
+ *   {"kind":"RawEvent","value":{"event_type":"KeyPress(Unknown(255))","name":null,"time":...}}
+ *
+ * Because it bypasses privacy by design it only exists when the server was
+ * started with `--debug-stream`, and only clients presenting the server
+ * token may open it. MouseMove is thinned to `MOVES_PER_SEC`.
+ */
+
+use crate::source::RawInputEvent;
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::{Lazy, OnceCell};
+use rdev::EventType;
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Most raw MouseMove events sent per second.
+const MOVES_PER_SEC: u32 = 20;
+
+static TX: OnceCell<broadcast::Sender<Action>> = OnceCell::new();
+static LAST_MOVE: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
+
+/// Turns the stream on; called once at startup for `--debug-stream`.
+pub fn enable() {
+    let _ = TX.set(broadcast::channel(1024).0);
+}
+
+pub fn enabled() -> bool {
+    TX.get().is_some()
+}
+
+/// A receiver for a `?stream=raw` client; `None` unless the stream is enabled.
+pub fn subscribe() -> Option<broadcast::Receiver<Action>> {
+    TX.get().map(broadcast::Sender::subscribe)
+}
+
+/// Sends `event` as it came from the hook, if anyone is watching.
+pub fn publish(event: &RawInputEvent) {
+    let Some(tx) = TX.get().filter(|tx| tx.receiver_count() > 0) else {
+        return;
+    };
+    if matches!(event.event_type, EventType::MouseMove { .. }) {
+        let now = Instant::now();
+        let mut last = LAST_MOVE.lock().unwrap();
+        if last.is_some_and(|last| now.duration_since(last) < Duration::from_secs(1) / MOVES_PER_SEC) {
+            return;
+        }
+        *last = Some(now);
+    }
+    let time = event
+        .time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since| since.as_secs_f64() * 1000.0);
+    let _ = tx.send(Action::new(
+        ActionKind::RawEvent,
+        ActionValue::Json(json!({
+            "event_type": format!("{:?}", event.event_type),
+            "name": event.name,
+            "time": time,
+        })),
+    ));
+}
@@ -0,0 +1,130 @@
+/*
+ * pet-input-server: src/realtime.rs
+ *
+ * `--realtime-input`: raise the priority of the input listener and
+ * translation threads so a busy machine (a streaming PC encoding video)
+ * doesn't starve them, and with `--input-cpu N` pin them to one core.
+ *
+ * Every step is best effort. On Windows the threads get
+ * THREAD_PRIORITY_TIME_CRITICAL; on Unix SCHED_FIFO is tried first and, if
+ * that is refused (it needs CAP_SYS_NICE or an rtprio limit), the thread's
+ * nice value is lowered instead. Whatever the OS allowed is printed and shown
+ * under `realtime` in `/status`.
+ */
+
+use once_cell::sync::{Lazy, OnceCell};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// What `--realtime-input` / `--input-cpu` asked for.
+#[derive(Clone, Copy, Debug)]
+pub struct Request {
+    pub priority: bool,
+    pub cpu: Option<usize>,
+}
+
+static REQUEST: OnceCell<Request> = OnceCell::new();
+/// Outcome per thread role, for `/status`.
+static OUTCOMES: Lazy<Mutex<BTreeMap<&'static str, serde_json::Value>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+pub fn configure(request: Request) {
+    let _ = REQUEST.set(request);
+}
+
+/// Applies the requested tuning to the calling thread; `role` names it in logs and `/status`.
+pub fn elevate(role: &'static str) {
+    let Some(request) = REQUEST.get() else { return };
+    let priority = request.priority.then(|| match raise_priority() {
+        Ok(how) => {
+            println!("Raised {} thread priority ({}).", role, how);
+            json!({ "ok": true, "how": how })
+        }
+        Err(e) => {
+            eprintln!("Warning: could not raise {} thread priority: {}", role, e);
+            let message = format!("Could not raise {} thread priority: {}", role, e);
+            crate::notice::raise(crate::notice::Code::RealtimeRefused, &message);
+            json!({ "ok": false, "error": e })
+        }
+    });
+    let pinned = request.cpu.map(|cpu| match pin(cpu) {
+        Ok(()) => {
+            println!("Pinned {} thread to CPU {}.", role, cpu);
+            json!({ "ok": true, "cpu": cpu })
+        }
+        Err(e) => {
+            eprintln!("Warning: could not pin {} thread to CPU {}: {}", role, cpu, e);
+            json!({ "ok": false, "cpu": cpu, "error": e })
+        }
+    });
+    OUTCOMES.lock().unwrap().insert(role, json!({ "priority": priority, "affinity": pinned }));
+}
+
+fn pin(cpu: usize) -> Result<(), String> {
+    let cores = core_affinity::get_core_ids().ok_or("the OS did not report the available CPUs")?;
+    let core = cores
+        .into_iter()
+        .find(|core| core.id == cpu)
+        .ok_or_else(|| format!("no CPU {} is available to this process", cpu))?;
+    if core_affinity::set_for_current(core) {
+        Ok(())
+    } else {
+        Err("the OS refused the affinity change".to_string())
+    }
+}
+
+#[cfg(windows)]
+fn raise_priority() -> Result<&'static str, String> {
+    use winapi::um::processthreadsapi::{GetCurrentThread, SetThreadPriority};
+    use winapi::um::winbase::THREAD_PRIORITY_TIME_CRITICAL;
+
+    if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL as i32) } != 0 {
+        Ok("THREAD_PRIORITY_TIME_CRITICAL")
+    } else {
+        Err(std::io::Error::last_os_error().to_string())
+    }
+}
+
+#[cfg(unix)]
+fn raise_priority() -> Result<&'static str, String> {
+    /// Low in the SCHED_FIFO range: above every normal thread, below the kernel's own.
+    const FIFO_PRIORITY: libc::c_int = 10;
+    /// The nice value used when real-time scheduling is refused.
+    const NICE: libc::c_int = -10;
+
+    let param = libc::sched_param {
+        sched_priority: FIFO_PRIORITY,
+    };
+    let fifo = unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) };
+    if fifo == 0 {
+        return Ok("SCHED_FIFO");
+    }
+    let fifo_error = std::io::Error::from_raw_os_error(fifo);
+    // On Linux a nice value applies to one thread when given its thread id.
+    #[cfg(target_os = "linux")]
+    let who = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+    #[cfg(not(target_os = "linux"))]
+    let who = 0;
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, who, NICE) } == 0 {
+        Ok("nice -10 (SCHED_FIFO was refused)")
+    } else {
+        Err(format!("SCHED_FIFO: {}; nice: {}", fifo_error, std::io::Error::last_os_error()))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn raise_priority() -> Result<&'static str, String> {
+    Err("not supported on this platform".to_string())
+}
+
+/// What was requested and what each thread got, for `/status`.
+pub fn to_json() -> serde_json::Value {
+    match REQUEST.get() {
+        None => serde_json::Value::Null,
+        Some(request) => json!({
+            "priority_requested": request.priority,
+            "cpu_requested": request.cpu,
+            "threads": *OUTCOMES.lock().unwrap(),
+        }),
+    }
+}
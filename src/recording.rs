@@ -0,0 +1,715 @@
+/*
+ * pet-input-server: src/recording.rs
+ *
+ * `--record <path>` writes every broadcast action as one JSON line:
+ * {"t":1234,"kind":"KeyboardPress","value":"KeyA"}, where `t` is milliseconds
+ * since the recording started. `--record-kinds` limits which kinds are written.
+ *
+ * With `--record-rotate 100MB` (or `1h`) the file is closed and renamed to
+ * `<path>.1`, `<path>.2`, ... whenever it reaches the limit, and recording
+ * continues in a fresh `<path>`; `t` keeps counting from the original start.
+ * Rotated segments can be gzipped in the background (`--record-gzip`) and
+ * pruned to the newest `--record-keep` ones. A restarted recording carries on
+ * numbering after the segments already on disk (its predecessor's unrotated
+ * `<path>` becomes the next one), and a segment is never written over.
+ *
+ * `--record-format binary` writes the same records much smaller: a zstd
+ * stream holding the `BINARY_HEADER` and then, per record, a little-endian
+ * u32 length and the record as named MessagePack. Size-based rotation then
+ * counts uncompressed bytes. Readers (`open`) tell plain, gzipped and binary
+ * files apart by their first bytes, and `convert` turns one into the other.
+ */
+
+use crate::{Action, ActionKind};
+use serde::{Deserialize, Serialize};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// First bytes of a binary recording once decompressed.
+const BINARY_HEADER: &[u8] = b"PETREC1\n";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RecordFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// Length-prefixed MessagePack records in a zstd stream
+    Binary,
+}
+
+impl RecordFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            RecordFormat::Jsonl => "application/x-ndjson",
+            RecordFormat::Binary => "application/octet-stream",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RecordedAction {
+    /// Milliseconds since the recording started.
+    pub t: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// When the active file is rotated.
+#[derive(Clone, Copy, Debug)]
+pub enum RotateAt {
+    Bytes(u64),
+    Age(Duration),
+}
+
+/// Parses `--record-rotate` values: a size (`500KB`, `100MB`, `2GB`) or a duration (`90s`, `30m`, `1h`).
+pub fn parse_rotate(value: &str) -> Result<RotateAt, String> {
+    let upper = value.trim().to_ascii_uppercase();
+    let split = upper.find(|c: char| !c.is_ascii_digit()).unwrap_or(upper.len());
+    let (number, unit) = upper.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected a size like 100MB or a duration like 1h, got '{}'", value))?;
+    let rotate = match unit {
+        "KB" => RotateAt::Bytes(number * 1024),
+        "MB" => RotateAt::Bytes(number * 1024 * 1024),
+        "GB" => RotateAt::Bytes(number * 1024 * 1024 * 1024),
+        "S" => RotateAt::Age(Duration::from_secs(number)),
+        "M" => RotateAt::Age(Duration::from_secs(number * 60)),
+        "H" => RotateAt::Age(Duration::from_secs(number * 3600)),
+        _ => return Err(format!("unknown unit in '{}' (use KB, MB, GB, s, m or h)", value)),
+    };
+    if number == 0 {
+        return Err("--record-rotate must be greater than zero".to_string());
+    }
+    Ok(rotate)
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rotation {
+    pub at: Option<RotateAt>,
+    pub gzip: bool,
+    /// Rotated segments to keep; older ones are deleted.
+    pub keep: Option<usize>,
+}
+
+/// A recording file open for writing in either format.
+enum RecordWriter {
+    Jsonl(BufWriter<File>),
+    Binary(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl RecordWriter {
+    fn create(path: &Path, format: RecordFormat) -> io::Result<RecordWriter> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match format {
+            RecordFormat::Jsonl => RecordWriter::Jsonl(file),
+            RecordFormat::Binary => {
+                let mut encoder = zstd::Encoder::new(file, ZSTD_LEVEL)?;
+                encoder.write_all(BINARY_HEADER)?;
+                RecordWriter::Binary(encoder)
+            }
+        })
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            RecordWriter::Jsonl(writer) => writer.write_all(bytes),
+            RecordWriter::Binary(encoder) => encoder.write_all(bytes),
+        }
+    }
+
+    /// Pushes everything written so far to the file; a binary file stays open for more frames.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RecordWriter::Jsonl(writer) => writer.flush(),
+            RecordWriter::Binary(encoder) => encoder.flush(),
+        }
+    }
+
+    /// Ends the zstd frame, so the file is complete if nothing more is written.
+    fn finish(&mut self) -> io::Result<()> {
+        if let RecordWriter::Binary(encoder) = self {
+            encoder.do_finish()?;
+        }
+        self.flush()
+    }
+
+    fn file(&self) -> &File {
+        match self {
+            RecordWriter::Jsonl(writer) => writer.get_ref(),
+            RecordWriter::Binary(encoder) => encoder.get_ref().get_ref(),
+        }
+    }
+}
+
+/// One record in `format`, ready to be appended.
+fn encode(format: RecordFormat, record: &RecordedAction) -> io::Result<Vec<u8>> {
+    match format {
+        RecordFormat::Jsonl => {
+            let mut encoded = serde_json::to_vec(record)?;
+            encoded.push(b'\n');
+            Ok(encoded)
+        }
+        RecordFormat::Binary => {
+            let body = rmp_serde::to_vec_named(record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let mut encoded = (body.len() as u32).to_le_bytes().to_vec();
+            encoded.extend(body);
+            Ok(encoded)
+        }
+    }
+}
+
+/// The file currently being written.
+struct Segment {
+    writer: RecordWriter,
+    bytes: u64,
+    opened: Instant,
+    /// Number the next rotated segment gets.
+    next_index: u64,
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    segment: Mutex<Segment>,
+    started: Instant,
+    /// Kinds to write; `None` writes everything.
+    kinds: Option<Vec<ActionKind>>,
+    format: RecordFormat,
+    rotation: Rotation,
+}
+
+impl Recorder {
+    /// Creates (or, without rotation, truncates) the recording file.
+    pub fn create(
+        path: &Path,
+        kinds: Option<Vec<ActionKind>>,
+        format: RecordFormat,
+        rotation: Rotation,
+    ) -> io::Result<Recorder> {
+        let mut next_index = 1;
+        if rotation.at.is_some() {
+            next_index = existing_segments(path)?.keys().last().map_or(1, |last| last + 1);
+            if std::fs::metadata(path).is_ok_and(|meta| meta.len() > 0) {
+                let previous = segment_path(path, next_index);
+                rename_new(path, &previous)?;
+                println!("Kept the previous recording as {}", previous.display());
+                next_index += 1;
+            }
+        }
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            segment: Mutex::new(Segment {
+                writer: RecordWriter::create(path, format)?,
+                bytes: 0,
+                opened: Instant::now(),
+                next_index,
+            }),
+            started: Instant::now(),
+            kinds,
+            format,
+            rotation,
+        })
+    }
+
+    pub fn records(&self, kind: ActionKind) -> bool {
+        crate::subscriptions::enabled(kind) && self.kinds.as_ref().is_none_or(|kinds| kinds.contains(&kind))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> RecordFormat {
+        self.format
+    }
+
+    fn write(&self, mut action: Action) -> io::Result<()> {
+        action.stamp_source();
+        let record = RecordedAction {
+            t: self.started.elapsed().as_millis() as u64,
+            action,
+        };
+        let encoded = encode(self.format, &record)?;
+        // Rotating under the same lock as the write keeps every record in exactly one segment.
+        let mut segment = self.segment.lock().unwrap();
+        let due = match self.rotation.at {
+            Some(RotateAt::Bytes(limit)) => segment.bytes > 0 && segment.bytes + encoded.len() as u64 > limit,
+            Some(RotateAt::Age(age)) => segment.opened.elapsed() >= age,
+            None => false,
+        };
+        if due {
+            self.rotate(&mut segment)?;
+        }
+        segment.writer.write_all(&encoded)?;
+        segment.bytes += encoded.len() as u64;
+        Ok(())
+    }
+
+    fn segment_path(&self, index: u64) -> PathBuf {
+        segment_path(&self.path, index)
+    }
+
+    fn rotate(&self, segment: &mut Segment) -> io::Result<()> {
+        segment.writer.finish()?;
+        let index = segment.next_index;
+        let rotated = self.segment_path(index);
+        rename_new(&self.path, &rotated)?;
+        segment.writer = RecordWriter::create(&self.path, self.format)?;
+        segment.bytes = 0;
+        segment.opened = Instant::now();
+        segment.next_index += 1;
+        println!("Recording rotated to {}", rotated.display());
+
+        // Binary segments are compressed already.
+        if self.rotation.gzip && self.format == RecordFormat::Jsonl {
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = gzip_file(&rotated) {
+                    eprintln!("Failed to compress {}: {}", rotated.display(), e);
+                }
+            });
+        }
+        // Segments left by earlier runs count too.
+        if let (Some(keep), Ok(segments)) = (self.rotation.keep, existing_segments(&self.path)) {
+            let oldest_kept = (index + 1).saturating_sub(keep as u64);
+            for file in segments.range(..oldest_kept).flat_map(|(_, files)| files) {
+                let _ = std::fs::remove_file(file);
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes buffered records and returns how many bytes of the file are complete.
+    /// Readers should stop at that length, since writing continues meanwhile.
+    pub fn flushed_len(&self) -> io::Result<u64> {
+        let mut segment = self.segment.lock().unwrap();
+        segment.writer.flush()?;
+        Ok(segment.writer.file().metadata()?.len())
+    }
+
+    /// Completes the file on shutdown; a later write would start a new zstd frame.
+    pub fn finish(&self) -> io::Result<()> {
+        self.segment.lock().unwrap().writer.finish()
+    }
+}
+
+fn segment_path(path: &Path, index: u64) -> PathBuf {
+    let mut name = path.to_path_buf().into_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.to_path_buf().into_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// The rotated segments of `path` on disk (`<path>.N` and `<path>.N.gz`), by index.
+fn existing_segments(path: &Path) -> io::Result<BTreeMap<u64, Vec<PathBuf>>> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let Some(prefix) = path.file_name().and_then(|name| name.to_str()).map(|name| format!("{}.", name)) else {
+        return Ok(BTreeMap::new());
+    };
+    let mut segments: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(rest) = name.to_str().and_then(|name| name.strip_prefix(&prefix)) else { continue };
+        let index = rest.strip_suffix(".gz").unwrap_or(rest);
+        if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(index) = index.parse() {
+                segments.entry(index).or_default().push(entry.path());
+            }
+        }
+    }
+    Ok(segments)
+}
+
+fn already_exists(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} already exists; not overwriting it", path.display()),
+    )
+}
+
+/// Moves `from` to `to`, failing instead of replacing anything at `to` or `to.gz`.
+fn rename_new(from: &Path, to: &Path) -> io::Result<()> {
+    if gz_path(to).exists() {
+        return Err(already_exists(&gz_path(to)));
+    }
+    // A hard link is only created if `to` doesn't exist, so nothing is replaced even in a race.
+    match std::fs::hard_link(from, to) {
+        Ok(()) => std::fs::remove_file(from),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(already_exists(to)),
+        // File systems without hard links: check, then rename.
+        Err(_) if to.exists() => Err(already_exists(to)),
+        Err(_) => std::fs::rename(from, to),
+    }
+}
+
+/// Replaces `path` with `path.gz`.
+fn gzip_file(path: &Path) -> io::Result<()> {
+    let gz_path = gz_path(path);
+    let mut input = File::open(path)?;
+    let output = OpenOptions::new().write(true).create_new(true).open(&gz_path)?;
+    let mut encoder = GzEncoder::new(BufWriter::new(output), flate2::Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    std::fs::remove_file(path)
+}
+
+/// Why a record could not be read.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    /// The record itself is broken; the ones after it can still be read.
+    Malformed(String),
+}
+
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(e) => e.fmt(f),
+            ReadError::Malformed(e) => e.fmt(f),
+        }
+    }
+}
+
+pub type Records = Box<dyn Iterator<Item = Result<RecordedAction, ReadError>>>;
+
+/// The format of the recording at `path`; gzipped segments count as JSONL.
+pub fn detect(path: &Path) -> io::Result<RecordFormat> {
+    let mut file = BufReader::new(File::open(path)?);
+    Ok(match file.fill_buf()?.starts_with(ZSTD_MAGIC) {
+        true => RecordFormat::Binary,
+        false => RecordFormat::Jsonl,
+    })
+}
+
+/// Opens a recording (or rotated segment) of any format and reads its records in order.
+pub fn open(path: &Path) -> io::Result<Records> {
+    let mut file = BufReader::new(File::open(path)?);
+    let head = file.fill_buf()?;
+    if head.starts_with(ZSTD_MAGIC) {
+        let mut decoder = zstd::Decoder::with_buffer(file)?;
+        let mut header = [0; BINARY_HEADER.len()];
+        decoder.read_exact(&mut header)?;
+        if header != BINARY_HEADER {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "zstd file is not a binary recording"));
+        }
+        return Ok(Box::new(BinaryRecords(Some(decoder))));
+    }
+    let lines: Box<dyn BufRead> = match head.starts_with(GZIP_MAGIC) {
+        true => Box::new(BufReader::new(GzDecoder::new(file))),
+        false => Box::new(file),
+    };
+    Ok(Box::new(lines.lines().filter_map(|line| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(serde_json::from_str(&line).map_err(|e| ReadError::Malformed(e.to_string()))),
+        Err(e) => Some(Err(ReadError::Io(e))),
+    })))
+}
+
+struct BinaryRecords(Option<zstd::Decoder<'static, BufReader<File>>>);
+
+impl Iterator for BinaryRecords {
+    type Item = Result<RecordedAction, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let decoder = self.0.as_mut()?;
+        let mut body = Vec::new();
+        let mut len = [0; 4];
+        let read = decoder
+            .read_exact(&mut len)
+            .and_then(|()| {
+                body.resize(u32::from_le_bytes(len) as usize, 0);
+                decoder.read_exact(&mut body)
+            });
+        match read {
+            Ok(()) => Some(rmp_serde::from_slice(&body).map_err(|e| ReadError::Malformed(e.to_string()))),
+            // The end, or the last frame of a file that is still being written (or whose writer crashed).
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.0 = None;
+                None
+            }
+            Err(e) => {
+                self.0 = None;
+                Some(Err(ReadError::Io(e)))
+            }
+        }
+    }
+}
+
+/// The `convert` subcommand: rewrites a recording in the other format (or `to`).
+/// Returns the format written and how many records it holds.
+pub fn convert(input: &Path, output: &Path, to: Option<RecordFormat>) -> Result<(RecordFormat, usize), String> {
+    let from = detect(input).map_err(|e| format!("Failed to open {}: {}", input.display(), e))?;
+    let to = to.unwrap_or(match from {
+        RecordFormat::Jsonl => RecordFormat::Binary,
+        RecordFormat::Binary => RecordFormat::Jsonl,
+    });
+    let records = open(input).map_err(|e| format!("Failed to open {}: {}", input.display(), e))?;
+    let write_error = |e: io::Error| format!("Failed to write {}: {}", output.display(), e);
+    let mut writer = RecordWriter::create(output, to).map_err(write_error)?;
+    let mut count = 0;
+    for (index, record) in records.enumerate() {
+        let record = record.map_err(|e| format!("{} record {}: {}", input.display(), index + 1, e))?;
+        writer.write_all(&encode(to, &record).map_err(write_error)?).map_err(write_error)?;
+        count += 1;
+    }
+    writer.finish().map_err(write_error)?;
+    Ok((to, count))
+}
+
+/// Records everything sent on `broadcast_rx` until the channel closes.
+/// The file is flushed once a second so a crash loses at most that much.
+pub async fn run(recorder: std::sync::Arc<Recorder>, mut broadcast_rx: broadcast::Receiver<Action>) {
+    println!("Recording to {}", recorder.path().display());
+    let mut flush = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            received = broadcast_rx.recv() => match received {
+                Ok(action) if !recorder.records(action.kind) => {}
+                Ok(action) => {
+                    if let Err(e) = recorder.write(action) {
+                        eprintln!("Recording stopped: {}", e);
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    eprintln!("Recording fell behind and skipped {} events.", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = flush.tick() => {
+                let _ = recorder.flushed_len();
+            }
+        }
+    }
+    let _ = recorder.flushed_len();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::sample;
+    use crate::ActionValue;
+
+    fn ts(records: Records) -> Vec<u64> {
+        records.map(|record| record.unwrap().t).collect()
+    }
+
+    #[test]
+    fn rotate_takes_sizes_and_durations() {
+        assert!(matches!(parse_rotate("500KB"), Ok(RotateAt::Bytes(512000))));
+        assert!(matches!(parse_rotate(" 100mb "), Ok(RotateAt::Bytes(104857600))));
+        assert!(matches!(parse_rotate("2GB"), Ok(RotateAt::Bytes(2147483648))));
+        assert!(matches!(parse_rotate("90s"), Ok(RotateAt::Age(d)) if d == Duration::from_secs(90)));
+        assert!(matches!(parse_rotate("30m"), Ok(RotateAt::Age(d)) if d == Duration::from_secs(1800)));
+        assert!(matches!(parse_rotate("1H"), Ok(RotateAt::Age(d)) if d == Duration::from_secs(3600)));
+        for bad in ["", "MB", "10", "10TB", "1.5GB", "0MB", "-1h"] {
+            assert!(parse_rotate(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn rotation_keeps_every_record_in_one_segment_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let rotation = Rotation {
+            at: Some(RotateAt::Bytes(200)),
+            ..Rotation::default()
+        };
+        let recorder = Recorder::create(&path, None, RecordFormat::Jsonl, rotation).unwrap();
+        for n in 0..20 {
+            recorder.write(Action::new(ActionKind::KeyboardPress, ActionValue::String(format!("Key{}", n)))).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let mut names = Vec::new();
+        let mut t = Vec::new();
+        let segments: Vec<PathBuf> = (1..).map(|n| recorder.segment_path(n)).take_while(|p| p.exists()).collect();
+        assert!(segments.len() >= 3, "{:?}", segments);
+        for segment in segments.iter().chain([&path]) {
+            assert!(std::fs::metadata(segment).unwrap().len() <= 200, "{}", segment.display());
+            for record in open(segment).unwrap() {
+                let record = record.unwrap();
+                t.push(record.t);
+                let ActionValue::String(name) = record.action.value else { panic!() };
+                names.push(name);
+            }
+        }
+        assert_eq!(names, (0..20).map(|n| format!("Key{}", n)).collect::<Vec<_>>());
+        // `t` keeps counting from the original start across segments.
+        assert!(t.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", t);
+    }
+
+    #[test]
+    fn old_segments_are_pruned_and_gzipped_ones_still_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let rotation = Rotation {
+            at: Some(RotateAt::Bytes(1)),
+            keep: Some(1),
+            ..Rotation::default()
+        };
+        let recorder = Recorder::create(&path, None, RecordFormat::Jsonl, rotation).unwrap();
+        for _ in 0..4 {
+            recorder.write(sample(ActionKind::MouseMove)).unwrap();
+        }
+        recorder.finish().unwrap();
+        let left: Vec<bool> = (1..=3).map(|n| recorder.segment_path(n).exists()).collect();
+        assert_eq!(left, [false, false, true]);
+
+        let segment = recorder.segment_path(3);
+        gzip_file(&segment).unwrap();
+        let mut gz = segment.into_os_string();
+        gz.push(".gz");
+        assert_eq!(ts(open(Path::new(&gz)).unwrap()).len(), 1);
+    }
+
+    fn byte_rotation(keep: Option<usize>) -> Rotation {
+        Rotation {
+            at: Some(RotateAt::Bytes(1)),
+            keep,
+            ..Rotation::default()
+        }
+    }
+
+    fn write_moves(recorder: &Recorder, count: usize) {
+        for _ in 0..count {
+            recorder.write(sample(ActionKind::MouseMove)).unwrap();
+        }
+        recorder.finish().unwrap();
+    }
+
+    #[test]
+    fn a_restarted_recording_carries_on_numbering() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        // .1 and .2 rotated, three records in all.
+        write_moves(&Recorder::create(&path, None, RecordFormat::Jsonl, byte_rotation(None)).unwrap(), 3);
+        let first_segment = std::fs::read(segment_path(&path, 1)).unwrap();
+        let unrotated = std::fs::read(&path).unwrap();
+        gzip_file(&segment_path(&path, 2)).unwrap();
+
+        let recorder = Recorder::create(&path, None, RecordFormat::Jsonl, byte_rotation(None)).unwrap();
+        // The first run's unrotated file became .3, before anything was written.
+        assert_eq!(std::fs::read(segment_path(&path, 3)).unwrap(), unrotated);
+        write_moves(&recorder, 2);
+        assert_eq!(std::fs::read(segment_path(&path, 1)).unwrap(), first_segment);
+        assert_eq!(ts(open(&gz_path(&segment_path(&path, 2))).unwrap()).len(), 1);
+        assert!(!segment_path(&path, 2).exists());
+        assert_eq!(existing_segments(&path).unwrap().keys().copied().collect::<Vec<_>>(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn rotation_refuses_to_overwrite_a_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let recorder = Recorder::create(&path, None, RecordFormat::Jsonl, byte_rotation(None)).unwrap();
+        recorder.write(sample(ActionKind::MouseMove)).unwrap();
+        // Something else took the next segment's name (or its .gz) meanwhile.
+        std::fs::write(gz_path(&segment_path(&path, 1)), b"not ours").unwrap();
+        let error = recorder.write(sample(ActionKind::MouseMove)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(gz_path(&segment_path(&path, 1))).unwrap(), b"not ours");
+        assert!(!segment_path(&path, 1).exists());
+
+        std::fs::write(segment_path(&path, 1), b"not ours either").unwrap();
+        assert_eq!(rename_new(&path, &segment_path(&path, 1)).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(segment_path(&path, 1)).unwrap(), b"not ours either");
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn keep_prunes_segments_from_earlier_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        for old in ["session.jsonl.1", "session.jsonl.2.gz", "session.jsonl.3", "session.jsonl.x", "other.4"] {
+            std::fs::write(dir.path().join(old), b"old").unwrap();
+        }
+        write_moves(&Recorder::create(&path, None, RecordFormat::Jsonl, byte_rotation(Some(2))).unwrap(), 3);
+        // This run rotated .4 and .5; only the newest two segments are left.
+        assert_eq!(existing_segments(&path).unwrap().keys().copied().collect::<Vec<_>>(), [4, 5]);
+        assert!(dir.path().join("session.jsonl.x").exists() && dir.path().join("other.4").exists());
+    }
+
+    /// Every kind's sample, as recorded.
+    fn record_all(path: &Path, format: RecordFormat) -> Recorder {
+        let recorder = Recorder::create(path, None, format, Rotation::default()).unwrap();
+        for kind in ActionKind::ALL {
+            recorder.write(sample(kind)).unwrap();
+        }
+        recorder
+    }
+
+    fn as_json(records: Records) -> Vec<serde_json::Value> {
+        records.map(|record| serde_json::to_value(record.unwrap().action.view("k")).unwrap()).collect()
+    }
+
+    fn expected() -> Vec<serde_json::Value> {
+        ActionKind::ALL.map(|kind| serde_json::to_value(sample(kind).view("k")).unwrap()).to_vec()
+    }
+
+    #[test]
+    fn both_formats_read_back_what_was_written() {
+        let dir = tempfile::tempdir().unwrap();
+        for format in [RecordFormat::Jsonl, RecordFormat::Binary] {
+            let path = dir.path().join(format!("{:?}", format));
+            record_all(&path, format).finish().unwrap();
+            assert_eq!(detect(&path).unwrap(), format);
+            let kinds: Vec<ActionKind> = open(&path).unwrap().map(|r| r.unwrap().action.kind).collect();
+            assert_eq!(kinds, ActionKind::ALL);
+            assert_eq!(as_json(open(&path).unwrap()), expected(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn an_unfinished_binary_file_reads_up_to_the_last_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("live.bin");
+        let recorder = record_all(&path, RecordFormat::Binary);
+        recorder.flushed_len().unwrap();
+        assert_eq!(open(&path).unwrap().count(), ActionKind::ALL.len());
+    }
+
+    #[test]
+    fn convert_round_trips_between_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let jsonl = dir.path().join("a.jsonl");
+        let binary = dir.path().join("a.bin");
+        let back = dir.path().join("b.jsonl");
+        record_all(&jsonl, RecordFormat::Jsonl).finish().unwrap();
+        let count = ActionKind::ALL.len();
+        assert_eq!(convert(&jsonl, &binary, None).unwrap(), (RecordFormat::Binary, count));
+        assert_eq!(convert(&binary, &back, None).unwrap(), (RecordFormat::Jsonl, count));
+        assert_eq!(std::fs::read(&jsonl).unwrap(), std::fs::read(&back).unwrap());
+        assert!(std::fs::metadata(&binary).unwrap().len() < std::fs::metadata(&jsonl).unwrap().len());
+    }
+
+    #[test]
+    fn a_broken_line_does_not_hide_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.jsonl");
+        let lines = r#"{"t":1,"kind":"KeyboardPress","value":"KeyA"}
+{"t":2,"kind":"KeyboardPress","val
+{"t":3,"kind":"KeyboardRelease","value":"KeyA"}
+"#;
+        std::fs::write(&path, lines).unwrap();
+        let records: Vec<_> = open(&path).unwrap().collect();
+        assert_eq!(records.len(), 3);
+        assert!(matches!(records[1], Err(ReadError::Malformed(_))));
+        assert_eq!(records[2].as_ref().unwrap().t, 3);
+    }
+}
@@ -0,0 +1,180 @@
+/*
+ * pet-input-server: src/regions.rs
+ */
+
+use crate::config::{RegionRect, RegionUnits};
+use crate::settings::SETTINGS;
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::OnceCell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::sync::broadcast;
+
+/// One configured region. Everything that changes at runtime is atomic, so the listener
+/// thread checks every mouse move without taking a lock.
+struct Region {
+    name: String,
+    rect: RegionRect,
+    /// Pixel bounds (left, top, right, bottom) as f64 bits; only meaningful once `resolved`.
+    bounds: [AtomicU64; 4],
+    /// False until the display size is known (normalized regions only).
+    resolved: AtomicBool,
+    inside: AtomicBool,
+}
+
+impl Region {
+    fn new(name: &str, rect: &RegionRect) -> Region {
+        Region {
+            name: name.to_string(),
+            rect: rect.clone(),
+            bounds: Default::default(),
+            resolved: AtomicBool::new(false),
+            inside: AtomicBool::new(false),
+        }
+    }
+
+    fn bounds(&self) -> Option<(f64, f64, f64, f64)> {
+        if !self.resolved.load(Ordering::Acquire) {
+            return None;
+        }
+        let [left, top, right, bottom] = self.bounds.each_ref().map(|b| f64::from_bits(b.load(Ordering::Relaxed)));
+        Some((left, top, right, bottom))
+    }
+
+    fn resolve(&self, display: Option<(u64, u64)>) {
+        let r = &self.rect;
+        let bounds = match (r.units, display) {
+            (RegionUnits::Pixels, _) => Some([r.x, r.y, r.x + r.w, r.y + r.h]),
+            (RegionUnits::Normalized, Some((width, height))) => {
+                let (width, height) = (width as f64, height as f64);
+                Some([r.x * width, r.y * height, (r.x + r.w) * width, (r.y + r.h) * height])
+            }
+            (RegionUnits::Normalized, None) => None,
+        };
+        if let Some(bounds) = bounds {
+            for (slot, value) in self.bounds.iter().zip(bounds) {
+                slot.store(value.to_bits(), Ordering::Relaxed);
+            }
+        }
+        self.resolved.store(bounds.is_some(), Ordering::Release);
+    }
+
+    /// The event for a cursor at (x, y), if it crossed this region's edge.
+    fn crossing(&self, x: f64, y: f64, margin: f64) -> Option<ActionKind> {
+        let (left, top, right, bottom) = self.bounds()?;
+        // Only the listener thread flips `inside`, so the load and store below don't race.
+        let inside = self.inside.load(Ordering::Relaxed);
+        let kind = if inside {
+            let outside = x < left - margin
+                || x >= right + margin
+                || y < top - margin
+                || y >= bottom + margin;
+            if !outside {
+                return None;
+            }
+            ActionKind::RegionExit
+        } else {
+            if x < left || x >= right || y < top || y >= bottom {
+                return None;
+            }
+            ActionKind::RegionEnter
+        };
+        self.inside.store(!inside, Ordering::Relaxed);
+        Some(kind)
+    }
+}
+
+static REGIONS: OnceCell<Vec<Region>> = OnceCell::new();
+
+fn regions() -> &'static [Region] {
+    REGIONS.get().map_or(&[], Vec::as_slice)
+}
+
+/// Installs the configured regions. Call once at startup, before the listener starts.
+pub fn init(regions: &BTreeMap<String, RegionRect>) {
+    let _ = REGIONS.set(regions.iter().map(|(name, rect)| Region::new(name, rect)).collect());
+}
+
+/// True if any region is written in normalized units and so depends on the display size.
+pub fn needs_display_size() -> bool {
+    regions().iter().any(|r| r.rect.units == RegionUnits::Normalized)
+}
+
+/// Re-computes pixel bounds for every region against the given display size.
+///
+/// A move checked while this runs may see a mix of old and new edges; it only happens when
+/// the display size changes and the next move sees the new bounds.
+pub fn resolve(display: Option<(u64, u64)>) {
+    for region in regions() {
+        region.resolve(display);
+    }
+}
+
+/// Checks the cursor against every region and sends RegionEnter/RegionExit on crossings.
+///
+/// Entering requires the cursor to be inside the rectangle, but leaving requires it to
+/// move `region_hysteresis_px` beyond the edge, so jitter on the boundary doesn't spam events.
+pub fn on_mouse_move(x: f64, y: f64, broadcast_tx: &broadcast::Sender<Action>) {
+    let regions = regions();
+    if regions.is_empty() {
+        return;
+    }
+    let margin = SETTINGS.region_hysteresis_px();
+    for region in regions {
+        let Some(kind) = region.crossing(x, y, margin) else {
+            continue;
+        };
+        crate::rates::count(kind);
+        let _ = broadcast_tx.send(Action::new(kind, ActionValue::String(region.name.clone())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: f64, y: f64, w: f64, h: f64, units: RegionUnits) -> Region {
+        Region::new("corner", &RegionRect { x, y, w, h, units })
+    }
+
+    #[test]
+    fn normalized_regions_wait_for_the_display_size() {
+        let corner = region(0.9, 0.0, 0.1, 0.1, RegionUnits::Normalized);
+        corner.resolve(None);
+        assert_eq!(corner.crossing(1900.0, 10.0, 0.0), None);
+        corner.resolve(Some((2000, 1000)));
+        assert_eq!(corner.bounds(), Some((1800.0, 0.0, 2000.0, 100.0)));
+        assert_eq!(corner.crossing(1900.0, 10.0, 0.0), Some(ActionKind::RegionEnter));
+        // Pixel regions don't need one.
+        let pixels = region(10.0, 10.0, 5.0, 5.0, RegionUnits::Pixels);
+        pixels.resolve(None);
+        assert_eq!(pixels.bounds(), Some((10.0, 10.0, 15.0, 15.0)));
+    }
+
+    #[test]
+    fn enter_and_exit_fire_once_per_crossing() {
+        let square = region(100.0, 100.0, 50.0, 50.0, RegionUnits::Pixels);
+        square.resolve(None);
+        assert_eq!(square.crossing(99.0, 120.0, 0.0), None);
+        assert_eq!(square.crossing(100.0, 120.0, 0.0), Some(ActionKind::RegionEnter));
+        assert_eq!(square.crossing(120.0, 120.0, 0.0), None);
+        // The right and bottom edges are exclusive.
+        assert_eq!(square.crossing(150.0, 120.0, 0.0), Some(ActionKind::RegionExit));
+        assert_eq!(square.crossing(160.0, 120.0, 0.0), None);
+    }
+
+    #[test]
+    fn leaving_takes_the_hysteresis_margin() {
+        let square = region(100.0, 100.0, 50.0, 50.0, RegionUnits::Pixels);
+        square.resolve(None);
+        assert_eq!(square.crossing(149.0, 120.0, 5.0), Some(ActionKind::RegionEnter));
+        // Jitter just past the edge stays inside.
+        for x in [150.0, 149.0, 154.0, 151.0] {
+            assert_eq!(square.crossing(x, 120.0, 5.0), None, "{}", x);
+        }
+        assert_eq!(square.crossing(155.0, 120.0, 5.0), Some(ActionKind::RegionExit));
+        // Entering again needs the cursor inside the rectangle itself.
+        assert_eq!(square.crossing(152.0, 120.0, 5.0), None);
+        assert_eq!(square.crossing(95.0, 96.0, 5.0), None);
+    }
+}
@@ -0,0 +1,56 @@
+/*
+ * pet-input-server: src/reliable.rs
+ *
+ * Opt-in reliable delivery (`?reliable=1`). Every event sent to the client
+ * gets a consecutive `seq` and is kept until the client acknowledges it with
+ * `{"ack":n}` (cumulative). A client that sees a gap asks for
+ * `{"resend_from":n}` and gets everything still buffered from `n` on, in
+ * order. The buffer is bounded; when a client falls that far behind, the
+ * oldest events are given up and the client learns so from the next resend.
+ */
+
+use crate::Action;
+use std::collections::VecDeque;
+
+/// Unacknowledged events kept per connection.
+const CAPACITY: usize = 1024;
+
+pub struct ResendBuffer {
+    next_seq: u64,
+    unacked: VecDeque<Action>,
+}
+
+impl ResendBuffer {
+    pub fn new() -> Self {
+        ResendBuffer {
+            next_seq: 1,
+            unacked: VecDeque::new(),
+        }
+    }
+
+    /// Numbers `action` and keeps a copy for resending.
+    pub fn stamp(&mut self, action: &mut Action) {
+        action.seq = Some(self.next_seq);
+        self.next_seq += 1;
+        if self.unacked.len() == CAPACITY {
+            self.unacked.pop_front();
+        }
+        self.unacked.push_back(action.clone());
+    }
+
+    /// Forgets everything up to and including `seq`.
+    pub fn ack(&mut self, seq: u64) {
+        while self.unacked.front().is_some_and(|action| action.seq <= Some(seq)) {
+            self.unacked.pop_front();
+        }
+    }
+
+    /// Buffered events from `seq` on; they start later than `seq` if those were given up.
+    pub fn resend_from(&self, seq: u64) -> Vec<Action> {
+        self.unacked
+            .iter()
+            .filter(|action| action.seq >= Some(seq))
+            .cloned()
+            .collect()
+    }
+}
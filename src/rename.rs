@@ -0,0 +1,126 @@
+/*
+ * pet-input-server: src/rename.rs
+ *
+ * Kind renaming for frontends that expect e.g. "keydown" instead of
+ * "KeyboardPress". Applied at serialization time, per connection profile.
+ */
+
+use crate::ActionKind;
+use std::collections::{BTreeMap, HashMap};
+
+/// One resolved kind → wire-name table.
+#[derive(Default, Debug)]
+pub struct KindRenames(HashMap<String, String>);
+
+impl KindRenames {
+    pub fn apply<'a>(&'a self, kind: &'a str) -> &'a str {
+        self.0.get(kind).map_or(kind, |s| s.as_str())
+    }
+}
+
+/// The global `[rename_kinds]` table plus named `[rename_profiles.<name>]` overlays.
+#[derive(Default, Debug)]
+pub struct RenameProfiles {
+    default: KindRenames,
+    profiles: HashMap<String, KindRenames>,
+}
+
+impl RenameProfiles {
+    /// Builds every profile, rejecting any where two kinds would end up with the same name.
+    pub fn build(
+        global: &BTreeMap<String, String>,
+        profiles: &BTreeMap<String, BTreeMap<String, String>>,
+    ) -> Result<RenameProfiles, String> {
+        let default = resolve("rename_kinds", global, &BTreeMap::new())?;
+        let profiles = profiles
+            .iter()
+            .map(|(name, overlay)| {
+                resolve(&format!("rename_profiles.{}", name), global, overlay)
+                    .map(|renames| (name.clone(), renames))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(RenameProfiles { default, profiles })
+    }
+
+    /// Looks up a connection's `?profile=` choice; `None` means the global default.
+    pub fn profile(&self, name: Option<&str>) -> Option<&KindRenames> {
+        match name {
+            None => Some(&self.default),
+            Some(name) => self.profiles.get(name),
+        }
+    }
+}
+
+fn resolve(
+    section: &str,
+    global: &BTreeMap<String, String>,
+    overlay: &BTreeMap<String, String>,
+) -> Result<KindRenames, String> {
+    let mut merged: BTreeMap<&str, &str> = global.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    merged.extend(overlay.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+
+    // Every kind's final wire name, including the ones left unchanged.
+    let mut owners: HashMap<&str, &str> = HashMap::new();
+    let kinds = ActionKind::names().into_iter().chain(merged.keys().copied());
+    for kind in kinds {
+        let wire = merged.get(kind).copied().unwrap_or(kind);
+        if let Some(other) = owners.insert(wire, kind) {
+            if other != kind {
+                return Err(format!(
+                    "[{}] maps both '{}' and '{}' to '{}'",
+                    section, other, kind, wire
+                ));
+            }
+        }
+    }
+
+    Ok(KindRenames(
+        merged
+            .into_iter()
+            .filter(|(k, v)| k != v)
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(from, to)| (from.to_string(), to.to_string())).collect()
+    }
+
+    #[test]
+    fn profiles_overlay_the_global_table() {
+        let global = table(&[("KeyboardPress", "keydown"), ("KeyboardRelease", "keyup")]);
+        let profiles = BTreeMap::from([("web".to_string(), table(&[("KeyboardPress", "kd"), ("MouseMove", "move")]))]);
+        let renames = RenameProfiles::build(&global, &profiles).unwrap();
+        let default = renames.profile(None).unwrap();
+        assert_eq!(default.apply("KeyboardPress"), "keydown");
+        assert_eq!(default.apply("MouseMove"), "MouseMove");
+        let web = renames.profile(Some("web")).unwrap();
+        assert_eq!(web.apply("KeyboardPress"), "kd");
+        assert_eq!(web.apply("KeyboardRelease"), "keyup");
+        assert_eq!(web.apply("MouseMove"), "move");
+        assert!(renames.profile(Some("missing")).is_none());
+    }
+
+    #[test]
+    fn two_kinds_cannot_share_a_wire_name() {
+        let error = RenameProfiles::build(&table(&[("KeyboardPress", "MouseMove")]), &BTreeMap::new()).unwrap_err();
+        assert!(error.contains("'KeyboardPress' and 'MouseMove'") || error.contains("'MouseMove' and 'KeyboardPress'"));
+        // A profile can collide with the global table it is laid over.
+        let global = table(&[("KeyboardPress", "key")]);
+        let profiles = BTreeMap::from([("p".to_string(), table(&[("KeyboardRelease", "key")]))]);
+        let error = RenameProfiles::build(&global, &profiles).unwrap_err();
+        assert!(error.starts_with("[rename_profiles.p]"), "{}", error);
+    }
+
+    #[test]
+    fn swapping_two_names_is_allowed() {
+        let global = table(&[("KeyboardPress", "KeyboardRelease"), ("KeyboardRelease", "KeyboardPress")]);
+        let renames = RenameProfiles::build(&global, &BTreeMap::new()).unwrap();
+        assert_eq!(renames.profile(None).unwrap().apply("KeyboardPress"), "KeyboardRelease");
+    }
+}
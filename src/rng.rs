@@ -0,0 +1,27 @@
+/*
+ * pet-input-server: src/rng.rs
+ *
+ * A shared xorshift generator, seeded lazily from the clock. Good enough
+ * for simulated packet loss and coordinate jitter; not for anything secret.
+ */
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+/// A value in `0.0..1.0`.
+pub fn fraction() -> f64 {
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| d.as_nanos() as u64)
+            | 1;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,95 @@
+/*
+ * pet-input-server: src/role.rs
+ *
+ * Which part of the output a WebSocket connection gets, picked by the first
+ * segment of the upgrade path, for clients that don't want to parse and skip
+ * what they never use:
+ *
+ * - `/events`: input and what is derived from it (moves, keys, notes, moods,
+ *   snapshots, cues), nothing else
+ * - `/meta`: the rest: connect notices, ServerNotice, Backpressure, Inactive,
+ *   replies to client commands, and Status and Rates without having to list
+ *   them in `?kinds=`
+ * - `/admin`: the `/meta` stream for a connection holding the server token;
+ *   control messages such as cues are sent from here
+ * - any other path: both, as before (the combined stream)
+ *
+ * The rest of the path picks the room as usual (`/events/office`); a room
+ * named after one of these paths is only reachable below them. A
+ * `ProtocolError` or `Disconnect` before a close goes to every connection,
+ * since it is about the connection itself.
+ */
+
+use crate::ActionKind;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Combined,
+    Events,
+    Meta,
+    Admin,
+}
+
+/// Kinds that describe the server or the connection rather than the user's input.
+const META_KINDS: &[ActionKind] = &[
+    ActionKind::Backpressure,
+    ActionKind::ProtocolError,
+    ActionKind::InputFailure,
+    ActionKind::Rates,
+    ActionKind::Oversized,
+    ActionKind::Inactive,
+    ActionKind::InputFlood,
+    ActionKind::Prefs,
+    ActionKind::TimeSyncProbe,
+    ActionKind::TimeSync,
+    ActionKind::SystemSuspend,
+    ActionKind::SystemResume,
+    ActionKind::Status,
+    ActionKind::CapturePaused,
+    ActionKind::CoordPrivacy,
+    ActionKind::ServerNotice,
+];
+
+/// The upgrade paths with a role, for `/capabilities`.
+pub const PATHS: &[&str] = &["/events", "/meta", "/admin"];
+
+impl Role {
+    /// The role named by `path`'s first segment, and the rest of the path (the room).
+    pub fn from_path(path: &str) -> (Role, &str) {
+        let trimmed = path.trim_start_matches('/');
+        let (first, rest) = trimmed.split_once('/').unwrap_or((trimmed, ""));
+        let role = match first {
+            "events" => Role::Events,
+            "meta" => Role::Meta,
+            "admin" => Role::Admin,
+            _ => return (Role::Combined, path),
+        };
+        (role, rest)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Combined => "combined",
+            Role::Events => "events",
+            Role::Meta => "meta",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Whether this stream carries `kind` at all.
+    pub fn carries(self, kind: ActionKind) -> bool {
+        match self {
+            Role::Combined => true,
+            Role::Events => !META_KINDS.contains(&kind),
+            Role::Meta | Role::Admin => META_KINDS.contains(&kind),
+        }
+    }
+
+    /// Whether a client gets `kind` without naming it in `?kinds=`.
+    pub fn by_default(self, kind: ActionKind) -> bool {
+        match self {
+            Role::Combined => !crate::OPT_IN_KINDS.contains(&kind),
+            _ => self.carries(kind),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+/*
+ * pet-input-server: src/rooms.rs
+ */
+
+use crate::config::RoomConfig;
+use crate::Action;
+use std::collections::BTreeMap;
+use tokio::sync::{broadcast, watch};
+
+pub const DEFAULT_ROOM: &str = "default";
+
+/// A group of clients with its own channel for room-scoped (e.g. injected) events.
+pub struct Room {
+    pub name: String,
+    token: Option<String>,
+    /// Whether live input from the listener reaches this room.
+    pub input: bool,
+    pub tx: broadcast::Sender<Action>,
+    /// The client that sent `{"claim_active":true}`; while set, only it receives events.
+    pub active: watch::Sender<Option<u64>>,
+}
+
+pub enum RoomError {
+    NotFound,
+    Unauthorized,
+}
+
+pub struct Rooms {
+    rooms: BTreeMap<String, Room>,
+    /// With no `[rooms]` configured, every path joins the default room, as before rooms existed.
+    legacy: bool,
+}
+
+impl Room {
+    fn new(name: &str, config: &RoomConfig) -> Room {
+        let (tx, _rx) = broadcast::channel(256);
+        Room {
+            name: name.to_string(),
+            token: config.token.clone(),
+            input: config.input,
+            tx,
+            active: watch::channel(None).0,
+        }
+    }
+
+    pub fn claim_active(&self, client: u64) {
+        self.active.send_if_modified(|active| active.replace(client) != Some(client));
+    }
+
+    /// Gives up exclusive delivery, if `client` still holds it.
+    pub fn release_active(&self, client: u64) {
+        self.active.send_if_modified(|active| {
+            let held = *active == Some(client);
+            if held {
+                *active = None;
+            }
+            held
+        });
+    }
+
+    /// What a connection must present to join, if anything.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// True if another client currently has exclusive delivery.
+    pub fn excludes(&self, client: u64) -> bool {
+        self.active.borrow().is_some_and(|active| active != client)
+    }
+
+    fn admit(&self, token: Option<&str>) -> Result<&Room, RoomError> {
+        match &self.token {
+            Some(expected) if !crate::http::token_eq(token, Some(expected)) => Err(RoomError::Unauthorized),
+            _ => Ok(self),
+        }
+    }
+}
+
+impl Rooms {
+    pub fn from_config(config: &BTreeMap<String, RoomConfig>) -> Rooms {
+        let mut rooms: BTreeMap<String, Room> = config
+            .iter()
+            .map(|(name, cfg)| (name.clone(), Room::new(name, cfg)))
+            .collect();
+        rooms
+            .entry(DEFAULT_ROOM.to_string())
+            .or_insert_with(|| Room::new(DEFAULT_ROOM, &RoomConfig::default()));
+        Rooms {
+            rooms,
+            legacy: config.is_empty(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Room> {
+        self.rooms.get(name)
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &Room> {
+        self.rooms.values()
+    }
+
+    /// Picks the room for a connection from its request path (`/work`) or, on `/`,
+    /// from whichever room's token it presented.
+    pub fn resolve(&self, path: &str, token: Option<&str>) -> Result<&Room, RoomError> {
+        let name = path.trim_matches('/');
+        if self.legacy {
+            return Ok(&self.rooms[DEFAULT_ROOM]);
+        }
+        if name.is_empty() {
+            let by_token = self
+                .rooms
+                .values()
+                .find(|room| room.token.is_some() && crate::http::token_eq(token, room.token.as_deref()));
+            return match by_token {
+                Some(room) => Ok(room),
+                None => self.rooms[DEFAULT_ROOM].admit(token),
+            };
+        }
+        match self.rooms.get(name) {
+            Some(room) => room.admit(token),
+            None => Err(RoomError::NotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rooms() -> Rooms {
+        let room = |token: Option<&str>, input| RoomConfig {
+            token: token.map(str::to_string),
+            input,
+        };
+        Rooms::from_config(&BTreeMap::from([
+            ("work".to_string(), room(Some("w0rk"), true)),
+            ("stage".to_string(), room(None, false)),
+        ]))
+    }
+
+    fn joins(rooms: &Rooms, path: &str, token: Option<&str>) -> Result<String, &'static str> {
+        match rooms.resolve(path, token) {
+            Ok(room) => Ok(room.name.clone()),
+            Err(RoomError::NotFound) => Err("not found"),
+            Err(RoomError::Unauthorized) => Err("unauthorized"),
+        }
+    }
+
+    #[test]
+    fn without_rooms_every_path_joins_the_default_room() {
+        let rooms = Rooms::from_config(&BTreeMap::new());
+        assert_eq!(joins(&rooms, "/", None), Ok(DEFAULT_ROOM.to_string()));
+        assert_eq!(joins(&rooms, "/anything", Some("x")), Ok(DEFAULT_ROOM.to_string()));
+        assert_eq!(rooms.all().count(), 1);
+    }
+
+    #[test]
+    fn rooms_are_picked_by_path_or_by_token() {
+        let rooms = rooms();
+        assert_eq!(joins(&rooms, "/work", Some("w0rk")), Ok("work".to_string()));
+        assert_eq!(joins(&rooms, "/work/", Some("w0rk")), Ok("work".to_string()));
+        assert_eq!(joins(&rooms, "/work", Some("nope")), Err("unauthorized"));
+        assert_eq!(joins(&rooms, "/work", None), Err("unauthorized"));
+        assert_eq!(joins(&rooms, "/stage", None), Ok("stage".to_string()));
+        assert_eq!(joins(&rooms, "/missing", None), Err("not found"));
+        // On `/` the token chooses the room; without a matching one it's the default room.
+        assert_eq!(joins(&rooms, "/", Some("w0rk")), Ok("work".to_string()));
+        assert_eq!(joins(&rooms, "/", Some("nope")), Ok(DEFAULT_ROOM.to_string()));
+        assert!(!rooms.get("stage").unwrap().input && rooms.get(DEFAULT_ROOM).unwrap().input);
+    }
+
+    #[test]
+    fn only_the_holder_releases_exclusive_delivery() {
+        let rooms = rooms();
+        let room = rooms.get("stage").unwrap();
+        assert!(!room.excludes(1) && !room.excludes(2));
+        room.claim_active(1);
+        assert!(!room.excludes(1) && room.excludes(2));
+        room.release_active(2);
+        assert!(room.excludes(2));
+        room.claim_active(2);
+        assert!(room.excludes(1));
+        room.release_active(2);
+        assert!(!room.excludes(1) && !room.excludes(2));
+    }
+}
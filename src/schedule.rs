@@ -0,0 +1,192 @@
+/*
+ * pet-input-server: src/schedule.rs
+ *
+ * Time-of-day windows (`22:00-07:00`, local time; a window that ends before
+ * it starts runs over midnight), and per-client quiet hours built on them.
+ *
+ * A client that passes `?quiet=22:00-07:00` (several windows separated by
+ * commas) gets a thinner stream inside the window, applied here so a
+ * low-power display doesn't have to wake up to renegotiate:
+ *
+ * - `quiet_mouse_ms` (default 1000): at most one MouseMove/MousePan per this
+ * - `quiet_intensity_ms` (default 10000): likewise for Intensity
+ * - `quiet_stats_ms` (default 60000): likewise for Rates and Status
+ *
+ * 0 leaves that group alone. All of these are storable prefs, so an e-ink
+ * client saves them once with `save_prefs`. Entering and leaving the window
+ * sends the client a `quiet_hours` ServerNotice.
+ */
+
+use crate::notice::Code;
+use crate::{Action, ActionKind};
+use chrono::Timelike;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::time::{Interval, MissedTickBehavior};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// How often a client's quiet hours are checked against the clock.
+const CHECK_EVERY: Duration = Duration::from_secs(15);
+
+/// `HH:MM-HH:MM`, as minutes since midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    start: u32,
+    end: u32,
+}
+
+fn parse_time(text: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid time {:?} (expected HH:MM)", text);
+    let (hours, minutes) = text.split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Window, String> {
+        let (start, end) = text
+            .split_once('-')
+            .ok_or_else(|| format!("invalid window {:?} (expected HH:MM-HH:MM)", text))?;
+        let window = Window {
+            start: parse_time(start.trim())?,
+            end: parse_time(end.trim())?,
+        };
+        if window.start == window.end {
+            return Err(format!("window {:?} is empty", text));
+        }
+        Ok(window)
+    }
+}
+
+impl Window {
+    /// Whether `minute` (since midnight) falls inside; the start is inclusive, the end is not.
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// Parses a comma-separated list of windows.
+pub fn parse_windows(text: &str) -> Result<Vec<Window>, String> {
+    text.split(',').map(str::parse).collect()
+}
+
+/// Minutes since local midnight, now.
+pub fn local_minute() -> u32 {
+    let now = chrono::Local::now();
+    (now.hour() * 60 + now.minute()) % MINUTES_PER_DAY
+}
+
+/// Whether any of `windows` contains the current local time.
+pub fn in_any(windows: &[Window]) -> bool {
+    let minute = local_minute();
+    windows.iter().any(|window| window.contains(minute))
+}
+
+#[derive(Clone, Copy)]
+enum Group {
+    Mouse,
+    Intensity,
+    Stats,
+}
+
+fn group_of(kind: ActionKind) -> Option<Group> {
+    match kind {
+        ActionKind::MouseMove | ActionKind::MousePan => Some(Group::Mouse),
+        ActionKind::Intensity => Some(Group::Intensity),
+        ActionKind::Rates | ActionKind::Status => Some(Group::Stats),
+        _ => None,
+    }
+}
+
+/// One client's quiet hours and what it has been sent inside them.
+pub struct QuietHours {
+    windows: Vec<Window>,
+    /// Minimum spacing per group, indexed by `Group`; zero is no limit.
+    spacing: [Duration; 3],
+    last_sent: [Option<Instant>; 3],
+    active: bool,
+    check: Interval,
+}
+
+fn spacing_param(query: &str, name: &str, default_ms: u64) -> Result<Duration, String> {
+    match crate::http::query_param(query, name) {
+        None => Ok(Duration::from_millis(default_ms)),
+        Some(ms) => ms
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| format!("invalid {} {:?} (expected milliseconds)", name, ms)),
+    }
+}
+
+impl QuietHours {
+    /// The connection's quiet hours from its query; `None` without `?quiet=`.
+    pub fn from_query(query: &str) -> Result<Option<QuietHours>, String> {
+        let Some(windows) = crate::http::query_param(query, "quiet") else {
+            return Ok(None);
+        };
+        let windows = parse_windows(windows)?;
+        let mut check = tokio::time::interval(CHECK_EVERY);
+        check.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let spacing = [
+            spacing_param(query, "quiet_mouse_ms", 1000)?,
+            spacing_param(query, "quiet_intensity_ms", 10_000)?,
+            spacing_param(query, "quiet_stats_ms", 60_000)?,
+        ];
+        Ok(Some(QuietHours {
+            windows,
+            spacing,
+            last_sent: [None; 3],
+            active: false,
+            check,
+        }))
+    }
+
+    /// Whether an event of `kind` may be sent now; `false` means drop it.
+    pub fn admit(&mut self, kind: ActionKind) -> bool {
+        let Some(group) = group_of(kind).filter(|_| self.active) else {
+            return true;
+        };
+        let spacing = self.spacing[group as usize];
+        if spacing.is_zero() {
+            return true;
+        }
+        let now = Instant::now();
+        let last = &mut self.last_sent[group as usize];
+        if last.is_some_and(|last| now.duration_since(last) < spacing) {
+            return false;
+        }
+        *last = Some(now);
+        true
+    }
+
+    /// Waits until the client enters or leaves its quiet hours, and returns the notice for it.
+    /// The first check is immediate, so a client connecting inside a window hears about it.
+    pub async fn transition(&mut self) -> Action {
+        loop {
+            self.check.tick().await;
+            let active = in_any(&self.windows);
+            if active == self.active {
+                continue;
+            }
+            self.active = active;
+            self.last_sent = [None; 3];
+            let message = if active {
+                "Quiet hours started; mouse, intensity and stats updates are reduced."
+            } else {
+                "Quiet hours ended; updates are back to normal."
+            };
+            return crate::notice::action(Code::QuietHours, message, active);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+/*
+ * pet-input-server: src/serializer.rs
+ *
+ * The per-Action encode step shared by every sink. Each sink holds a boxed
+ * `Serializer`, picked by negotiation (`?format=` for WebSocket clients) or
+ * by the sink's own default. With `?envelope=1` the structured formats nest
+ * the action as `{"event":{...},"meta":{...}}` (see `EnvelopeView`).
+ *
+ * Before encoding, control characters in any string of the action (kind,
+ * value, source, nested JSON) are replaced with U+FFFD. JSON escaping would
+ * keep them legal, but a key name with a raw \x1b or \x00 in it is never
+ * intended and strict or naive clients handle them badly.
+ */
+
+use crate::{Action, ActionValue};
+use std::borrow::Cow;
+
+/// One encoded action, ready to be framed by the sink.
+pub enum EncodedFrame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl EncodedFrame {
+    pub fn len(&self) -> usize {
+        match self {
+            EncodedFrame::Text(text) => text.len(),
+            EncodedFrame::Binary(bytes) => bytes.len(),
+        }
+    }
+}
+
+pub trait Serializer: Send + Sync {
+    fn encode(&self, action: &Action) -> EncodedFrame {
+        self.encode_as(action, action.kind.as_str())
+    }
+
+    /// Encodes `action` under the wire name `kind` (its `[rename_kinds]` name).
+    fn encode_as(&self, action: &Action, kind: &str) -> EncodedFrame;
+
+    /// Like `encode_as`, in the `?envelope=1` layout; formats without nesting keep their flat form.
+    fn encode_enveloped(&self, action: &Action, kind: &str) -> EncodedFrame {
+        self.encode_as(action, kind)
+    }
+
+    /// The inverse of `encode`; `None` for write-only formats that drop structure.
+    /// Only the tests read frames back, to check every format round-trips.
+    #[cfg(test)]
+    fn decode(&self, _frame: &EncodedFrame) -> Option<Result<Action, String>> {
+        None
+    }
+}
+
+fn is_clean(text: &str) -> bool {
+    !text.chars().any(char::is_control)
+}
+
+fn clean_str(text: &mut String) {
+    if !is_clean(text) {
+        *text = text.chars().map(|c| if c.is_control() { '\u{FFFD}' } else { c }).collect();
+    }
+}
+
+/// A renamed kind comes from the config file, so it is cleaned like any other string.
+fn cleaned(text: &str) -> Cow<'_, str> {
+    if is_clean(text) {
+        Cow::Borrowed(text)
+    } else {
+        let mut text = text.to_string();
+        clean_str(&mut text);
+        Cow::Owned(text)
+    }
+}
+
+fn json_is_clean(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::String(text) => is_clean(text),
+        serde_json::Value::Array(items) => items.iter().all(json_is_clean),
+        serde_json::Value::Object(map) => map.iter().all(|(key, value)| is_clean(key) && json_is_clean(value)),
+        _ => true,
+    }
+}
+
+fn clean_json(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(text) => clean_str(text),
+        serde_json::Value::Array(items) => items.iter_mut().for_each(clean_json),
+        serde_json::Value::Object(map) => {
+            if !map.keys().all(|key| is_clean(key)) {
+                *map = std::mem::take(map)
+                    .into_iter()
+                    .map(|(mut key, value)| {
+                        clean_str(&mut key);
+                        (key, value)
+                    })
+                    .collect();
+            }
+            map.values_mut().for_each(clean_json);
+        }
+        _ => {}
+    }
+}
+
+/// The action with control characters replaced; borrowed (no copy) in the usual case where there are none.
+fn sanitized(action: &Action) -> Cow<'_, Action> {
+    let value_clean = match &action.value {
+        ActionValue::String(text) => is_clean(text),
+        ActionValue::Note(note) => is_clean(&note.note),
+        ActionValue::Json(value) => json_is_clean(value),
+        _ => true,
+    };
+    let strings = [action.source.as_ref(), action.shifted.as_ref(), action.label.as_ref()];
+    if value_clean && strings.into_iter().flatten().all(|text| is_clean(text)) {
+        return Cow::Borrowed(action);
+    }
+
+    let mut action = action.clone();
+    action
+        .source
+        .iter_mut()
+        .chain(action.shifted.iter_mut())
+        .chain(action.label.iter_mut())
+        .for_each(clean_str);
+    match &mut action.value {
+        ActionValue::String(text) => clean_str(text),
+        ActionValue::Note(note) => clean_str(&mut note.note),
+        ActionValue::Json(value) => clean_json(value),
+        _ => {}
+    }
+    Cow::Owned(action)
+}
+
+/// `{"kind":"KeyboardPress","value":"KeyA"}` text frames; the default everywhere.
+pub struct Json;
+
+impl Serializer for Json {
+    fn encode_as(&self, action: &Action, kind: &str) -> EncodedFrame {
+        let kind = cleaned(kind);
+        // Every ActionValue maps onto plain JSON, so this cannot fail.
+        let text = serde_json::to_string(&sanitized(action).view(&kind)).expect("actions always serialize to JSON");
+        EncodedFrame::Text(text)
+    }
+
+    fn encode_enveloped(&self, action: &Action, kind: &str) -> EncodedFrame {
+        let kind = cleaned(kind);
+        let text = serde_json::to_string(&sanitized(action).envelope(&kind)).expect("actions always serialize to JSON");
+        EncodedFrame::Text(text)
+    }
+
+    #[cfg(test)]
+    fn decode(&self, frame: &EncodedFrame) -> Option<Result<Action, String>> {
+        Some(match frame {
+            EncodedFrame::Text(text) => serde_json::from_str(text).map_err(|e| e.to_string()),
+            EncodedFrame::Binary(bytes) => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+        })
+    }
+}
+
+/// The same structure as `Json`, as MessagePack binary frames (maps keep their field names).
+pub struct MessagePack;
+
+impl Serializer for MessagePack {
+    fn encode_as(&self, action: &Action, kind: &str) -> EncodedFrame {
+        let kind = cleaned(kind);
+        let bytes =
+            rmp_serde::to_vec_named(&sanitized(action).view(&kind)).expect("actions always serialize to MessagePack");
+        EncodedFrame::Binary(bytes)
+    }
+
+    fn encode_enveloped(&self, action: &Action, kind: &str) -> EncodedFrame {
+        let kind = cleaned(kind);
+        let bytes = rmp_serde::to_vec_named(&sanitized(action).envelope(&kind))
+            .expect("actions always serialize to MessagePack");
+        EncodedFrame::Binary(bytes)
+    }
+
+    #[cfg(test)]
+    fn decode(&self, frame: &EncodedFrame) -> Option<Result<Action, String>> {
+        Some(match frame {
+            EncodedFrame::Binary(bytes) => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            EncodedFrame::Text(_) => Err("MessagePack frames are binary".to_string()),
+        })
+    }
+}
+
+/// One line per action for the tiniest clients, no JSON parser needed:
+///
+/// ```text
+/// line   = kind *(" " field) *(" " name "=" value) "\n"
+/// ```
+///
+/// - MouseMove `x y` (`angle radius` with polar coords), MouseDelta `dx dy`
+/// - string values (keys, buttons, region names) as one token: `KeyboardPress KeyA`
+/// - Note `note velocity`, Intensity `number`
+/// - JSON values: arrays as one token per item (`Chord KeyS KeyT`), objects as
+///   `name=value` pairs in name order (`Rates KeyboardPress=4 total=62`),
+///   null as nothing (`Inactive`), anything nested as compact JSON
+/// - then `source=`, `shifted=`, `label=`, `timestamp_ms=`, `mono_ns=`,
+///   `os_timestamp_ms=`, `hook_timestamp_ms=`, `seq=`, `consumed=true` when present
+///
+/// Whitespace inside a token is replaced with `_`.
+pub struct Plaintext;
+
+fn token(text: &str) -> String {
+    text.chars().map(|c| if c.is_whitespace() { '_' } else { c }).collect()
+}
+
+fn json_token(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => token(text),
+        other => token(&other.to_string()),
+    }
+}
+
+impl Serializer for Plaintext {
+    fn encode_as(&self, action: &Action, kind: &str) -> EncodedFrame {
+        let action = sanitized(action);
+        let mut fields = vec![token(&cleaned(kind))];
+        match &action.value {
+            ActionValue::String(text) => fields.push(token(text)),
+            ActionValue::Coords(coords) => fields.extend([coords.x.to_string(), coords.y.to_string()]),
+            ActionValue::Delta(delta) => fields.extend([delta.dx.to_string(), delta.dy.to_string()]),
+            ActionValue::Polar(polar) => fields.extend([polar.angle.to_string(), polar.radius.to_string()]),
+            ActionValue::Note(note) => fields.extend([token(&note.note), note.velocity.to_string()]),
+            ActionValue::Number(number) => fields.push(number.to_string()),
+            ActionValue::Json(serde_json::Value::Null) => {}
+            ActionValue::Json(serde_json::Value::Array(items)) => fields.extend(items.iter().map(json_token)),
+            ActionValue::Json(serde_json::Value::Object(map)) => {
+                let mut pairs: Vec<_> = map.iter().collect();
+                pairs.sort_by(|a, b| a.0.cmp(b.0));
+                fields.extend(pairs.into_iter().map(|(name, value)| format!("{}={}", token(name), json_token(value))));
+            }
+            ActionValue::Json(scalar) => fields.push(json_token(scalar)),
+        }
+        if let Some(source) = &action.source {
+            fields.push(format!("source={}", token(source)));
+        }
+        if let Some(shifted) = &action.shifted {
+            fields.push(format!("shifted={}", token(shifted)));
+        }
+        if let Some(label) = &action.label {
+            fields.push(format!("label={}", token(label)));
+        }
+        if let Some(timestamp_ms) = action.timestamp_ms {
+            fields.push(format!("timestamp_ms={}", timestamp_ms));
+        }
+        if let Some(mono_ns) = action.mono_ns {
+            fields.push(format!("mono_ns={}", mono_ns));
+        }
+        if let Some(os_timestamp_ms) = action.os_timestamp_ms {
+            fields.push(format!("os_timestamp_ms={}", os_timestamp_ms));
+        }
+        if let Some(hook_timestamp_ms) = action.hook_timestamp_ms {
+            fields.push(format!("hook_timestamp_ms={}", hook_timestamp_ms));
+        }
+        if let Some(seq) = action.seq {
+            fields.push(format!("seq={}", seq));
+        }
+        if action.consumed {
+            fields.push("consumed=true".to_string());
+        }
+        let mut line = fields.join(" ");
+        line.push('\n');
+        EncodedFrame::Text(line)
+    }
+}
+
+/// Formats a client can ask for with `?format=`.
+pub const FORMATS: &[&str] = &["json", "msgpack", "plaintext"];
+
+pub fn by_name(name: &str) -> Option<Box<dyn Serializer>> {
+    match name {
+        "json" => Some(Box::new(Json)),
+        "msgpack" => Some(Box::new(MessagePack)),
+        "plaintext" => Some(Box::new(Plaintext)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::sample;
+    use crate::ActionKind;
+
+    fn bytes(frame: EncodedFrame) -> Vec<u8> {
+        match frame {
+            EncodedFrame::Text(text) => text.into_bytes(),
+            EncodedFrame::Binary(bytes) => bytes,
+        }
+    }
+
+    /// A sample with every optional field set, so those round-trip too.
+    fn full_sample(kind: ActionKind) -> Action {
+        Action {
+            source: Some("desk".to_string()),
+            shifted: Some("!".to_string()),
+            label: Some("Strg".to_string()),
+            timestamp_ms: Some(1700000000000.5),
+            mono_ns: Some(42),
+            os_timestamp_ms: Some(1700000000000.0),
+            hook_timestamp_ms: Some(1700000000000.25),
+            seq: Some(7),
+            consumed: true,
+            ..sample(kind)
+        }
+    }
+
+    #[test]
+    fn every_kind_round_trips_through_every_decodable_format() {
+        for format in FORMATS {
+            let serializer = by_name(format).unwrap();
+            for action in ActionKind::ALL.into_iter().flat_map(|kind| [sample(kind), full_sample(kind)]) {
+                let frame = serializer.encode(&action);
+                let Some(decoded) = serializer.decode(&frame) else { continue };
+                let decoded = decoded.unwrap_or_else(|e| panic!("{} {}: {}", format, action.kind, e));
+                assert_eq!(bytes(serializer.encode(&decoded)), bytes(frame), "{} {}", format, action.kind);
+            }
+        }
+    }
+
+    #[test]
+    fn only_plaintext_is_write_only() {
+        for format in FORMATS {
+            let serializer = by_name(format).unwrap();
+            let decodes = serializer.decode(&serializer.encode(&sample(ActionKind::KeyboardPress))).is_some();
+            assert_eq!(decodes, *format != "plaintext", "{}", format);
+        }
+        assert!(by_name("xml").is_none());
+    }
+
+    #[test]
+    fn plaintext_is_one_line_per_action() {
+        for kind in ActionKind::ALL {
+            let EncodedFrame::Text(line) = Plaintext.encode(&full_sample(kind)) else { panic!("{} is binary", kind) };
+            assert!(line.ends_with('\n') && line.matches('\n').count() == 1, "{:?}", line);
+            assert!(line.starts_with(kind.as_str()), "{:?}", line);
+        }
+        let chord = Plaintext.encode(&sample(ActionKind::Chord));
+        assert_eq!(bytes(chord), b"Chord KeyS KeyT KeyA\n");
+    }
+
+    #[test]
+    fn control_characters_are_replaced() {
+        let mut action = Action::new(ActionKind::KeyboardPress, ActionValue::String("Key\u{1b}[A\0".to_string()));
+        action.source = Some("desk\n".to_string());
+        let EncodedFrame::Text(text) = Json.encode(&action) else { unreachable!() };
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["value"], "Key\u{FFFD}[A\u{FFFD}");
+        assert_eq!(value["source"], "desk\u{FFFD}");
+
+        let nested = Action::new(ActionKind::Cue, ActionValue::Json(serde_json::json!({ "na\tme": ["\u{7}"] })));
+        let EncodedFrame::Text(text) = Json.encode(&nested) else { unreachable!() };
+        assert!(!text.chars().any(char::is_control), "{:?}", text);
+        assert!(text.contains("na\u{FFFD}me"), "{:?}", text);
+        assert_eq!(bytes(Plaintext.encode_as(&sample(ActionKind::Inactive), "Idle\u{7}")), "Idle\u{FFFD}\n".as_bytes());
+    }
+
+    #[test]
+    fn clean_actions_are_not_copied() {
+        assert!(matches!(sanitized(&full_sample(ActionKind::Snapshot)), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn envelope_nests_the_event() {
+        let EncodedFrame::Text(text) = Json.encode_enveloped(&full_sample(ActionKind::KeyboardPress), "Key") else {
+            unreachable!()
+        };
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["event"]["kind"], "Key");
+        assert_eq!(value["event"]["value"], "KeyA");
+        assert!(value.get("meta").is_some(), "{}", text);
+    }
+
+    /// What clients are written against; a change here is a protocol change.
+    #[test]
+    fn encodings_match_the_documented_forms() {
+        let expected = [
+            (ActionKind::KeyboardPress, r#"{"kind":"KeyboardPress","value":"KeyA"}"#, "KeyboardPress KeyA"),
+            (ActionKind::MouseMove, r#"{"kind":"MouseMove","value":{"x":640,"y":360}}"#, "MouseMove 640 360"),
+            (ActionKind::MouseDelta, r#"{"kind":"MouseDelta","value":{"dx":12,"dy":-3}}"#, "MouseDelta 12 -3"),
+            (ActionKind::Note, r#"{"kind":"Note","value":{"note":"C4","velocity":100}}"#, "Note C4 100"),
+            (ActionKind::Intensity, r#"{"kind":"Intensity","value":0.42}"#, "Intensity 0.42"),
+            (ActionKind::Inactive, r#"{"kind":"Inactive","value":null}"#, "Inactive"),
+            (ActionKind::Chord, r#"{"kind":"Chord","value":["KeyS","KeyT","KeyA"]}"#, "Chord KeyS KeyT KeyA"),
+            (
+                ActionKind::Rates,
+                r#"{"kind":"Rates","value":{"KeyboardPress":4,"MouseMove":58,"total":62}}"#,
+                "Rates KeyboardPress=4 MouseMove=58 total=62",
+            ),
+        ];
+        for (kind, json, plaintext) in expected {
+            assert_eq!(bytes(Json.encode(&sample(kind))), json.as_bytes(), "{}", kind);
+            assert_eq!(bytes(Plaintext.encode(&sample(kind))), format!("{}\n", plaintext).as_bytes(), "{}", kind);
+        }
+        let msgpack = [&[0x82, 0xa4][..], b"kind", &[0xad], b"KeyboardPress", &[0xa5], b"value", &[0xa4], b"KeyA"];
+        assert_eq!(bytes(MessagePack.encode(&sample(ActionKind::KeyboardPress))), msgpack.concat());
+    }
+}
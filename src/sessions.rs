@@ -0,0 +1,73 @@
+/*
+ * pet-input-server: src/sessions.rs
+ *
+ * Automatic resume for clients that reconnect often (page reloads). A client
+ * that connects with `?client_id=<id>` has its options (the `prefs::KEYS`
+ * query options, with `max_rate` and `preview` as last changed over the
+ * socket) remembered when it disconnects. If it reconnects with the same id
+ * within `--client-session-ttl-secs` they apply again; anything it passes
+ * explicitly still wins.
+ *
+ * Unlike `?name=` preferences nothing has to be saved by hand, and nothing
+ * outlives the process: entries are kept in memory only, expire after the
+ * TTL, and at most `MAX_SESSIONS` are kept (the oldest goes first).
+ */
+
+use crate::prefs::{self, Prefs};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Remembered clients at most; bounds memory when ids are random per page load.
+const MAX_SESSIONS: usize = 1024;
+/// Longest accepted `client_id`.
+pub const MAX_ID_LEN: usize = 128;
+
+pub struct Sessions {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Prefs)>>,
+}
+
+impl Sessions {
+    /// A zero `ttl` turns the feature off.
+    pub fn new(ttl: Duration) -> Sessions {
+        Sessions {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `query` with the remembered options for `id` filled in, and whether there were any.
+    pub fn apply(&self, id: &str, query: &str) -> (String, bool) {
+        let mut entries = self.entries.lock().unwrap();
+        let ttl = self.ttl;
+        entries.retain(|_, (at, _)| at.elapsed() < ttl);
+        match entries.get(id) {
+            Some((_, options)) => (prefs::fill_in(query, options), true),
+            None => (query.to_string(), false),
+        }
+    }
+
+    /// Remembers the options in `query` for `id` as of now.
+    pub fn remember(&self, id: &str, query: &str) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_SESSIONS && !entries.contains_key(id) {
+            let oldest = entries.iter().min_by_key(|(_, (at, _))| *at).map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(id.to_string(), (Instant::now(), prefs::options_of(query)));
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ * pet-input-server: src/settings.rs
+ */
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Settings that can be changed while the server runs (via `PATCH /config`).
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub mouse_throttle_ms: u64,
+    pub region_hysteresis_px: f64,
+    /// `--key-filter`: only keyboard events whose mapped name matches are broadcast.
+    pub key_filter: Option<regex::Regex>,
+    /// `--only-button`: only this button's presses, releases, chords and pans are broadcast.
+    pub only_button: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            mouse_throttle_ms: 16, // ~60fps
+            region_hysteresis_px: 4.0,
+            key_filter: None,
+            only_button: None,
+        }
+    }
+}
+
+/// The settings in effect. A patch builds a whole new `Settings` and swaps it in, so a
+/// reader sees either all of a patch or none of it.
+pub struct RuntimeSettings(RwLock<Arc<Settings>>);
+
+pub static SETTINGS: Lazy<RuntimeSettings> = Lazy::new(|| RuntimeSettings(RwLock::new(Arc::default())));
+
+/// Buttons `only_button` accepts.
+const BUTTONS: [&str; 3] = ["Mouse1", "Mouse2", "Mouse3"];
+
+/// A partial update; fields left out keep their current value, and `null` clears a filter.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsPatch {
+    pub mouse_throttle_ms: Option<u64>,
+    pub region_hysteresis_px: Option<f64>,
+    #[serde(default, deserialize_with = "present")]
+    pub key_filter: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub only_button: Option<Option<String>>,
+}
+
+/// Tells a field given as `null` (`Some(None)`) from one left out (`None`).
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<String>>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(Some)
+}
+
+impl Settings {
+    /// A copy with `patch` applied, or why it can't be.
+    fn patched(&self, patch: &SettingsPatch) -> Result<Settings, String> {
+        let mut next = self.clone();
+        if let Some(ms) = patch.mouse_throttle_ms {
+            next.mouse_throttle_ms = ms;
+        }
+        if let Some(px) = patch.region_hysteresis_px {
+            if !px.is_finite() || px < 0.0 {
+                return Err("region_hysteresis_px must be a non-negative number".to_string());
+            }
+            next.region_hysteresis_px = px;
+        }
+        if let Some(pattern) = &patch.key_filter {
+            next.key_filter = match pattern {
+                Some(pattern) => Some(
+                    regex::Regex::new(pattern).map_err(|e| format!("invalid key_filter '{}': {}", pattern, e))?,
+                ),
+                None => None,
+            };
+        }
+        if let Some(button) = &patch.only_button {
+            if button.as_deref().is_some_and(|button| !BUTTONS.contains(&button)) {
+                return Err(format!("only_button must be one of {}", BUTTONS.join(", ")));
+            }
+            next.only_button = button.clone();
+        }
+        Ok(next)
+    }
+}
+
+impl RuntimeSettings {
+    /// The settings in effect now; hold on to it to read several fields consistently.
+    pub fn current(&self) -> Arc<Settings> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn mouse_throttle(&self) -> Duration {
+        Duration::from_millis(self.0.read().unwrap().mouse_throttle_ms)
+    }
+
+    pub fn region_hysteresis_px(&self) -> f64 {
+        self.0.read().unwrap().region_hysteresis_px
+    }
+
+    /// Current values as JSON, keyed like `SettingsPatch`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let settings = self.current();
+        json!({
+            "mouse_throttle_ms": settings.mouse_throttle_ms,
+            "region_hysteresis_px": settings.region_hysteresis_px,
+            "key_filter": settings.key_filter.as_ref().map(regex::Regex::as_str),
+            "only_button": settings.only_button,
+        })
+    }
+
+    /// Validates the whole patch and swaps in the result, so a bad patch changes nothing.
+    pub fn apply(&self, patch: &SettingsPatch) -> Result<(), String> {
+        let mut current = self.0.write().unwrap();
+        *current = Arc::new(current.patched(patch)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(json: serde_json::Value) -> SettingsPatch {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn a_patch_changes_only_what_it_names() {
+        let settings = RuntimeSettings(RwLock::new(Arc::default()));
+        settings.apply(&patch(json!({"mouse_throttle_ms": 8, "key_filter": "^F[0-9]+$"}))).unwrap();
+        settings.apply(&patch(json!({"only_button": "Mouse2"}))).unwrap();
+        assert_eq!(
+            settings.to_json(),
+            json!({
+                "mouse_throttle_ms": 8,
+                "region_hysteresis_px": 4.0,
+                "key_filter": "^F[0-9]+$",
+                "only_button": "Mouse2",
+            })
+        );
+        // `null` clears a filter; leaving it out keeps it.
+        settings.apply(&patch(json!({"key_filter": null}))).unwrap();
+        let current = settings.current();
+        assert!(current.key_filter.is_none());
+        assert_eq!(current.only_button.as_deref(), Some("Mouse2"));
+    }
+
+    #[test]
+    fn a_bad_field_rejects_the_whole_patch() {
+        let settings = RuntimeSettings(RwLock::new(Arc::default()));
+        let before = settings.current();
+        for bad in [
+            json!({"mouse_throttle_ms": 1, "key_filter": "("}),
+            json!({"mouse_throttle_ms": 1, "only_button": "Mouse9"}),
+            json!({"mouse_throttle_ms": 1, "region_hysteresis_px": -1.0}),
+        ] {
+            assert!(settings.apply(&patch(bad.clone())).is_err(), "{}", bad);
+            assert!(Arc::ptr_eq(&settings.current(), &before), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn unknown_fields_are_not_patches() {
+        assert!(serde_json::from_value::<SettingsPatch>(json!({"bind": "0.0.0.0:1"})).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+/*
+ * pet-input-server: src/shedding.rs
+ *
+ * Optional load shedding in front of the broadcast channel (`--shed-at-percent`).
+ * `send` never blocks: once the slowest receiver is a whole buffer behind, the
+ * oldest entries are overwritten and that receiver only learns of it as a
+ * `Lagged` count, losing clicks and key presses as readily as cursor moves.
+ *
+ * With this on, the translation stage checks the slowest receiver's backlog
+ * (`Sender::len`) before sending a continuous event, and drops it while the
+ * backlog is above the mark, leaving the remaining room to discrete events.
+ * Only MouseMove is shed here; MousePan is only sent alongside a sent move, so
+ * it goes with it. Shedding stops once the backlog is under three quarters of
+ * the mark, so it doesn't flap event by event.
+ */
+
+use crate::{Action, ActionKind};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tokio::sync::broadcast;
+
+/// Kinds dropped while shedding; anything else always goes through.
+const CONTINUOUS_KINDS: &[ActionKind] = &[ActionKind::MouseMove];
+
+/// Backlog (queued events) at which shedding starts; 0 means disabled.
+static MARK: AtomicUsize = AtomicUsize::new(0);
+static SHEDDING: AtomicBool = AtomicBool::new(false);
+/// Backlog seen by the last check, for `/status`.
+static BACKLOG: AtomicUsize = AtomicUsize::new(0);
+
+/// Enables shedding at `percent` of a channel holding `capacity` events.
+pub fn configure(capacity: usize, percent: u8) {
+    let mark = (capacity * percent as usize / 100).max(1);
+    MARK.store(mark, Ordering::Relaxed);
+    println!(
+        "Shedding mouse moves while a client is {} or more events behind (of {}).",
+        mark, capacity
+    );
+}
+
+/// Whether an event of `kind` should be sent now; `false` means drop it.
+pub fn admit(broadcast_tx: &broadcast::Sender<Action>, kind: ActionKind) -> bool {
+    let mark = MARK.load(Ordering::Relaxed);
+    if mark == 0 || !CONTINUOUS_KINDS.contains(&kind) {
+        return true;
+    }
+    let backlog = broadcast_tx.len();
+    BACKLOG.store(backlog, Ordering::Relaxed);
+    let shedding = SHEDDING.load(Ordering::Relaxed);
+    if !shedding && backlog >= mark {
+        SHEDDING.store(true, Ordering::Relaxed);
+        let message = format!(
+            "A client is {} events behind; shedding mouse moves to protect clicks and keys.",
+            backlog
+        );
+        eprintln!("{}", message);
+        crate::notice::raise(crate::notice::Code::BroadcastShedding, &message);
+    } else if shedding && backlog < mark * 3 / 4 {
+        SHEDDING.store(false, Ordering::Relaxed);
+        let message = format!("Broadcast backlog down to {}; no longer shedding.", backlog);
+        println!("{}", message);
+        crate::notice::clear(crate::notice::Code::BroadcastShedding, &message);
+    }
+    if SHEDDING.load(Ordering::Relaxed) {
+        crate::metrics::shed(kind);
+        return false;
+    }
+    true
+}
+
+/// Settings and state for `/status`; `null` when disabled.
+pub fn to_json() -> serde_json::Value {
+    let mark = MARK.load(Ordering::Relaxed);
+    if mark == 0 {
+        return serde_json::Value::Null;
+    }
+    json!({
+        "mark": mark,
+        "backlog": BACKLOG.load(Ordering::Relaxed),
+        "active": SHEDDING.load(Ordering::Relaxed),
+        "shed": crate::metrics::shed_json(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::sample;
+
+    /// One test, since the mark and state are process-wide.
+    #[test]
+    fn a_slow_consumer_loses_moves_but_not_keys() {
+        let (broadcast_tx, mut slow) = broadcast::channel(16);
+        configure(16, 50);
+        let mut moves = 0;
+        for _ in 0..20 {
+            if admit(&broadcast_tx, ActionKind::MouseMove) {
+                broadcast_tx.send(sample(ActionKind::MouseMove)).unwrap();
+                moves += 1;
+            }
+        }
+        assert_eq!(moves, 8);
+        assert!(to_json()["active"].as_bool().unwrap());
+
+        // Discrete events go through while shedding.
+        assert!(admit(&broadcast_tx, ActionKind::KeyboardPress));
+        broadcast_tx.send(sample(ActionKind::KeyboardPress)).unwrap();
+
+        // Catching up a little isn't enough: shedding waits for three quarters of the mark.
+        slow.try_recv().unwrap();
+        slow.try_recv().unwrap();
+        assert!(!admit(&broadcast_tx, ActionKind::MouseMove));
+        slow.try_recv().unwrap();
+        slow.try_recv().unwrap();
+        assert!(admit(&broadcast_tx, ActionKind::MouseMove));
+        broadcast_tx.send(sample(ActionKind::MouseMove)).unwrap();
+        assert!(!to_json()["active"].as_bool().unwrap());
+
+        // The slow receiver never lagged, so it still gets the key press.
+        let rest: Vec<ActionKind> = std::iter::from_fn(|| slow.try_recv().ok()).map(|a| a.kind).collect();
+        let mut expected = vec![ActionKind::MouseMove; 4];
+        expected.extend([ActionKind::KeyboardPress, ActionKind::MouseMove]);
+        assert_eq!(rest, expected);
+        MARK.store(0, Ordering::Relaxed);
+    }
+}
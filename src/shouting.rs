@@ -0,0 +1,49 @@
+/*
+ * pet-input-server: src/shouting.rs
+ *
+ * Derived `ShoutingDetected` signal: `true` once `--shouting-threshold`
+ * uppercase letters have been typed in a row (Caps Lock or Shift), `false`
+ * at the next lowercase letter. Other keys neither extend nor break a run.
+ * Caps Lock is assumed off at startup and toggled on each press.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use tokio::sync::broadcast;
+
+/// Letters in a row that count as shouting; 0 disables detection.
+static THRESHOLD: AtomicU32 = AtomicU32::new(0);
+static CAPS_LOCK: AtomicBool = AtomicBool::new(false);
+static RUN: AtomicU32 = AtomicU32::new(0);
+static SHOUTING: AtomicBool = AtomicBool::new(false);
+
+pub fn configure(threshold: u32) {
+    THRESHOLD.store(threshold, Ordering::Relaxed);
+}
+
+/// Called for every key press with the mapped name and whether Shift is held.
+pub fn on_key_press(key: &str, shift: bool, broadcast_tx: &broadcast::Sender<Action>) {
+    let threshold = THRESHOLD.load(Ordering::Relaxed);
+    if threshold == 0 {
+        return;
+    }
+    if key == "CapsLock" {
+        CAPS_LOCK.fetch_xor(true, Ordering::Relaxed);
+        return;
+    }
+    let is_letter = key.len() == 4 && key.starts_with("Key") && key.as_bytes()[3].is_ascii_uppercase();
+    if !is_letter {
+        return;
+    }
+    let uppercase = CAPS_LOCK.load(Ordering::Relaxed) != shift;
+    let shouting = if uppercase {
+        RUN.fetch_add(1, Ordering::Relaxed) + 1 >= threshold
+    } else {
+        RUN.store(0, Ordering::Relaxed);
+        false
+    };
+    if SHOUTING.swap(shouting, Ordering::Relaxed) != shouting {
+        crate::rates::count(ActionKind::ShoutingDetected);
+        let _ = broadcast_tx.send(Action::new(ActionKind::ShoutingDetected, ActionValue::Json(shouting.into())));
+    }
+}
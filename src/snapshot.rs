@@ -0,0 +1,146 @@
+/*
+ * pet-input-server: src/snapshot.rs
+ *
+ * Snapshot delivery for render-on-tick pets (`--snapshot-mode`, per client
+ * `?snapshot=1`). Instead of a stream of input events, the connection folds
+ * them into one state and sends it every `--snapshot-interval` ms, whether or
+ * not anything changed:
+ *
+ *   {"kind":"Snapshot","value":{"cursor":{"x":640.0,"y":360.0},
+ *    "keys":["KeyA","Shift"],"buttons":[],"modifiers":{"shift":true,
+ *    "control":false,"alt":false,"meta":false},"intensity":0.42,
+ *    "mood":"focused","events":7}}
+ *
+ * `cursor` is the last MouseMove value as the client would have got it
+ * (precision, polar coords), null until the cursor is known; `keys` and
+ * `buttons` are held down, sorted; `events` counts the input events folded in
+ * since the previous snapshot. `intensity` and `mood` are null unless those
+ * features are on. Kinds not covered here (notices, cues) are still sent as
+ * they happen.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// Kinds folded into the snapshot instead of being sent.
+pub const COVERED_KINDS: &[ActionKind] = &[
+    ActionKind::MouseMove,
+    ActionKind::MouseDelta,
+    ActionKind::MousePress,
+    ActionKind::MouseRelease,
+    ActionKind::KeyboardPress,
+    ActionKind::KeyboardRelease,
+    ActionKind::KeyLongPress,
+    ActionKind::ButtonChord,
+    ActionKind::MousePan,
+    ActionKind::MousePanEnd,
+    ActionKind::Chord,
+    ActionKind::Note,
+    ActionKind::RegionEnter,
+    ActionKind::RegionExit,
+    ActionKind::Intensity,
+    ActionKind::Mood,
+];
+
+/// Kinds that change what a snapshot says, so they must be captured for a snapshot client.
+const TRACKED_KINDS: &[ActionKind] = &[
+    ActionKind::MouseMove,
+    ActionKind::MousePress,
+    ActionKind::MouseRelease,
+    ActionKind::KeyboardPress,
+    ActionKind::KeyboardRelease,
+    ActionKind::Intensity,
+    ActionKind::Mood,
+];
+
+pub fn covers(kind: ActionKind) -> bool {
+    COVERED_KINDS.contains(&kind)
+}
+
+pub fn tracks(kind: ActionKind) -> bool {
+    TRACKED_KINDS.contains(&kind)
+}
+
+/// One connection's folded state and its tick.
+pub struct Snapshotter {
+    interval: Interval,
+    cursor: Option<ActionValue>,
+    keys: BTreeSet<String>,
+    buttons: BTreeSet<String>,
+    intensity: Option<f64>,
+    mood: Option<String>,
+    events: u64,
+}
+
+impl Snapshotter {
+    pub fn new(period: Duration) -> Snapshotter {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        Snapshotter {
+            interval,
+            cursor: None,
+            keys: BTreeSet::new(),
+            buttons: BTreeSet::new(),
+            intensity: None,
+            mood: None,
+            events: 0,
+        }
+    }
+
+    /// Resolves when the next snapshot is due.
+    pub async fn tick(&mut self) {
+        self.interval.tick().await;
+    }
+
+    /// Folds one covered action into the state.
+    pub fn fold(&mut self, action: &Action) {
+        match (action.kind, &action.value) {
+            (ActionKind::MouseMove, value) => self.cursor = Some(value.clone()),
+            (ActionKind::KeyboardPress, ActionValue::String(key)) => {
+                self.keys.insert(key.clone());
+            }
+            (ActionKind::KeyboardRelease, ActionValue::String(key)) => {
+                self.keys.remove(key);
+            }
+            (ActionKind::MousePress, ActionValue::String(button)) => {
+                self.buttons.insert(button.clone());
+            }
+            (ActionKind::MouseRelease, ActionValue::String(button)) => {
+                self.buttons.remove(button);
+            }
+            (ActionKind::Intensity, ActionValue::Number(value)) => self.intensity = Some(*value),
+            (ActionKind::Mood, ActionValue::String(mood)) => self.mood = Some(mood.clone()),
+            _ => {}
+        }
+        if !matches!(action.kind, ActionKind::Intensity | ActionKind::Mood) {
+            self.events += 1;
+        }
+    }
+
+    /// Whether a modifier is held, by its collapsed name or either side (`--preserve-side`).
+    fn modifier(&self, name: &str) -> bool {
+        self.keys.iter().any(|key| key.strip_prefix(name).is_some_and(|side| matches!(side, "" | "Left" | "Right")))
+    }
+
+    /// The snapshot for this tick; starts the event count over.
+    pub fn take(&mut self) -> Action {
+        let value = json!({
+            "cursor": self.cursor,
+            "keys": self.keys,
+            "buttons": self.buttons,
+            "modifiers": {
+                "shift": self.modifier("Shift"),
+                "control": self.modifier("Control"),
+                "alt": self.modifier("Alt"),
+                "meta": self.modifier("Meta"),
+            },
+            "intensity": self.intensity,
+            "mood": self.mood,
+            "events": std::mem::take(&mut self.events),
+        });
+        Action::new(ActionKind::Snapshot, ActionValue::Json(value))
+    }
+}
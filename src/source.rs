@@ -0,0 +1,213 @@
+/*
+ * pet-input-server: src/source.rs
+ *
+ * Where raw input comes from. The pipeline (`event_callback`) only sees
+ * `RawInputEvent`s pushed into a sink, so the OS hook can be swapped for a
+ * scripted sequence or a `--replay` of an earlier `--record` file.
+ */
+
+use crate::recording;
+use crate::{key_for_name, media_key_for_name, ActionKind, ActionValue, Coords};
+use rdev::{Button, Event, EventType};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+#[cfg(windows)]
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant, SystemTime};
+
+/// One raw input event, with the OS's timestamp, before translation into an `Action`.
+pub type RawInputEvent = Event;
+
+/// Set by a grabbing source while the event passed to the sink is being kept from the OS.
+pub static CONSUMING: AtomicBool = AtomicBool::new(false);
+
+pub trait EventSource {
+    /// Whether the source is started over after the system wakes from sleep.
+    const RESTART_ON_RESUME: bool = false;
+
+    /// Feeds events into `sink` until the source ends (`Ok`) or fails.
+    fn run(self, sink: impl Fn(RawInputEvent) + 'static) -> Result<(), String>;
+}
+
+/// The global OS hook.
+#[derive(Clone, Copy)]
+pub struct RdevSource;
+
+/// The thread in `rdev::listen`, for `interrupt`.
+#[cfg(windows)]
+static LISTENER_THREAD: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+impl EventSource for RdevSource {
+    // Windows' hook is what most often dies across a sleep. Only there can the old run be
+    // stopped: rdev keeps one global callback, so a second `listen` next to a live one
+    // would get every event twice.
+    const RESTART_ON_RESUME: bool = cfg!(windows);
+
+    fn run(self, sink: impl Fn(RawInputEvent) + 'static) -> Result<(), String> {
+        println!("Input listener thread started. Listening for global input...");
+        #[cfg(windows)]
+        // SAFETY: no preconditions.
+        LISTENER_THREAD.store(unsafe { winapi::um::processthreadsapi::GetCurrentThreadId() }, Ordering::Relaxed);
+        rdev::listen(sink).map_err(|error| format!("{:?}", error))
+    }
+}
+
+/// Asks a running `RdevSource` to return. On Windows its message wait ends and the
+/// thread exits, taking its hooks with it; elsewhere this does nothing.
+pub fn interrupt() {
+    #[cfg(windows)]
+    {
+        use winapi::um::winuser::{PostThreadMessageW, WM_QUIT};
+        let thread = LISTENER_THREAD.swap(0, Ordering::Relaxed);
+        if thread != 0 {
+            // SAFETY: posting to a thread that has exited just fails.
+            unsafe { PostThreadMessageW(thread, WM_QUIT, 0, 0) };
+        }
+    }
+}
+
+/// A fixed list of events, each delivered at its offset from when `run` starts.
+#[derive(Clone, Default)]
+pub struct MockSource {
+    pub events: Vec<(Duration, EventType)>,
+}
+
+impl EventSource for MockSource {
+    fn run(self, sink: impl Fn(RawInputEvent) + 'static) -> Result<(), String> {
+        let started = Instant::now();
+        for (at, event) in self.events {
+            if let Some(wait) = at.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+            sink(Event {
+                time: SystemTime::now(),
+                name: None,
+                event_type: event,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Replays the raw input behind a `--record` file with its original timing.
+/// Derived kinds (notes, regions, intensity, ...) are skipped; the pipeline regenerates them.
+#[derive(Clone)]
+pub struct ReplaySource(MockSource);
+
+impl ReplaySource {
+    pub fn open(path: &Path) -> Result<ReplaySource, String> {
+        let records = recording::open(path).map_err(|e| format!("Failed to open replay {}: {}", path.display(), e))?;
+        let mut events = Vec::new();
+        for (index, record) in records.enumerate() {
+            let recorded = record.map_err(|e| format!("{} record {}: {}", path.display(), index + 1, e))?;
+            if let Some(event) = raw_event(recorded.action.kind, &recorded.action.value) {
+                events.push((Duration::from_millis(recorded.t), event));
+            }
+        }
+        println!("Replaying {} input events from {}", events.len(), path.display());
+        Ok(ReplaySource(MockSource { events }))
+    }
+}
+
+impl EventSource for ReplaySource {
+    fn run(self, sink: impl Fn(RawInputEvent) + 'static) -> Result<(), String> {
+        self.0.run(sink)?;
+        println!("Replay finished.");
+        Ok(())
+    }
+}
+
+/// Turns a recorded action back into the raw event that produced it.
+fn raw_event(kind: ActionKind, value: &ActionValue) -> Option<EventType> {
+    match (kind, value) {
+        (ActionKind::MouseMove, ActionValue::Coords(Coords { x, y })) => Some(EventType::MouseMove { x: *x, y: *y }),
+        (ActionKind::MousePress, ActionValue::String(name)) => button_for_name(name).map(EventType::ButtonPress),
+        (ActionKind::MouseRelease, ActionValue::String(name)) => button_for_name(name).map(EventType::ButtonRelease),
+        (ActionKind::KeyboardPress, ActionValue::String(name)) => key_for_name(name).map(EventType::KeyPress),
+        (ActionKind::KeyboardRelease, ActionValue::String(name)) => key_for_name(name).map(EventType::KeyRelease),
+        (ActionKind::MediaKey, ActionValue::String(name)) => media_key_for_name(name).map(EventType::KeyPress),
+        _ => None,
+    }
+}
+
+fn button_for_name(name: &str) -> Option<Button> {
+    match name {
+        "Mouse1" => Some(Button::Left),
+        "Mouse2" => Some(Button::Right),
+        "Mouse3" => Some(Button::Middle),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::sample;
+    use crate::recording::RecordedAction;
+    use crate::Action;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn mock_events_arrive_in_order_and_not_before_their_offset() {
+        let source = MockSource {
+            events: vec![
+                (Duration::ZERO, EventType::KeyPress(rdev::Key::KeyA)),
+                (Duration::from_millis(30), EventType::KeyRelease(rdev::Key::KeyA)),
+                (Duration::from_millis(60), EventType::ButtonPress(Button::Left)),
+            ],
+        };
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = seen.clone();
+        let started = Instant::now();
+        source.run(move |event| sink.borrow_mut().push((started.elapsed(), event.event_type))).unwrap();
+        let seen = seen.borrow();
+        let types: Vec<EventType> = seen.iter().map(|(_, event)| *event).collect();
+        assert_eq!(
+            types,
+            [
+                EventType::KeyPress(rdev::Key::KeyA),
+                EventType::KeyRelease(rdev::Key::KeyA),
+                EventType::ButtonPress(Button::Left)
+            ]
+        );
+        assert!(seen[1].0 >= Duration::from_millis(30) && seen[2].0 >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn replay_brings_back_raw_input_and_skips_derived_kinds() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("replay.jsonl");
+        let actions = [
+            (0, Action::new(ActionKind::MouseMove, ActionValue::Coords(Coords { x: 10.0, y: 20.0 }))),
+            (5, Action::new(ActionKind::MousePress, ActionValue::String("Mouse2".to_string()))),
+            (6, sample(ActionKind::Note)),
+            (9, Action::new(ActionKind::KeyboardPress, ActionValue::String("KeyA".to_string()))),
+            (12, Action::new(ActionKind::KeyboardRelease, ActionValue::String("NoSuchKey".to_string()))),
+        ];
+        let lines: Vec<String> = actions
+            .into_iter()
+            .map(|(t, action)| serde_json::to_string(&RecordedAction { t, action }).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+
+        let ReplaySource(mock) = ReplaySource::open(&path).unwrap();
+        assert_eq!(
+            mock.events,
+            [
+                (Duration::ZERO, EventType::MouseMove { x: 10.0, y: 20.0 }),
+                (Duration::from_millis(5), EventType::ButtonPress(Button::Right)),
+                (Duration::from_millis(9), EventType::KeyPress(rdev::Key::KeyA)),
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_names_the_broken_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.jsonl");
+        std::fs::write(&path, "{\"t\":0,\"kind\":\"MouseMove\",\"value\":{\"x\":1,\"y\":2}}\nnot json\n").unwrap();
+        let error = ReplaySource::open(&path).err().unwrap();
+        assert!(error.contains("record 2"), "{}", error);
+    }
+}
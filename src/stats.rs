@@ -0,0 +1,319 @@
+/*
+ * pet-input-server: src/stats.rs
+ *
+ * Activity metrics over a stream of timestamped actions: events by kind,
+ * typing and clicking speed per time bucket, the most pressed keys, the
+ * longest idle gap and how far the mouse travelled. `analyze` feeds it a
+ * `--record` file line by line; the live server can feed it the same way.
+ */
+
+use crate::recording::{self, ReadError};
+use crate::{Action, ActionKind, ActionValue, Coords};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::Path;
+
+/// Keys listed in the heatmap.
+const TOP_KEYS: usize = 20;
+/// Characters per word for WPM, the usual typing-test convention.
+const CHARS_PER_WORD: f64 = 5.0;
+
+/// Kinds that are raw user input, as opposed to derived or server events.
+fn is_input(kind: ActionKind) -> bool {
+    matches!(
+        kind,
+        ActionKind::MouseMove
+            | ActionKind::MousePress
+            | ActionKind::MouseRelease
+            | ActionKind::KeyboardPress
+            | ActionKind::KeyboardRelease
+            | ActionKind::MediaKey
+    )
+}
+
+#[derive(Default, Clone, Copy)]
+struct Bucket {
+    keys: u64,
+    clicks: u64,
+}
+
+pub struct Stats {
+    bucket_ms: u64,
+    first_t: Option<u64>,
+    last_t: u64,
+    by_kind: BTreeMap<&'static str, u64>,
+    buckets: BTreeMap<u64, Bucket>,
+    keys: HashMap<String, u64>,
+    last_input_t: Option<u64>,
+    /// Longest gap between two input events, and where it started.
+    longest_idle: Option<(u64, u64)>,
+    last_cursor: Option<(f64, f64)>,
+    mouse_distance: f64,
+}
+
+impl Stats {
+    pub fn new(bucket_ms: u64) -> Self {
+        Stats {
+            bucket_ms: bucket_ms.max(1),
+            first_t: None,
+            last_t: 0,
+            by_kind: BTreeMap::new(),
+            buckets: BTreeMap::new(),
+            keys: HashMap::new(),
+            last_input_t: None,
+            longest_idle: None,
+            last_cursor: None,
+            mouse_distance: 0.0,
+        }
+    }
+
+    /// Adds one action that happened `t` ms into the session.
+    pub fn add(&mut self, t: u64, action: &Action) {
+        let first_t = *self.first_t.get_or_insert(t);
+        self.last_t = self.last_t.max(t);
+        *self.by_kind.entry(action.kind.as_str()).or_default() += 1;
+        if !is_input(action.kind) {
+            return;
+        }
+
+        if let Some(last) = self.last_input_t {
+            let gap = t.saturating_sub(last);
+            if self.longest_idle.is_none_or(|(longest, _)| gap > longest) {
+                self.longest_idle = Some((gap, last.saturating_sub(first_t)));
+            }
+        }
+        self.last_input_t = Some(t);
+
+        let bucket = self.buckets.entry(t.saturating_sub(first_t) / self.bucket_ms).or_default();
+        match (action.kind, &action.value) {
+            (ActionKind::KeyboardPress, ActionValue::String(key)) => {
+                bucket.keys += 1;
+                *self.keys.entry(key.clone()).or_default() += 1;
+            }
+            (ActionKind::MousePress, _) => bucket.clicks += 1,
+            (ActionKind::MouseMove, ActionValue::Coords(Coords { x, y })) => {
+                if let Some((last_x, last_y)) = self.last_cursor.replace((*x, *y)) {
+                    self.mouse_distance += (x - last_x).hypot(y - last_y);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let bucket_secs = self.bucket_ms as f64 / 1000.0;
+        let bucket_count = self.buckets.keys().next_back().map_or(0, |last| last + 1);
+        let timeline: Vec<serde_json::Value> = (0..bucket_count)
+            .map(|index| {
+                let bucket = self.buckets.get(&index).copied().unwrap_or_default();
+                let kpm = bucket.keys as f64 * 60.0 / bucket_secs;
+                json!({
+                    "start_s": index as f64 * bucket_secs,
+                    "keys": bucket.keys,
+                    "clicks": bucket.clicks,
+                    "kpm": round1(kpm),
+                    "wpm": round1(kpm / CHARS_PER_WORD),
+                    "cps": round1(bucket.clicks as f64 / bucket_secs),
+                })
+            })
+            .collect();
+        let mut top_keys: Vec<(&String, &u64)> = self.keys.iter().collect();
+        top_keys.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let top_keys: Vec<serde_json::Value> = top_keys
+            .into_iter()
+            .take(TOP_KEYS)
+            .map(|(key, presses)| json!({ "key": key, "presses": presses }))
+            .collect();
+        json!({
+            "duration_s": self.first_t.map_or(0.0, |first| (self.last_t - first) as f64 / 1000.0),
+            "events_by_kind": self.by_kind,
+            "bucket_s": bucket_secs,
+            "timeline": timeline,
+            "top_keys": top_keys,
+            "longest_idle": self.longest_idle.map(|(gap, at)| json!({
+                "seconds": gap as f64 / 1000.0,
+                "starts_at_s": at as f64 / 1000.0,
+            })),
+            "mouse_distance_px": self.mouse_distance.round(),
+        })
+    }
+}
+
+fn round1(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+/// The `analyze` subcommand: reads a recording record by record, prints a report and
+/// optionally writes it as JSON and the timeline as CSV.
+pub fn analyze(path: &Path, bucket_secs: u64, json_out: Option<&Path>, csv_out: Option<&Path>) -> Result<(), String> {
+    let records = recording::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut stats = Stats::new(bucket_secs * 1000);
+    let mut malformed = 0;
+    for record in records {
+        match record {
+            Ok(recorded) => stats.add(recorded.t, &recorded.action),
+            Err(ReadError::Malformed(_)) => malformed += 1,
+            Err(ReadError::Io(e)) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+    let mut report = stats.to_json();
+    report["malformed_lines"] = malformed.into();
+
+    print_report(&report);
+    if let Some(out) = json_out {
+        let text = serde_json::to_string_pretty(&report).expect("report serializes");
+        std::fs::write(out, text).map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+        println!("Wrote {}", out.display());
+    }
+    if let Some(out) = csv_out {
+        write_csv(out, &report).map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+        println!("Wrote {}", out.display());
+    }
+    Ok(())
+}
+
+fn print_report(report: &serde_json::Value) {
+    println!("Duration: {}s", report["duration_s"]);
+    if report["malformed_lines"].as_u64() != Some(0) {
+        println!("Skipped malformed lines: {}", report["malformed_lines"]);
+    }
+    println!("Events by kind:");
+    for (kind, count) in report["events_by_kind"].as_object().into_iter().flatten() {
+        println!("  {:<20} {}", kind, count);
+    }
+    println!("Timeline ({}s buckets):", report["bucket_s"]);
+    println!("  {:>8} {:>8} {:>8} {:>6}", "start_s", "kpm", "wpm", "cps");
+    for bucket in report["timeline"].as_array().into_iter().flatten() {
+        println!(
+            "  {:>8} {:>8} {:>8} {:>6}",
+            bucket["start_s"].to_string(),
+            bucket["kpm"].to_string(),
+            bucket["wpm"].to_string(),
+            bucket["cps"].to_string()
+        );
+    }
+    println!("Top keys:");
+    for key in report["top_keys"].as_array().into_iter().flatten() {
+        println!("  {:<20} {}", key["key"].as_str().unwrap_or_default(), key["presses"]);
+    }
+    match report["longest_idle"].as_object() {
+        Some(idle) => println!("Longest idle gap: {}s (at {}s)", idle["seconds"], idle["starts_at_s"]),
+        None => println!("Longest idle gap: -"),
+    }
+    println!("Mouse distance: {}px", report["mouse_distance_px"]);
+}
+
+fn write_csv(path: &Path, report: &serde_json::Value) -> std::io::Result<()> {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(out, "start_s,keys,clicks,kpm,wpm,cps")?;
+    for bucket in report["timeline"].as_array().into_iter().flatten() {
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            bucket["start_s"], bucket["keys"], bucket["clicks"], bucket["kpm"], bucket["wpm"], bucket["cps"]
+        )?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::sample;
+
+    fn key(kind: ActionKind, name: &str) -> Action {
+        Action::new(kind, ActionValue::String(name.to_string()))
+    }
+
+    fn move_to(x: f64, y: f64) -> Action {
+        Action::new(ActionKind::MouseMove, ActionValue::Coords(Coords { x, y }))
+    }
+
+    #[test]
+    fn a_short_session_reports_speed_keys_idle_and_distance() {
+        let mut stats = Stats::new(10_000);
+        let session = [
+            (1_000, key(ActionKind::KeyboardPress, "KeyA")),
+            (1_500, key(ActionKind::KeyboardRelease, "KeyA")),
+            (2_000, key(ActionKind::KeyboardPress, "KeyB")),
+            (2_500, key(ActionKind::KeyboardPress, "KeyA")),
+            (3_000, sample(ActionKind::MousePress)),
+            (3_000, move_to(0.0, 0.0)),
+            (4_000, move_to(3.0, 4.0)),
+            // Not input, so it neither ends the idle gap nor lands in a bucket.
+            (15_000, sample(ActionKind::Rates)),
+            (25_000, key(ActionKind::KeyboardPress, "KeyA")),
+        ];
+        for (t, action) in &session {
+            stats.add(*t, action);
+        }
+
+        assert_eq!(
+            stats.to_json(),
+            json!({
+                "duration_s": 24.0,
+                "events_by_kind": {
+                    "KeyboardPress": 4,
+                    "KeyboardRelease": 1,
+                    "MouseMove": 2,
+                    "MousePress": 1,
+                    "Rates": 1,
+                },
+                "bucket_s": 10.0,
+                "timeline": [
+                    { "start_s": 0.0, "keys": 3, "clicks": 1, "kpm": 18.0, "wpm": 3.6, "cps": 0.1 },
+                    { "start_s": 10.0, "keys": 0, "clicks": 0, "kpm": 0.0, "wpm": 0.0, "cps": 0.0 },
+                    { "start_s": 20.0, "keys": 1, "clicks": 0, "kpm": 6.0, "wpm": 1.2, "cps": 0.0 },
+                ],
+                "top_keys": [
+                    { "key": "KeyA", "presses": 3 },
+                    { "key": "KeyB", "presses": 1 },
+                ],
+                "longest_idle": { "seconds": 21.0, "starts_at_s": 3.0 },
+                "mouse_distance_px": 5.0,
+            })
+        );
+    }
+
+    #[test]
+    fn top_keys_are_the_twenty_most_pressed_ties_by_name() {
+        let mut stats = Stats::new(60_000);
+        let mut t = 0;
+        let mut press = |stats: &mut Stats, name: &str| {
+            t += 100;
+            stats.add(t, &key(ActionKind::KeyboardPress, name));
+        };
+        for i in (0..25).rev() {
+            press(&mut stats, &format!("K{:02}", i));
+        }
+        for name in ["Z", "Y", "Z", "Z", "Y"] {
+            press(&mut stats, name);
+        }
+
+        let report = stats.to_json();
+        let top: Vec<(&str, u64)> = report["top_keys"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| (entry["key"].as_str().unwrap(), entry["presses"].as_u64().unwrap()))
+            .collect();
+        let mut expected = vec![("Z", 3), ("Y", 2)];
+        let names: Vec<String> = (0..18).map(|i| format!("K{:02}", i)).collect();
+        expected.extend(names.iter().map(|name| (name.as_str(), 1)));
+        assert_eq!(top, expected);
+    }
+
+    #[test]
+    fn a_session_without_input_has_no_idle_gap_or_timeline() {
+        let mut stats = Stats::new(60_000);
+        stats.add(5_000, &sample(ActionKind::ServerNotice));
+        stats.add(9_000, &sample(ActionKind::Rates));
+
+        let report = stats.to_json();
+        assert_eq!(report["duration_s"], json!(4.0));
+        assert_eq!(report["timeline"], json!([]));
+        assert_eq!(report["longest_idle"], json!(null));
+        assert_eq!(report["mouse_distance_px"], json!(0.0));
+    }
+}
@@ -0,0 +1,145 @@
+/*
+ * pet-input-server: src/steno.rs
+ *
+ * Chording mode for steno-style layouts (`--chord-window-ms`). Keys pressed
+ * within the window of a chord's first key join that chord; the chord is
+ * sent as `Chord` ["KeyS","KeyT","KeyA"] (press order) once all of its keys
+ * are up. A key pressed after the window starts the next chord, even while
+ * the previous one is still held (a straggler). Chords are sent in the
+ * order they started, so a later chord released first waits for the earlier
+ * one. With `--chord-only`, the individual KeyboardPress/KeyboardRelease
+ * events are not sent while chording mode is on.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Grouping window in ms; 0 means chording mode is off.
+static WINDOW_MS: AtomicU64 = AtomicU64::new(0);
+static CHORD_ONLY: AtomicBool = AtomicBool::new(false);
+
+struct Chord {
+    started: Instant,
+    /// Every key of the chord, in press order.
+    keys: Vec<String>,
+    /// Keys of the chord still down.
+    held: Vec<String>,
+}
+
+static CHORDS: Lazy<Mutex<VecDeque<Chord>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+pub fn configure(window_ms: u64, chord_only: bool) {
+    WINDOW_MS.store(window_ms, Ordering::Relaxed);
+    CHORD_ONLY.store(chord_only, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    WINDOW_MS.load(Ordering::Relaxed) > 0
+}
+
+/// Whether individual key events are held back in favour of chords.
+pub fn suppresses_keys() -> bool {
+    enabled() && CHORD_ONLY.load(Ordering::Relaxed)
+}
+
+pub fn on_key_press(key: &str) {
+    let window = Duration::from_millis(WINDOW_MS.load(Ordering::Relaxed));
+    press(&mut CHORDS.lock().unwrap(), key, window, Instant::now());
+}
+
+pub fn on_key_release(key: &str, broadcast_tx: &broadcast::Sender<Action>) {
+    let finished = release(&mut CHORDS.lock().unwrap(), key);
+    for keys in finished {
+        crate::rates::count(ActionKind::Chord);
+        let _ = broadcast_tx.send(Action::new(ActionKind::Chord, ActionValue::Json(keys.into())));
+    }
+}
+
+fn press(chords: &mut VecDeque<Chord>, key: &str, window: Duration, now: Instant) {
+    // Auto-repeat sends more presses for a key that is already down.
+    if chords.iter().any(|chord| chord.held.iter().any(|k| k == key)) {
+        return;
+    }
+    match chords.back_mut() {
+        Some(chord) if now.duration_since(chord.started) <= window && !chord.held.is_empty() => {
+            chord.keys.push(key.to_string());
+            chord.held.push(key.to_string());
+        }
+        _ => chords.push_back(Chord {
+            started: now,
+            keys: vec![key.to_string()],
+            held: vec![key.to_string()],
+        }),
+    }
+}
+
+/// Marks `key` up and returns the keys of every chord that is now complete, oldest first.
+fn release(chords: &mut VecDeque<Chord>, key: &str) -> Vec<Vec<String>> {
+    let Some(chord) = chords.iter_mut().find(|chord| chord.held.iter().any(|k| k == key)) else {
+        // Pressed before chording mode saw it.
+        return Vec::new();
+    };
+    chord.held.retain(|k| k != key);
+    let mut finished = Vec::new();
+    while chords.front().is_some_and(|chord| chord.held.is_empty()) {
+        finished.push(chords.pop_front().expect("front exists").keys);
+    }
+    finished
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(50);
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn keys_within_the_window_form_one_chord() {
+        let (mut chords, t) = (VecDeque::new(), Instant::now());
+        press(&mut chords, "KeyS", WINDOW, t);
+        press(&mut chords, "KeyT", WINDOW, at(t, 20));
+        press(&mut chords, "KeyA", WINDOW, at(t, 50));
+        assert!(release(&mut chords, "KeyT").is_empty());
+        assert!(release(&mut chords, "KeyA").is_empty());
+        assert_eq!(release(&mut chords, "KeyS"), [["KeyS", "KeyT", "KeyA"]]);
+        assert!(chords.is_empty());
+    }
+
+    #[test]
+    fn a_straggler_starts_the_next_chord() {
+        let (mut chords, t) = (VecDeque::new(), Instant::now());
+        press(&mut chords, "KeyS", WINDOW, t);
+        press(&mut chords, "KeyK", WINDOW, at(t, 51));
+        // The later chord finishes first but waits for the earlier one.
+        assert!(release(&mut chords, "KeyK").is_empty());
+        assert_eq!(release(&mut chords, "KeyS"), [vec!["KeyS"], vec!["KeyK"]]);
+    }
+
+    #[test]
+    fn a_released_chord_does_not_take_new_keys() {
+        let (mut chords, t) = (VecDeque::new(), Instant::now());
+        press(&mut chords, "KeyS", WINDOW, t);
+        assert_eq!(release(&mut chords, "KeyS"), [["KeyS"]]);
+        press(&mut chords, "KeyT", WINDOW, at(t, 10));
+        assert_eq!(release(&mut chords, "KeyT"), [["KeyT"]]);
+    }
+
+    #[test]
+    fn auto_repeat_and_unknown_releases_are_ignored() {
+        let (mut chords, t) = (VecDeque::new(), Instant::now());
+        press(&mut chords, "KeyS", WINDOW, t);
+        press(&mut chords, "KeyS", WINDOW, at(t, 30));
+        press(&mut chords, "KeyS", WINDOW, at(t, 200));
+        assert!(release(&mut chords, "KeyP").is_empty());
+        assert_eq!(release(&mut chords, "KeyS"), [["KeyS"]]);
+    }
+}
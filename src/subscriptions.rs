@@ -0,0 +1,194 @@
+/*
+ * pet-input-server: src/subscriptions.rs
+ *
+ * The union of what live-input consumers want, as a bitmask indexed by
+ * `ActionKind::index`. The input callback checks it before translating a key:
+ * when no connected client subscribes to anything keyboard-derived, key
+ * events are dropped before they are mapped, counted or broadcast.
+ * Sinks configured on the command line (recording, MQTT, uinput mirror)
+ * pin their kinds into the mask for the life of the process.
+ *
+ * Kinds switched off in the config's `[events]` table are masked out of
+ * everything: no sink receives them and their inputs are not captured.
+ */
+
+use crate::ActionKind;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Masks are `u64`s, one bit per kind.
+const _: () = assert!(crate::kind::COUNT <= 64);
+
+/// Kinds that are produced from key events; if none of them is wanted, keys are not captured.
+const KEYBOARD_KINDS: &[ActionKind] = &[
+    ActionKind::KeyboardPress,
+    ActionKind::KeyboardRelease,
+    ActionKind::KeyLongPress,
+    ActionKind::Note,
+    ActionKind::Intensity,
+    ActionKind::KeyHeat,
+    ActionKind::ModifierTap,
+    ActionKind::ShoutingDetected,
+    ActionKind::MediaKey,
+    ActionKind::Chord,
+    // Fed from key presses too: typing speed, per-kind counts, held keys.
+    ActionKind::Mood,
+    ActionKind::Rates,
+    ActionKind::Snapshot,
+];
+
+/// Per-client masks, by client id.
+static CLIENTS: Lazy<Mutex<HashMap<u64, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+/// Kinds wanted by command-line sinks, whatever clients ask for.
+static PINNED: AtomicU64 = AtomicU64::new(0);
+static UNION: AtomicU64 = AtomicU64::new(0);
+/// Kinds disabled by `[events]`.
+static DISABLED: AtomicU64 = AtomicU64::new(0);
+
+fn bit(kind: ActionKind) -> u64 {
+    1 << kind.index()
+}
+
+/// Applies the config's `[events]` table; kinds not listed stay enabled.
+/// `Config::load` has already rejected unknown names.
+pub fn configure_enabled(events: &BTreeMap<String, bool>) {
+    let disabled = events
+        .iter()
+        .filter(|(_, enabled)| !**enabled)
+        .filter_map(|(kind, _)| kind.parse().ok())
+        .fold(0, |mask, kind| mask | bit(kind));
+    DISABLED.store(disabled, Ordering::Relaxed);
+}
+
+/// Whether `kind` may be emitted at all.
+pub fn enabled(kind: ActionKind) -> bool {
+    DISABLED.load(Ordering::Relaxed) & bit(kind) == 0
+}
+
+/// The mask for a client, given its `?kinds=` filter.
+pub fn mask_of(wants: impl Fn(ActionKind) -> bool) -> u64 {
+    ActionKind::ALL
+        .into_iter()
+        .filter(|kind| wants(*kind))
+        .fold(0, |mask, kind| mask | bit(kind))
+}
+
+/// Counts a command-line sink that needs every event, whatever clients ask for.
+pub fn pin_all() {
+    pin(u64::MAX);
+}
+
+/// Counts a command-line sink that needs the kinds in `mask`.
+pub fn pin(mask: u64) {
+    PINNED.fetch_or(mask, Ordering::Relaxed);
+}
+
+/// Whether anything currently consumes keyboard-derived events.
+pub fn keyboard_wanted() -> bool {
+    let keyboard = KEYBOARD_KINDS.iter().fold(0, |mask, kind| mask | bit(*kind));
+    let wanted = PINNED.load(Ordering::Relaxed) | UNION.load(Ordering::Relaxed);
+    wanted & keyboard & !DISABLED.load(Ordering::Relaxed) != 0
+}
+
+/// Keeps one client's mask in the union while it is held.
+pub struct Registration(u64);
+
+pub fn register(client: u64, mask: u64) -> Registration {
+    let mut clients = CLIENTS.lock().unwrap();
+    clients.insert(client, mask);
+    recompute(&clients);
+    Registration(client)
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut clients = CLIENTS.lock().unwrap();
+        clients.remove(&self.0);
+        recompute(&clients);
+    }
+}
+
+fn recompute(clients: &HashMap<u64, u64>) {
+    let union = clients.values().fold(0, |union, mask| union | mask);
+    UNION.store(union, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// The masks are process-wide, so these tests take turns, and so do the
+    /// pipeline tests that register a client of their own.
+    pub(crate) static SERIAL: Mutex<()> = Mutex::new(());
+
+    fn only(kinds: &[ActionKind]) -> u64 {
+        mask_of(|kind| kinds.contains(&kind))
+    }
+
+    #[test]
+    fn keys_are_wanted_only_while_a_client_wants_a_keyboard_kind() {
+        let _serial = SERIAL.lock().unwrap();
+        assert!(!keyboard_wanted());
+        let mouse = register(1, only(&[ActionKind::MouseMove, ActionKind::MousePress]));
+        assert!(!keyboard_wanted());
+        let notes = register(2, only(&[ActionKind::Note]));
+        assert!(keyboard_wanted());
+        drop(notes);
+        assert!(!keyboard_wanted());
+        drop(mouse);
+        assert_eq!(UNION.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn kinds_derived_from_keys_want_keys() {
+        let _serial = SERIAL.lock().unwrap();
+        for kind in [ActionKind::Mood, ActionKind::Rates, ActionKind::Snapshot] {
+            let client = register(5, only(&[kind, ActionKind::MouseMove]));
+            assert!(keyboard_wanted(), "{}", kind);
+            drop(client);
+        }
+        assert!(!keyboard_wanted());
+    }
+
+    #[test]
+    fn disabled_kinds_do_not_count() {
+        let _serial = SERIAL.lock().unwrap();
+        configure_enabled(&BTreeMap::from([("KeyboardPress".to_string(), false), ("Note".to_string(), true)]));
+        assert!(!enabled(ActionKind::KeyboardPress) && enabled(ActionKind::Note));
+        let client = register(3, only(&[ActionKind::KeyboardPress]));
+        assert!(!keyboard_wanted());
+        drop(client);
+        configure_enabled(&BTreeMap::new());
+        assert!(enabled(ActionKind::KeyboardPress));
+    }
+
+    #[test]
+    fn the_union_survives_connection_churn() {
+        let _serial = SERIAL.lock().unwrap();
+        let keyboard = only(&[ActionKind::KeyboardPress]);
+        let mouse = only(&[ActionKind::MouseMove]);
+        let steady = register(4, mouse);
+        let threads: Vec<_> = (0..8u64)
+            .map(|thread| {
+                std::thread::spawn(move || {
+                    for n in 0..500 {
+                        let id = 1000 + thread * 1000 + n;
+                        let registration = register(id, if n % 2 == 0 { keyboard } else { mouse });
+                        assert_ne!(UNION.load(Ordering::Relaxed) & mouse, 0);
+                        drop(registration);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        // Every churned client is gone; only the steady one's kinds remain.
+        assert_eq!(UNION.load(Ordering::Relaxed), mouse);
+        assert!(!keyboard_wanted());
+        drop(steady);
+        assert_eq!(CLIENTS.lock().unwrap().len(), 0);
+    }
+}
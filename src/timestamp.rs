@@ -0,0 +1,156 @@
+/*
+ * pet-input-server: src/timestamp.rs
+ *
+ * When an input event happened. rdev hands us the OS's own event time,
+ * which can be earlier than our callback under load; it is used unless it
+ * is obviously bogus (the epoch, or far from our own clock), in which case
+ * the callback time stands in. The gap between the two is kept as a
+ * histogram for GET /status, showing how much delay the hook adds.
+ *
+ * For micro-timing analysis, `--monotonic-ns` adds the hook's own reading of
+ * a monotonic clock, in ns since startup. It never jumps with NTP or clock
+ * changes, but means nothing outside the run that produced it.
+ *
+ * To tell OS-to-callback delay from callback-to-wire delay, `--os-timestamps`
+ * adds both ends separately: rdev's time exactly as delivered (no
+ * plausibility check) and the wall-clock time our callback queued the event.
+ */
+
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// An OS time further ahead of the callback than this is not trusted.
+const MAX_AHEAD: Duration = Duration::from_secs(1);
+/// Nor is one further behind; no hook is that slow.
+const MAX_BEHIND: Duration = Duration::from_secs(10);
+
+/// Upper bounds (ms) of the delay histogram buckets; the last bucket is open-ended.
+const BUCKETS_MS: &[u64] = &[1, 2, 5, 10, 20, 50, 100];
+
+static COUNTS: [AtomicU64; 8] = [const { AtomicU64::new(0) }; 8];
+static FALLBACKS: AtomicU64 = AtomicU64::new(0);
+
+/// Set by `--timestamps`: input actions carry `timestamp_ms`.
+pub static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Set by `--monotonic-ns`: input actions carry `mono_ns`.
+pub static MONOTONIC: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--os-timestamps`: input actions carry `os_timestamp_ms` and `hook_timestamp_ms`.
+pub static OS_TIMES: AtomicBool = AtomicBool::new(false);
+
+/// Zero point of `mono_ns`.
+static BASELINE: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Fixes the `mono_ns` zero point; call at startup.
+pub fn start_monotonic() {
+    Lazy::force(&BASELINE);
+}
+
+/// `at` in ns since the zero point.
+pub fn monotonic_ns(at: Instant) -> u64 {
+    at.saturating_duration_since(*BASELINE).as_nanos() as u64
+}
+
+/// `time` in ms since the epoch, negative if before it.
+pub fn epoch_ms(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs_f64() * 1000.0,
+        Err(before) => -before.duration().as_secs_f64() * 1000.0,
+    }
+}
+
+/// The wall-clock time of `at` (when the hook queued an event), in ms since the epoch.
+pub fn hook_ms(at: Instant) -> f64 {
+    epoch_ms(SystemTime::now() - at.elapsed())
+}
+
+/// Picks the event's time (`os_time` if plausible, otherwise now), in ms since the epoch.
+pub fn event_time(os_time: SystemTime) -> f64 {
+    let now = SystemTime::now();
+    let chosen = match delay_bucket(os_time, now) {
+        Some(bucket) => {
+            COUNTS[bucket].fetch_add(1, Ordering::Relaxed);
+            os_time
+        }
+        None => {
+            FALLBACKS.fetch_add(1, Ordering::Relaxed);
+            now
+        }
+    };
+    chosen.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+}
+
+/// The histogram bucket for an OS time seen at `now`, or `None` if it is not plausible.
+/// A time slightly ahead of `now` (clock granularity) counts as no delay.
+fn delay_bucket(os_time: SystemTime, now: SystemTime) -> Option<usize> {
+    if os_time <= UNIX_EPOCH {
+        return None;
+    }
+    match now.duration_since(os_time) {
+        Ok(behind) if behind <= MAX_BEHIND => {
+            let ms = behind.as_millis() as u64;
+            Some(BUCKETS_MS.iter().position(|bound| ms < *bound).unwrap_or(BUCKETS_MS.len()))
+        }
+        Err(ahead) if ahead.duration() <= MAX_AHEAD => Some(0),
+        _ => None,
+    }
+}
+
+/// The OS-to-callback delay histogram for GET /status.
+pub fn to_json() -> serde_json::Value {
+    // `below_ms: null` is the open-ended last bucket.
+    let buckets: Vec<serde_json::Value> = COUNTS
+        .iter()
+        .enumerate()
+        .map(|(index, count)| json!({ "below_ms": BUCKETS_MS.get(index), "count": count.load(Ordering::Relaxed) }))
+        .collect();
+    json!({
+        "buckets": buckets,
+        "bogus_os_times": FALLBACKS.load(Ordering::Relaxed),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn plausible_os_times_land_in_their_delay_bucket() {
+        let now = SystemTime::now();
+        assert_eq!(delay_bucket(now, now), Some(0));
+        assert_eq!(delay_bucket(now - ms(1), now), Some(1));
+        assert_eq!(delay_bucket(now - ms(7), now), Some(3));
+        assert_eq!(delay_bucket(now - ms(99), now), Some(6));
+        assert_eq!(delay_bucket(now - ms(100), now), Some(BUCKETS_MS.len()));
+        assert_eq!(delay_bucket(now - MAX_BEHIND, now), Some(BUCKETS_MS.len()));
+        assert_eq!(delay_bucket(now + ms(500), now), Some(0));
+    }
+
+    #[test]
+    fn bogus_os_times_fall_back_to_now() {
+        let now = SystemTime::now();
+        assert_eq!(delay_bucket(UNIX_EPOCH, now), None);
+        assert_eq!(delay_bucket(now - MAX_BEHIND - ms(1), now), None);
+        assert_eq!(delay_bucket(now + MAX_AHEAD + ms(1), now), None);
+
+        let before = epoch_ms(SystemTime::now());
+        let fallbacks = FALLBACKS.load(Ordering::Relaxed);
+        let chosen = event_time(UNIX_EPOCH);
+        assert!(chosen >= before && chosen <= epoch_ms(SystemTime::now()));
+        assert!(FALLBACKS.load(Ordering::Relaxed) > fallbacks);
+        let hour_old = SystemTime::now() - Duration::from_secs(3600);
+        assert!(event_time(hour_old) > epoch_ms(hour_old) + 3_500_000.0);
+    }
+
+    #[test]
+    fn a_plausible_os_time_is_kept() {
+        let os_time = SystemTime::now() - ms(30);
+        assert_eq!(event_time(os_time), epoch_ms(os_time));
+    }
+}
@@ -0,0 +1,135 @@
+/*
+ * pet-input-server: src/timesync.rs
+ *
+ * Clock offset estimate for clients that do their own latency math. Started
+ * with `?timesync=1` or `{"cmd":"timesync"}`; ignored by everyone else.
+ *
+ *   server → TimeSyncProbe {"probe":n,"server_ms":S1}
+ *   client → {"cmd":"timesync_echo","probe":n,"client_ms":C}   (received at S2)
+ *
+ * After `PROBES` round trips the sample with the smallest RTT wins, since it
+ * has the least room for asymmetric delay, and the server sends
+ * TimeSync {"offset_ms": C - (S1+S2)/2, "rtt_ms": S2-S1}. A positive offset
+ * means the client's clock is ahead.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PROBES: u32 = 4;
+
+struct Sample {
+    offset_ms: f64,
+    rtt_ms: f64,
+}
+
+fn now_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+}
+
+/// One probe sequence on one connection.
+pub struct Exchange {
+    samples: Vec<Sample>,
+    /// Probe number and send time of the probe awaiting its echo.
+    pending: Option<(u32, f64)>,
+}
+
+impl Exchange {
+    /// Starts a sequence; the returned probe has to be sent to the client.
+    pub fn start() -> (Exchange, Action) {
+        Exchange::start_at(now_ms())
+    }
+
+    fn start_at(now: f64) -> (Exchange, Action) {
+        let mut exchange = Exchange {
+            samples: Vec::new(),
+            pending: None,
+        };
+        let probe = exchange.probe(now);
+        (exchange, probe)
+    }
+
+    fn probe(&mut self, sent: f64) -> Action {
+        let number = self.samples.len() as u32;
+        self.pending = Some((number, sent));
+        Action::new(ActionKind::TimeSyncProbe, ActionValue::Json(json!({ "probe": number, "server_ms": sent })))
+    }
+
+    /// Takes a client echo and returns the next probe, or the final `TimeSync` once done.
+    /// `None` if the echo doesn't answer the outstanding probe.
+    pub fn on_echo(&mut self, probe: u32, client_ms: f64) -> Option<Action> {
+        self.echo_at(probe, client_ms, now_ms())
+    }
+
+    fn echo_at(&mut self, probe: u32, client_ms: f64, received: f64) -> Option<Action> {
+        let (_, sent) = self.pending.take_if(|(number, _)| *number == probe)?;
+        self.samples.push(Sample {
+            offset_ms: client_ms - (sent + received) / 2.0,
+            rtt_ms: received - sent,
+        });
+        if (self.samples.len() as u32) < PROBES {
+            return Some(self.probe(received));
+        }
+        let best = self.samples.iter().min_by(|a, b| a.rtt_ms.total_cmp(&b.rtt_ms))?;
+        Some(Action::new(
+            ActionKind::TimeSync,
+            ActionValue::Json(json!({
+                "offset_ms": (best.offset_ms * 10.0).round() / 10.0,
+                "rtt_ms": (best.rtt_ms * 10.0).round() / 10.0,
+            })),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(action: &Action, name: &str) -> f64 {
+        let ActionValue::Json(value) = &action.value else { panic!("{:?}", action) };
+        value[name].as_f64().unwrap()
+    }
+
+    /// Runs a whole exchange against a client whose clock is `offset` ms ahead, with the
+    /// given (outbound, return) delays per probe.
+    fn run(offset: f64, delays: &[(f64, f64)]) -> Action {
+        let (mut exchange, mut probe) = Exchange::start_at(1000.0);
+        for (n, (out, back)) in delays.iter().enumerate() {
+            assert_eq!(probe.kind, ActionKind::TimeSyncProbe);
+            assert_eq!(field(&probe, "probe") as usize, n);
+            let sent = field(&probe, "server_ms");
+            let client_ms = sent + out + offset;
+            probe = exchange.echo_at(n as u32, client_ms, sent + out + back).unwrap();
+        }
+        probe
+    }
+
+    #[test]
+    fn symmetric_delay_gives_the_exact_offset() {
+        let result = run(250.0, &[(5.0, 5.0); PROBES as usize]);
+        assert_eq!(result.kind, ActionKind::TimeSync);
+        assert_eq!(field(&result, "offset_ms"), 250.0);
+        assert_eq!(field(&result, "rtt_ms"), 10.0);
+    }
+
+    #[test]
+    fn the_fastest_round_trip_wins() {
+        // Only the third probe is quick; its small asymmetry is all the error left.
+        let result = run(-40.0, &[(80.0, 2.0), (3.0, 60.0), (1.5, 0.5), (30.0, 30.0)]);
+        assert_eq!(field(&result, "rtt_ms"), 2.0);
+        assert_eq!(field(&result, "offset_ms"), -39.5);
+    }
+
+    #[test]
+    fn stray_echoes_are_ignored() {
+        let (mut exchange, _) = Exchange::start_at(1000.0);
+        assert!(exchange.echo_at(3, 1000.0, 1001.0).is_none());
+        let next = exchange.echo_at(0, 1000.0, 1002.0).unwrap();
+        assert_eq!(field(&next, "probe"), 1.0);
+        // The answered probe can't be answered twice.
+        assert!(exchange.echo_at(0, 1000.0, 1003.0).is_none());
+    }
+}
@@ -0,0 +1,179 @@
+/*
+ * pet-input-server: src/uinput_mirror.rs
+ *
+ * `--mirror-uinput` (Linux, `uinput` feature): replays captured input into a
+ * virtual keyboard/mouse so other local programs see it without their own hook.
+ *
+ * The virtual device's events come back through our own listener, so every
+ * injected event is remembered briefly and its echo is dropped in the callback.
+ */
+
+use crate::{map_button, map_key, Action, ActionKind, ActionValue, Coords};
+use once_cell::sync::Lazy;
+use rdev::EventType;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+const EV_KEY: i32 = 0x01;
+const EV_ABS: i32 = 0x03;
+const ABS_X: i32 = 0x00;
+const ABS_Y: i32 = 0x01;
+
+/// How long an injected event waits for its echo before it is forgotten.
+const ECHO_WINDOW: Duration = Duration::from_millis(250);
+
+/// Protocol key name → Linux evdev key code (input-event-codes.h).
+const EVDEV_CODES: &[(&str, i32)] = &[
+    ("Escape", 1), ("Num1", 2), ("Num2", 3), ("Num3", 4), ("Num4", 5), ("Num5", 6),
+    ("Num6", 7), ("Num7", 8), ("Num8", 9), ("Num9", 10), ("Num0", 11), ("-", 12),
+    ("=", 13), ("Backspace", 14), ("Tab", 15), ("KeyQ", 16), ("KeyW", 17), ("KeyE", 18),
+    ("KeyR", 19), ("KeyT", 20), ("KeyY", 21), ("KeyU", 22), ("KeyI", 23), ("KeyO", 24),
+    ("KeyP", 25), ("[", 26), ("]", 27), ("Return", 28), ("Control", 29),
+    ("ControlLeft", 29), ("KeyA", 30), ("KeyS", 31), ("KeyD", 32), ("KeyF", 33),
+    ("KeyG", 34), ("KeyH", 35), ("KeyJ", 36), ("KeyK", 37), ("KeyL", 38), (";", 39),
+    ("'", 40), ("`", 41), ("Shift", 42), ("ShiftLeft", 42), ("\\", 43), ("KeyZ", 44), ("KeyX", 45),
+    ("KeyC", 46), ("KeyV", 47), ("KeyB", 48), ("KeyN", 49), ("KeyM", 50), (",", 51),
+    (".", 52), ("/", 53), ("ShiftRight", 54), ("Alt", 56), ("AltLeft", 56), ("Space", 57),
+    ("CapsLock", 58), ("F1", 59), ("F2", 60), ("F3", 61), ("F4", 62), ("F5", 63),
+    ("F6", 64), ("F7", 65), ("F8", 66), ("F9", 67), ("F10", 68), ("F11", 87), ("F12", 88),
+    ("ControlRight", 97), ("AltRight", 100), ("Home", 102), ("UpArrow", 103),
+    ("PageUp", 104), ("LeftArrow", 105), ("RightArrow", 106), ("End", 107),
+    ("DownArrow", 108), ("PageDown", 109), ("Insert", 110), ("Delete", 111),
+    ("Meta", 125), ("MetaLeft", 125), ("MetaRight", 126), ("Menu", 127),
+    ("NumLock", 69), ("ScrollLock", 70), ("PrintScreen", 99), ("Pause", 119),
+    ("Numpad7", 71), ("Numpad8", 72), ("Numpad9", 73), ("NumpadSubtract", 74),
+    ("Numpad4", 75), ("Numpad5", 76), ("Numpad6", 77), ("NumpadAdd", 78), ("Numpad1", 79),
+    ("Numpad2", 80), ("Numpad3", 81), ("Numpad0", 82), ("NumpadDecimal", 83),
+    ("NumpadEnter", 96), ("NumpadDivide", 98), ("NumpadMultiply", 55),
+    ("Mouse1", 0x110), ("Mouse2", 0x111), ("Mouse3", 0x112),
+];
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static ECHOES: Lazy<Mutex<VecDeque<(String, Instant)>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+fn evdev_code(name: &str) -> Option<i32> {
+    EVDEV_CODES.iter().find(|(n, _)| *n == name).map(|(_, c)| *c)
+}
+
+/// A comparable fingerprint of an event, shared by the injecting and echo-checking sides.
+fn signature(kind: ActionKind, detail: &str) -> String {
+    format!("{}:{}", kind, detail)
+}
+
+/// True if `event` is the echo of something we just injected; the echo is consumed.
+pub fn is_echo(event: &EventType) -> bool {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return false;
+    }
+    let sig = match *event {
+        EventType::KeyPress(key) => map_key(key).map(|k| signature(ActionKind::KeyboardPress, &k)),
+        EventType::KeyRelease(key) => map_key(key).map(|k| signature(ActionKind::KeyboardRelease, &k)),
+        EventType::ButtonPress(b) => Some(signature(ActionKind::MousePress, &map_button(b))),
+        EventType::ButtonRelease(b) => Some(signature(ActionKind::MouseRelease, &map_button(b))),
+        EventType::MouseMove { x, y } => Some(signature(ActionKind::MouseMove, &format!("{:.0},{:.0}", x, y))),
+        EventType::Wheel { .. } => None,
+    };
+    let Some(sig) = sig else { return false };
+
+    let mut echoes = ECHOES.lock().unwrap();
+    let now = Instant::now();
+    echoes.retain(|(_, at)| now.duration_since(*at) < ECHO_WINDOW);
+    match echoes.iter().position(|(s, _)| *s == sig) {
+        Some(i) => {
+            echoes.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Checks `/dev/uinput` up front so permission problems get an actionable message.
+fn check_access() -> Result<(), String> {
+    match std::fs::OpenOptions::new().write(true).open("/dev/uinput") {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(
+            "/dev/uinput does not exist. Load the module with `sudo modprobe uinput`.".to_string(),
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Err(
+            "Permission denied opening /dev/uinput. Add a udev rule such as \
+             KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\" and add your user to the \
+             'input' group (then log in again)."
+                .to_string(),
+        ),
+        Err(e) => Err(format!("Cannot open /dev/uinput: {}", e)),
+    }
+}
+
+/// Creates the virtual device and replays broadcast actions into it on a dedicated thread.
+/// Mouse motion is only mirrored when the display size is known (it sets the axis range).
+pub fn spawn(mut broadcast_rx: broadcast::Receiver<Action>, display: Option<(u64, u64)>) -> Result<(), String> {
+    use uinput::event::absolute::Position;
+    use uinput::event::controller::{Controller, Mouse};
+    use uinput::event::Keyboard;
+
+    check_access()?;
+    let build = || -> Result<uinput::Device, uinput::Error> {
+        let mut builder = uinput::default()?
+            .name("pet-input-server mirror")?
+            .event(Keyboard::All)?
+            .event(Controller::Mouse(Mouse::Left))?
+            .event(Controller::Mouse(Mouse::Right))?
+            .event(Controller::Mouse(Mouse::Middle))?;
+        if let Some((width, height)) = display {
+            builder = builder
+                .event(Position::X)?
+                .max(width as i32)
+                .event(Position::Y)?
+                .max(height as i32);
+        }
+        builder.create()
+    };
+    let mut device = build().map_err(|e| format!("Failed to create uinput device: {}", e))?;
+    if display.is_none() {
+        eprintln!("Display size unknown; --mirror-uinput will mirror keys and buttons only.");
+    }
+    ACTIVE.store(true, Ordering::Relaxed);
+    println!("Mirroring input into virtual device 'pet-input-server mirror'.");
+
+    std::thread::spawn(move || loop {
+        let action = match broadcast_rx.blocking_recv() {
+            Ok(action) => action,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if let Err(e) = replay(&mut device, &action, display.is_some()) {
+            eprintln!("uinput mirror error: {}", e);
+        }
+    });
+    Ok(())
+}
+
+fn replay(device: &mut uinput::Device, action: &Action, with_motion: bool) -> Result<(), uinput::Error> {
+    let (writes, detail): (Vec<(i32, i32, i32)>, String) = match (action.kind, &action.value) {
+        (ActionKind::KeyboardPress | ActionKind::MousePress, ActionValue::String(name)) => match evdev_code(name) {
+            Some(code) => (vec![(EV_KEY, code, 1)], name.clone()),
+            None => return Ok(()),
+        },
+        (ActionKind::KeyboardRelease | ActionKind::MouseRelease, ActionValue::String(name)) => match evdev_code(name) {
+            Some(code) => (vec![(EV_KEY, code, 0)], name.clone()),
+            None => return Ok(()),
+        },
+        (ActionKind::MouseMove, ActionValue::Coords(Coords { x, y })) if with_motion => (
+            vec![(EV_ABS, ABS_X, *x as i32), (EV_ABS, ABS_Y, *y as i32)],
+            format!("{:.0},{:.0}", x, y),
+        ),
+        _ => return Ok(()),
+    };
+
+    ECHOES
+        .lock()
+        .unwrap()
+        .push_back((signature(action.kind, &detail), Instant::now()));
+    for (kind, code, value) in writes {
+        device.write(kind, code, value)?;
+    }
+    device.synchronize()
+}
@@ -0,0 +1,223 @@
+/*
+ * pet-input-server: src/window.rs
+ *
+ * `--track-window-bounds` (`window-bounds` feature): polls the focused
+ * window's geometry and broadcasts `WindowBounds` {"x","y","w","h"} (screen
+ * pixels, outer frame where the platform reports it) when it changes.
+ * While a window is being dragged or resized, changes are coalesced to at
+ * most one event per `COALESCE`; the settled geometry is always sent.
+ * Supported on X11 (via `_NET_ACTIVE_WINDOW`) and Windows.
+ */
+
+use crate::{Action, ActionKind, ActionValue};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+const POLL_EVERY: Duration = Duration::from_millis(100);
+const COALESCE: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Bounds {
+    x: i32,
+    y: i32,
+    w: u32,
+    h: u32,
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::Bounds;
+    use std::os::raw::{c_int, c_uchar, c_ulong};
+    use x11::xlib;
+
+    /// X errors (a window that closed between two calls) must not take the process down.
+    unsafe extern "C" fn ignore_error(_: *mut xlib::Display, _: *mut xlib::XErrorEvent) -> c_int {
+        0
+    }
+
+    pub struct Probe {
+        display: *mut xlib::Display,
+        active_atom: xlib::Atom,
+    }
+
+    impl Probe {
+        pub fn open() -> Result<Probe, String> {
+            unsafe {
+                let display = xlib::XOpenDisplay(std::ptr::null());
+                if display.is_null() {
+                    return Err("cannot open the X display (window tracking needs X11)".to_string());
+                }
+                xlib::XSetErrorHandler(Some(ignore_error));
+                let active_atom = xlib::XInternAtom(display, c"_NET_ACTIVE_WINDOW".as_ptr(), xlib::True);
+                if active_atom == 0 {
+                    return Err("the window manager does not publish _NET_ACTIVE_WINDOW".to_string());
+                }
+                Ok(Probe { display, active_atom })
+            }
+        }
+
+        fn active_window(&self, root: xlib::Window) -> Option<xlib::Window> {
+            unsafe {
+                let mut actual_type = 0;
+                let mut format: c_int = 0;
+                let mut items: c_ulong = 0;
+                let mut remaining: c_ulong = 0;
+                let mut data: *mut c_uchar = std::ptr::null_mut();
+                let status = xlib::XGetWindowProperty(
+                    self.display,
+                    root,
+                    self.active_atom,
+                    0,
+                    1,
+                    xlib::False,
+                    xlib::XA_WINDOW,
+                    &mut actual_type,
+                    &mut format,
+                    &mut items,
+                    &mut remaining,
+                    &mut data,
+                );
+                if status != xlib::Success as c_int || data.is_null() {
+                    return None;
+                }
+                let window = (items == 1 && format == 32).then(|| *(data as *const xlib::Window));
+                xlib::XFree(data.cast());
+                window.filter(|window| *window != 0)
+            }
+        }
+
+        pub fn focused(&mut self) -> Option<Bounds> {
+            unsafe {
+                let root = xlib::XDefaultRootWindow(self.display);
+                let window = self.active_window(root)?;
+                let (mut geometry_root, mut x, mut y, mut w, mut h, mut border, mut depth) = (0, 0, 0, 0, 0, 0, 0);
+                let ok = xlib::XGetGeometry(
+                    self.display,
+                    window,
+                    &mut geometry_root,
+                    &mut x,
+                    &mut y,
+                    &mut w,
+                    &mut h,
+                    &mut border,
+                    &mut depth,
+                );
+                if ok == 0 {
+                    return None;
+                }
+                // Geometry is relative to the parent (often a WM frame); translate to the root.
+                let mut child = 0;
+                let translated =
+                    xlib::XTranslateCoordinates(self.display, window, root, 0, 0, &mut x, &mut y, &mut child);
+                (translated != 0).then_some(Bounds { x, y, w, h })
+            }
+        }
+    }
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            unsafe {
+                xlib::XCloseDisplay(self.display);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::Bounds;
+    use winapi::shared::windef::RECT;
+    use winapi::um::winuser::{GetForegroundWindow, GetWindowRect};
+
+    pub struct Probe;
+
+    impl Probe {
+        pub fn open() -> Result<Probe, String> {
+            Ok(Probe)
+        }
+
+        pub fn focused(&mut self) -> Option<Bounds> {
+            unsafe {
+                let window = GetForegroundWindow();
+                if window.is_null() {
+                    return None;
+                }
+                let mut rect = RECT {
+                    left: 0,
+                    top: 0,
+                    right: 0,
+                    bottom: 0,
+                };
+                if GetWindowRect(window, &mut rect) == 0 {
+                    return None;
+                }
+                Some(Bounds {
+                    x: rect.left,
+                    y: rect.top,
+                    w: (rect.right - rect.left).max(0) as u32,
+                    h: (rect.bottom - rect.top).max(0) as u32,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use super::Bounds;
+
+    pub struct Probe;
+
+    impl Probe {
+        pub fn open() -> Result<Probe, String> {
+            Err("window tracking is only supported on X11 and Windows".to_string())
+        }
+
+        pub fn focused(&mut self) -> Option<Bounds> {
+            None
+        }
+    }
+}
+
+fn bounds_action(bounds: Bounds) -> Action {
+    crate::rates::count(ActionKind::WindowBounds);
+    Action::new(
+        ActionKind::WindowBounds,
+        ActionValue::Json(json!({ "x": bounds.x, "y": bounds.y, "w": bounds.w, "h": bounds.h })),
+    )
+}
+
+pub fn spawn(broadcast_tx: broadcast::Sender<Action>) -> Result<(), String> {
+    // The probe is opened on the polling thread; X display connections stay on one thread.
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut probe = match platform::Probe::open() {
+            Ok(probe) => {
+                let _ = ready_tx.send(Ok(()));
+                probe
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+        let mut sent: Option<Bounds> = None;
+        let mut sent_at = Instant::now() - COALESCE;
+        loop {
+            std::thread::sleep(POLL_EVERY);
+            let Some(current) = probe.focused() else { continue };
+            if sent != Some(current) && sent_at.elapsed() >= COALESCE {
+                sent = Some(current);
+                sent_at = Instant::now();
+                let _ = broadcast_tx.send(bounds_action(current));
+            }
+        }
+    });
+    ready_rx
+        .recv()
+        .unwrap_or_else(|_| Err("window tracking thread exited".to_string()))
+        .map_err(|e| format!("Cannot use --track-window-bounds: {}", e))?;
+    println!("Tracking the focused window's bounds.");
+    Ok(())
+}
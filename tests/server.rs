@@ -0,0 +1,425 @@
+/*
+ * pet-input-server: tests/server.rs
+ *
+ * End-to-end scenarios against the library: one server per test process,
+ * started with `run_with` on `--port 0` and a scripted input source, and real
+ * WebSocket (or bare TCP) clients. Each scenario connects its clients, plays
+ * its script through the source, then checks what every client got. The
+ * server keeps its state between scenarios, so they run in order from one
+ * table, and a scenario that kills the input source has to come last.
+ */
+
+use clap::Parser;
+use futures_util::{SinkExt, StreamExt};
+use pet_input_server::{run_with, Cli, EventSource, MockSource, RawInputEvent};
+use rdev::{Button, EventType, Key};
+use serde_json::{json, Value};
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::sync::{oneshot, watch};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::MaybeTlsStream;
+
+const INPUT_KINDS: &[&str] = &["MouseMove", "MousePress", "MouseRelease", "KeyboardPress", "KeyboardRelease"];
+
+/// How long a client waits without a frame before deciding nothing more is coming.
+const QUIET: Duration = Duration::from_millis(300);
+
+/// What the scripted source does next.
+enum Step {
+    /// Delivers the events, then reports back.
+    Play(MockSource, oneshot::Sender<()>),
+    /// Fails the source, as a broken OS hook would.
+    Fail,
+}
+
+/// Input that scenarios feed one step at a time. Restarts after a failure carry on
+/// with the same queue of steps.
+#[derive(Clone)]
+struct ScriptedSource(Arc<Mutex<mpsc::Receiver<Step>>>);
+
+impl EventSource for ScriptedSource {
+    fn run(self, sink: impl Fn(RawInputEvent) + 'static) -> Result<(), String> {
+        let sink = Rc::new(sink);
+        loop {
+            let step = self.0.lock().unwrap().recv();
+            match step {
+                Ok(Step::Play(script, played)) => {
+                    let sink = sink.clone();
+                    script.run(move |event| sink(event))?;
+                    let _ = played.send(());
+                }
+                Ok(Step::Fail) => return Err("scripted failure".to_string()),
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// The running server and the way into its input source.
+struct Server {
+    port: u16,
+    steps: mpsc::Sender<Step>,
+    _dir: tempfile::TempDir,
+}
+
+impl Server {
+    fn start() -> Server {
+        let dir = tempfile::tempdir().unwrap();
+        let discovery = dir.path().join("server.json");
+        let cli = Cli::parse_from([
+            "pet-input-server",
+            "--port",
+            "0",
+            "--discovery-file",
+            discovery.to_str().unwrap(),
+            "--handshake-timeout-secs",
+            "1",
+            // Floods are part of some scripts; the alarm would throttle the ones after.
+            "--flood-ceiling",
+            "0",
+        ]);
+        let (steps, script) = mpsc::channel();
+        let source = ScriptedSource(Arc::new(Mutex::new(script)));
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(run_with(cli, source));
+        });
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let port = loop {
+            let info = std::fs::read_to_string(&discovery)
+                .ok()
+                .and_then(|text| serde_json::from_str::<Value>(&text).ok());
+            if let Some(port) = info.as_ref().and_then(|info| info["port"].as_u64()) {
+                break port as u16;
+            }
+            assert!(Instant::now() < deadline, "server did not start");
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        Server { port, steps, _dir: dir }
+    }
+
+    fn url(&self, query: &str) -> String {
+        format!("ws://127.0.0.1:{}/{}", self.port, query)
+    }
+
+    async fn play(&self, script: &Script) {
+        match script {
+            Script::Events(events) => {
+                let (played, done) = oneshot::channel();
+                let mock = MockSource { events: events.clone() };
+                self.steps.send(Step::Play(mock, played)).unwrap();
+                done.await.unwrap();
+            }
+            Script::Failures(count) => {
+                for _ in 0..*count {
+                    self.steps.send(Step::Fail).unwrap();
+                }
+            }
+        }
+    }
+}
+
+enum Script {
+    /// Raw input at offsets from when the script starts.
+    Events(Vec<(Duration, EventType)>),
+    /// This many source failures in a row.
+    Failures(usize),
+}
+
+/// What one client of a scenario expects.
+enum Expect {
+    /// Exactly these input actions, decoded as `format` says; other kinds are skipped.
+    Input(&'static str, Vec<Value>),
+    /// A frame of this kind, after which the server still accepts new clients.
+    Kind(&'static str),
+    /// Never sends a handshake, and is dropped without a byte after the 1s timeout.
+    DroppedBeforeHandshake,
+    /// Stops reading during the script. When it reads again it is told `Backpressure`
+    /// `high` and has lost some of the `sent` key presses, but is still connected.
+    StalledAndShed { sent: usize },
+    /// Stops reading during the script and is disconnected with this close code.
+    StalledAndClosed(u16),
+}
+
+struct Scenario {
+    name: &'static str,
+    script: Script,
+    clients: Vec<(&'static str, Expect)>,
+}
+
+type Socket = tokio_tungstenite::WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// The next frame, or `None` if there is none within `wait`.
+async fn next_frame(socket: &mut Socket, wait: Duration) -> Option<Message> {
+    let received = tokio::time::timeout(wait, socket.next()).await.ok()?;
+    Some(received.expect("connection closed").unwrap())
+}
+
+/// Connects, reads whatever the server sends on connect (hello, last cursor) until it
+/// goes quiet, then reports ready.
+async fn connect(url: String, ready: oneshot::Sender<()>) -> Socket {
+    let (socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    settle(socket, ready).await
+}
+
+/// Like `connect`, with a receive buffer small enough that the server's writes back up
+/// soon after the client stops reading.
+async fn connect_small(url: String, ready: oneshot::Sender<()>) -> Socket {
+    let address = url.trim_start_matches("ws://").split('/').next().unwrap().parse().unwrap();
+    let tcp = tokio::net::TcpSocket::new_v4().unwrap();
+    tcp.set_recv_buffer_size(4096).unwrap();
+    let stream = tcp.connect(address).await.unwrap();
+    let (socket, _) = tokio_tungstenite::client_async(url, MaybeTlsStream::Plain(stream)).await.unwrap();
+    settle(socket, ready).await
+}
+
+async fn settle(mut socket: Socket, ready: oneshot::Sender<()>) -> Socket {
+    while next_frame(&mut socket, QUIET).await.is_some() {}
+    let _ = ready.send(());
+    socket
+}
+
+fn decode(format: &str, message: Message) -> Option<Value> {
+    match (format, message) {
+        ("json", Message::Text(text)) => Some(serde_json::from_str(&text).unwrap()),
+        ("msgpack", Message::Binary(bytes)) => Some(rmp_serde::from_slice(&bytes).unwrap()),
+        ("plaintext", Message::Text(text)) => {
+            let mut words = text.trim_end().splitn(2, ' ');
+            Some(json!({"kind": words.next().unwrap(), "value": words.next().unwrap_or("")}))
+        }
+        (_, Message::Ping(_) | Message::Pong(_)) => None,
+        (format, message) => panic!("unexpected {:?} frame for {}", message, format),
+    }
+}
+
+async fn run_client(url: String, expect: Expect, ready: oneshot::Sender<()>, mut played: watch::Receiver<bool>) {
+    match expect {
+        Expect::Input(format, expected) => {
+            let mut socket = connect(url.clone(), ready).await;
+            let _ = played.wait_for(|played| *played).await;
+            let mut input = Vec::new();
+            let deadline = Instant::now() + Duration::from_secs(10);
+            // Everything that was coming has arrived once the server goes quiet.
+            while let Some(message) = next_frame(&mut socket, QUIET).await {
+                let Some(action) = decode(format, message) else { continue };
+                if INPUT_KINDS.iter().any(|kind| action["kind"] == *kind) {
+                    input.push(action);
+                }
+                assert!(Instant::now() < deadline, "{}: frames kept coming", url);
+            }
+            assert_eq!(input, expected, "{}", url);
+        }
+        Expect::Kind(kind) => {
+            let mut socket = connect(url.clone(), ready).await;
+            let deadline = Instant::now() + Duration::from_secs(30);
+            loop {
+                let message = next_frame(&mut socket, Duration::from_secs(1)).await;
+                assert!(Instant::now() < deadline, "{}: no {} before the deadline", url, kind);
+                let Some(Message::Text(text)) = message else { continue };
+                let action: Value = serde_json::from_str(&text).unwrap();
+                if action["kind"] == kind {
+                    break;
+                }
+            }
+            // Still serving: a new client connects fine.
+            tokio_tungstenite::connect_async(url).await.unwrap();
+        }
+        Expect::DroppedBeforeHandshake => {
+            let address = url.trim_start_matches("ws://").split('/').next().unwrap().to_string();
+            let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            let _ = ready.send(());
+            let started = Instant::now();
+            let mut buffer = [0; 64];
+            let read = tokio::time::timeout(Duration::from_secs(10), stream.read(&mut buffer)).await;
+            assert_eq!(read.expect("still open after 10s").unwrap(), 0, "{}", url);
+            assert!(started.elapsed() >= Duration::from_millis(900), "{:?}", started.elapsed());
+        }
+        Expect::StalledAndShed { sent } => {
+            let mut socket = connect_small(url.clone(), ready).await;
+            let _ = played.wait_for(|played| *played).await;
+            let (mut high, mut presses) = (false, 0);
+            while let Some(message) = next_frame(&mut socket, QUIET).await {
+                let Some(action) = decode("json", message) else { continue };
+                high |= action == json!({"kind": "Backpressure", "value": "high"});
+                presses += usize::from(action["kind"] == "KeyboardPress");
+            }
+            assert!(high, "{}: no Backpressure notice", url);
+            assert!(presses < sent, "{}: got all {} presses", url, sent);
+            // Dropped events, not the connection.
+            socket.send(Message::Ping(Vec::new())).await.unwrap();
+        }
+        Expect::StalledAndClosed(code) => {
+            let mut socket = connect_small(url.clone(), ready).await;
+            let _ = played.wait_for(|played| *played).await;
+            // Past `--queue-block-timeout-ms`, so the server gives up on the client.
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            let closed = loop {
+                let frame = tokio::time::timeout(Duration::from_secs(10), socket.next()).await;
+                match frame.expect("still open after 10s") {
+                    Some(Ok(Message::Close(frame))) => break frame,
+                    Some(Ok(_)) => continue,
+                    other => panic!("{}: no close frame: {:?}", url, other),
+                }
+            };
+            assert_eq!(closed.map(|frame| u16::from(frame.code)), Some(code), "{}", url);
+        }
+    }
+}
+
+/// Connects every client, plays the script once they are all ready, and checks them.
+async fn run_scenario(server: &Server, scenario: Scenario) {
+    println!("Scenario: {}", scenario.name);
+    let (played_tx, played_rx) = watch::channel(false);
+    let mut readies = Vec::new();
+    let mut clients = Vec::new();
+    for (query, expect) in scenario.clients {
+        let (ready, ready_rx) = oneshot::channel();
+        readies.push(ready_rx);
+        clients.push(tokio::spawn(run_client(server.url(query), expect, ready, played_rx.clone())));
+    }
+    for ready in readies {
+        ready.await.unwrap_or_else(|_| panic!("{}: a client failed to connect", scenario.name));
+    }
+    server.play(&scenario.script).await;
+    played_tx.send(true).unwrap();
+    for client in clients {
+        if let Err(e) = client.await {
+            panic!("{}: {}", scenario.name, e);
+        }
+    }
+}
+
+fn at(ms: u64, event: EventType) -> (Duration, EventType) {
+    (Duration::from_millis(ms), event)
+}
+
+/// Some of each input kind, 50ms apart.
+fn mixed_input() -> Script {
+    Script::Events(vec![
+        at(0, EventType::MouseMove { x: 10.0, y: 20.0 }),
+        at(50, EventType::ButtonPress(Button::Left)),
+        at(100, EventType::KeyPress(Key::KeyA)),
+        at(150, EventType::KeyRelease(Key::KeyA)),
+        at(200, EventType::ButtonRelease(Button::Left)),
+        at(250, EventType::MouseMove { x: 30.0, y: 40.0 }),
+    ])
+}
+
+fn structured() -> Vec<Value> {
+    vec![
+        json!({"kind": "MouseMove", "value": {"x": 10, "y": 20}}),
+        json!({"kind": "MousePress", "value": "Mouse1"}),
+        json!({"kind": "KeyboardPress", "value": "KeyA"}),
+        json!({"kind": "KeyboardRelease", "value": "KeyA"}),
+        json!({"kind": "MouseRelease", "value": "Mouse1"}),
+        json!({"kind": "MouseMove", "value": {"x": 30, "y": 40}}),
+    ]
+}
+
+fn plaintext() -> Vec<Value> {
+    vec![
+        json!({"kind": "MouseMove", "value": "10 20"}),
+        json!({"kind": "MousePress", "value": "Mouse1"}),
+        json!({"kind": "KeyboardPress", "value": "KeyA"}),
+        json!({"kind": "KeyboardRelease", "value": "KeyA"}),
+        json!({"kind": "MouseRelease", "value": "Mouse1"}),
+        json!({"kind": "MouseMove", "value": "30 40"}),
+    ]
+}
+
+fn only(actions: Vec<Value>, kinds: &[&str]) -> Vec<Value> {
+    actions.into_iter().filter(|action| kinds.iter().any(|kind| action["kind"] == *kind)).collect()
+}
+
+/// `pairs` presses and releases of one key, as fast as the source can deliver them.
+fn key_flood(pairs: usize) -> Script {
+    let pair = [EventType::KeyPress(Key::KeyB), EventType::KeyRelease(Key::KeyB)];
+    Script::Events(pair.iter().cycle().take(pairs * 2).map(|event| (Duration::ZERO, *event)).collect())
+}
+
+fn moves(points: &[(u32, u32)]) -> Vec<Value> {
+    points.iter().map(|(x, y)| json!({"kind": "MouseMove", "value": {"x": x, "y": y}})).collect()
+}
+
+/// Key presses (and as many releases) in the stalled-reader scenario; far more than the
+/// broadcast ring and socket buffers hold.
+const FLOOD_PAIRS: usize = 20_000;
+
+fn scenarios() -> Vec<Scenario> {
+    let keys = ["KeyboardPress", "KeyboardRelease"];
+    let buttons = ["MousePress", "MouseRelease"];
+    vec![
+        Scenario {
+            name: "every client gets the input in its format and kinds",
+            script: mixed_input(),
+            clients: vec![
+                ("", Expect::Input("json", structured())),
+                ("?format=json", Expect::Input("json", structured())),
+                ("?format=msgpack", Expect::Input("msgpack", structured())),
+                ("?format=plaintext", Expect::Input("plaintext", plaintext())),
+                ("?kinds=KeyboardPress,KeyboardRelease", Expect::Input("json", only(structured(), &keys))),
+                (
+                    "?format=msgpack&kinds=MousePress,MouseRelease",
+                    Expect::Input("msgpack", only(structured(), &buttons)),
+                ),
+                ("?format=plaintext&kinds=MouseMove", Expect::Input("plaintext", only(plaintext(), &["MouseMove"]))),
+            ],
+        },
+        Scenario {
+            name: "mouse moves closer than the 16ms throttle are held back",
+            script: Script::Events(vec![
+                at(0, EventType::MouseMove { x: 1.0, y: 1.0 }),
+                at(4, EventType::MouseMove { x: 2.0, y: 2.0 }),
+                at(8, EventType::MouseMove { x: 3.0, y: 3.0 }),
+                at(40, EventType::MouseMove { x: 4.0, y: 4.0 }),
+                at(44, EventType::MouseMove { x: 5.0, y: 5.0 }),
+            ]),
+            clients: vec![("", Expect::Input("json", moves(&[(1, 1), (4, 4)])))],
+        },
+        Scenario {
+            name: "max_rate caps one client and leaves the others alone",
+            script: Script::Events(vec![
+                at(0, EventType::MouseMove { x: 1.0, y: 1.0 }),
+                at(30, EventType::MouseMove { x: 2.0, y: 2.0 }),
+                at(60, EventType::MouseMove { x: 3.0, y: 3.0 }),
+                at(90, EventType::MouseMove { x: 4.0, y: 4.0 }),
+            ]),
+            clients: vec![
+                ("?max_rate=1", Expect::Input("json", moves(&[(1, 1)]))),
+                ("?max_rate=100", Expect::Input("json", moves(&[(1, 1), (2, 2), (3, 3), (4, 4)]))),
+                ("", Expect::Input("json", moves(&[(1, 1), (2, 2), (3, 3), (4, 4)]))),
+            ],
+        },
+        Scenario {
+            name: "a connection that never handshakes is dropped",
+            script: Script::Events(Vec::new()),
+            clients: vec![("", Expect::DroppedBeforeHandshake)],
+        },
+        Scenario {
+            name: "a client that stops reading is shed, or closed under the block policy",
+            script: key_flood(FLOOD_PAIRS),
+            clients: vec![
+                ("?kinds=KeyboardPress", Expect::StalledAndShed { sent: FLOOD_PAIRS }),
+                ("?kinds=KeyboardPress&queue=block", Expect::StalledAndClosed(4004)),
+            ],
+        },
+        // Last: the source doesn't come back. It fails, is restarted after 1s, 2s and
+        // 4s, and fails each time.
+        Scenario {
+            name: "a dead input source is reported to clients",
+            script: Script::Failures(4),
+            clients: vec![("", Expect::Kind("InputFailure"))],
+        },
+    ]
+}
+
+#[tokio::test]
+async fn scenarios_run_against_one_server() {
+    let server = Server::start();
+    for scenario in scenarios() {
+        run_scenario(&server, scenario).await;
+    }
+}